@@ -0,0 +1,65 @@
+// External imports.
+use std::time::{Duration, Instant};
+
+// Internal imports.
+use cerium_framework::ast;
+use cerium_framework::crate_path;
+use cerium_framework::ddlog_interface;
+use cerium_framework::parse_into_relation_tree;
+use cerium_framework::standard_type_check_without_parse;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+// Time from saving the changed file to having a result (parse + delta + check against parse + full check).
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Save to Result");
+    for index in 0..5 {
+        let original_path = crate_path(&format!(
+            "benches/dataset/program2/{}_program2_original.c",
+            index
+        ));
+        let changed_path = crate_path(&format!(
+            "benches/dataset/program2/{}_program2_change.c",
+            index
+        ));
+        let original_ast = parse_into_relation_tree(original_path);
+        let mut checker = ddlog_interface::TypeChecker::new(ddlog_interface::OutputLevel::Quiet);
+        checker.check_initial(&original_ast).unwrap();
+        group.bench_function(
+            BenchmarkId::new("Standard", format!("program2/{}", index)),
+            |b| {
+                b.iter(|| {
+                    standard_type_check_without_parse(parse_into_relation_tree(
+                        changed_path.clone(),
+                    ));
+                })
+            },
+        );
+        // Each change is undone again outside of the timed part so every iteration starts from the original program.
+        group.bench_function(
+            BenchmarkId::new("Incremental", format!("program2/{}", index)),
+            |b| {
+                b.iter_custom(|iterations| {
+                    let mut total = Duration::ZERO;
+                    for _ in 0..iterations {
+                        let start = Instant::now();
+                        let changed_ast = parse_into_relation_tree(changed_path.clone());
+                        let (insertion_set, deletion_set, _) =
+                            ast::get_diff_relation_set(&original_ast, &changed_ast);
+                        checker
+                            .check_relations(insertion_set.clone(), deletion_set.clone())
+                            .unwrap();
+                        total += start.elapsed();
+                        checker
+                            .check_relations(deletion_set, insertion_set)
+                            .unwrap();
+                    }
+                    total
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -1,3 +1,4 @@
+use cerium_framework::crate_path;
 use cerium_framework::single_datalog_type_check;
 use cerium_framework::single_standard_type_check;
 use criterion::{criterion_group, criterion_main, Criterion};
@@ -6,16 +7,12 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("Program 1 Initial Run");
     group.bench_function("Standard", |b| {
         b.iter(|| {
-            single_standard_type_check(String::from(
-                "./benches/dataset/program2/4_program2_original.c",
-            ))
+            single_standard_type_check(crate_path("benches/dataset/program2/4_program2_original.c"))
         })
     });
     group.bench_function("Incremental", |b| {
         b.iter(|| {
-            single_datalog_type_check(String::from(
-                "./benches/dataset/program2/4_program2_original.c",
-            ))
+            single_datalog_type_check(crate_path("benches/dataset/program2/4_program2_original.c"))
         })
     });
     group.finish();
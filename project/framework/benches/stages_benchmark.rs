@@ -1,32 +1,70 @@
 // External imports.
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 
 // Internal imports.
 use cerium_framework::ast;
 use cerium_framework::compute_diff;
+use cerium_framework::crate_path;
 use cerium_framework::ddlog_interface;
+use cerium_framework::definitions;
 use cerium_framework::parse_into_relation_tree;
+use cerium_framework::parser_interface;
 use cerium_framework::standard_type_check_without_parse;
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 
 // Just time actual type checking computation without any of the rest.
 pub fn criterion_benchmark(c: &mut Criterion) {
     // We will separately set up and run the benchmarks in order to have to not deal with passing inputs.
-    let (hddlog, _) = type_checker_ddlog::run(1, false).unwrap();
+    let mut checker = ddlog_interface::TypeChecker::new(ddlog_interface::OutputLevel::Quiet);
     let mut group = c.benchmark_group("Stage Timing");
     group.bench_function("DDlog setup", |b| {
         b.iter(|| {
-            type_checker_ddlog::run(1, false).unwrap();
+            ddlog_interface::TypeChecker::new(ddlog_interface::OutputLevel::Quiet);
         })
     });
-    let initial_ast = parse_into_relation_tree(String::from(
-        "./benches/dataset/program1/0_program1_original.c",
-    ));
+    let initial_ast =
+        parse_into_relation_tree(crate_path("benches/dataset/program1/0_program1_original.c"));
     group.bench_function("Initial parse", |b| {
         b.iter(|| {
-            parse_into_relation_tree(String::from(
-                "./benches/dataset/program1/0_program1_original.c",
-            ));
+            parse_into_relation_tree(crate_path("benches/dataset/program1/0_program1_original.c"));
+        })
+    });
+    let base_programs: Vec<String> = (0..5)
+        .map(|index| crate_path(&format!("benches/dataset/base_programs/example{}.c", index)))
+        .collect();
+    group.bench_function("Serial parse of base programs", |b| {
+        b.iter(|| {
+            for file_path in &base_programs {
+                parse_into_relation_tree(file_path.clone());
+            }
+        })
+    });
+    group.bench_function("Parallel parse of base programs", |b| {
+        b.iter(|| {
+            parser_interface::parse_files(&base_programs);
+        })
+    });
+    // The diff helpers clone the tree repeatedly so this is worth tracking on its own.
+    group.bench_function("Clone tree", |b| {
+        b.iter(|| {
+            initial_ast.clone();
+        })
+    });
+    // Lookups during checking and diffing, cloned against borrowed.
+    let node_ids: Vec<definitions::ID> = initial_ast.iter().map(|(id, _)| id).collect();
+    group.bench_function("Look up every relation by clone", |b| {
+        b.iter(|| {
+            for node_id in &node_ids {
+                initial_ast.get_relation(*node_id);
+            }
+        })
+    });
+    group.bench_function("Look up every relation by reference", |b| {
+        b.iter(|| {
+            for node_id in &node_ids {
+                initial_ast.relation_ref(*node_id);
+            }
         })
     });
     let initial_insertions = ast::get_initial_relation_set(&initial_ast);
@@ -37,13 +75,9 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     });
     group.bench_function("Initial datalog type check", |b| {
         b.iter(|| {
-            ddlog_interface::run_ddlog_type_checker(
-                &hddlog,
-                initial_insertions.clone(),
-                HashSet::new(),
-                false,
-                true,
-            );
+            checker
+                .check_relations(initial_insertions.clone(), HashSet::new())
+                .unwrap();
         })
     });
     group.bench_function("Standard type check", |b| {
@@ -51,34 +85,163 @@ pub fn criterion_benchmark(c: &mut Criterion) {
             standard_type_check_without_parse(initial_ast.clone());
         })
     });
-    let modified_ast = parse_into_relation_tree(String::from(
-        "./benches/dataset/program1/0_program1_original.c",
-    ));
+    let modified_ast =
+        parse_into_relation_tree(crate_path("benches/dataset/program1/0_program1_original.c"));
     group.bench_function("Modified parse", |b| {
         b.iter(|| {
-            parse_into_relation_tree(String::from(
-                "./benches/dataset/program1/0_program1_original.c",
-            ));
+            parse_into_relation_tree(crate_path("benches/dataset/program1/0_program1_original.c"));
         })
     });
     let (insertion_set, deletion_set, _) = compute_diff(initial_ast.clone(), modified_ast.clone());
+    // Diff by reference so the measurement isn't dominated by cloning the input trees.
     group.bench_function("Compute program delta", |b| {
         b.iter(|| {
-            compute_diff(initial_ast.clone(), modified_ast.clone());
+            ast::get_diff_relation_set(&initial_ast, &modified_ast);
         })
     });
     group.bench_function("Modified datalog type check", |b| {
         b.iter(|| {
-            ddlog_interface::run_ddlog_type_checker(
-                &hddlog,
-                insertion_set.clone(),
-                deletion_set.clone(),
-                false,
-                true,
-            );
+            checker
+                .check_relations(insertion_set.clone(), deletion_set.clone())
+                .unwrap();
+        })
+    });
+    // A one line change in a file with many functions, where only the changed function has to be parsed again.
+    let original_source =
+        fs::read_to_string(crate_path("benches/dataset/program2/4_program2_original.c")).unwrap();
+    let changed_path = crate_path("benches/dataset/program2/4_program2_change.c");
+    let changed_source = fs::read_to_string(&changed_path).unwrap();
+    let edit_start = original_source
+        .bytes()
+        .zip(changed_source.bytes())
+        .take_while(|(original, changed)| original == changed)
+        .count();
+    let edit_end = changed_source.len()
+        - original_source
+            .bytes()
+            .rev()
+            .zip(changed_source.bytes().rev())
+            .take(original_source.len().min(changed_source.len()) - edit_start)
+            .take_while(|(original, changed)| original == changed)
+            .count();
+    let original_ast = parser_interface::parse_source_into_ast(&original_source).unwrap();
+    group.bench_function("Full re-parse and delta", |b| {
+        b.iter(|| {
+            let source = fs::read_to_string(&changed_path).unwrap();
+            let changed_ast = parser_interface::parse_source_into_ast(&source).unwrap();
+            ast::get_diff_relation_set(&original_ast, &changed_ast);
+        })
+    });
+    group.bench_function("Partial re-parse and delta", |b| {
+        b.iter(|| {
+            parser_interface::reparse_changed(&changed_path, &original_ast, edit_start..edit_end)
+                .unwrap();
+        })
+    });
+    // An else-if chain nests one statement per branch, so anything done per level adds up quickly.
+    let else_if_chain = |last: &str| {
+        let branches: Vec<String> = (0..200)
+            .map(|index| format!("if (a == {}) {{ b = {}; }}", index, index))
+            .collect();
+        format!(
+            "int main(void) {{ int a = 1; int b = 0; {} else {{ b = {}; }} return b; }}",
+            branches.join(" else "),
+            last
+        )
+    };
+    let chain_ast = parser_interface::parse_source_into_ast(&else_if_chain("0")).unwrap();
+    let changed_chain_ast = parser_interface::parse_source_into_ast(&else_if_chain("a")).unwrap();
+    group.bench_function("Compute delta of an else-if chain", |b| {
+        b.iter(|| {
+            ast::get_diff_relation_set(&chain_ast, &changed_chain_ast);
+        })
+    });
+    // Treat each function as a separate file to compare N transactions against one batched one.
+    // Every iteration inserts and then removes all the chunks so the DDlog state is the same each time.
+    let chunks: Vec<HashSet<definitions::AstRelation>> = initial_ast
+        .walk(initial_ast.get_root())
+        .filter_map(|(id, relation)| match relation {
+            definitions::AstRelation::FunDef { .. } => Some(
+                initial_ast
+                    .walk(id)
+                    .map(|(_, relation)| relation.clone())
+                    .collect(),
+            ),
+            _ => None,
+        })
+        .collect();
+    group.bench_function("Separate datalog type checks", |b| {
+        b.iter(|| {
+            for chunk in &chunks {
+                checker
+                    .check_relations(chunk.clone(), HashSet::new())
+                    .unwrap();
+            }
+            for chunk in &chunks {
+                checker
+                    .check_relations(HashSet::new(), chunk.clone())
+                    .unwrap();
+            }
+        })
+    });
+    group.bench_function("Batched datalog type check", |b| {
+        b.iter(|| {
+            checker
+                .check_batch(
+                    chunks
+                        .iter()
+                        .map(|chunk| (chunk.clone(), HashSet::new()))
+                        .collect(),
+                )
+                .unwrap();
+            checker
+                .check_batch(
+                    chunks
+                        .iter()
+                        .map(|chunk| (HashSet::new(), chunk.clone()))
+                        .collect(),
+                )
+                .unwrap();
         })
     });
     group.finish();
+
+    // The same kind of change in programs of growing size (labelled by size class and number of statements).
+    let mut group = c.benchmark_group("Delta by Program Size");
+    for index in 0..5 {
+        let original_ast = parse_into_relation_tree(crate_path(&format!(
+            "benches/dataset/program2/{}_program2_original.c",
+            index
+        )));
+        let changed_ast = parse_into_relation_tree(crate_path(&format!(
+            "benches/dataset/program2/{}_program2_change.c",
+            index
+        )));
+        let histogram = original_ast.relation_histogram();
+        let statements =
+            histogram.get("Item").unwrap_or(&0) + histogram.get("EndItem").unwrap_or(&0);
+        group.bench_function(
+            BenchmarkId::new(
+                size_class(&histogram),
+                format!("program2/{} ({} statements)", index, statements),
+            ),
+            |b| {
+                b.iter(|| {
+                    ast::get_diff_relation_set(&original_ast, &changed_ast);
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+// Rough size classes by total number of relations.
+fn size_class(histogram: &HashMap<&'static str, usize>) -> &'static str {
+    match histogram.values().sum::<usize>() {
+        0..=499 => "Small",
+        500..=1999 => "Medium",
+        _ => "Large",
+    }
 }
 
 criterion_group!(benches, criterion_benchmark);
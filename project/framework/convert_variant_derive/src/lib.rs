@@ -51,3 +51,84 @@ pub fn derive_convert_to_relid(input: TokenStream) -> TokenStream {
     };
     TokenStream::from(full_function)
 }
+
+// Derives a function that returns the name of the enum variant (e.g. for summaries and debugging output).
+#[proc_macro_derive(VariantName)]
+pub fn derive_variant_name(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = parse_macro_input!(input as DeriveInput);
+    let ref name = input.ident;
+    let ref data = input.data;
+    let mut variant_cases;
+    match data {
+        Data::Enum(data_enum) => {
+            variant_cases = TokenStream2::new();
+            for variant in &data_enum.variants {
+                let ref variant_name = variant.ident;
+                let variant_string = variant_name.to_string();
+                let fields_in_variant = match &variant.fields {
+                    Fields::Unnamed(_) => quote_spanned! {variant.span()=> (..) },
+                    Fields::Unit => quote_spanned! { variant.span()=> },
+                    Fields::Named(_) => quote_spanned! {variant.span()=> {..} },
+                };
+                variant_cases.extend(quote_spanned! {variant.span() =>
+                    #name::#variant_name #fields_in_variant => #variant_string,
+                })
+            }
+        }
+        _ => return derive_error!("VariantName only implemented for enums"),
+    };
+    let full_function = quote! {
+        impl #name {
+            pub fn variant_name(&self) -> &'static str {
+                match self {
+                    #variant_cases
+                }
+            }
+        }
+    };
+    TokenStream::from(full_function)
+}
+
+// Derives a function that writes a variant as a DDlog CLI relation value (e.g. 'Arg(4, "a", 5)'),
+// with the fields in declaration order (the same order as in the DDlog relation).
+#[proc_macro_derive(DDlogRecord)]
+pub fn derive_ddlog_record(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = parse_macro_input!(input as DeriveInput);
+    let ref name = input.ident;
+    let ref data = input.data;
+    let mut variant_cases;
+    match data {
+        Data::Enum(data_enum) => {
+            variant_cases = TokenStream2::new();
+            for variant in &data_enum.variants {
+                let ref variant_name = variant.ident;
+                let variant_string = variant_name.to_string();
+                let fields: Vec<_> = match &variant.fields {
+                    Fields::Named(fields) => fields.named.iter().map(|f| &f.ident).collect(),
+                    Fields::Unit => vec![],
+                    Fields::Unnamed(_) => {
+                        return derive_error!("DDlogRecord only implemented for named fields")
+                    }
+                };
+                // Debug output of integers, booleans, strings and vectors is also valid DDlog syntax.
+                variant_cases.extend(quote_spanned! {variant.span() =>
+                    #name::#variant_name { #(#fields),* } => {
+                        let args: Vec<String> = vec![#(format!("{:?}", #fields)),*];
+                        format!("{}({})", #variant_string, args.join(", "))
+                    }
+                })
+            }
+        }
+        _ => return derive_error!("DDlogRecord only implemented for enums"),
+    };
+    let full_function = quote! {
+        impl #name {
+            pub fn ddlog_record(&self) -> String {
+                match self {
+                    #variant_cases
+                }
+            }
+        }
+    };
+    TokenStream::from(full_function)
+}
@@ -1,37 +1,47 @@
 use crate::definitions::{AstRelation, ID};
-use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 // For storing information about node location (will be useful for error reporting).
-#[derive(Debug, Clone, Copy)]
-struct Location {}
+// Byte offsets into the parsed source (end is exclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    pub start: usize,
+    pub end: usize,
+}
 
 // Main tree representing program that we will maintain throughout runtime.
 // For simplicity make the whole tree have the same lifetime (arena allocation).
-#[derive(Debug, Clone)]
+// IDs are dense so the arena is indexed directly by ID (deleted nodes leave a gap).
+// New IDs come from a counter that never goes down, so a deleted node's ID is never handed out again
+// (a stale reference to it can't end up pointing at an unrelated node).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Tree {
-    arena: HashMap<ID, AstNode>,
-    max_id: ID,
+    arena: Vec<Option<AstNode>>,
+    next_id: ID,
     root_id: ID,
 }
 
 impl fmt::Display for Tree {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.max_id)
+        write!(f, "{}", self.last_id())
     }
 }
 
 impl Tree {
     pub fn new() -> Self {
         Self {
-            arena: HashMap::new(),
-            max_id: 0,
+            arena: Vec::new(),
+            next_id: 0,
             root_id: 0,
         }
     }
 
     pub fn get_node(&self, index: ID) -> AstNode {
-        let result = self.arena.get(&index);
+        let result = self.lookup(index);
         match result {
             Some(node) => node.clone(),
             None => panic!("No node with this ID in tree"),
@@ -39,57 +49,143 @@ impl Tree {
     }
 
     pub fn get_relation(&self, index: ID) -> AstRelation {
-        let result = self.arena.get(&index);
+        let result = self.lookup(index);
         match result {
             Some(node) => node.relation.clone(),
             None => panic!("No relation with this ID ({}) in tree", index),
         }
     }
 
+    // Borrowing lookup for read-only traversals (get_relation clones the names along with the relation).
+    pub fn relation_ref(&self, index: ID) -> &AstRelation {
+        match self.lookup(index) {
+            Some(node) => &node.relation,
+            None => panic!("No relation with this ID ({}) in tree", index),
+        }
+    }
+
+    // The IDs of the nodes linked below this one, in the order they were linked.
+    pub fn get_children(&self, index: ID) -> Vec<ID> {
+        match self.lookup(index) {
+            Some(node) => node.children.clone(),
+            None => panic!("No node with this ID ({}) in tree", index),
+        }
+    }
+
+    // Existing nodes are never overwritten (that would leave their parents pointing at the wrong node).
     pub fn add_node(&mut self, node_id: ID, relation: AstRelation) {
-        self.arena.insert(node_id, AstNode::new(node_id, relation));
-        if node_id > self.max_id {
-            self.max_id = node_id;
+        if self.lookup(node_id).is_some() {
+            panic!("Node with this ID ({}) already in tree", node_id);
         }
+        let hash = self.compute_hash(&relation);
+        self.insert(node_id, AstNode::new(node_id, relation, hash));
+        if node_id >= self.next_id {
+            self.next_id = node_id + 1;
+        }
+    }
+
+    // Links the statements of a block in order and returns the first item (an empty item if there are no statements).
+    // The items get IDs counting up from next_id (parsers hand out IDs themselves).
+    pub fn add_item_chain(&mut self, stmt_ids: Vec<ID>, next_id: &mut ID) -> ID {
+        let mut allocate_id = || {
+            let node_id = *next_id;
+            *next_id = *next_id + 1;
+            node_id
+        };
+        if stmt_ids.is_empty() {
+            let node_id = allocate_id();
+            self.add_node(node_id, AstRelation::EmptyItem { id: node_id });
+            return node_id;
+        }
+        // Built backwards since every item has to know the one after it.
+        let mut next_stmt_id = None;
+        for stmt_id in stmt_ids.into_iter().rev() {
+            let node_id = allocate_id();
+            let relation = match next_stmt_id {
+                Some(next_stmt_id) => AstRelation::Item {
+                    id: node_id,
+                    stmt_id,
+                    next_stmt_id,
+                },
+                None => AstRelation::EndItem {
+                    id: node_id,
+                    stmt_id,
+                },
+            };
+            let child_ids = get_child_ids(&relation);
+            self.add_node(node_id, relation);
+            self.replace_children(node_id, child_ids);
+            next_stmt_id = Some(node_id);
+        }
+        next_stmt_id.unwrap()
     }
 
     pub fn add_root_node(&mut self, node_id: ID, relation: AstRelation) {
-        self.arena.insert(node_id, AstNode::new(node_id, relation));
+        if self.lookup(node_id).is_some() {
+            panic!("Node with this ID ({}) already in tree", node_id);
+        }
+        let hash = self.compute_hash(&relation);
+        self.insert(node_id, AstNode::new(node_id, relation, hash));
         self.root_id = node_id;
-        if node_id > self.max_id {
-            self.max_id = node_id;
+        if node_id >= self.next_id {
+            self.next_id = node_id + 1;
         }
     }
 
     pub fn link_child(&mut self, node_id: ID, child_id: ID) {
-        if self.arena.contains_key(&node_id) && self.arena.contains_key(&child_id) {
-            self.arena.get_mut(&node_id).unwrap().link_child(child_id);
+        if self.lookup(child_id).is_some() {
+            if let Some(node) = self.lookup_mut(node_id) {
+                node.link_child(child_id);
+            }
         }
     }
 
     pub fn replace_children(&mut self, node_id: ID, child_ids: Vec<ID>) {
-        if self.arena.contains_key(&node_id) {
-            self.arena
-                .get_mut(&node_id)
-                .unwrap()
-                .replace_children(child_ids);
+        if let Some(node) = self.lookup_mut(node_id) {
+            node.replace_children(child_ids);
         }
     }
 
     pub fn size(&self) -> usize {
-        self.arena.len()
+        self.arena.iter().flatten().count()
+    }
+
+    // Number of nodes of each relation kind (e.g. to relate diff cost to the shape of a program).
+    pub fn relation_histogram(&self) -> HashMap<&'static str, usize> {
+        let mut histogram = HashMap::new();
+        for node in self.arena.iter().flatten() {
+            *histogram.entry(node.relation.variant_name()).or_insert(0) += 1;
+        }
+        histogram
     }
 
     pub fn pretty_print(&self) {
-        self.arena
-            .get(&self.root_id)
+        self.lookup(self.root_id)
             .unwrap()
-            .pretty_print(&String::from(""), &self.arena);
+            .pretty_print(&String::from(""), self);
+    }
+
+    // Reconstruct (reindented) C source from the tree.
+    // Constants only keep their type so they are printed as placeholder values of that type.
+    pub fn to_c_source(&self) -> String {
+        let mut source = String::new();
+        match self.get_relation(self.root_id) {
+            AstRelation::TransUnit { id: _, body_ids } => {
+                for (index, body_id) in body_ids.iter().enumerate() {
+                    if index > 0 {
+                        source.push('\n');
+                    }
+                    write_c_external_declaration(self, *body_id, &mut source);
+                }
+            }
+            _ => write_c_external_declaration(self, self.root_id, &mut source),
+        }
+        source
     }
 
     pub fn flat_print(&self) {
-        for node in &self.arena {
-            println!("{:?}", node.1.relation);
+        for node in self.arena.iter().flatten() {
+            println!("{}", node.relation);
         }
     }
 
@@ -97,37 +193,578 @@ impl Tree {
         self.root_id
     }
 
+    // Names of the functions defined at the top level in source order (e.g. for a call graph).
+    pub fn function_names(&self) -> Vec<String> {
+        self.function_defs()
+            .into_iter()
+            .map(|(_, fun_name)| fun_name)
+            .collect()
+    }
+
+    // The first definition wins if a function is defined more than once.
+    pub fn function_def_id(&self, name: &str) -> Option<ID> {
+        self.function_defs()
+            .into_iter()
+            .find(|(_, fun_name)| fun_name == name)
+            .map(|(id, _)| id)
+    }
+
+    fn function_defs(&self) -> Vec<(ID, String)> {
+        match self.lookup(self.root_id).map(|node| &node.relation) {
+            Some(AstRelation::TransUnit { id: _, body_ids }) => body_ids
+                .iter()
+                .filter_map(
+                    |body_id| match self.lookup(*body_id).map(|node| &node.relation) {
+                        Some(AstRelation::FunDef { id, fun_name, .. }) => {
+                            Some((*id, fun_name.clone()))
+                        }
+                        _ => None,
+                    },
+                )
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    // All nodes in ID order.
+    pub fn iter(&self) -> impl Iterator<Item = (ID, &AstRelation)> {
+        self.arena
+            .iter()
+            .flatten()
+            .map(|node| (node.node_id, &node.relation))
+    }
+
+    // Nodes below (and including) the given node depth-first in child order (same order as pretty_print).
+    pub fn walk(&self, root: ID) -> impl Iterator<Item = (ID, &AstRelation)> {
+        let mut stack = vec![root];
+        std::iter::from_fn(move || {
+            while let Some(node_id) = stack.pop() {
+                if let Some(node) = self.lookup(node_id) {
+                    stack.extend(self.get_children(node_id).into_iter().rev());
+                    return Some((node_id, &node.relation));
+                }
+            }
+            None
+        })
+    }
+
+    // Whether both trees hold the same program, however their nodes are numbered (e.g. after compact or a diff).
+    // Top level declarations are compared in order.
+    pub fn structurally_eq(&self, other: &Tree) -> bool {
+        let ids_match = |id1: &ID, id2: &ID| {
+            relations_match(
+                self.relation_ref(*id1),
+                other.relation_ref(*id2),
+                self,
+                other,
+            )
+        };
+        let (decl_ids1, decl_ids2) = match (
+            self.relation_ref(self.root_id),
+            other.relation_ref(other.root_id),
+        ) {
+            (
+                AstRelation::TransUnit {
+                    id: _,
+                    body_ids: decl_ids1,
+                },
+                AstRelation::TransUnit {
+                    id: _,
+                    body_ids: decl_ids2,
+                },
+            ) => (decl_ids1, decl_ids2),
+            _ => return false,
+        };
+        decl_ids1.len() == decl_ids2.len()
+            && decl_ids1.iter().zip(decl_ids2).all(|(decl_id1, decl_id2)| {
+                match (self.relation_ref(*decl_id1), other.relation_ref(*decl_id2)) {
+                    (
+                        AstRelation::FunDef {
+                            id: _,
+                            fun_name: fun_name1,
+                            return_type_id: return_type_id1,
+                            arg_ids: arg_ids1,
+                            body_id: body_id1,
+                        },
+                        AstRelation::FunDef {
+                            id: _,
+                            fun_name: fun_name2,
+                            return_type_id: return_type_id2,
+                            arg_ids: arg_ids2,
+                            body_id: body_id2,
+                        },
+                    ) => {
+                        fun_name1 == fun_name2
+                            && arg_ids1.len() == arg_ids2.len()
+                            && arg_ids1
+                                .iter()
+                                .zip(arg_ids2)
+                                .all(|(a1, a2)| ids_match(a1, a2))
+                            && ids_match(return_type_id1, return_type_id2)
+                            && ids_match(body_id1, body_id2)
+                    }
+                    (AstRelation::FunDef { .. }, _) | (_, AstRelation::FunDef { .. }) => false,
+                    _ => ids_match(decl_id1, decl_id2),
+                }
+            })
+    }
+
+    // Checks that every child ID referenced by a relation (or linked as a child) is in the tree.
+    // Returns the missing IDs otherwise.
+    pub fn validate(&self) -> Result<(), Vec<ID>> {
+        let mut missing = vec![];
+        for node in self.arena.iter().flatten() {
+            let mut child_ids = get_child_ids(&node.relation);
+            child_ids.extend(node.children.iter());
+            for child_id in child_ids {
+                if self.lookup(child_id).is_none() && !missing.contains(&child_id) {
+                    missing.push(child_id);
+                }
+            }
+        }
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            missing.sort();
+            Err(missing)
+        }
+    }
+
+    // Only top level declarations and the root get a location at the moment.
+    pub fn get_location(&self, node_id: ID) -> Option<Location> {
+        self.lookup(node_id).and_then(|node| node.location)
+    }
+
+    pub fn set_location(&mut self, node_id: ID, location: Option<Location>) {
+        if let Some(node) = self.lookup_mut(node_id) {
+            node.location = location;
+        }
+    }
+
+    // Only this node's hash is recomputed (ancestors keep their old one).
+    // The diff rehashes the items above a changed statement itself, a stale hash anywhere else can at worst
+    // make a match fail.
     pub fn update_relation(&mut self, node_id: ID, relation: AstRelation) {
-        if self.arena.contains_key(&node_id) {
-            self.arena
-                .get_mut(&node_id)
-                .unwrap()
-                .update_relation(relation);
+        let hash = self.compute_hash(&relation);
+        if let Some(node) = self.lookup_mut(node_id) {
+            node.update_relation(relation, hash);
+        }
+    }
+
+    // For when something below the node changed without the node itself changing.
+    fn rehash(&mut self, node_id: ID) {
+        let relation = self.get_relation(node_id);
+        self.update_relation(node_id, relation);
+    }
+
+    // Structural hash of the subtree below a node (None if it couldn't be computed).
+    pub fn subtree_hash(&self, node_id: ID) -> Option<u64> {
+        self.lookup(node_id).and_then(|node| node.hash)
+    }
+
+    // Hash of the whole program that doesn't depend on the node numbering (e.g. to skip checking an unchanged file).
+    // Nodes are renumbered in the order they are reached from the root (in each relation's own child order) first.
+    pub fn content_hash(&self) -> u64 {
+        let mut order = vec![];
+        let mut stack = vec![self.root_id];
+        while let Some(node_id) = stack.pop() {
+            order.push(node_id);
+            if let Some(node) = self.lookup(node_id) {
+                stack.extend(get_child_ids(&node.relation).into_iter().rev());
+            }
+        }
+        let mapping: HashMap<ID, ID> = order
+            .iter()
+            .enumerate()
+            .map(|(position, node_id)| (*node_id, position as ID))
+            .collect();
+        let mut hasher = DefaultHasher::new();
+        for node_id in order {
+            if let Some(node) = self.lookup(node_id) {
+                remap_relation_ids(&node.relation, &mapping).hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    // Nodes are added bottom-up (by the parsers as well as the diff) so the children's hashes are already there.
+    // Covers exactly what relations_match looks at: names and operators but not IDs.
+    fn compute_hash(&self, relation: &AstRelation) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        std::mem::discriminant(relation).hash(&mut hasher);
+        let child_ids = match relation {
+            // Never compared structurally.
+            AstRelation::FunDef { .. } | AstRelation::TransUnit { .. } => return None,
+            AstRelation::FunCall { fun_name, .. } => {
+                fun_name.hash(&mut hasher);
+                get_child_ids(relation)
+            }
+            AstRelation::Array { size, .. } => {
+                size.hash(&mut hasher);
+                get_child_ids(relation)
+            }
+            AstRelation::Struct { name, .. } | AstRelation::StructDef { name, .. } => {
+                name.hash(&mut hasher);
+                get_child_ids(relation)
+            }
+            AstRelation::Arg { var_name, .. }
+            | AstRelation::Var { var_name, .. }
+            | AstRelation::Decl { var_name, .. }
+            | AstRelation::Assign { var_name, .. } => {
+                var_name.hash(&mut hasher);
+                get_child_ids(relation)
+            }
+            AstRelation::Field { field_name, .. } => {
+                field_name.hash(&mut hasher);
+                get_child_ids(relation)
+            }
+            AstRelation::Member {
+                field_name, arrow, ..
+            } => {
+                field_name.hash(&mut hasher);
+                arrow.hash(&mut hasher);
+                get_child_ids(relation)
+            }
+            AstRelation::BinaryOp { op, .. } | AstRelation::UnaryOp { op, .. } => {
+                op.hash(&mut hasher);
+                get_child_ids(relation)
+            }
+            AstRelation::Label { name, .. }
+            | AstRelation::Goto {
+                label_name: name, ..
+            } => {
+                name.hash(&mut hasher);
+                get_child_ids(relation)
+            }
+            _ => get_child_ids(relation),
+        };
+        child_ids.len().hash(&mut hasher);
+        for child_id in child_ids {
+            self.subtree_hash(child_id)?.hash(&mut hasher);
         }
+        Some(hasher.finish())
     }
 
     pub fn delete_node(&mut self, node_id: ID) {
-        self.arena.remove(&node_id);
-        self.max_id = *self.arena.keys().max().unwrap();
+        if let Some(slot) = self.arena.get_mut(node_id as usize) {
+            *slot = None;
+        }
+        // Drop trailing gaps (the next insertion will grow the arena again as needed).
+        while let Some(None) = self.arena.last() {
+            self.arena.pop();
+        }
+    }
+
+    // Independent copy of the subtree below a node (e.g. a single function), IDs are kept as they are.
+    pub fn subtree(&self, root_id: ID) -> Tree {
+        let mut tree = Tree::new();
+        let mut stack = vec![root_id];
+        while let Some(node_id) = stack.pop() {
+            if let Some(node) = self.lookup(node_id) {
+                if tree.lookup(node_id).is_none() {
+                    stack.extend(self.get_children(node_id));
+                    tree.insert(node_id, node.clone());
+                }
+            }
+        }
+        tree.root_id = root_id;
+        // Nodes added later still get IDs that are fresh in the original tree.
+        tree.next_id = self.next_id;
+        tree
+    }
+
+    // Replays an edit script computed against this tree (the inverse of get_edit_script).
+    // Moves need no work of their own, the new parent's relation already refers to the moved node.
+    pub fn apply(&self, script: &EditScript) -> Tree {
+        let mut tree = self.clone();
+        for edit in script {
+            match edit {
+                Edit::Delete(relation) => tree.delete_node(get_relation_id(relation)),
+                Edit::Update { old: _, new } => {
+                    if let Some(node) = tree.lookup_mut(get_relation_id(new)) {
+                        node.relation = new.clone();
+                    }
+                }
+                Edit::Move { .. } => {}
+                Edit::Insert(relation) => {
+                    tree.add_node(get_relation_id(relation), relation.clone())
+                }
+            }
+        }
+        // Inserted nodes can sit below existing ones, so links and hashes are redone from the root down.
+        let root_id = tree.root_id;
+        tree.relink(root_id);
+        tree
+    }
+
+    fn relink(&mut self, node_id: ID) {
+        let relation = self.get_relation(node_id);
+        let child_ids = get_child_ids(&relation);
+        for child_id in &child_ids {
+            self.relink(*child_id);
+        }
+        self.replace_children(node_id, child_ids);
+        self.rehash(node_id);
+    }
+
+    // Renumbers the nodes densely (keeping their relative order) and returns the old -> new IDs.
+    // Expects a valid tree, any IDs held outside it (e.g. facts already sent to DDlog) are stale afterwards.
+    pub fn compact(&mut self) -> HashMap<ID, ID> {
+        let mapping: HashMap<ID, ID> = self
+            .iter()
+            .enumerate()
+            .map(|(new_id, (old_id, _))| (old_id, new_id as ID))
+            .collect();
+        let arena = std::mem::take(&mut self.arena);
+        self.arena = arena
+            .into_iter()
+            .flatten()
+            .map(|node| AstNode {
+                node_id: mapping[&node.node_id],
+                relation: remap_relation_ids(&node.relation, &mapping),
+                location: node.location,
+                children: node.children.iter().map(|id| mapping[id]).collect(),
+                hash: node.hash,
+            })
+            .map(Some)
+            .collect();
+        self.root_id = mapping.get(&self.root_id).copied().unwrap_or(0);
+        self.next_id = self.arena.len() as ID;
+        mapping
+    }
+
+    // Combines the translation units of several files into one (e.g. to check a whole program).
+    // Each tree is shifted into its own ID range, the top-level declarations keep their file order.
+    pub fn merge(trees: Vec<Tree>) -> Tree {
+        let mut merged = Tree::new();
+        let mut body_ids = Vec::new();
+        for tree in trees {
+            let offset = merged.next_id;
+            let mapping: HashMap<ID, ID> = tree
+                .iter()
+                .map(|(node_id, _)| (node_id, node_id + offset))
+                .collect();
+            match tree.get_relation(tree.root_id) {
+                AstRelation::TransUnit {
+                    id: _,
+                    body_ids: ids,
+                } => body_ids.extend(ids.iter().map(|id| mapping[id])),
+                _ => panic!("Expected a translation unit"),
+            }
+            for node in tree.arena.into_iter().flatten() {
+                if node.node_id == tree.root_id {
+                    continue;
+                }
+                merged.insert(
+                    mapping[&node.node_id],
+                    AstNode {
+                        node_id: mapping[&node.node_id],
+                        relation: remap_relation_ids(&node.relation, &mapping),
+                        location: node.location,
+                        children: node.children.iter().map(|id| mapping[id]).collect(),
+                        hash: node.hash,
+                    },
+                );
+            }
+            merged.next_id = offset + tree.next_id;
+        }
+        let root_id = merged.allocate_id();
+        merged.add_root_node(
+            root_id,
+            AstRelation::TransUnit {
+                id: root_id,
+                body_ids: body_ids.clone(),
+            },
+        );
+        merged.replace_children(root_id, body_ids);
+        merged
+    }
+
+    // Hands out an ID that has never been used in this tree.
+    fn allocate_id(&mut self) -> ID {
+        let node_id = self.next_id;
+        self.next_id = self.next_id + 1;
+        node_id
+    }
+
+    // Highest ID that is still in use.
+    fn last_id(&self) -> ID {
+        match self.arena.iter().rposition(|node| node.is_some()) {
+            Some(index) => index as ID,
+            None => 0,
+        }
+    }
+
+    fn lookup(&self, node_id: ID) -> Option<&AstNode> {
+        if node_id < 0 {
+            return None;
+        }
+        self.arena
+            .get(node_id as usize)
+            .and_then(|node| node.as_ref())
+    }
+
+    fn lookup_mut(&mut self, node_id: ID) -> Option<&mut AstNode> {
+        if node_id < 0 {
+            return None;
+        }
+        self.arena
+            .get_mut(node_id as usize)
+            .and_then(|node| node.as_mut())
+    }
+
+    fn insert(&mut self, node_id: ID, node: AstNode) {
+        let index = node_id as usize;
+        if index >= self.arena.len() {
+            self.arena.resize(index + 1, None);
+        }
+        self.arena[index] = Some(node);
+    }
+
+    // Allows caching a parsed tree (e.g. as a baseline to diff against across restarts).
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+// Builds a tree by hand (e.g. in tests) without having to manage IDs or links.
+// Every method adds one node below the ones it refers to and returns its ID.
+#[derive(Debug, Default)]
+pub struct TreeBuilder {
+    tree: Tree,
+}
+
+impl TreeBuilder {
+    pub fn new() -> Self {
+        Self { tree: Tree::new() }
+    }
+
+    pub fn int(&mut self) -> ID {
+        self.add(|id| AstRelation::Int { id })
+    }
+
+    pub fn float(&mut self) -> ID {
+        self.add(|id| AstRelation::Float { id })
+    }
+
+    pub fn void(&mut self) -> ID {
+        self.add(|id| AstRelation::Void { id })
+    }
+
+    pub fn var(&mut self, var_name: &str) -> ID {
+        self.add(|id| AstRelation::Var {
+            id,
+            var_name: var_name.to_string(),
+        })
+    }
+
+    pub fn binary_op(&mut self, op: &str, arg1_id: ID, arg2_id: ID) -> ID {
+        self.add(|id| AstRelation::BinaryOp {
+            id,
+            op: op.to_string(),
+            arg1_id,
+            arg2_id,
+        })
+    }
+
+    pub fn fun_call(&mut self, fun_name: &str, arg_ids: Vec<ID>) -> ID {
+        self.add(|id| AstRelation::FunCall {
+            id,
+            fun_name: fun_name.to_string(),
+            arg_ids,
+        })
+    }
+
+    pub fn assign(&mut self, var_name: &str, type_id: ID, expr_id: ID) -> ID {
+        self.add(|id| AstRelation::Assign {
+            id,
+            var_name: var_name.to_string(),
+            type_id,
+            expr_id,
+        })
+    }
+
+    pub fn ret(&mut self, expr_id: ID) -> ID {
+        self.add(|id| AstRelation::Return { id, expr_id })
+    }
+
+    // Chains the statements into items inside a new compound.
+    pub fn compound(&mut self, stmt_ids: Vec<ID>) -> ID {
+        let mut next_id = self.tree.next_id;
+        let start_id = self.tree.add_item_chain(stmt_ids, &mut next_id);
+        self.add(|id| AstRelation::Compound { id, start_id })
+    }
+
+    pub fn arg(&mut self, var_name: &str, type_id: ID) -> ID {
+        self.add(|id| AstRelation::Arg {
+            id,
+            var_name: var_name.to_string(),
+            type_id,
+        })
+    }
+
+    pub fn fun_def(
+        &mut self,
+        fun_name: &str,
+        return_type_id: ID,
+        arg_ids: Vec<ID>,
+        body_id: ID,
+    ) -> ID {
+        self.add(|id| AstRelation::FunDef {
+            id,
+            fun_name: fun_name.to_string(),
+            return_type_id,
+            arg_ids,
+            body_id,
+        })
+    }
+
+    // Puts the given top level declarations under a translation unit.
+    pub fn build(mut self, body_ids: Vec<ID>) -> Tree {
+        let root_id = self.tree.allocate_id();
+        let relation = AstRelation::TransUnit {
+            id: root_id,
+            body_ids: body_ids.clone(),
+        };
+        self.tree.add_root_node(root_id, relation);
+        self.tree.replace_children(root_id, body_ids);
+        self.tree
+    }
+
+    fn add(&mut self, relation: impl FnOnce(ID) -> AstRelation) -> ID {
+        let node_id = self.tree.allocate_id();
+        let relation = relation(node_id);
+        let child_ids = get_child_ids(&relation);
+        self.tree.add_node(node_id, relation);
+        self.tree.replace_children(node_id, child_ids);
+        node_id
     }
 }
 
 // Building block of AST.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AstNode {
     node_id: ID,
     relation: AstRelation,
-    location: Location,
+    location: Option<Location>,
     children: Vec<ID>,
+    // Hash of the subtree ignoring IDs (lets most mismatches be found without walking the subtrees).
+    #[serde(default)]
+    hash: Option<u64>,
 }
 
 impl AstNode {
-    fn new(node_id: ID, relation: AstRelation) -> Self {
+    fn new(node_id: ID, relation: AstRelation, hash: Option<u64>) -> Self {
         Self {
             node_id,
             relation,
-            location: Location {},
+            location: None,
             children: Vec::new(),
+            hash,
         }
     }
 
@@ -139,197 +776,631 @@ impl AstNode {
         self.children = child_ids;
     }
 
-    fn pretty_print(&self, indent: &String, arena: &HashMap<ID, AstNode>) {
+    fn pretty_print(&self, indent: &String, tree: &Tree) {
         println!("{}{:?}", indent, self.relation);
         let new_indent = format!("{}{}", indent, "   ");
         for child_id in &self.children {
-            arena
-                .get(child_id)
+            tree.lookup(*child_id)
                 .unwrap()
-                .pretty_print(&new_indent, arena)
+                .pretty_print(&new_indent, tree)
         }
     }
 
-    fn update_relation(&mut self, relation: AstRelation) {
+    fn update_relation(&mut self, relation: AstRelation, hash: Option<u64>) {
         self.relation = relation;
+        self.hash = hash;
     }
 }
 
 // Flattens AST and converts into a set of relations.
 pub fn get_initial_relation_set(ast: &Tree) -> HashSet<AstRelation> {
     let mut relation_set: HashSet<AstRelation> = HashSet::new();
-    for node in ast.clone().arena {
-        relation_set.insert(node.1.relation);
+    for node in ast.arena.iter().flatten() {
+        relation_set.insert(node.relation.clone());
     }
     relation_set
 }
 
-// Finds the differences between the to ASTs with structural differencing and flattens.
-// Returns separate sets for relations that need to be deleted and relations that are inserted.
-// Here IDs are allocated in a way that unchanged nodes retain their previous IDs.
-// (By adjusting towards the existing tree.)
-pub fn get_diff_relation_set(
-    prev_ast: &Tree,
-    new_ast: &Tree,
-) -> (HashSet<AstRelation>, HashSet<AstRelation>, Tree) {
-    let mut updated_tree = prev_ast.clone();
-    let prev_root = prev_ast.get_node(prev_ast.get_root());
-    let new_root = new_ast.get_node(new_ast.get_root());
-    let mut insertion_set = HashSet::new();
-    let mut deletion_set = HashSet::new();
+// Moves one set of relations into another, iterating over whichever is smaller.
+// (Otherwise deeply nested statements like else-if chains get copied again at every level.)
+fn merge_relations(relation_set: &mut HashSet<AstRelation>, mut other_set: HashSet<AstRelation>) {
+    if other_set.len() > relation_set.len() {
+        std::mem::swap(relation_set, &mut other_set);
+    }
+    relation_set.extend(other_set);
+}
 
-    // For now we are assuming all top level declarations are function and we will identify them by names.
-    // (Also assuming you are more likely to change function order rather than name).
-    let mut fun_to_be_deleted: HashMap<ID, bool> = HashMap::new();
-    // Need to check against this in the end to find functions that are completely new.
-    let mut matching_new_funs: Vec<ID> = vec![];
-    for fun_id in &prev_root.children {
-        match prev_ast.get_relation(*fun_id) {
-            AstRelation::FunDef {
-                id: prev_id,
-                fun_name: prev_fun_name,
-                return_type_id: prev_return_type_id,
-                arg_ids: prev_arg_ids,
-                body_id: prev_body_id,
-            } => {
-                fun_to_be_deleted.insert(prev_id, true);
-                'new_search: for new_fun_id in &new_root.children {
-                    let node_to_compare = new_ast.get_node(*new_fun_id);
-                    match node_to_compare.relation {
-                        AstRelation::FunDef {
-                            // IDs here are really just a lookup tool.
-                            id: new_id,
-                            fun_name: new_fun_name,
-                            return_type_id: new_return_type_id,
-                            arg_ids: new_arg_ids,
-                            body_id: new_body_id,
-                        } => {
-                            // Case: function name matches so we keep comparing.
-                            if prev_fun_name == new_fun_name {
-                                matching_new_funs.push(new_id);
-                                // Compare return type (could either match or not but will definitely be there).
-                                let prev_return_type = prev_ast.get_relation(prev_return_type_id);
-                                let new_return_type = new_ast.get_relation(new_return_type_id);
-                                if !relations_match(
-                                    &prev_return_type,
-                                    &new_return_type,
-                                    prev_ast,
-                                    new_ast,
-                                ) {
-                                    // If return type has changed:
-                                    // Delete the current return type relation.
-                                    deletion_set.insert(prev_return_type);
-                                    // Change the ID in the new return type to match the previous one.
-                                    let replacement = replace_id_in_relation(
-                                        &new_return_type,
-                                        prev_return_type_id,
-                                    );
-                                    // Update the corresponding node in the tree.
-                                    updated_tree
-                                        .update_relation(prev_return_type_id, replacement.clone());
-                                    // Insert the new relation.
-                                    insertion_set.insert(replacement);
-                                }
+// Relation sets iterate in a different order every run, so sort by ID wherever the order is visible
+// (updates sent to DDlog and debug output).
+pub fn sort_relations(relation_set: &HashSet<AstRelation>) -> Vec<AstRelation> {
+    let mut relations: Vec<AstRelation> = relation_set.iter().cloned().collect();
+    relations.sort_by(|r1, r2| {
+        get_relation_id(r1)
+            .cmp(&get_relation_id(r2))
+            .then_with(|| r1.cmp(r2))
+    });
+    relations
+}
 
-                                // Compare argument types (in this case order matters).
-                                // If there are insertions/deletions and not just replacements we have to adjust the function relation.
-                                let mut remaining_args: Vec<ID> = vec![];
-                                let mut args_have_changed = false;
-                                for (index, prev_arg_id) in prev_arg_ids.iter().enumerate() {
-                                    if index < new_arg_ids.len() {
-                                        let new_arg_id = new_arg_ids[index];
-                                        // If a corresponding index relation exist, name and type could differ or match.
-                                        let prev_arg = prev_ast.get_relation(*prev_arg_id);
-                                        let new_arg = new_ast.get_relation(new_arg_id);
-                                        match (prev_arg, new_arg) {
-                                            (
-                                                AstRelation::Arg {
-                                                    id,
-                                                    var_name: var_name1,
-                                                    type_id: type_id1,
-                                                },
-                                                AstRelation::Arg {
-                                                    id: _,
-                                                    var_name: var_name2,
-                                                    type_id: type_id2,
-                                                },
-                                            ) => {
-                                                let prev_type = prev_ast.get_relation(type_id1);
-                                                let new_type = new_ast.get_relation(type_id2);
-                                                if !relations_match(
-                                                    &prev_type, &new_type, prev_ast, new_ast,
-                                                ) {
-                                                    // Replace type.
-                                                    deletion_set.insert(prev_type);
-                                                    let replacement =
-                                                        replace_id_in_relation(&new_type, type_id1);
-                                                    updated_tree.update_relation(
-                                                        type_id1,
-                                                        replacement.clone(),
-                                                    );
-                                                    insertion_set.insert(replacement);
-                                                }
-                                                if var_name1 != var_name2 {
-                                                    // Replace name.
-                                                    let replacement = AstRelation::Arg {
-                                                        id,
-                                                        var_name: var_name2,
-                                                        type_id: type_id1,
-                                                    };
-                                                    updated_tree
-                                                        .update_relation(id, replacement.clone());
-                                                    updated_tree
-                                                        .replace_children(id, vec![type_id1]);
-                                                    insertion_set.insert(replacement);
-                                                }
-                                            }
-                                            _ => panic!("Unexpected node during diffing"),
-                                        }
-                                        remaining_args.push(*prev_arg_id);
-                                    } else {
-                                        // This means the previous argument list was longer so we need to delete some.
-                                        let (deletions, new_updated_tree) =
-                                            delete_onwards(*prev_arg_id, updated_tree);
-                                        for relation in deletions {
-                                            deletion_set.insert(relation);
-                                        }
-                                        updated_tree = new_updated_tree;
-                                        args_have_changed = true;
-                                    }
-                                }
-                                // This means there are more arguments in the new tree.
-                                if new_arg_ids.len() > prev_arg_ids.len() {
-                                    for (index, new_arg_id) in new_arg_ids.iter().enumerate() {
-                                        if index >= prev_arg_ids.len() {
-                                            let (insertions, new_updated_tree, updated_arg_id) =
-                                                insert_onwards(
-                                                    *new_arg_id,
-                                                    updated_tree,
-                                                    new_ast.clone(),
-                                                );
-                                            for relation in insertions {
-                                                insertion_set.insert(relation);
-                                            }
-                                            updated_tree = new_updated_tree;
-                                            remaining_args.push(updated_arg_id);
-                                            args_have_changed = true;
-                                        }
-                                    }
-                                }
-                                if args_have_changed {
-                                    deletion_set.insert(prev_ast.get_relation(prev_id));
-                                    let replacement = AstRelation::FunDef {
-                                        id: prev_id,
-                                        fun_name: prev_fun_name,
-                                        return_type_id: prev_return_type_id,
-                                        // Just change arguments.
-                                        arg_ids: remaining_args.clone(),
-                                        body_id: prev_body_id,
-                                    };
-                                    insertion_set.insert(replacement.clone());
+fn write_c_external_declaration(tree: &Tree, node_id: ID, source: &mut String) {
+    match tree.get_relation(node_id) {
+        AstRelation::FunDef {
+            id: _,
+            fun_name,
+            return_type_id,
+            arg_ids,
+            body_id,
+        } => {
+            let args = if arg_ids.is_empty() {
+                String::from("void")
+            } else {
+                arg_ids
+                    .iter()
+                    .map(|arg_id| match tree.get_relation(*arg_id) {
+                        AstRelation::Arg {
+                            id: _,
+                            var_name,
+                            type_id,
+                        } => c_declaration(tree, type_id, &var_name),
+                        _ => panic!("Unexpected syntax"),
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            };
+            let signature = format!("{}({})", fun_name, args);
+            source.push_str(&c_declaration(tree, return_type_id, &signature));
+            source.push('\n');
+            write_c_compound(tree, body_id, "", source);
+        }
+        AstRelation::StructDef {
+            id: _,
+            name,
+            field_ids,
+        } => {
+            source.push_str(&format!("struct {}\n{{\n", name));
+            for field_id in field_ids {
+                if let AstRelation::Field {
+                    id: _,
+                    field_name,
+                    type_id,
+                } = tree.get_relation(field_id)
+                {
+                    source.push_str(&format!(
+                        "    {};\n",
+                        c_declaration(tree, type_id, &field_name)
+                    ));
+                }
+            }
+            source.push_str("};\n");
+        }
+        _ => panic!("Unexpected syntax"),
+    }
+}
+
+fn write_c_compound(tree: &Tree, node_id: ID, indent: &str, source: &mut String) {
+    source.push_str(&format!("{}{{\n", indent));
+    write_c_items(tree, node_id, &format!("{}    ", indent), source);
+    source.push_str(&format!("{}}}\n", indent));
+}
+
+// Bodies without braces are a single statement, indented one level further.
+fn write_c_body(tree: &Tree, node_id: ID, indent: &str, source: &mut String) {
+    match tree.get_relation(node_id) {
+        AstRelation::Compound { .. } => write_c_compound(tree, node_id, indent, source),
+        _ => write_c_statement(tree, node_id, &format!("{}    ", indent), source),
+    }
+}
+
+// Statements of a compound without the surrounding braces.
+fn write_c_items(tree: &Tree, node_id: ID, indent: &str, source: &mut String) {
+    let mut next_id = match tree.get_relation(node_id) {
+        AstRelation::Compound { id: _, start_id } => start_id,
+        _ => panic!("Unexpected syntax"),
+    };
+    loop {
+        match tree.get_relation(next_id) {
+            AstRelation::Item {
+                id: _,
+                stmt_id,
+                next_stmt_id,
+            } => {
+                write_c_statement(tree, stmt_id, indent, source);
+                next_id = next_stmt_id;
+            }
+            AstRelation::EndItem { id: _, stmt_id } => {
+                write_c_statement(tree, stmt_id, indent, source);
+                return;
+            }
+            AstRelation::EmptyItem { .. } => return,
+            _ => panic!("Unexpected syntax"),
+        }
+    }
+}
+
+fn write_c_statement(tree: &Tree, node_id: ID, indent: &str, source: &mut String) {
+    match tree.get_relation(node_id) {
+        AstRelation::Assign {
+            id: _,
+            var_name,
+            type_id,
+            expr_id,
+        } => source.push_str(&format!(
+            "{}{} = {};\n",
+            indent,
+            c_declaration(tree, type_id, &var_name),
+            c_expression(tree, expr_id)
+        )),
+        AstRelation::Decl {
+            id: _,
+            var_name,
+            type_id,
+        } => source.push_str(&format!(
+            "{}{};\n",
+            indent,
+            c_declaration(tree, type_id, &var_name)
+        )),
+        AstRelation::Return { id: _, expr_id } => match tree.get_relation(expr_id) {
+            AstRelation::Void { id: _ } => source.push_str(&format!("{}return;\n", indent)),
+            _ => source.push_str(&format!(
+                "{}return {};\n",
+                indent,
+                c_expression(tree, expr_id)
+            )),
+        },
+        AstRelation::If {
+            id: _,
+            cond_id,
+            then_id,
+        } => {
+            source.push_str(&format!("{}if ({})\n", indent, c_expression(tree, cond_id)));
+            write_c_body(tree, then_id, indent, source);
+        }
+        AstRelation::IfElse {
+            id: _,
+            cond_id,
+            then_id,
+            else_id,
+        } => {
+            source.push_str(&format!("{}if ({})\n", indent, c_expression(tree, cond_id)));
+            write_c_body(tree, then_id, indent, source);
+            match tree.get_relation(else_id) {
+                // Else-if chains stay on one line.
+                AstRelation::If { .. } | AstRelation::IfElse { .. } => {
+                    let mut else_source = String::new();
+                    write_c_statement(tree, else_id, indent, &mut else_source);
+                    source.push_str(&format!("{}else {}", indent, &else_source[indent.len()..]));
+                }
+                _ => {
+                    source.push_str(&format!("{}else\n", indent));
+                    write_c_body(tree, else_id, indent, source);
+                }
+            }
+        }
+        AstRelation::While {
+            id: _,
+            cond_id,
+            body_id,
+        } => {
+            source.push_str(&format!(
+                "{}while ({})\n",
+                indent,
+                c_expression(tree, cond_id)
+            ));
+            write_c_body(tree, body_id, indent, source);
+        }
+        AstRelation::Switch {
+            id: _,
+            expr_id,
+            case_ids,
+        } => {
+            source.push_str(&format!(
+                "{}switch ({})\n{}{{\n",
+                indent,
+                c_expression(tree, expr_id),
+                indent
+            ));
+            // Case bodies are printed without braces so they parse back into the same compounds.
+            let case_indent = format!("{}    ", indent);
+            for case_id in case_ids {
+                let body_id = match tree.get_relation(case_id) {
+                    AstRelation::Case {
+                        id: _,
+                        label_id,
+                        body_id,
+                    } => {
+                        source.push_str(&format!(
+                            "{}case {}:\n",
+                            case_indent,
+                            c_expression(tree, label_id)
+                        ));
+                        body_id
+                    }
+                    AstRelation::DefaultCase { id: _, body_id } => {
+                        source.push_str(&format!("{}default:\n", case_indent));
+                        body_id
+                    }
+                    _ => panic!("Unexpected syntax"),
+                };
+                write_c_items(tree, body_id, &format!("{}    ", case_indent), source);
+            }
+            source.push_str(&format!("{}}}\n", indent));
+        }
+        AstRelation::Compound { .. } => write_c_compound(tree, node_id, indent, source),
+        AstRelation::Break { id: _ } => source.push_str(&format!("{}break;\n", indent)),
+        AstRelation::Continue { id: _ } => source.push_str(&format!("{}continue;\n", indent)),
+        AstRelation::Label {
+            id: _,
+            name,
+            stmt_id,
+        } => {
+            source.push_str(&format!("{}{}:\n", indent, name));
+            write_c_statement(tree, stmt_id, indent, source);
+        }
+        AstRelation::Goto { id: _, label_name } => {
+            source.push_str(&format!("{}goto {};\n", indent, label_name))
+        }
+        // Any expression can be a statement.
+        _ => source.push_str(&format!("{}{};\n", indent, c_expression(tree, node_id))),
+    }
+}
+
+fn c_expression(tree: &Tree, node_id: ID) -> String {
+    match tree.get_relation(node_id) {
+        AstRelation::Var { id: _, var_name } => var_name,
+        AstRelation::Int { id: _ } => String::from("0"),
+        AstRelation::Float { id: _ } | AstRelation::Double { id: _ } => String::from("0.0"),
+        AstRelation::Char { id: _ } => String::from("'a'"),
+        // Nested operators are always parenthesised (except on the right of an assignment)
+        // so precedence doesn't have to be tracked.
+        AstRelation::BinaryOp {
+            id: _,
+            op,
+            arg1_id,
+            arg2_id,
+        } if op == "=" => format!(
+            "{} = {}",
+            c_operand(tree, arg1_id),
+            c_expression(tree, arg2_id)
+        ),
+        AstRelation::BinaryOp {
+            id: _,
+            op,
+            arg1_id,
+            arg2_id,
+        } => format!(
+            "{} {} {}",
+            c_operand(tree, arg1_id),
+            op,
+            c_operand(tree, arg2_id)
+        ),
+        AstRelation::Index {
+            id: _,
+            array_id,
+            index_id,
+        } => format!(
+            "{}[{}]",
+            c_postfix_operand(tree, array_id),
+            c_expression(tree, index_id)
+        ),
+        AstRelation::UnaryOp { id: _, op, expr_id } => {
+            format!("{}{}", op, c_operand(tree, expr_id))
+        }
+        AstRelation::Deref { id: _, expr_id } => format!("*{}", c_operand(tree, expr_id)),
+        AstRelation::AddressOf { id: _, expr_id } => format!("&{}", c_operand(tree, expr_id)),
+        AstRelation::Cast {
+            id: _,
+            target_type_id,
+            expr_id,
+        } => format!(
+            "({}){}",
+            c_declaration(tree, target_type_id, ""),
+            c_operand(tree, expr_id)
+        ),
+        // Types and constants share the leaf relations, so those operands are printed as types.
+        AstRelation::SizeOf { id: _, operand_id } => match tree.get_relation(operand_id) {
+            AstRelation::Ptr { .. }
+            | AstRelation::Array { .. }
+            | AstRelation::Struct { .. }
+            | AstRelation::Void { .. }
+            | AstRelation::Int { .. }
+            | AstRelation::Float { .. }
+            | AstRelation::Double { .. }
+            | AstRelation::Char { .. } => {
+                format!("sizeof({})", c_declaration(tree, operand_id, ""))
+            }
+            _ => format!("sizeof {}", c_operand(tree, operand_id)),
+        },
+        AstRelation::Conditional {
+            id: _,
+            cond_id,
+            then_id,
+            else_id,
+        } => format!(
+            "{} ? {} : {}",
+            c_operand(tree, cond_id),
+            c_operand(tree, then_id),
+            c_operand(tree, else_id)
+        ),
+        AstRelation::Member {
+            id: _,
+            base_id,
+            field_name,
+            arrow,
+        } => format!(
+            "{}{}{}",
+            c_postfix_operand(tree, base_id),
+            if arrow { "->" } else { "." },
+            field_name
+        ),
+        AstRelation::ArrayInit { id: _, elem_ids } => format!(
+            "{{{}}}",
+            elem_ids
+                .iter()
+                .map(|elem_id| c_expression(tree, *elem_id))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        AstRelation::FunCall {
+            id: _,
+            fun_name,
+            arg_ids,
+        } => format!(
+            "{}({})",
+            fun_name,
+            arg_ids
+                .iter()
+                .map(|arg_id| c_expression(tree, *arg_id))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        _ => panic!("Unexpected syntax"),
+    }
+}
+
+fn c_operand(tree: &Tree, node_id: ID) -> String {
+    match tree.get_relation(node_id) {
+        AstRelation::BinaryOp { .. }
+        | AstRelation::Cast { .. }
+        | AstRelation::Conditional { .. } => {
+            format!("({})", c_expression(tree, node_id))
+        }
+        _ => c_expression(tree, node_id),
+    }
+}
+
+// Postfix operators bind tighter than prefix ones, so those need parentheses as well (e.g. (*p)[0]).
+fn c_postfix_operand(tree: &Tree, node_id: ID) -> String {
+    match tree.get_relation(node_id) {
+        AstRelation::Deref { .. }
+        | AstRelation::AddressOf { .. }
+        | AstRelation::UnaryOp { .. }
+        | AstRelation::SizeOf { .. } => format!("({})", c_expression(tree, node_id)),
+        _ => c_operand(tree, node_id),
+    }
+}
+
+// Builds a C declarator around the name (pointers are prefixes and arrays suffixes).
+fn c_declaration(tree: &Tree, type_id: ID, name: &str) -> String {
+    match tree.get_relation(type_id) {
+        AstRelation::Ptr {
+            id: _,
+            pointee_type_id,
+        } => c_declaration(tree, pointee_type_id, &format!("*{}", name)),
+        AstRelation::Array {
+            id: _,
+            elem_type_id,
+            size,
+        } => {
+            let name = if name.starts_with('*') {
+                format!("({})", name)
+            } else {
+                String::from(name)
+            };
+            let size = if size > 0 {
+                size.to_string()
+            } else {
+                String::new()
+            };
+            c_declaration(tree, elem_type_id, &format!("{}[{}]", name, size))
+        }
+        AstRelation::FunPtr {
+            id: _,
+            return_type_id,
+            arg_type_ids,
+        } => {
+            let args = if arg_type_ids.is_empty() {
+                String::from("void")
+            } else {
+                arg_type_ids
+                    .iter()
+                    .map(|arg_type_id| c_declaration(tree, *arg_type_id, ""))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            };
+            c_declaration(tree, return_type_id, &format!("(*{})({})", name, args))
+        }
+        relation => {
+            let base = match relation {
+                AstRelation::Void { id: _ } => String::from("void"),
+                AstRelation::Int { id: _ } => String::from("int"),
+                AstRelation::Float { id: _ } => String::from("float"),
+                AstRelation::Double { id: _ } => String::from("double"),
+                AstRelation::Char { id: _ } => String::from("char"),
+                AstRelation::Struct { id: _, name } => format!("struct {}", name),
+                _ => panic!("Unexpected syntax"),
+            };
+            if name.is_empty() {
+                base
+            } else {
+                format!("{} {}", base, name)
+            }
+        }
+    }
+}
+
+// Finds the differences between the to ASTs with structural differencing and flattens.
+// Returns separate sets for relations that need to be deleted and relations that are inserted.
+// Here IDs are allocated in a way that unchanged nodes retain their previous IDs.
+// (By adjusting towards the existing tree.)
+pub fn get_diff_relation_set(
+    prev_ast: &Tree,
+    new_ast: &Tree,
+) -> (HashSet<AstRelation>, HashSet<AstRelation>, Tree) {
+    let mut updated_tree = prev_ast.clone();
+    let prev_decl_ids = prev_ast.get_children(prev_ast.get_root());
+    let new_decl_ids = new_ast.get_children(new_ast.get_root());
+    let mut insertion_set = HashSet::new();
+    let mut deletion_set = HashSet::new();
+
+    // Top level declarations are functions (and struct definitions) which we will identify by names.
+    // (Also assuming you are more likely to change function order rather than name).
+    let mut fun_to_be_deleted: HashMap<ID, bool> = HashMap::new();
+    // Need to check against this in the end to find functions that are completely new.
+    // (Maps each matched new declaration to the previous one it keeps the ID of.)
+    let mut matching_new_funs: HashMap<ID, ID> = HashMap::new();
+    for fun_id in &prev_decl_ids {
+        match prev_ast.get_relation(*fun_id) {
+            AstRelation::FunDef {
+                id: prev_id,
+                fun_name: prev_fun_name,
+                return_type_id: prev_return_type_id,
+                arg_ids: prev_arg_ids,
+                body_id: prev_body_id,
+            } => {
+                fun_to_be_deleted.insert(prev_id, true);
+                'new_search: for new_fun_id in &new_decl_ids {
+                    let node_to_compare = new_ast.get_node(*new_fun_id);
+                    match node_to_compare.relation {
+                        AstRelation::FunDef {
+                            // IDs here are really just a lookup tool.
+                            id: new_id,
+                            fun_name: new_fun_name,
+                            return_type_id: new_return_type_id,
+                            arg_ids: new_arg_ids,
+                            body_id: new_body_id,
+                        } => {
+                            // Case: function name matches so we keep comparing.
+                            if prev_fun_name == new_fun_name {
+                                matching_new_funs.insert(new_id, prev_id);
+                                // Compare return type (could either match or not but will definitely be there).
+                                let prev_return_type = prev_ast.get_relation(prev_return_type_id);
+                                let new_return_type = new_ast.get_relation(new_return_type_id);
+                                let mut return_type_id = prev_return_type_id;
+                                if !relations_match(
+                                    &prev_return_type,
+                                    &new_return_type,
+                                    prev_ast,
+                                    new_ast,
+                                ) {
+                                    return_type_id = replace_type(
+                                        prev_return_type_id,
+                                        new_return_type_id,
+                                        &mut updated_tree,
+                                        new_ast,
+                                        &mut insertion_set,
+                                        &mut deletion_set,
+                                    );
+                                }
+
+                                // Arguments are matched by name (like functions) so reordering them keeps every argument.
+                                // Whatever is left is matched by position, so a renamed argument is still kept.
+                                // If there are insertions/deletions or a new order we have to adjust the function relation.
+                                let arg_matches =
+                                    match_args(&prev_arg_ids, &new_arg_ids, prev_ast, new_ast);
+                                let mut remaining_args: Vec<ID> = vec![];
+                                for (new_arg_id, prev_arg_id) in
+                                    new_arg_ids.iter().zip(arg_matches.iter())
+                                {
+                                    let prev_arg_id = match prev_arg_id {
+                                        Some(prev_arg_id) => *prev_arg_id,
+                                        None => {
+                                            // No previous argument left for this one so it is new.
+                                            let (insertions, updated_arg_id) = insert_onwards(
+                                                *new_arg_id,
+                                                &mut updated_tree,
+                                                new_ast,
+                                            );
+                                            merge_relations(&mut insertion_set, insertions);
+                                            remaining_args.push(updated_arg_id);
+                                            continue;
+                                        }
+                                    };
+                                    // Name and type could differ or match.
+                                    let prev_arg = prev_ast.get_relation(prev_arg_id);
+                                    let new_arg = new_ast.get_relation(*new_arg_id);
+                                    match (prev_arg, new_arg) {
+                                        (
+                                            AstRelation::Arg {
+                                                id,
+                                                var_name: var_name1,
+                                                type_id: type_id1,
+                                            },
+                                            AstRelation::Arg {
+                                                id: _,
+                                                var_name: var_name2,
+                                                type_id: type_id2,
+                                            },
+                                        ) => {
+                                            let prev_type = prev_ast.get_relation(type_id1);
+                                            let new_type = new_ast.get_relation(type_id2);
+                                            let mut type_id = type_id1;
+                                            if !relations_match(
+                                                &prev_type, &new_type, prev_ast, new_ast,
+                                            ) {
+                                                type_id = replace_type(
+                                                    type_id1,
+                                                    type_id2,
+                                                    &mut updated_tree,
+                                                    new_ast,
+                                                    &mut insertion_set,
+                                                    &mut deletion_set,
+                                                );
+                                            }
+                                            if var_name1 != var_name2 || type_id != type_id1 {
+                                                // Replace name (or point at the new type).
+                                                deletion_set.insert(prev_ast.get_relation(id));
+                                                let replacement = AstRelation::Arg {
+                                                    id,
+                                                    var_name: var_name2,
+                                                    type_id,
+                                                };
+                                                updated_tree
+                                                    .update_relation(id, replacement.clone());
+                                                updated_tree.replace_children(id, vec![type_id]);
+                                                insertion_set.insert(replacement);
+                                            } else {
+                                                // The type may have been swapped in place.
+                                                updated_tree.rehash(id);
+                                            }
+                                        }
+                                        _ => panic!("Unexpected node during diffing"),
+                                    }
+                                    remaining_args.push(prev_arg_id);
+                                }
+                                // Previous arguments without a match have been removed.
+                                for prev_arg_id in &prev_arg_ids {
+                                    if !arg_matches.contains(&Some(*prev_arg_id)) {
+                                        let deletions =
+                                            delete_onwards(*prev_arg_id, &mut updated_tree);
+                                        merge_relations(&mut deletion_set, deletions);
+                                    }
+                                }
+                                if remaining_args != prev_arg_ids
+                                    || return_type_id != prev_return_type_id
+                                {
+                                    deletion_set.insert(prev_ast.get_relation(prev_id));
+                                    let replacement = AstRelation::FunDef {
+                                        id: prev_id,
+                                        fun_name: prev_fun_name,
+                                        return_type_id,
+                                        // Just change arguments (and the return type).
+                                        arg_ids: remaining_args.clone(),
+                                        body_id: prev_body_id,
+                                    };
+                                    insertion_set.insert(replacement.clone());
                                     updated_tree.update_relation(prev_id, replacement);
                                     updated_tree.replace_children(prev_id, remaining_args);
-                                    updated_tree.link_child(prev_id, prev_return_type_id);
+                                    updated_tree.link_child(prev_id, return_type_id);
                                     updated_tree.link_child(prev_id, prev_body_id);
                                 }
 
@@ -347,19 +1418,31 @@ pub fn get_diff_relation_set(
                                             start_id: start_id2,
                                         },
                                     ) => {
-                                        let (insertions, deletions, new_updated_tree, _) =
-                                            compare_items(
-                                                start_id1,
-                                                start_id2,
-                                                updated_tree.clone(),
-                                                new_ast.clone(),
-                                            );
-                                        updated_tree = new_updated_tree;
-                                        for relation in insertions {
-                                            insertion_set.insert(relation);
-                                        }
-                                        for relation in deletions {
-                                            deletion_set.insert(relation);
+                                        let (insertions, deletions, start_id) = compare_items(
+                                            start_id1,
+                                            start_id2,
+                                            &mut updated_tree,
+                                            new_ast,
+                                            &mut MatchCache::new(),
+                                        );
+                                        let body_changed =
+                                            !insertions.is_empty() || !deletions.is_empty();
+                                        merge_relations(&mut insertion_set, insertions);
+                                        merge_relations(&mut deletion_set, deletions);
+                                        // A new first statement means the body has to point at its item instead.
+                                        if start_id != start_id1 {
+                                            deletion_set
+                                                .insert(prev_ast.get_relation(prev_body_id));
+                                            let replacement = AstRelation::Compound {
+                                                id: prev_body_id,
+                                                start_id,
+                                            };
+                                            insertion_set.insert(replacement.clone());
+                                            updated_tree.update_relation(prev_body_id, replacement);
+                                            updated_tree
+                                                .replace_children(prev_body_id, vec![start_id]);
+                                        } else if body_changed {
+                                            updated_tree.rehash(prev_body_id);
                                         }
                                     }
                                     _ => panic!("Unexpected node during diffing"),
@@ -371,7 +1454,38 @@ pub fn get_diff_relation_set(
                                 break 'new_search;
                             }
                         }
-                        _ => panic!("Unexpected node during diffing"),
+                        _ => {}
+                    }
+                }
+            }
+            // Struct definitions are only kept if they are unchanged (otherwise they are replaced entirely).
+            AstRelation::StructDef {
+                id: prev_id,
+                name: prev_name,
+                field_ids: _,
+            } => {
+                fun_to_be_deleted.insert(prev_id, true);
+                for new_decl_id in &new_decl_ids {
+                    let new_relation = new_ast.get_relation(*new_decl_id);
+                    if let AstRelation::StructDef {
+                        id: new_id,
+                        name: new_name,
+                        field_ids: _,
+                    } = &new_relation
+                    {
+                        if prev_name == *new_name
+                            && !matching_new_funs.contains_key(new_id)
+                            && relations_match(
+                                &prev_ast.get_relation(prev_id),
+                                &new_relation,
+                                prev_ast,
+                                new_ast,
+                            )
+                        {
+                            matching_new_funs.insert(*new_id, prev_id);
+                            fun_to_be_deleted.insert(prev_id, false);
+                            break;
+                        }
                     }
                 }
             }
@@ -379,28 +1493,24 @@ pub fn get_diff_relation_set(
         }
     }
     // Iterate over prev functions to be deleted and add result to deletion set (pass tree to be updated as well).
-    let mut remaining_funs: Vec<ID> = vec![];
     for (prev_fun_id, indicator) in fun_to_be_deleted {
         if indicator {
-            let (deletions, new_updated_tree) = delete_onwards(prev_fun_id, updated_tree.clone());
-            updated_tree = new_updated_tree;
-            for relation in deletions {
-                deletion_set.insert(relation);
-            }
-        } else {
-            remaining_funs.push(prev_fun_id);
+            let deletions = delete_onwards(prev_fun_id, &mut updated_tree);
+            merge_relations(&mut deletion_set, deletions);
         }
     }
     // Iterate over new functions to see which ones aren't matching and add to insertion set (tree as well).
-    for new_fun_id in &new_root.children {
-        if !matching_new_funs.contains(new_fun_id) {
-            let (insertions, new_updated_tree, inserted_fun_id) =
-                insert_onwards(*new_fun_id, updated_tree.clone(), new_ast.clone());
-            updated_tree = new_updated_tree;
-            for relation in insertions {
-                insertion_set.insert(relation);
+    // The remaining declarations are listed in the order of the new file.
+    let mut remaining_funs: Vec<ID> = vec![];
+    for new_fun_id in &new_decl_ids {
+        match matching_new_funs.get(new_fun_id) {
+            Some(prev_fun_id) => remaining_funs.push(*prev_fun_id),
+            None => {
+                let (insertions, inserted_fun_id) =
+                    insert_onwards(*new_fun_id, &mut updated_tree, new_ast);
+                merge_relations(&mut insertion_set, insertions);
+                remaining_funs.push(inserted_fun_id);
             }
-            remaining_funs.push(inserted_fun_id);
         }
     }
     // Replace root with translation unit that has the correct list of declarations.
@@ -408,9 +1518,8 @@ pub fn get_diff_relation_set(
     if let AstRelation::TransUnit { id: _, body_ids } = prev_ast.get_relation(prev_ast.get_root()) {
         prev_funs = body_ids;
     }
-    if !(remaining_funs.iter().all(|item| prev_funs.contains(item)))
-        || !(prev_funs.iter().all(|item| remaining_funs.contains(item)))
-    {
+    // Only a changed or reordered list of declarations replaces it (order matters as functions are checked in turn).
+    if remaining_funs != prev_funs {
         deletion_set.insert(prev_ast.get_relation(prev_ast.get_root()));
         let final_root = AstRelation::TransUnit {
             id: prev_ast.get_root(),
@@ -425,12 +1534,169 @@ pub fn get_diff_relation_set(
     (insertion_set, deletion_set, updated_tree)
 }
 
+// Number of inserted and deleted relations per kind (e.g. for debugging or editor status bars).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DiffSummary {
+    pub inserted: BTreeMap<&'static str, usize>,
+    pub deleted: BTreeMap<&'static str, usize>,
+}
+
+impl fmt::Display for DiffSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let format_counts = |counts: &BTreeMap<&'static str, usize>| {
+            let total: usize = counts.values().sum();
+            let kinds: Vec<String> = counts
+                .iter()
+                .map(|(kind, count)| format!("{}: {}", kind, count))
+                .collect();
+            format!("{} ({})", total, kinds.join(", "))
+        };
+        write!(
+            f,
+            "inserted {}, deleted {}",
+            format_counts(&self.inserted),
+            format_counts(&self.deleted)
+        )
+    }
+}
+
+pub fn summarize_diff(
+    insertion_set: &HashSet<AstRelation>,
+    deletion_set: &HashSet<AstRelation>,
+) -> DiffSummary {
+    let mut summary = DiffSummary::default();
+    for relation in insertion_set {
+        *summary.inserted.entry(relation.variant_name()).or_insert(0) += 1;
+    }
+    for relation in deletion_set {
+        *summary.deleted.entry(relation.variant_name()).or_insert(0) += 1;
+    }
+    summary
+}
+
+// A single step of an edit script (IDs are the ones from the previous tree, or fresh ones for inserted nodes).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Edit {
+    Insert(AstRelation),
+    Delete(AstRelation),
+    // The node keeps its ID but its relation changed.
+    Update { old: AstRelation, new: AstRelation },
+    // The node itself is unchanged but now hangs below a different parent.
+    Move { id: ID, new_parent: ID },
+}
+
+pub type EditScript = Vec<Edit>;
+
+// Same structural differencing as get_diff_relation_set but with the edits classified.
+// Deletions come first, then updates, moves and insertions (each in ID order).
+pub fn get_edit_script(prev_ast: &Tree, new_ast: &Tree) -> (EditScript, Tree) {
+    let (insertion_set, deletion_set, updated_tree) = get_diff_relation_set(prev_ast, new_ast);
+    let inserted_ids: HashSet<ID> = insertion_set.iter().map(get_relation_id).collect();
+    let mut edit_script = vec![];
+    for relation in sort_relations(&deletion_set) {
+        if !inserted_ids.contains(&get_relation_id(&relation)) {
+            edit_script.push(Edit::Delete(relation));
+        }
+    }
+    // IDs are never reused so an inserted relation with a known ID must be a replacement.
+    // (Looked up in the tree since not every replaced relation ends up in the deletion set.)
+    let mut insertions = vec![];
+    for relation in sort_relations(&insertion_set) {
+        match prev_ast.lookup(get_relation_id(&relation)) {
+            Some(node) => edit_script.push(Edit::Update {
+                old: node.relation.clone(),
+                new: relation,
+            }),
+            None => insertions.push(Edit::Insert(relation)),
+        }
+    }
+    let prev_parents = get_parent_ids(prev_ast);
+    for (id, new_parent) in get_parent_ids(&updated_tree) {
+        if let Some(prev_parent) = prev_parents.get(&id) {
+            if *prev_parent != new_parent {
+                edit_script.push(Edit::Move { id, new_parent });
+            }
+        }
+    }
+    edit_script.extend(insertions);
+    (edit_script, updated_tree)
+}
+
+// Replaces a changed type and returns the ID it has afterwards.
+// Leaf types are swapped in place, anything with children (pointers, arrays, function pointers)
+// is replaced as a whole since the new children only exist in the new tree.
+fn replace_type(
+    prev_type_id: ID,
+    new_type_id: ID,
+    updated_tree: &mut Tree,
+    new_ast: &Tree,
+    insertion_set: &mut HashSet<AstRelation>,
+    deletion_set: &mut HashSet<AstRelation>,
+) -> ID {
+    let prev_type = updated_tree.get_relation(prev_type_id);
+    let new_type = new_ast.get_relation(new_type_id);
+    if get_child_ids(&prev_type).is_empty() && get_child_ids(&new_type).is_empty() {
+        deletion_set.insert(prev_type);
+        let replacement = replace_id_in_relation(&new_type, prev_type_id);
+        updated_tree.update_relation(prev_type_id, replacement.clone());
+        insertion_set.insert(replacement);
+        return prev_type_id;
+    }
+    let deletions = delete_onwards(prev_type_id, updated_tree);
+    merge_relations(deletion_set, deletions);
+    let (insertions, type_id) = insert_onwards(new_type_id, updated_tree, new_ast);
+    merge_relations(insertion_set, insertions);
+    type_id
+}
+
+// For every new argument the previous argument it corresponds to (None if it is a new argument).
+// Arguments with the same name are matched first, the others in the order they are left in.
+fn match_args(
+    prev_arg_ids: &[ID],
+    new_arg_ids: &[ID],
+    prev_ast: &Tree,
+    new_ast: &Tree,
+) -> Vec<Option<ID>> {
+    let arg_name = |ast: &Tree, arg_id: ID| match ast.relation_ref(arg_id) {
+        AstRelation::Arg { var_name, .. } => var_name.clone(),
+        _ => panic!("Unexpected node during diffing"),
+    };
+    let mut unmatched_prev_ids: Vec<ID> = prev_arg_ids.to_vec();
+    let mut matches: Vec<Option<ID>> = new_arg_ids
+        .iter()
+        .map(|new_arg_id| {
+            let new_name = arg_name(new_ast, *new_arg_id);
+            let position = unmatched_prev_ids
+                .iter()
+                .position(|prev_arg_id| arg_name(prev_ast, *prev_arg_id) == new_name)?;
+            Some(unmatched_prev_ids.remove(position))
+        })
+        .collect();
+    let mut unmatched_prev_ids = unmatched_prev_ids.into_iter();
+    for arg_match in matches.iter_mut().filter(|arg_match| arg_match.is_none()) {
+        *arg_match = unmatched_prev_ids.next();
+    }
+    matches
+}
+
+// Maps each node to the node referring to it.
+fn get_parent_ids(ast: &Tree) -> BTreeMap<ID, ID> {
+    let mut parent_ids = BTreeMap::new();
+    for (id, relation) in ast.iter() {
+        for child_id in get_child_ids(relation) {
+            parent_ids.insert(child_id, id);
+        }
+    }
+    parent_ids
+}
+
 fn compare_items(
     item_id1: ID,
     item_id2: ID,
-    t1: Tree,
-    t2: Tree,
-) -> (HashSet<AstRelation>, HashSet<AstRelation>, Tree, ID) {
+    t1: &mut Tree,
+    t2: &Tree,
+    cache: &mut MatchCache,
+) -> (HashSet<AstRelation>, HashSet<AstRelation>, ID) {
     let mut insertion_set = HashSet::new();
     let mut deletion_set = HashSet::new();
     let item1 = t1.get_relation(item_id1);
@@ -449,15 +1715,16 @@ fn compare_items(
                 next_stmt_id: next_stmt_id2,
             },
         ) => {
-            if relations_match(
-                &t1.get_relation(stmt_id1),
-                &t2.get_relation(stmt_id2),
-                &t1,
-                &t2,
-            ) {
-                // If the statements match just move on to the next item.
-                let (insertions, deletions, mut updated_tree, next_id) =
-                    compare_items(next_stmt_id1, next_stmt_id2, t1, t2);
+            if let Some((stmt_insertions, stmt_deletions)) =
+                compare_statements(stmt_id1, stmt_id2, t1, t2, cache)
+            {
+                // If the statements match (after diffing nested blocks) just move on to the next item.
+                let (insertions, deletions, next_id) =
+                    compare_items(next_stmt_id1, next_stmt_id2, t1, t2, cache);
+                merge_relations(&mut insertion_set, stmt_insertions);
+                merge_relations(&mut deletion_set, stmt_deletions);
+                merge_relations(&mut insertion_set, insertions);
+                merge_relations(&mut deletion_set, deletions);
                 // However the ID of the next statement could have changed due to a new insertion.
                 if next_stmt_id1 != next_id {
                     let replacement = AstRelation::Item {
@@ -465,46 +1732,33 @@ fn compare_items(
                         stmt_id: stmt_id1,
                         next_stmt_id: next_id,
                     };
-                    for relation in insertions {
-                        insertion_set.insert(relation);
-                    }
-                    for relation in deletions {
-                        deletion_set.insert(relation);
-                    }
                     insertion_set.insert(replacement.clone());
                     deletion_set.insert(item1_clone);
-                    updated_tree.update_relation(id1, replacement);
-                    updated_tree.replace_children(id1, vec![stmt_id1, next_id]);
-                    return (insertion_set, deletion_set, updated_tree, id1);
-                } else {
-                    return (insertions, deletions, updated_tree, id1);
+                    t1.update_relation(id1, replacement);
+                    t1.replace_children(id1, vec![stmt_id1, next_id]);
+                } else if !insertion_set.is_empty() || !deletion_set.is_empty() {
+                    t1.rehash(id1);
                 }
+                return (insertion_set, deletion_set, id1);
             } else {
                 // Otherwise: keep comparing the prev item and insert a new item.
-                let (insertions, deletions, updated_tree, next_id) =
-                    compare_items(id1, next_stmt_id2, t1, t2.clone());
-                for relation in insertions {
-                    insertion_set.insert(relation);
-                }
-                for relation in deletions {
-                    deletion_set.insert(relation);
-                }
-                let new_id = updated_tree.max_id + 1;
-                let (insertions, mut updated_tree, stmt_id) =
-                    insert_onwards(stmt_id2, updated_tree, t2);
-                for relation in insertions {
-                    insertion_set.insert(relation);
-                }
+                let (insertions, deletions, next_id) =
+                    compare_items(id1, next_stmt_id2, t1, t2, cache);
+                merge_relations(&mut insertion_set, insertions);
+                merge_relations(&mut deletion_set, deletions);
+                let (insertions, stmt_id) = insert_onwards(stmt_id2, t1, t2);
+                merge_relations(&mut insertion_set, insertions);
+                let new_id = t1.allocate_id();
                 let new_item = AstRelation::Item {
                     id: new_id,
                     stmt_id: stmt_id,
                     next_stmt_id: next_id,
                 };
                 insertion_set.insert(new_item.clone());
-                updated_tree.add_node(new_id, new_item);
-                updated_tree.link_child(new_id, stmt_id);
-                updated_tree.link_child(new_id, next_id);
-                return (insertion_set, deletion_set, updated_tree, new_id);
+                t1.add_node(new_id, new_item);
+                t1.link_child(new_id, stmt_id);
+                t1.link_child(new_id, next_id);
+                return (insertion_set, deletion_set, new_id);
             }
         }
         (
@@ -518,55 +1772,46 @@ fn compare_items(
                 next_stmt_id: next_stmt_id2,
             },
         ) => {
-            if relations_match(
+            if relations_match_cached(
                 &t1.get_relation(stmt_id1),
                 &t2.get_relation(stmt_id2),
-                &t1,
-                &t2,
+                t1,
+                t2,
+                cache,
             ) {
                 // Insert from whole item onwards.
-                let (insertions, mut updated_tree, next_item) =
-                    insert_onwards(next_stmt_id2, t1, t2);
+                let (insertions, next_item) = insert_onwards(next_stmt_id2, t1, t2);
                 // Change the prev item to normal instead of end item.
                 let replacement = AstRelation::Item {
                     id: id1,
                     stmt_id: stmt_id1,
                     next_stmt_id: next_item,
                 };
-                for relation in insertions {
-                    insertion_set.insert(relation);
-                }
+                merge_relations(&mut insertion_set, insertions);
                 insertion_set.insert(replacement.clone());
                 deletion_set.insert(item1_clone);
-                updated_tree.update_relation(id1, replacement);
-                updated_tree.replace_children(id1, vec![stmt_id1, next_item]);
-                return (insertion_set, deletion_set, updated_tree, id1);
+                t1.update_relation(id1, replacement);
+                t1.replace_children(id1, vec![stmt_id1, next_item]);
+                return (insertion_set, deletion_set, id1);
             } else {
                 // Otherwise: keep comparing the prev item and insert a new item.
-                let (insertions, deletions, updated_tree, next_id) =
-                    compare_items(id1, next_stmt_id2, t1, t2.clone());
-                for relation in insertions {
-                    insertion_set.insert(relation);
-                }
-                for relation in deletions {
-                    deletion_set.insert(relation);
-                }
-                let (insertions, mut new_updated_tree, stmt_id) =
-                    insert_onwards(stmt_id2, updated_tree, t2);
-                for relation in insertions {
-                    insertion_set.insert(relation);
-                }
-                let new_id = new_updated_tree.max_id + 1;
+                let (insertions, deletions, next_id) =
+                    compare_items(id1, next_stmt_id2, t1, t2, cache);
+                merge_relations(&mut insertion_set, insertions);
+                merge_relations(&mut deletion_set, deletions);
+                let (insertions, stmt_id) = insert_onwards(stmt_id2, t1, t2);
+                merge_relations(&mut insertion_set, insertions);
+                let new_id = t1.allocate_id();
                 let new_item = AstRelation::Item {
                     id: new_id,
                     stmt_id: stmt_id,
                     next_stmt_id: next_id,
                 };
                 insertion_set.insert(new_item.clone());
-                new_updated_tree.add_node(new_id, new_item);
-                new_updated_tree.link_child(new_id, stmt_id);
-                new_updated_tree.link_child(new_id, next_id);
-                return (insertion_set, deletion_set, new_updated_tree, new_id);
+                t1.add_node(new_id, new_item);
+                t1.link_child(new_id, stmt_id);
+                t1.link_child(new_id, next_id);
+                return (insertion_set, deletion_set, new_id);
             }
         }
         (
@@ -580,17 +1825,16 @@ fn compare_items(
                 stmt_id: stmt_id2,
             },
         ) => {
-            if relations_match(
+            if relations_match_cached(
                 &t1.get_relation(stmt_id1),
                 &t2.get_relation(stmt_id2),
-                &t1,
-                &t2,
+                t1,
+                t2,
+                cache,
             ) {
                 // Delete from next statement onwards.
-                let (deletions, mut updated_tree) = delete_onwards(next_stmt_id1, t1);
-                for relation in deletions {
-                    deletion_set.insert(relation);
-                }
+                let deletions = delete_onwards(next_stmt_id1, t1);
+                merge_relations(&mut deletion_set, deletions);
                 // Make this item an end item instead.
                 let replacement = AstRelation::EndItem {
                     id: id1,
@@ -598,21 +1842,16 @@ fn compare_items(
                 };
                 insertion_set.insert(replacement.clone());
                 deletion_set.insert(item1_clone);
-                updated_tree.update_relation(id1, replacement);
-                updated_tree.replace_children(id1, vec![stmt_id1]);
-                return (insertion_set, deletion_set, updated_tree, id1);
+                t1.update_relation(id1, replacement);
+                t1.replace_children(id1, vec![stmt_id1]);
+                return (insertion_set, deletion_set, id1);
             } else {
                 // Delete from next statement onwards.
-                let (deletions, updated_tree) = delete_onwards(next_stmt_id1, t1);
-                for relation in deletions {
-                    deletion_set.insert(relation);
-                }
+                let deletions = delete_onwards(next_stmt_id1, t1);
+                merge_relations(&mut deletion_set, deletions);
                 // Insert the differing statement.
-                let (insertions, mut updated_tree, stmt_id) =
-                    insert_onwards(stmt_id2, updated_tree, t2);
-                for relation in insertions {
-                    insertion_set.insert(relation);
-                }
+                let (insertions, stmt_id) = insert_onwards(stmt_id2, t1, t2);
+                merge_relations(&mut insertion_set, insertions);
                 // Make this item an end item instead.
                 let replacement = AstRelation::EndItem {
                     id: id1,
@@ -620,9 +1859,9 @@ fn compare_items(
                 };
                 insertion_set.insert(replacement.clone());
                 deletion_set.insert(item1_clone);
-                updated_tree.update_relation(id1, replacement);
-                updated_tree.replace_children(id1, vec![stmt_id]);
-                return (insertion_set, deletion_set, updated_tree, id1);
+                t1.update_relation(id1, replacement);
+                t1.replace_children(id1, vec![stmt_id]);
+                return (insertion_set, deletion_set, id1);
             }
         }
         (
@@ -636,36 +1875,91 @@ fn compare_items(
                 stmt_id: stmt_id2,
             },
         ) => {
-            if relations_match(
-                &t1.get_relation(stmt_id1),
-                &t2.get_relation(stmt_id2),
-                &t1,
-                &t2,
-            ) {
-                return (insertion_set, deletion_set, t1, id1);
+            if let Some((stmt_insertions, stmt_deletions)) =
+                compare_statements(stmt_id1, stmt_id2, t1, t2, cache)
+            {
+                if !stmt_insertions.is_empty() || !stmt_deletions.is_empty() {
+                    t1.rehash(id1);
+                }
+                return (stmt_insertions, stmt_deletions, id1);
             } else {
-                let (insertions, mut updated_tree, stmt_id) = insert_onwards(stmt_id2, t1, t2);
+                let (insertions, stmt_id) = insert_onwards(stmt_id2, t1, t2);
                 let replacement = AstRelation::EndItem {
                     id: id1,
                     stmt_id: stmt_id,
                 };
-                for relation in insertions {
-                    insertion_set.insert(relation);
-                }
+                merge_relations(&mut insertion_set, insertions);
                 insertion_set.insert(replacement.clone());
                 deletion_set.insert(item1_clone);
-                updated_tree.update_relation(id1, replacement);
-                updated_tree.replace_children(id1, vec![stmt_id]);
-                return (insertion_set, deletion_set, updated_tree, id1);
+                t1.update_relation(id1, replacement);
+                t1.replace_children(id1, vec![stmt_id]);
+                return (insertion_set, deletion_set, id1);
             }
         }
+        (AstRelation::EmptyItem { id: id1 }, AstRelation::EmptyItem { id: _ }) => {
+            return (insertion_set, deletion_set, id1);
+        }
+        // A block that becomes empty (or stops being empty) is replaced from its first item.
+        (AstRelation::EmptyItem { .. }, _) | (_, AstRelation::EmptyItem { .. }) => {
+            let deletions = delete_onwards(item_id1, t1);
+            merge_relations(&mut deletion_set, deletions);
+            let (insertions, new_id) = insert_onwards(item_id2, t1, t2);
+            merge_relations(&mut insertion_set, insertions);
+            return (insertion_set, deletion_set, new_id);
+        }
         (_, _) => panic!("Unexpected node during diffing"),
     }
 }
 
+// Changes needed for a statement of the first tree to match one of the second (None if it has to be replaced).
+// Nested blocks are diffed item by item like function bodies, anything else has to match as a whole.
+fn compare_statements(
+    stmt_id1: ID,
+    stmt_id2: ID,
+    t1: &mut Tree,
+    t2: &Tree,
+    cache: &mut MatchCache,
+) -> Option<(HashSet<AstRelation>, HashSet<AstRelation>)> {
+    let stmt1 = t1.get_relation(stmt_id1);
+    let stmt2 = t2.get_relation(stmt_id2);
+    if relations_match_cached(&stmt1, &stmt2, t1, t2, cache) {
+        return Some((HashSet::new(), HashSet::new()));
+    }
+    let (start_id1, start_id2) = match (&stmt1, &stmt2) {
+        (
+            AstRelation::Compound {
+                id: _,
+                start_id: start_id1,
+            },
+            AstRelation::Compound {
+                id: _,
+                start_id: start_id2,
+            },
+        ) => (*start_id1, *start_id2),
+        _ => return None,
+    };
+    let (mut insertion_set, mut deletion_set, start_id) =
+        compare_items(start_id1, start_id2, t1, t2, &mut MatchCache::new());
+    if start_id != start_id1 {
+        let replacement = AstRelation::Compound {
+            id: stmt_id1,
+            start_id,
+        };
+        insertion_set.insert(replacement.clone());
+        deletion_set.insert(stmt1);
+        t1.update_relation(stmt_id1, replacement);
+        t1.replace_children(stmt_id1, vec![start_id]);
+    } else {
+        t1.rehash(stmt_id1);
+    }
+    // Earlier comparisons involving the block no longer hold.
+    cache.clear();
+    Some((insertion_set, deletion_set))
+}
+
 // Delete the node with the given ID and all its children.
 // Don't forget to unlink this node from any parents before calling this.
-fn delete_onwards(node_id: ID, mut ast: Tree) -> (HashSet<AstRelation>, Tree) {
+fn delete_onwards(node_id: ID, ast: &mut Tree) -> HashSet<AstRelation> {
     let mut delete_set: HashSet<AstRelation> = HashSet::new();
     let relation_to_be_deleted = ast.get_relation(node_id);
     let relation_to_be_deleted_clone = relation_to_be_deleted.clone();
@@ -674,34 +1968,58 @@ fn delete_onwards(node_id: ID, mut ast: Tree) -> (HashSet<AstRelation>, Tree) {
         AstRelation::Char { id: _ } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            return (delete_set, ast);
+            return delete_set;
         }
         AstRelation::Float { id: _ } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            return (delete_set, ast);
+            return delete_set;
+        }
+        AstRelation::Double { id: _ } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            return delete_set;
         }
         AstRelation::Int { id: _ } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
+            return delete_set;
+        }
+        AstRelation::Array {
+            id: _,
+            elem_type_id,
+            size: _,
+        } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(elem_type_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::FunPtr {
+            id: _,
+            return_type_id,
+            arg_type_ids,
+        } => {
+            delete_set.insert(relation_to_be_deleted_clone);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(return_type_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            for arg_type_id in arg_type_ids {
+                let child_set = delete_onwards(arg_type_id, ast);
+                merge_relations(&mut delete_set, child_set);
             }
-            return (delete_set, ast);
+            return delete_set;
+        }
+        AstRelation::Struct { id: _, name: _ } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            return delete_set;
         }
         AstRelation::Void { id: _ } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            return (delete_set, ast);
+            return delete_set;
         }
         // Other nodes just recursively apply function and add result to deletion set before returning.
         AstRelation::Arg {
@@ -711,54 +2029,88 @@ fn delete_onwards(node_id: ID, mut ast: Tree) -> (HashSet<AstRelation>, Tree) {
         } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let (child_set, updated_ast) = delete_onwards(type_id, ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            return (delete_set, updated_ast);
+            let child_set = delete_onwards(type_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::Cast {
+            id: _,
+            target_type_id,
+            expr_id,
+        } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(target_type_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            let child_set = delete_onwards(expr_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::SizeOf { id: _, operand_id } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(operand_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::Conditional {
+            id: _,
+            cond_id,
+            then_id,
+            else_id,
+        } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(cond_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            let child_set = delete_onwards(then_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            let child_set = delete_onwards(else_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::Member {
+            id: _,
+            base_id,
+            field_name: _,
+            arrow: _,
+        } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(base_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
         }
         AstRelation::Var { id: _, var_name: _ } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            return (delete_set, ast);
+            return delete_set;
         }
         AstRelation::BinaryOp {
             id: _,
+            op: _,
             arg1_id,
             arg2_id,
         } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let (child_set, updated_ast) = delete_onwards(arg1_id, ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            let (child_set, updated_ast) = delete_onwards(arg2_id, updated_ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            return (delete_set, updated_ast);
+            let child_set = delete_onwards(arg1_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            let child_set = delete_onwards(arg2_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
         }
         AstRelation::EndItem { id: _, stmt_id } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let (child_set, updated_ast) = delete_onwards(stmt_id, ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            return (delete_set, updated_ast);
+            let child_set = delete_onwards(stmt_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::EmptyItem { id: _ } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            return delete_set;
         }
         AstRelation::Item {
             id: _,
@@ -767,30 +2119,44 @@ fn delete_onwards(node_id: ID, mut ast: Tree) -> (HashSet<AstRelation>, Tree) {
         } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let (child_set, updated_ast) = delete_onwards(stmt_id, ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            let (child_set, updated_ast) = delete_onwards(next_stmt_id, updated_ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            return (delete_set, updated_ast);
+            let child_set = delete_onwards(stmt_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            let child_set = delete_onwards(next_stmt_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::Break { id: _ } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            return delete_set;
+        }
+        AstRelation::Continue { id: _ } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            return delete_set;
+        }
+        AstRelation::Label {
+            id: _,
+            name: _,
+            stmt_id,
+        } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(stmt_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::Goto { .. } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            return delete_set;
         }
         AstRelation::Compound { id: _, start_id } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let (child_set, updated_ast) = delete_onwards(start_id, ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            return (delete_set, updated_ast);
+            let child_set = delete_onwards(start_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
         }
         AstRelation::While {
             id: _,
@@ -799,18 +2165,46 @@ fn delete_onwards(node_id: ID, mut ast: Tree) -> (HashSet<AstRelation>, Tree) {
         } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let (child_set, updated_ast) = delete_onwards(cond_id, ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            let (child_set, updated_ast) = delete_onwards(body_id, updated_ast);
-            for relation in child_set {
-                delete_set.insert(relation);
+            let child_set = delete_onwards(cond_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            let child_set = delete_onwards(body_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::Switch {
+            id: _,
+            expr_id,
+            case_ids,
+        } => {
+            delete_set.insert(relation_to_be_deleted_clone);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(expr_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            for case_id in case_ids {
+                let child_set = delete_onwards(case_id, ast);
+                merge_relations(&mut delete_set, child_set);
             }
-            return (delete_set, updated_ast);
+            return delete_set;
+        }
+        AstRelation::Case {
+            id: _,
+            label_id,
+            body_id,
+        } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(label_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            let child_set = delete_onwards(body_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::DefaultCase { id: _, body_id } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(body_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
         }
         AstRelation::IfElse {
             id: _,
@@ -820,22 +2214,13 @@ fn delete_onwards(node_id: ID, mut ast: Tree) -> (HashSet<AstRelation>, Tree) {
         } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let (child_set, updated_ast) = delete_onwards(cond_id, ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            let (child_set, updated_ast) = delete_onwards(then_id, updated_ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            let (child_set, updated_ast) = delete_onwards(else_id, updated_ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            return (delete_set, updated_ast);
+            let child_set = delete_onwards(cond_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            let child_set = delete_onwards(then_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            let child_set = delete_onwards(else_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
         }
         AstRelation::If {
             id: _,
@@ -844,30 +2229,86 @@ fn delete_onwards(node_id: ID, mut ast: Tree) -> (HashSet<AstRelation>, Tree) {
         } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let (child_set, updated_ast) = delete_onwards(cond_id, ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            let (child_set, updated_ast) = delete_onwards(then_id, updated_ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            return (delete_set, updated_ast);
+            let child_set = delete_onwards(cond_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            let child_set = delete_onwards(then_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::Decl {
+            id: _,
+            var_name: _,
+            type_id,
+        } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(type_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
         }
         AstRelation::Return { id: _, expr_id } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let (child_set, updated_ast) = delete_onwards(expr_id, ast);
-            for relation in child_set {
-                delete_set.insert(relation);
+            let child_set = delete_onwards(expr_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::Index {
+            id: _,
+            array_id,
+            index_id,
+        } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(array_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            let child_set = delete_onwards(index_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::ArrayInit { id: _, elem_ids } => {
+            delete_set.insert(relation_to_be_deleted_clone);
+            ast.delete_node(node_id);
+            for elem_id in elem_ids {
+                let child_set = delete_onwards(elem_id, ast);
+                merge_relations(&mut delete_set, child_set);
             }
-            return (delete_set, updated_ast);
+            return delete_set;
+        }
+        AstRelation::UnaryOp {
+            id: _,
+            op: _,
+            expr_id,
+        } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(expr_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::Deref { id: _, expr_id } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(expr_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::AddressOf { id: _, expr_id } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(expr_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::Ptr {
+            id: _,
+            pointee_type_id,
+        } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(pointee_type_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
         }
         AstRelation::Assign {
             id: _,
@@ -877,18 +2318,35 @@ fn delete_onwards(node_id: ID, mut ast: Tree) -> (HashSet<AstRelation>, Tree) {
         } => {
             delete_set.insert(relation_to_be_deleted);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let (child_set, updated_ast) = delete_onwards(type_id, ast);
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
-            let (child_set, updated_ast) = delete_onwards(expr_id, updated_ast);
-            for relation in child_set {
-                delete_set.insert(relation);
+            let child_set = delete_onwards(type_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            let child_set = delete_onwards(expr_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
+        }
+        AstRelation::StructDef {
+            id: _,
+            name: _,
+            field_ids,
+        } => {
+            delete_set.insert(relation_to_be_deleted_clone);
+            ast.delete_node(node_id);
+            for child_id in field_ids {
+                let child_set = delete_onwards(child_id, ast);
+                merge_relations(&mut delete_set, child_set);
             }
-            return (delete_set, updated_ast);
+            return delete_set;
+        }
+        AstRelation::Field {
+            id: _,
+            field_name: _,
+            type_id,
+        } => {
+            delete_set.insert(relation_to_be_deleted);
+            ast.delete_node(node_id);
+            let child_set = delete_onwards(type_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
         }
         AstRelation::FunCall {
             id: _,
@@ -897,18 +2355,11 @@ fn delete_onwards(node_id: ID, mut ast: Tree) -> (HashSet<AstRelation>, Tree) {
         } => {
             delete_set.insert(relation_to_be_deleted_clone);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let mut updated_ast = ast.clone();
             for arg_id in arg_ids {
-                let (child_set, new_updated_ast) = delete_onwards(arg_id, updated_ast.clone());
-                updated_ast = new_updated_ast;
-                for relation in child_set {
-                    delete_set.insert(relation);
-                }
+                let child_set = delete_onwards(arg_id, ast);
+                merge_relations(&mut delete_set, child_set);
             }
-            return (delete_set, ast);
+            return delete_set;
         }
         AstRelation::FunDef {
             id: _,
@@ -919,43 +2370,24 @@ fn delete_onwards(node_id: ID, mut ast: Tree) -> (HashSet<AstRelation>, Tree) {
         } => {
             delete_set.insert(relation_to_be_deleted_clone);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let mut updated_ast = ast.clone();
-            let (child_set, new_updated_ast) = delete_onwards(return_type_id, updated_ast);
-            updated_ast = new_updated_ast;
-            for relation in child_set {
-                delete_set.insert(relation);
-            }
+            let child_set = delete_onwards(return_type_id, ast);
+            merge_relations(&mut delete_set, child_set);
             for arg_id in arg_ids {
-                let (child_set, new_updated_ast) = delete_onwards(arg_id, updated_ast.clone());
-                updated_ast = new_updated_ast;
-                for relation in child_set {
-                    delete_set.insert(relation);
-                }
-            }
-            let (child_set, updated_ast) = delete_onwards(body_id, updated_ast);
-            for relation in child_set {
-                delete_set.insert(relation);
+                let child_set = delete_onwards(arg_id, ast);
+                merge_relations(&mut delete_set, child_set);
             }
-            return (delete_set, updated_ast);
+            let child_set = delete_onwards(body_id, ast);
+            merge_relations(&mut delete_set, child_set);
+            return delete_set;
         }
         AstRelation::TransUnit { id: _, body_ids } => {
             delete_set.insert(relation_to_be_deleted_clone);
             ast.delete_node(node_id);
-            if node_id == ast.max_id {
-                ast.max_id = *ast.arena.keys().max().unwrap();
-            }
-            let mut updated_ast = ast.clone();
             for body_id in body_ids {
-                let (child_set, new_updated_ast) = delete_onwards(body_id, updated_ast);
-                updated_ast = new_updated_ast;
-                for relation in child_set {
-                    delete_set.insert(relation);
-                }
+                let child_set = delete_onwards(body_id, ast);
+                merge_relations(&mut delete_set, child_set);
             }
-            return (delete_set, ast);
+            return delete_set;
         }
     }
 }
@@ -964,38 +2396,97 @@ fn delete_onwards(node_id: ID, mut ast: Tree) -> (HashSet<AstRelation>, Tree) {
 // Don't forget to link this node to any parents before calling this.
 // (ast = tree we are updating, new_ast = tree we get the relations to insert from.)
 // Here we need to pay attention to not confuse IDs in maintained tree vs. IDs in new tree which we don't actually care about.
-fn insert_onwards(node_id: ID, mut ast: Tree, new_ast: Tree) -> (HashSet<AstRelation>, Tree, ID) {
+fn insert_onwards(node_id: ID, ast: &mut Tree, new_ast: &Tree) -> (HashSet<AstRelation>, ID) {
     let mut insertion_set: HashSet<AstRelation> = HashSet::new();
     let relation_to_be_inserted = new_ast.get_relation(node_id);
     match relation_to_be_inserted {
         // Leaf nodes we don't have to consider any children recursively.
         AstRelation::Char { id: _ } => {
-            let new_id = ast.max_id + 1;
+            let new_id = ast.allocate_id();
             let new_relation = replace_id_in_relation(&relation_to_be_inserted, new_id);
             insertion_set.insert(new_relation.clone());
             ast.add_node(new_id, new_relation);
-            return (insertion_set, ast, new_id);
+            return (insertion_set, new_id);
         }
         AstRelation::Float { id: _ } => {
-            let new_id = ast.max_id + 1;
+            let new_id = ast.allocate_id();
+            let new_relation = replace_id_in_relation(&relation_to_be_inserted, new_id);
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Double { id: _ } => {
+            let new_id = ast.allocate_id();
             let new_relation = replace_id_in_relation(&relation_to_be_inserted, new_id);
             insertion_set.insert(new_relation.clone());
             ast.add_node(new_id, new_relation);
-            return (insertion_set, ast, new_id);
+            return (insertion_set, new_id);
         }
         AstRelation::Int { id: _ } => {
-            let new_id = ast.max_id + 1;
+            let new_id = ast.allocate_id();
             let new_relation = replace_id_in_relation(&relation_to_be_inserted, new_id);
             insertion_set.insert(new_relation.clone());
             ast.add_node(new_id, new_relation);
-            return (insertion_set, ast, new_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Array {
+            id: _,
+            elem_type_id,
+            size,
+        } => {
+            let (insertions, elem_type_child_id) = insert_onwards(elem_type_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Array {
+                id: new_id,
+                elem_type_id: elem_type_child_id,
+                size,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, elem_type_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::FunPtr {
+            id: _,
+            return_type_id,
+            arg_type_ids,
+        } => {
+            let (insertions, return_type_child_id) = insert_onwards(return_type_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let mut new_arg_type_ids: Vec<ID> = vec![];
+            for arg_type_id in arg_type_ids {
+                let (insertions, arg_type_child_id) = insert_onwards(arg_type_id, ast, new_ast);
+                new_arg_type_ids.push(arg_type_child_id);
+                merge_relations(&mut insertion_set, insertions);
+            }
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::FunPtr {
+                id: new_id,
+                return_type_id: return_type_child_id,
+                arg_type_ids: new_arg_type_ids.clone(),
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, return_type_child_id);
+            for arg_type_child_id in new_arg_type_ids {
+                ast.link_child(new_id, arg_type_child_id);
+            }
+            return (insertion_set, new_id);
+        }
+        AstRelation::Struct { id: _, name } => {
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Struct { id: new_id, name };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            return (insertion_set, new_id);
         }
         AstRelation::Void { id: _ } => {
-            let new_id = ast.max_id + 1;
+            let new_id = ast.allocate_id();
             let new_relation = replace_id_in_relation(&relation_to_be_inserted, new_id);
             insertion_set.insert(new_relation.clone());
             ast.add_node(new_id, new_relation);
-            return (insertion_set, ast, new_id);
+            return (insertion_set, new_id);
         }
         // Other nodes have to take care with linking children correctly for both relations and nodes.
         AstRelation::Arg {
@@ -1003,144 +2494,309 @@ fn insert_onwards(node_id: ID, mut ast: Tree, new_ast: Tree) -> (HashSet<AstRela
             var_name,
             type_id,
         } => {
-            let (insertions, mut updated_ast, type_child_id) =
-                insert_onwards(type_id, ast, new_ast);
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let new_id = updated_ast.max_id + 1;
+            let (insertions, type_child_id) = insert_onwards(type_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::Arg {
                 id: new_id,
                 var_name,
                 type_id: type_child_id,
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.link_child(new_id, type_child_id);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, type_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Cast {
+            id: _,
+            target_type_id,
+            expr_id,
+        } => {
+            let (insertions, target_type_child_id) = insert_onwards(target_type_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let (insertions, expr_child_id) = insert_onwards(expr_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Cast {
+                id: new_id,
+                target_type_id: target_type_child_id,
+                expr_id: expr_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, target_type_child_id);
+            ast.link_child(new_id, expr_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::SizeOf { id: _, operand_id } => {
+            let (insertions, operand_child_id) = insert_onwards(operand_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::SizeOf {
+                id: new_id,
+                operand_id: operand_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, operand_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Conditional {
+            id: _,
+            cond_id,
+            then_id,
+            else_id,
+        } => {
+            let (insertions, cond_child_id) = insert_onwards(cond_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let (insertions, then_child_id) = insert_onwards(then_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let (insertions, else_child_id) = insert_onwards(else_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Conditional {
+                id: new_id,
+                cond_id: cond_child_id,
+                then_id: then_child_id,
+                else_id: else_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, cond_child_id);
+            ast.link_child(new_id, then_child_id);
+            ast.link_child(new_id, else_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Member {
+            id: _,
+            base_id,
+            field_name,
+            arrow,
+        } => {
+            let (insertions, base_child_id) = insert_onwards(base_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Member {
+                id: new_id,
+                base_id: base_child_id,
+                field_name,
+                arrow,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, base_child_id);
+            return (insertion_set, new_id);
         }
         AstRelation::Var { id: _, var_name } => {
-            let new_id = ast.max_id + 1;
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::Var {
                 id: new_id,
                 var_name,
             };
             insertion_set.insert(new_relation.clone());
             ast.add_node(new_id, new_relation);
-            return (insertion_set, ast, new_id);
+            return (insertion_set, new_id);
         }
         AstRelation::BinaryOp {
             id: _,
+            op,
             arg1_id,
             arg2_id,
         } => {
-            let (insertions, updated_ast, arg1_child_id) =
-                insert_onwards(arg1_id, ast, new_ast.clone());
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let (insertions, mut updated_ast, arg2_child_id) =
-                insert_onwards(arg2_id, updated_ast, new_ast);
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let new_id = updated_ast.max_id + 1;
+            let (insertions, arg1_child_id) = insert_onwards(arg1_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let (insertions, arg2_child_id) = insert_onwards(arg2_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::BinaryOp {
                 id: new_id,
+                op,
                 arg1_id: arg1_child_id,
                 arg2_id: arg2_child_id,
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.link_child(new_id, arg1_child_id);
-            updated_ast.link_child(new_id, arg2_child_id);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, arg1_child_id);
+            ast.link_child(new_id, arg2_child_id);
+            return (insertion_set, new_id);
         }
         AstRelation::EndItem { id: _, stmt_id } => {
-            let (insertions, mut updated_ast, stmt_child_id) =
-                insert_onwards(stmt_id, ast, new_ast);
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let new_id = updated_ast.max_id + 1;
+            let (insertions, stmt_child_id) = insert_onwards(stmt_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::EndItem {
                 id: new_id,
                 stmt_id: stmt_child_id,
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.link_child(new_id, stmt_child_id);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, stmt_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::EmptyItem { id: _ } => {
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::EmptyItem { id: new_id };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            return (insertion_set, new_id);
         }
         AstRelation::Item {
             id: _,
             stmt_id,
             next_stmt_id,
         } => {
-            let (insertions, updated_ast, stmt_child_id) =
-                insert_onwards(stmt_id, ast, new_ast.clone());
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let (insertions, mut updated_ast, next_stmt_child_id) =
-                insert_onwards(next_stmt_id, updated_ast, new_ast);
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let new_id = updated_ast.max_id + 1;
+            let (insertions, stmt_child_id) = insert_onwards(stmt_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let (insertions, next_stmt_child_id) = insert_onwards(next_stmt_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::Item {
                 id: new_id,
                 stmt_id: stmt_child_id,
                 next_stmt_id: next_stmt_child_id,
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.link_child(new_id, stmt_child_id);
-            updated_ast.link_child(new_id, next_stmt_child_id);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, stmt_child_id);
+            ast.link_child(new_id, next_stmt_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Break { id: _ } => {
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Break { id: new_id };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Continue { id: _ } => {
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Continue { id: new_id };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Label {
+            id: _,
+            name,
+            stmt_id,
+        } => {
+            let (insertions, stmt_child_id) = insert_onwards(stmt_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Label {
+                id: new_id,
+                name,
+                stmt_id: stmt_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, stmt_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Goto { id: _, label_name } => {
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Goto {
+                id: new_id,
+                label_name,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            return (insertion_set, new_id);
         }
         AstRelation::Compound { id: _, start_id } => {
-            let (insertions, mut updated_ast, start_child_id) =
-                insert_onwards(start_id, ast, new_ast);
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let new_id = updated_ast.max_id + 1;
+            let (insertions, start_child_id) = insert_onwards(start_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::Compound {
                 id: new_id,
                 start_id: start_child_id,
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.link_child(new_id, start_child_id);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, start_child_id);
+            return (insertion_set, new_id);
         }
         AstRelation::While {
             id: _,
             cond_id,
             body_id,
         } => {
-            let (insertions, updated_ast, cond_child_id) =
-                insert_onwards(cond_id, ast, new_ast.clone());
-            for relation in insertions {
-                insertion_set.insert(relation);
+            let (insertions, cond_child_id) = insert_onwards(cond_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let (insertions, body_child_id) = insert_onwards(body_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::While {
+                id: new_id,
+                cond_id: cond_child_id,
+                body_id: body_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, cond_child_id);
+            ast.link_child(new_id, body_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Switch {
+            id: _,
+            expr_id,
+            case_ids,
+        } => {
+            let (insertions, expr_child_id) = insert_onwards(expr_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let mut new_case_ids: Vec<ID> = vec![];
+            for case_id in case_ids {
+                let (insertions, case_child_id) = insert_onwards(case_id, ast, new_ast);
+                new_case_ids.push(case_child_id);
+                merge_relations(&mut insertion_set, insertions);
             }
-            let (insertions, mut updated_ast, body_child_id) =
-                insert_onwards(body_id, updated_ast, new_ast);
-            for relation in insertions {
-                insertion_set.insert(relation);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Switch {
+                id: new_id,
+                expr_id: expr_child_id,
+                case_ids: new_case_ids.clone(),
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, expr_child_id);
+            for case_child_id in new_case_ids {
+                ast.link_child(new_id, case_child_id);
             }
-            let new_id = updated_ast.max_id + 1;
-            let new_relation = AstRelation::If {
+            return (insertion_set, new_id);
+        }
+        AstRelation::Case {
+            id: _,
+            label_id,
+            body_id,
+        } => {
+            let (insertions, label_child_id) = insert_onwards(label_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let (insertions, body_child_id) = insert_onwards(body_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Case {
                 id: new_id,
-                cond_id: cond_child_id,
-                then_id: body_child_id,
+                label_id: label_child_id,
+                body_id: body_child_id,
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.link_child(new_id, cond_child_id);
-            updated_ast.link_child(new_id, body_child_id);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, label_child_id);
+            ast.link_child(new_id, body_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::DefaultCase { id: _, body_id } => {
+            let (insertions, body_child_id) = insert_onwards(body_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::DefaultCase {
+                id: new_id,
+                body_id: body_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, body_child_id);
+            return (insertion_set, new_id);
         }
         AstRelation::IfElse {
             id: _,
@@ -1148,22 +2804,13 @@ fn insert_onwards(node_id: ID, mut ast: Tree, new_ast: Tree) -> (HashSet<AstRela
             then_id,
             else_id,
         } => {
-            let (insertions, updated_ast, cond_child_id) =
-                insert_onwards(cond_id, ast, new_ast.clone());
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let (insertions, updated_ast, then_child_id) =
-                insert_onwards(then_id, updated_ast, new_ast.clone());
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let (insertions, mut updated_ast, else_child_id) =
-                insert_onwards(else_id, updated_ast, new_ast);
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let new_id = updated_ast.max_id + 1;
+            let (insertions, cond_child_id) = insert_onwards(cond_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let (insertions, then_child_id) = insert_onwards(then_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let (insertions, else_child_id) = insert_onwards(else_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::IfElse {
                 id: new_id,
                 cond_id: cond_child_id,
@@ -1171,54 +2818,159 @@ fn insert_onwards(node_id: ID, mut ast: Tree, new_ast: Tree) -> (HashSet<AstRela
                 else_id: else_child_id,
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.link_child(new_id, cond_child_id);
-            updated_ast.link_child(new_id, then_child_id);
-            updated_ast.link_child(new_id, else_child_id);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, cond_child_id);
+            ast.link_child(new_id, then_child_id);
+            ast.link_child(new_id, else_child_id);
+            return (insertion_set, new_id);
         }
         AstRelation::If {
             id: _,
             cond_id,
             then_id,
         } => {
-            let (insertions, updated_ast, cond_child_id) =
-                insert_onwards(cond_id, ast, new_ast.clone());
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let (insertions, mut updated_ast, then_child_id) =
-                insert_onwards(then_id, updated_ast, new_ast);
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let new_id = updated_ast.max_id + 1;
+            let (insertions, cond_child_id) = insert_onwards(cond_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let (insertions, then_child_id) = insert_onwards(then_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::If {
                 id: new_id,
                 cond_id: cond_child_id,
                 then_id: then_child_id,
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.link_child(new_id, cond_child_id);
-            updated_ast.link_child(new_id, then_child_id);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, cond_child_id);
+            ast.link_child(new_id, then_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Decl {
+            id: _,
+            var_name,
+            type_id,
+        } => {
+            let (insertions, type_child_id) = insert_onwards(type_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Decl {
+                id: new_id,
+                var_name,
+                type_id: type_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, type_child_id);
+            return (insertion_set, new_id);
         }
         AstRelation::Return { id: _, expr_id } => {
-            let (insertions, mut updated_ast, expr_child_id) =
-                insert_onwards(expr_id, ast, new_ast);
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let new_id = updated_ast.max_id + 1;
+            let (insertions, expr_child_id) = insert_onwards(expr_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::Return {
                 id: new_id,
                 expr_id: expr_child_id,
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.link_child(new_id, expr_child_id);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, expr_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Index {
+            id: _,
+            array_id,
+            index_id,
+        } => {
+            let (insertions, array_child_id) = insert_onwards(array_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let (insertions, index_child_id) = insert_onwards(index_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Index {
+                id: new_id,
+                array_id: array_child_id,
+                index_id: index_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, array_child_id);
+            ast.link_child(new_id, index_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::ArrayInit { id: _, elem_ids } => {
+            let mut new_elem_ids: Vec<ID> = vec![];
+            for elem_id in elem_ids {
+                let (insertions, elem_child_id) = insert_onwards(elem_id, ast, new_ast);
+                new_elem_ids.push(elem_child_id);
+                merge_relations(&mut insertion_set, insertions);
+            }
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::ArrayInit {
+                id: new_id,
+                elem_ids: new_elem_ids.clone(),
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            for elem_child_id in new_elem_ids {
+                ast.link_child(new_id, elem_child_id);
+            }
+            return (insertion_set, new_id);
+        }
+        AstRelation::UnaryOp { id: _, op, expr_id } => {
+            let (insertions, expr_child_id) = insert_onwards(expr_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::UnaryOp {
+                id: new_id,
+                op,
+                expr_id: expr_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, expr_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Deref { id: _, expr_id } => {
+            let (insertions, expr_child_id) = insert_onwards(expr_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Deref {
+                id: new_id,
+                expr_id: expr_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, expr_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::AddressOf { id: _, expr_id } => {
+            let (insertions, expr_child_id) = insert_onwards(expr_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::AddressOf {
+                id: new_id,
+                expr_id: expr_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, expr_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::Ptr {
+            id: _,
+            pointee_type_id,
+        } => {
+            let (insertions, pointee_child_id) = insert_onwards(pointee_type_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Ptr {
+                id: new_id,
+                pointee_type_id: pointee_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, pointee_child_id);
+            return (insertion_set, new_id);
         }
         AstRelation::Assign {
             id: _,
@@ -1226,17 +2978,11 @@ fn insert_onwards(node_id: ID, mut ast: Tree, new_ast: Tree) -> (HashSet<AstRela
             type_id,
             expr_id,
         } => {
-            let (insertions, updated_ast, type_child_id) =
-                insert_onwards(type_id, ast, new_ast.clone());
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let (insertions, mut updated_ast, expr_child_id) =
-                insert_onwards(expr_id, updated_ast, new_ast);
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
-            let new_id = updated_ast.max_id + 1;
+            let (insertions, type_child_id) = insert_onwards(type_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let (insertions, expr_child_id) = insert_onwards(expr_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::Assign {
                 id: new_id,
                 var_name,
@@ -1244,37 +2990,74 @@ fn insert_onwards(node_id: ID, mut ast: Tree, new_ast: Tree) -> (HashSet<AstRela
                 expr_id: expr_child_id,
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.link_child(new_id, type_child_id);
-            updated_ast.link_child(new_id, expr_child_id);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, type_child_id);
+            ast.link_child(new_id, expr_child_id);
+            return (insertion_set, new_id);
+        }
+        AstRelation::StructDef {
+            id: _,
+            name,
+            field_ids,
+        } => {
+            let mut new_field_ids: Vec<ID> = vec![];
+            for child_id in field_ids {
+                let (insertions, new_child_id) = insert_onwards(child_id, ast, new_ast);
+                new_field_ids.push(new_child_id);
+                merge_relations(&mut insertion_set, insertions);
+            }
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::StructDef {
+                id: new_id,
+                name,
+                field_ids: new_field_ids.clone(),
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            for child_id in new_field_ids {
+                ast.link_child(new_id, child_id);
+            }
+            return (insertion_set, new_id);
+        }
+        AstRelation::Field {
+            id: _,
+            field_name,
+            type_id,
+        } => {
+            let (insertions, type_child_id) = insert_onwards(type_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
+            let new_relation = AstRelation::Field {
+                id: new_id,
+                field_name,
+                type_id: type_child_id,
+            };
+            insertion_set.insert(new_relation.clone());
+            ast.add_node(new_id, new_relation);
+            ast.link_child(new_id, type_child_id);
+            return (insertion_set, new_id);
         }
         AstRelation::FunCall {
             id: _,
             fun_name,
             arg_ids,
         } => {
-            let mut updated_ast = ast.clone();
             let mut new_child_ids: Vec<ID> = vec![];
             for arg_id in arg_ids {
-                let (insertions, new_updated_ast, arg_child_id) =
-                    insert_onwards(arg_id, updated_ast, new_ast.clone());
+                let (insertions, arg_child_id) = insert_onwards(arg_id, ast, new_ast);
                 new_child_ids.push(arg_child_id);
-                updated_ast = new_updated_ast;
-                for relation in insertions {
-                    insertion_set.insert(relation);
-                }
+                merge_relations(&mut insertion_set, insertions);
             }
-            let new_id = ast.max_id + 1;
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::FunCall {
                 id: new_id,
                 fun_name,
                 arg_ids: new_child_ids.clone(),
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.replace_children(new_id, new_child_ids);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.replace_children(new_id, new_child_ids);
+            return (insertion_set, new_id);
         }
         AstRelation::FunDef {
             id: _,
@@ -1283,27 +3066,17 @@ fn insert_onwards(node_id: ID, mut ast: Tree, new_ast: Tree) -> (HashSet<AstRela
             arg_ids,
             body_id,
         } => {
-            let (insertions, mut updated_ast, return_child_id) =
-                insert_onwards(return_type_id, ast, new_ast.clone());
-            for relation in insertions {
-                insertion_set.insert(relation);
-            }
+            let (insertions, return_child_id) = insert_onwards(return_type_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
             let mut new_child_ids: Vec<ID> = vec![];
             for arg_id in arg_ids {
-                let (insertions, new_updated_ast, arg_child_id) =
-                    insert_onwards(arg_id, updated_ast, new_ast.clone());
+                let (insertions, arg_child_id) = insert_onwards(arg_id, ast, new_ast);
                 new_child_ids.push(arg_child_id);
-                updated_ast = new_updated_ast;
-                for relation in insertions {
-                    insertion_set.insert(relation);
-                }
-            }
-            let (insertions, mut updated_ast, body_child_id) =
-                insert_onwards(body_id, updated_ast, new_ast);
-            for relation in insertions {
-                insertion_set.insert(relation);
+                merge_relations(&mut insertion_set, insertions);
             }
-            let new_id = updated_ast.max_id + 1;
+            let (insertions, body_child_id) = insert_onwards(body_id, ast, new_ast);
+            merge_relations(&mut insertion_set, insertions);
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::FunDef {
                 id: new_id,
                 fun_name,
@@ -1312,54 +3085,760 @@ fn insert_onwards(node_id: ID, mut ast: Tree, new_ast: Tree) -> (HashSet<AstRela
                 body_id: body_child_id,
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.replace_children(new_id, new_child_ids);
-            updated_ast.link_child(new_id, return_child_id);
-            updated_ast.link_child(new_id, body_child_id);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.replace_children(new_id, new_child_ids);
+            ast.link_child(new_id, return_child_id);
+            ast.link_child(new_id, body_child_id);
+            return (insertion_set, new_id);
         }
         AstRelation::TransUnit { id: _, body_ids } => {
-            let mut updated_ast = ast.clone();
             let mut new_child_ids: Vec<ID> = vec![];
             for body_id in body_ids {
-                let (insertions, new_updated_ast, arg_child_id) =
-                    insert_onwards(body_id, updated_ast, new_ast.clone());
+                let (insertions, arg_child_id) = insert_onwards(body_id, ast, new_ast);
                 new_child_ids.push(arg_child_id);
-                updated_ast = new_updated_ast;
-                for relation in insertions {
-                    insertion_set.insert(relation);
-                }
+                merge_relations(&mut insertion_set, insertions);
             }
-            let new_id = ast.max_id + 1;
+            let new_id = ast.allocate_id();
             let new_relation = AstRelation::TransUnit {
                 id: new_id,
                 body_ids: new_child_ids.clone(),
             };
             insertion_set.insert(new_relation.clone());
-            updated_ast.add_node(new_id, new_relation);
-            updated_ast.replace_children(new_id, new_child_ids);
-            return (insertion_set, updated_ast, new_id);
+            ast.add_node(new_id, new_relation);
+            ast.replace_children(new_id, new_child_ids);
+            return (insertion_set, new_id);
         }
     }
 }
 
+// Rewrites every ID in the relation.
+fn remap_relation_ids(r: &AstRelation, mapping: &HashMap<ID, ID>) -> AstRelation {
+    let m = |id: &ID| mapping[id];
+    let ms = |ids: &Vec<ID>| ids.iter().map(|id| mapping[id]).collect::<Vec<ID>>();
+    match r {
+        AstRelation::TransUnit { id, body_ids } => AstRelation::TransUnit {
+            id: m(id),
+            body_ids: ms(body_ids),
+        },
+        AstRelation::FunDef {
+            id,
+            fun_name,
+            return_type_id,
+            arg_ids,
+            body_id,
+        } => AstRelation::FunDef {
+            id: m(id),
+            fun_name: fun_name.clone(),
+            return_type_id: m(return_type_id),
+            arg_ids: ms(arg_ids),
+            body_id: m(body_id),
+        },
+        AstRelation::StructDef {
+            id,
+            name,
+            field_ids,
+        } => AstRelation::StructDef {
+            id: m(id),
+            name: name.clone(),
+            field_ids: ms(field_ids),
+        },
+        AstRelation::Field {
+            id,
+            field_name,
+            type_id,
+        } => AstRelation::Field {
+            id: m(id),
+            field_name: field_name.clone(),
+            type_id: m(type_id),
+        },
+        AstRelation::FunCall {
+            id,
+            fun_name,
+            arg_ids,
+        } => AstRelation::FunCall {
+            id: m(id),
+            fun_name: fun_name.clone(),
+            arg_ids: ms(arg_ids),
+        },
+        AstRelation::Assign {
+            id,
+            var_name,
+            type_id,
+            expr_id,
+        } => AstRelation::Assign {
+            id: m(id),
+            var_name: var_name.clone(),
+            type_id: m(type_id),
+            expr_id: m(expr_id),
+        },
+        AstRelation::Decl {
+            id,
+            var_name,
+            type_id,
+        } => AstRelation::Decl {
+            id: m(id),
+            var_name: var_name.clone(),
+            type_id: m(type_id),
+        },
+        AstRelation::Return { id, expr_id } => AstRelation::Return {
+            id: m(id),
+            expr_id: m(expr_id),
+        },
+        AstRelation::If {
+            id,
+            cond_id,
+            then_id,
+        } => AstRelation::If {
+            id: m(id),
+            cond_id: m(cond_id),
+            then_id: m(then_id),
+        },
+        AstRelation::IfElse {
+            id,
+            cond_id,
+            then_id,
+            else_id,
+        } => AstRelation::IfElse {
+            id: m(id),
+            cond_id: m(cond_id),
+            then_id: m(then_id),
+            else_id: m(else_id),
+        },
+        AstRelation::While {
+            id,
+            cond_id,
+            body_id,
+        } => AstRelation::While {
+            id: m(id),
+            cond_id: m(cond_id),
+            body_id: m(body_id),
+        },
+        AstRelation::Break { id } => AstRelation::Break { id: m(id) },
+        AstRelation::Continue { id } => AstRelation::Continue { id: m(id) },
+        AstRelation::Label { id, name, stmt_id } => AstRelation::Label {
+            id: m(id),
+            name: name.clone(),
+            stmt_id: m(stmt_id),
+        },
+        AstRelation::Goto { id, label_name } => AstRelation::Goto {
+            id: m(id),
+            label_name: label_name.clone(),
+        },
+        AstRelation::Switch {
+            id,
+            expr_id,
+            case_ids,
+        } => AstRelation::Switch {
+            id: m(id),
+            expr_id: m(expr_id),
+            case_ids: ms(case_ids),
+        },
+        AstRelation::Case {
+            id,
+            label_id,
+            body_id,
+        } => AstRelation::Case {
+            id: m(id),
+            label_id: m(label_id),
+            body_id: m(body_id),
+        },
+        AstRelation::DefaultCase { id, body_id } => AstRelation::DefaultCase {
+            id: m(id),
+            body_id: m(body_id),
+        },
+        AstRelation::Compound { id, start_id } => AstRelation::Compound {
+            id: m(id),
+            start_id: m(start_id),
+        },
+        AstRelation::Item {
+            id,
+            stmt_id,
+            next_stmt_id,
+        } => AstRelation::Item {
+            id: m(id),
+            stmt_id: m(stmt_id),
+            next_stmt_id: m(next_stmt_id),
+        },
+        AstRelation::EndItem { id, stmt_id } => AstRelation::EndItem {
+            id: m(id),
+            stmt_id: m(stmt_id),
+        },
+        AstRelation::EmptyItem { id } => AstRelation::EmptyItem { id: m(id) },
+        AstRelation::BinaryOp {
+            id,
+            op,
+            arg1_id,
+            arg2_id,
+        } => AstRelation::BinaryOp {
+            id: m(id),
+            op: op.clone(),
+            arg1_id: m(arg1_id),
+            arg2_id: m(arg2_id),
+        },
+        AstRelation::UnaryOp { id, op, expr_id } => AstRelation::UnaryOp {
+            id: m(id),
+            op: op.clone(),
+            expr_id: m(expr_id),
+        },
+        AstRelation::Index {
+            id,
+            array_id,
+            index_id,
+        } => AstRelation::Index {
+            id: m(id),
+            array_id: m(array_id),
+            index_id: m(index_id),
+        },
+        AstRelation::ArrayInit { id, elem_ids } => AstRelation::ArrayInit {
+            id: m(id),
+            elem_ids: ms(elem_ids),
+        },
+        AstRelation::Deref { id, expr_id } => AstRelation::Deref {
+            id: m(id),
+            expr_id: m(expr_id),
+        },
+        AstRelation::AddressOf { id, expr_id } => AstRelation::AddressOf {
+            id: m(id),
+            expr_id: m(expr_id),
+        },
+        AstRelation::Cast {
+            id,
+            target_type_id,
+            expr_id,
+        } => AstRelation::Cast {
+            id: m(id),
+            target_type_id: m(target_type_id),
+            expr_id: m(expr_id),
+        },
+        AstRelation::SizeOf { id, operand_id } => AstRelation::SizeOf {
+            id: m(id),
+            operand_id: m(operand_id),
+        },
+        AstRelation::Conditional {
+            id,
+            cond_id,
+            then_id,
+            else_id,
+        } => AstRelation::Conditional {
+            id: m(id),
+            cond_id: m(cond_id),
+            then_id: m(then_id),
+            else_id: m(else_id),
+        },
+        AstRelation::Member {
+            id,
+            base_id,
+            field_name,
+            arrow,
+        } => AstRelation::Member {
+            id: m(id),
+            base_id: m(base_id),
+            field_name: field_name.clone(),
+            arrow: *arrow,
+        },
+        AstRelation::Var { id, var_name } => AstRelation::Var {
+            id: m(id),
+            var_name: var_name.clone(),
+        },
+        AstRelation::Arg {
+            id,
+            var_name,
+            type_id,
+        } => AstRelation::Arg {
+            id: m(id),
+            var_name: var_name.clone(),
+            type_id: m(type_id),
+        },
+        AstRelation::Ptr {
+            id,
+            pointee_type_id,
+        } => AstRelation::Ptr {
+            id: m(id),
+            pointee_type_id: m(pointee_type_id),
+        },
+        AstRelation::Array {
+            id,
+            elem_type_id,
+            size,
+        } => AstRelation::Array {
+            id: m(id),
+            elem_type_id: m(elem_type_id),
+            size: *size,
+        },
+        AstRelation::FunPtr {
+            id,
+            return_type_id,
+            arg_type_ids,
+        } => AstRelation::FunPtr {
+            id: m(id),
+            return_type_id: m(return_type_id),
+            arg_type_ids: ms(arg_type_ids),
+        },
+        AstRelation::Struct { id, name } => AstRelation::Struct {
+            id: m(id),
+            name: name.clone(),
+        },
+        AstRelation::Void { id } => AstRelation::Void { id: m(id) },
+        AstRelation::Int { id } => AstRelation::Int { id: m(id) },
+        AstRelation::Float { id } => AstRelation::Float { id: m(id) },
+        AstRelation::Double { id } => AstRelation::Double { id: m(id) },
+        AstRelation::Char { id } => AstRelation::Char { id: m(id) },
+    }
+}
+
 fn replace_id_in_relation(r: &AstRelation, id: ID) -> AstRelation {
     match r {
+        AstRelation::Array {
+            id: _,
+            elem_type_id,
+            size,
+        } => {
+            return AstRelation::Array {
+                id,
+                elem_type_id: *elem_type_id,
+                size: *size,
+            }
+        }
+        AstRelation::FunPtr {
+            id: _,
+            return_type_id,
+            arg_type_ids,
+        } => {
+            return AstRelation::FunPtr {
+                id,
+                return_type_id: *return_type_id,
+                arg_type_ids: arg_type_ids.clone(),
+            }
+        }
+        AstRelation::Struct { id: _, name } => {
+            return AstRelation::Struct {
+                id,
+                name: name.clone(),
+            }
+        }
         AstRelation::Void { id: _ } => return AstRelation::Void { id },
         AstRelation::Int { id: _ } => return AstRelation::Int { id },
         AstRelation::Float { id: _ } => return AstRelation::Float { id },
+        AstRelation::Double { id: _ } => return AstRelation::Double { id },
         AstRelation::Char { id: _ } => return AstRelation::Char { id },
-        _ => panic!("ID replacement not implemented for this relation type"),
+        AstRelation::TransUnit { id: _, body_ids } => {
+            return AstRelation::TransUnit {
+                id,
+                body_ids: body_ids.clone(),
+            }
+        }
+        AstRelation::FunDef {
+            id: _,
+            fun_name,
+            return_type_id,
+            arg_ids,
+            body_id,
+        } => {
+            return AstRelation::FunDef {
+                id,
+                fun_name: fun_name.clone(),
+                return_type_id: *return_type_id,
+                arg_ids: arg_ids.clone(),
+                body_id: *body_id,
+            }
+        }
+        AstRelation::StructDef {
+            id: _,
+            name,
+            field_ids,
+        } => {
+            return AstRelation::StructDef {
+                id,
+                name: name.clone(),
+                field_ids: field_ids.clone(),
+            }
+        }
+        AstRelation::Field {
+            id: _,
+            field_name,
+            type_id,
+        } => {
+            return AstRelation::Field {
+                id,
+                field_name: field_name.clone(),
+                type_id: *type_id,
+            }
+        }
+        AstRelation::FunCall {
+            id: _,
+            fun_name,
+            arg_ids,
+        } => {
+            return AstRelation::FunCall {
+                id,
+                fun_name: fun_name.clone(),
+                arg_ids: arg_ids.clone(),
+            }
+        }
+        AstRelation::Assign {
+            id: _,
+            var_name,
+            type_id,
+            expr_id,
+        } => {
+            return AstRelation::Assign {
+                id,
+                var_name: var_name.clone(),
+                type_id: *type_id,
+                expr_id: *expr_id,
+            }
+        }
+        AstRelation::Decl {
+            id: _,
+            var_name,
+            type_id,
+        } => {
+            return AstRelation::Decl {
+                id,
+                var_name: var_name.clone(),
+                type_id: *type_id,
+            }
+        }
+        AstRelation::Return { id: _, expr_id } => {
+            return AstRelation::Return {
+                id,
+                expr_id: *expr_id,
+            }
+        }
+        AstRelation::Index {
+            id: _,
+            array_id,
+            index_id,
+        } => {
+            return AstRelation::Index {
+                id,
+                array_id: *array_id,
+                index_id: *index_id,
+            }
+        }
+        AstRelation::ArrayInit { id: _, elem_ids } => {
+            return AstRelation::ArrayInit {
+                id,
+                elem_ids: elem_ids.clone(),
+            }
+        }
+        AstRelation::UnaryOp { id: _, op, expr_id } => {
+            return AstRelation::UnaryOp {
+                id,
+                op: op.clone(),
+                expr_id: *expr_id,
+            }
+        }
+        AstRelation::Deref { id: _, expr_id } => {
+            return AstRelation::Deref {
+                id,
+                expr_id: *expr_id,
+            }
+        }
+        AstRelation::AddressOf { id: _, expr_id } => {
+            return AstRelation::AddressOf {
+                id,
+                expr_id: *expr_id,
+            }
+        }
+        AstRelation::Ptr {
+            id: _,
+            pointee_type_id,
+        } => {
+            return AstRelation::Ptr {
+                id,
+                pointee_type_id: *pointee_type_id,
+            }
+        }
+        AstRelation::If {
+            id: _,
+            cond_id,
+            then_id,
+        } => {
+            return AstRelation::If {
+                id,
+                cond_id: *cond_id,
+                then_id: *then_id,
+            }
+        }
+        AstRelation::IfElse {
+            id: _,
+            cond_id,
+            then_id,
+            else_id,
+        } => {
+            return AstRelation::IfElse {
+                id,
+                cond_id: *cond_id,
+                then_id: *then_id,
+                else_id: *else_id,
+            }
+        }
+        AstRelation::While {
+            id: _,
+            cond_id,
+            body_id,
+        } => {
+            return AstRelation::While {
+                id,
+                cond_id: *cond_id,
+                body_id: *body_id,
+            }
+        }
+        AstRelation::Switch {
+            id: _,
+            expr_id,
+            case_ids,
+        } => {
+            return AstRelation::Switch {
+                id,
+                expr_id: *expr_id,
+                case_ids: case_ids.clone(),
+            }
+        }
+        AstRelation::Case {
+            id: _,
+            label_id,
+            body_id,
+        } => {
+            return AstRelation::Case {
+                id,
+                label_id: *label_id,
+                body_id: *body_id,
+            }
+        }
+        AstRelation::DefaultCase { id: _, body_id } => {
+            return AstRelation::DefaultCase {
+                id,
+                body_id: *body_id,
+            }
+        }
+        AstRelation::Break { id: _ } => return AstRelation::Break { id },
+        AstRelation::Continue { id: _ } => return AstRelation::Continue { id },
+        AstRelation::Label {
+            id: _,
+            name,
+            stmt_id,
+        } => {
+            return AstRelation::Label {
+                id,
+                name: name.clone(),
+                stmt_id: *stmt_id,
+            }
+        }
+        AstRelation::Goto { id: _, label_name } => {
+            return AstRelation::Goto {
+                id,
+                label_name: label_name.clone(),
+            }
+        }
+        AstRelation::Compound { id: _, start_id } => {
+            return AstRelation::Compound {
+                id,
+                start_id: *start_id,
+            }
+        }
+        AstRelation::Item {
+            id: _,
+            stmt_id,
+            next_stmt_id,
+        } => {
+            return AstRelation::Item {
+                id,
+                stmt_id: *stmt_id,
+                next_stmt_id: *next_stmt_id,
+            }
+        }
+        AstRelation::EndItem { id: _, stmt_id } => {
+            return AstRelation::EndItem {
+                id,
+                stmt_id: *stmt_id,
+            }
+        }
+        AstRelation::EmptyItem { id: _ } => return AstRelation::EmptyItem { id },
+        AstRelation::BinaryOp {
+            id: _,
+            op,
+            arg1_id,
+            arg2_id,
+        } => {
+            return AstRelation::BinaryOp {
+                id,
+                op: op.clone(),
+                arg1_id: *arg1_id,
+                arg2_id: *arg2_id,
+            }
+        }
+        AstRelation::Cast {
+            id: _,
+            target_type_id,
+            expr_id,
+        } => {
+            return AstRelation::Cast {
+                id,
+                target_type_id: *target_type_id,
+                expr_id: *expr_id,
+            }
+        }
+        AstRelation::SizeOf { id: _, operand_id } => {
+            return AstRelation::SizeOf {
+                id,
+                operand_id: *operand_id,
+            }
+        }
+        AstRelation::Conditional {
+            id: _,
+            cond_id,
+            then_id,
+            else_id,
+        } => {
+            return AstRelation::Conditional {
+                id,
+                cond_id: *cond_id,
+                then_id: *then_id,
+                else_id: *else_id,
+            }
+        }
+        AstRelation::Member {
+            id: _,
+            base_id,
+            field_name,
+            arrow,
+        } => {
+            return AstRelation::Member {
+                id,
+                base_id: *base_id,
+                field_name: field_name.clone(),
+                arrow: *arrow,
+            }
+        }
+        AstRelation::Var { id: _, var_name } => {
+            return AstRelation::Var {
+                id,
+                var_name: var_name.clone(),
+            }
+        }
+        AstRelation::Arg {
+            id: _,
+            var_name,
+            type_id,
+        } => {
+            return AstRelation::Arg {
+                id,
+                var_name: var_name.clone(),
+                type_id: *type_id,
+            }
+        }
+    }
+}
+
+// Return true if they are of the same type (and have the same name, if applicable).
+// So effectively same structure just ignoring exact IDs.
+fn relations_match(r1: &AstRelation, r2: &AstRelation, t1: &Tree, t2: &Tree) -> bool {
+    relations_match_cached(r1, r2, t1, t2, &mut MatchCache::new())
+}
+
+// Results of comparing a subtree of the first tree with one of the second, keyed by their IDs.
+// Only valid while neither subtree changes, so a cache is kept for at most one function body diff
+// (where only the top level items change).
+type MatchCache = HashMap<(ID, ID), bool>;
+
+// Long chains (e.g. else-if) get compared against several candidates so remember earlier results.
+fn relations_match_cached(
+    r1: &AstRelation,
+    r2: &AstRelation,
+    t1: &Tree,
+    t2: &Tree,
+    cache: &mut MatchCache,
+) -> bool {
+    let key = (get_relation_id(r1), get_relation_id(r2));
+    // Matching subtrees always hash the same, so only equal hashes need the full comparison.
+    if let (Some(hash1), Some(hash2)) = (t1.subtree_hash(key.0), t2.subtree_hash(key.1)) {
+        if hash1 != hash2 {
+            return false;
+        }
     }
+    if let Some(result) = cache.get(&key) {
+        return *result;
+    }
+    let result = compare_relations(r1, r2, t1, t2, cache);
+    cache.insert(key, result);
+    result
 }
 
-// Return true if they are of the same type (and have the same name, if applicable).
-// So effectively same structure just ignoring exact IDs.
-fn relations_match(r1: &AstRelation, r2: &AstRelation, t1: &Tree, t2: &Tree) -> bool {
+fn compare_relations(
+    r1: &AstRelation,
+    r2: &AstRelation,
+    t1: &Tree,
+    t2: &Tree,
+    cache: &mut MatchCache,
+) -> bool {
     match (r1, r2) {
         (AstRelation::Char { id: _ }, AstRelation::Char { id: _ }) => return true,
         (AstRelation::Float { id: _ }, AstRelation::Float { id: _ }) => return true,
+        (AstRelation::Double { id: _ }, AstRelation::Double { id: _ }) => return true,
         (AstRelation::Int { id: _ }, AstRelation::Int { id: _ }) => return true,
+        (
+            AstRelation::Array {
+                id: _,
+                elem_type_id: elem_type_id1,
+                size: size1,
+            },
+            AstRelation::Array {
+                id: _,
+                elem_type_id: elem_type_id2,
+                size: size2,
+            },
+        ) => {
+            return size1 == size2
+                && relations_match_cached(
+                    t1.relation_ref(*elem_type_id1),
+                    t2.relation_ref(*elem_type_id2),
+                    t1,
+                    t2,
+                    cache,
+                );
+        }
+        (
+            AstRelation::FunPtr {
+                id: _,
+                return_type_id: return_type_id1,
+                arg_type_ids: arg_type_ids1,
+            },
+            AstRelation::FunPtr {
+                id: _,
+                return_type_id: return_type_id2,
+                arg_type_ids: arg_type_ids2,
+            },
+        ) => {
+            if arg_type_ids1.len() != arg_type_ids2.len() {
+                return false;
+            }
+            for (index, arg_type_id1) in arg_type_ids1.iter().enumerate() {
+                if !relations_match_cached(
+                    t1.relation_ref(*arg_type_id1),
+                    t2.relation_ref(arg_type_ids2[index]),
+                    t1,
+                    t2,
+                    cache,
+                ) {
+                    return false;
+                }
+            }
+            return relations_match_cached(
+                t1.relation_ref(*return_type_id1),
+                t2.relation_ref(*return_type_id2),
+                t1,
+                t2,
+                cache,
+            );
+        }
+        (
+            AstRelation::Struct { id: _, name: name1 },
+            AstRelation::Struct { id: _, name: name2 },
+        ) => {
+            return name1 == name2;
+        }
         (AstRelation::Void { id: _ }, AstRelation::Void { id: _ }) => return true,
         (
             AstRelation::Arg {
@@ -1374,13 +3853,116 @@ fn relations_match(r1: &AstRelation, r2: &AstRelation, t1: &Tree, t2: &Tree) ->
             },
         ) => {
             return var_name1 == var_name2
-                && relations_match(
-                    &t1.get_relation(*type_id1),
-                    &t2.get_relation(*type_id2),
+                && relations_match_cached(
+                    t1.relation_ref(*type_id1),
+                    t2.relation_ref(*type_id2),
                     t1,
                     t2,
+                    cache,
                 )
         }
+        (
+            AstRelation::Cast {
+                id: _,
+                target_type_id: target_type_id1,
+                expr_id: expr_id1,
+            },
+            AstRelation::Cast {
+                id: _,
+                target_type_id: target_type_id2,
+                expr_id: expr_id2,
+            },
+        ) => {
+            return relations_match_cached(
+                t1.relation_ref(*target_type_id1),
+                t2.relation_ref(*target_type_id2),
+                t1,
+                t2,
+                cache,
+            ) && relations_match_cached(
+                t1.relation_ref(*expr_id1),
+                t2.relation_ref(*expr_id2),
+                t1,
+                t2,
+                cache,
+            );
+        }
+        (
+            AstRelation::SizeOf {
+                id: _,
+                operand_id: operand_id1,
+            },
+            AstRelation::SizeOf {
+                id: _,
+                operand_id: operand_id2,
+            },
+        ) => {
+            return relations_match_cached(
+                t1.relation_ref(*operand_id1),
+                t2.relation_ref(*operand_id2),
+                t1,
+                t2,
+                cache,
+            )
+        }
+        (
+            AstRelation::Conditional {
+                id: _,
+                cond_id: cond_id1,
+                then_id: then_id1,
+                else_id: else_id1,
+            },
+            AstRelation::Conditional {
+                id: _,
+                cond_id: cond_id2,
+                then_id: then_id2,
+                else_id: else_id2,
+            },
+        ) => {
+            return relations_match_cached(
+                t1.relation_ref(*cond_id1),
+                t2.relation_ref(*cond_id2),
+                t1,
+                t2,
+                cache,
+            ) && relations_match_cached(
+                t1.relation_ref(*then_id1),
+                t2.relation_ref(*then_id2),
+                t1,
+                t2,
+                cache,
+            ) && relations_match_cached(
+                t1.relation_ref(*else_id1),
+                t2.relation_ref(*else_id2),
+                t1,
+                t2,
+                cache,
+            )
+        }
+        (
+            AstRelation::Member {
+                id: _,
+                base_id: base_id1,
+                field_name: field_name1,
+                arrow: arrow1,
+            },
+            AstRelation::Member {
+                id: _,
+                base_id: base_id2,
+                field_name: field_name2,
+                arrow: arrow2,
+            },
+        ) => {
+            return field_name1 == field_name2
+                && arrow1 == arrow2
+                && relations_match_cached(
+                    t1.relation_ref(*base_id1),
+                    t2.relation_ref(*base_id2),
+                    t1,
+                    t2,
+                    cache,
+                );
+        }
         (
             AstRelation::Var {
                 id: _,
@@ -1394,27 +3976,34 @@ fn relations_match(r1: &AstRelation, r2: &AstRelation, t1: &Tree, t2: &Tree) ->
         (
             AstRelation::BinaryOp {
                 id: _,
+                op: op1,
                 arg1_id: arg1_id1,
                 arg2_id: arg2_id1,
             },
             AstRelation::BinaryOp {
                 id: _,
+                op: op2,
                 arg1_id: arg1_id2,
                 arg2_id: arg2_id2,
             },
         ) => {
-            return relations_match(
-                &t1.get_relation(*arg1_id1),
-                &t2.get_relation(*arg1_id2),
-                t1,
-                t2,
-            ) && relations_match(
-                &t1.get_relation(*arg2_id1),
-                &t2.get_relation(*arg2_id2),
-                t1,
-                t2,
-            )
+            return op1 == op2
+                && relations_match_cached(
+                    t1.relation_ref(*arg1_id1),
+                    t2.relation_ref(*arg1_id2),
+                    t1,
+                    t2,
+                    cache,
+                )
+                && relations_match_cached(
+                    t1.relation_ref(*arg2_id1),
+                    t2.relation_ref(*arg2_id2),
+                    t1,
+                    t2,
+                    cache,
+                )
         }
+        (AstRelation::EmptyItem { id: _ }, AstRelation::EmptyItem { id: _ }) => return true,
         (
             AstRelation::EndItem {
                 id: _,
@@ -1425,11 +4014,12 @@ fn relations_match(r1: &AstRelation, r2: &AstRelation, t1: &Tree, t2: &Tree) ->
                 stmt_id: stmt_id2,
             },
         ) => {
-            return relations_match(
-                &t1.get_relation(*stmt_id1),
-                &t2.get_relation(*stmt_id2),
+            return relations_match_cached(
+                t1.relation_ref(*stmt_id1),
+                t2.relation_ref(*stmt_id2),
                 t1,
                 t2,
+                cache,
             )
         }
         (
@@ -1444,18 +4034,53 @@ fn relations_match(r1: &AstRelation, r2: &AstRelation, t1: &Tree, t2: &Tree) ->
                 next_stmt_id: next_stmt_id2,
             },
         ) => {
-            return relations_match(
-                &t1.get_relation(*stmt_id1),
-                &t2.get_relation(*stmt_id2),
+            return relations_match_cached(
+                t1.relation_ref(*stmt_id1),
+                t2.relation_ref(*stmt_id2),
                 t1,
                 t2,
-            ) && relations_match(
-                &t1.get_relation(*next_stmt_id1),
-                &t2.get_relation(*next_stmt_id2),
+                cache,
+            ) && relations_match_cached(
+                t1.relation_ref(*next_stmt_id1),
+                t2.relation_ref(*next_stmt_id2),
                 t1,
                 t2,
+                cache,
             )
         }
+        (AstRelation::Break { id: _ }, AstRelation::Break { id: _ }) => return true,
+        (AstRelation::Continue { id: _ }, AstRelation::Continue { id: _ }) => return true,
+        (
+            AstRelation::Label {
+                id: _,
+                name: name1,
+                stmt_id: stmt_id1,
+            },
+            AstRelation::Label {
+                id: _,
+                name: name2,
+                stmt_id: stmt_id2,
+            },
+        ) => {
+            return name1 == name2
+                && relations_match_cached(
+                    t1.relation_ref(*stmt_id1),
+                    t2.relation_ref(*stmt_id2),
+                    t1,
+                    t2,
+                    cache,
+                )
+        }
+        (
+            AstRelation::Goto {
+                id: _,
+                label_name: label_name1,
+            },
+            AstRelation::Goto {
+                id: _,
+                label_name: label_name2,
+            },
+        ) => return label_name1 == label_name2,
         (
             AstRelation::Compound {
                 id: _,
@@ -1466,11 +4091,12 @@ fn relations_match(r1: &AstRelation, r2: &AstRelation, t1: &Tree, t2: &Tree) ->
                 start_id: start_id2,
             },
         ) => {
-            return relations_match(
-                &t1.get_relation(*start_id1),
-                &t2.get_relation(*start_id2),
+            return relations_match_cached(
+                t1.relation_ref(*start_id1),
+                t2.relation_ref(*start_id2),
                 t1,
                 t2,
+                cache,
             )
         }
         (
@@ -1485,88 +4111,322 @@ fn relations_match(r1: &AstRelation, r2: &AstRelation, t1: &Tree, t2: &Tree) ->
                 body_id: body_id2,
             },
         ) => {
-            return relations_match(
-                &t1.get_relation(*body_id1),
-                &t2.get_relation(*body_id2),
+            return relations_match_cached(
+                t1.relation_ref(*body_id1),
+                t2.relation_ref(*body_id2),
+                t1,
+                t2,
+                cache,
+            ) && relations_match_cached(
+                t1.relation_ref(*cond_id1),
+                t2.relation_ref(*cond_id2),
+                t1,
+                t2,
+                cache,
+            )
+        }
+        (
+            AstRelation::If {
+                id: _,
+                cond_id: cond_id1,
+                then_id: then_id1,
+            },
+            AstRelation::If {
+                id: _,
+                cond_id: cond_id2,
+                then_id: then_id2,
+            },
+        ) => {
+            return relations_match_cached(
+                t1.relation_ref(*then_id1),
+                t2.relation_ref(*then_id2),
+                t1,
+                t2,
+                cache,
+            ) && relations_match_cached(
+                t1.relation_ref(*cond_id1),
+                t2.relation_ref(*cond_id2),
+                t1,
+                t2,
+                cache,
+            )
+        }
+        (
+            AstRelation::IfElse {
+                id: _,
+                cond_id: cond_id1,
+                then_id: then_id1,
+                else_id: else_id1,
+            },
+            AstRelation::IfElse {
+                id: _,
+                cond_id: cond_id2,
+                then_id: then_id2,
+                else_id: else_id2,
+            },
+        ) => {
+            return relations_match_cached(
+                t1.relation_ref(*then_id1),
+                t2.relation_ref(*then_id2),
+                t1,
+                t2,
+                cache,
+            ) && relations_match_cached(
+                t1.relation_ref(*cond_id1),
+                t2.relation_ref(*cond_id2),
+                t1,
+                t2,
+                cache,
+            ) && relations_match_cached(
+                t1.relation_ref(*else_id1),
+                t2.relation_ref(*else_id2),
+                t1,
+                t2,
+                cache,
+            )
+        }
+        (
+            AstRelation::Switch {
+                id: _,
+                expr_id: expr_id1,
+                case_ids: case_ids1,
+            },
+            AstRelation::Switch {
+                id: _,
+                expr_id: expr_id2,
+                case_ids: case_ids2,
+            },
+        ) => {
+            if case_ids1.len() != case_ids2.len() {
+                return false;
+            }
+            for (index, case_id1) in case_ids1.iter().enumerate() {
+                if !relations_match_cached(
+                    t1.relation_ref(*case_id1),
+                    t2.relation_ref(case_ids2[index]),
+                    t1,
+                    t2,
+                    cache,
+                ) {
+                    return false;
+                }
+            }
+            return relations_match_cached(
+                t1.relation_ref(*expr_id1),
+                t2.relation_ref(*expr_id2),
+                t1,
+                t2,
+                cache,
+            );
+        }
+        (
+            AstRelation::Case {
+                id: _,
+                label_id: label_id1,
+                body_id: body_id1,
+            },
+            AstRelation::Case {
+                id: _,
+                label_id: label_id2,
+                body_id: body_id2,
+            },
+        ) => {
+            return relations_match_cached(
+                t1.relation_ref(*label_id1),
+                t2.relation_ref(*label_id2),
+                t1,
+                t2,
+                cache,
+            ) && relations_match_cached(
+                t1.relation_ref(*body_id1),
+                t2.relation_ref(*body_id2),
+                t1,
+                t2,
+                cache,
+            )
+        }
+        (
+            AstRelation::DefaultCase {
+                id: _,
+                body_id: body_id1,
+            },
+            AstRelation::DefaultCase {
+                id: _,
+                body_id: body_id2,
+            },
+        ) => {
+            return relations_match_cached(
+                t1.relation_ref(*body_id1),
+                t2.relation_ref(*body_id2),
                 t1,
                 t2,
-            ) && relations_match(
-                &t1.get_relation(*cond_id1),
-                &t2.get_relation(*cond_id2),
+                cache,
+            )
+        }
+        (
+            AstRelation::Decl {
+                id: _,
+                var_name: var_name1,
+                type_id: type_id1,
+            },
+            AstRelation::Decl {
+                id: _,
+                var_name: var_name2,
+                type_id: type_id2,
+            },
+        ) => {
+            return var_name1 == var_name2
+                && relations_match_cached(
+                    t1.relation_ref(*type_id1),
+                    t2.relation_ref(*type_id2),
+                    t1,
+                    t2,
+                    cache,
+                );
+        }
+        (
+            AstRelation::Return {
+                id: _,
+                expr_id: expr_id1,
+            },
+            AstRelation::Return {
+                id: _,
+                expr_id: expr_id2,
+            },
+        ) => {
+            return relations_match_cached(
+                t1.relation_ref(*expr_id1),
+                t2.relation_ref(*expr_id2),
                 t1,
                 t2,
+                cache,
             )
         }
         (
-            AstRelation::If {
+            AstRelation::Index {
+                id: _,
+                array_id: array_id1,
+                index_id: index_id1,
+            },
+            AstRelation::Index {
+                id: _,
+                array_id: array_id2,
+                index_id: index_id2,
+            },
+        ) => {
+            return relations_match_cached(
+                t1.relation_ref(*array_id1),
+                t2.relation_ref(*array_id2),
+                t1,
+                t2,
+                cache,
+            ) && relations_match_cached(
+                t1.relation_ref(*index_id1),
+                t2.relation_ref(*index_id2),
+                t1,
+                t2,
+                cache,
+            );
+        }
+        (
+            AstRelation::ArrayInit {
+                id: _,
+                elem_ids: elem_ids1,
+            },
+            AstRelation::ArrayInit {
+                id: _,
+                elem_ids: elem_ids2,
+            },
+        ) => {
+            if elem_ids1.len() != elem_ids2.len() {
+                return false;
+            }
+            for (index, elem_id1) in elem_ids1.iter().enumerate() {
+                if !relations_match_cached(
+                    t1.relation_ref(*elem_id1),
+                    t2.relation_ref(elem_ids2[index]),
+                    t1,
+                    t2,
+                    cache,
+                ) {
+                    return false;
+                }
+            }
+            return true;
+        }
+        (
+            AstRelation::UnaryOp {
+                id: _,
+                op: op1,
+                expr_id: expr_id1,
+            },
+            AstRelation::UnaryOp {
+                id: _,
+                op: op2,
+                expr_id: expr_id2,
+            },
+        ) => {
+            return op1 == op2
+                && relations_match_cached(
+                    t1.relation_ref(*expr_id1),
+                    t2.relation_ref(*expr_id2),
+                    t1,
+                    t2,
+                    cache,
+                )
+        }
+        (
+            AstRelation::Deref {
                 id: _,
-                cond_id: cond_id1,
-                then_id: then_id1,
+                expr_id: expr_id1,
             },
-            AstRelation::If {
+            AstRelation::Deref {
                 id: _,
-                cond_id: cond_id2,
-                then_id: then_id2,
+                expr_id: expr_id2,
             },
         ) => {
-            return relations_match(
-                &t1.get_relation(*then_id1),
-                &t2.get_relation(*then_id2),
-                t1,
-                t2,
-            ) && relations_match(
-                &t1.get_relation(*cond_id1),
-                &t2.get_relation(*cond_id2),
+            return relations_match_cached(
+                t1.relation_ref(*expr_id1),
+                t2.relation_ref(*expr_id2),
                 t1,
                 t2,
+                cache,
             )
         }
         (
-            AstRelation::IfElse {
+            AstRelation::AddressOf {
                 id: _,
-                cond_id: cond_id1,
-                then_id: then_id1,
-                else_id: else_id1,
+                expr_id: expr_id1,
             },
-            AstRelation::IfElse {
+            AstRelation::AddressOf {
                 id: _,
-                cond_id: cond_id2,
-                then_id: then_id2,
-                else_id: else_id2,
+                expr_id: expr_id2,
             },
         ) => {
-            return relations_match(
-                &t1.get_relation(*then_id1),
-                &t2.get_relation(*then_id2),
-                t1,
-                t2,
-            ) && relations_match(
-                &t1.get_relation(*cond_id1),
-                &t2.get_relation(*cond_id2),
-                t1,
-                t2,
-            ) && relations_match(
-                &t1.get_relation(*else_id1),
-                &t2.get_relation(*else_id2),
+            return relations_match_cached(
+                t1.relation_ref(*expr_id1),
+                t2.relation_ref(*expr_id2),
                 t1,
                 t2,
+                cache,
             )
         }
         (
-            AstRelation::Return {
+            AstRelation::Ptr {
                 id: _,
-                expr_id: expr_id1,
+                pointee_type_id: pointee_type_id1,
             },
-            AstRelation::Return {
+            AstRelation::Ptr {
                 id: _,
-                expr_id: expr_id2,
+                pointee_type_id: pointee_type_id2,
             },
         ) => {
-            return relations_match(
-                &t1.get_relation(*expr_id1),
-                &t2.get_relation(*expr_id2),
+            return relations_match_cached(
+                t1.relation_ref(*pointee_type_id1),
+                t2.relation_ref(*pointee_type_id2),
                 t1,
                 t2,
+                cache,
             )
         }
         (
@@ -1584,18 +4444,69 @@ fn relations_match(r1: &AstRelation, r2: &AstRelation, t1: &Tree, t2: &Tree) ->
             },
         ) => {
             return var_name1 == var_name2
-                && return relations_match(
-                    &t1.get_relation(*type_id1),
-                    &t2.get_relation(*type_id2),
+                && return relations_match_cached(
+                    t1.relation_ref(*type_id1),
+                    t2.relation_ref(*type_id2),
                     t1,
                     t2,
-                ) && return relations_match(
-                    &t1.get_relation(*expr_id1),
-                    &t2.get_relation(*expr_id2),
+                    cache,
+                ) && return relations_match_cached(
+                    t1.relation_ref(*expr_id1),
+                    t2.relation_ref(*expr_id2),
                     t1,
                     t2,
+                    cache,
                 )
         }
+        (
+            AstRelation::StructDef {
+                id: _,
+                name: name1,
+                field_ids: field_ids1,
+            },
+            AstRelation::StructDef {
+                id: _,
+                name: name2,
+                field_ids: field_ids2,
+            },
+        ) => {
+            if field_ids1.len() != field_ids2.len() {
+                return false;
+            }
+            for (index, child_id1) in field_ids1.iter().enumerate() {
+                if !relations_match_cached(
+                    t1.relation_ref(*child_id1),
+                    t2.relation_ref(field_ids2[index]),
+                    t1,
+                    t2,
+                    cache,
+                ) {
+                    return false;
+                }
+            }
+            return name1 == name2;
+        }
+        (
+            AstRelation::Field {
+                id: _,
+                field_name: field_name1,
+                type_id: type_id1,
+            },
+            AstRelation::Field {
+                id: _,
+                field_name: field_name2,
+                type_id: type_id2,
+            },
+        ) => {
+            return field_name1 == field_name2
+                && relations_match_cached(
+                    t1.relation_ref(*type_id1),
+                    t2.relation_ref(*type_id2),
+                    t1,
+                    t2,
+                    cache,
+                );
+        }
         (
             AstRelation::FunCall {
                 id: _,
@@ -1608,18 +4519,21 @@ fn relations_match(r1: &AstRelation, r2: &AstRelation, t1: &Tree, t2: &Tree) ->
                 arg_ids: arg_ids2,
             },
         ) => {
-            let mut args_result: bool = true;
+            if fun_name1 != fun_name2 || arg_ids1.len() != arg_ids2.len() {
+                return false;
+            }
             for (index, arg_id1) in arg_ids1.iter().enumerate() {
-                if !relations_match(
-                    &t1.get_relation(*arg_id1),
-                    &t2.get_relation(arg_ids2[index]),
+                if !relations_match_cached(
+                    t1.relation_ref(*arg_id1),
+                    t2.relation_ref(arg_ids2[index]),
                     t1,
                     t2,
+                    cache,
                 ) {
-                    args_result = false;
+                    return false;
                 }
             }
-            return args_result && fun_name1 == fun_name2;
+            return true;
         }
         (
             AstRelation::FunDef {
@@ -1649,31 +4563,82 @@ pub fn get_relation_id(r: &AstRelation) -> ID {
     match r {
         AstRelation::Char { id } => return *id,
         AstRelation::Float { id } => return *id,
+        AstRelation::Double { id } => return *id,
         AstRelation::Int { id } => return *id,
+        AstRelation::Array {
+            id,
+            elem_type_id: _,
+            size: _,
+        } => return *id,
+        AstRelation::FunPtr {
+            id,
+            return_type_id: _,
+            arg_type_ids: _,
+        } => return *id,
+        AstRelation::Struct { id, name: _ } => return *id,
         AstRelation::Void { id } => return *id,
         AstRelation::Arg {
             id,
             var_name: _,
             type_id: _,
         } => return *id,
+        AstRelation::Cast {
+            id,
+            target_type_id: _,
+            expr_id: _,
+        } => return *id,
+        AstRelation::SizeOf { id, operand_id: _ } => return *id,
+        AstRelation::Conditional {
+            id,
+            cond_id: _,
+            then_id: _,
+            else_id: _,
+        } => return *id,
+        AstRelation::Member {
+            id,
+            base_id: _,
+            field_name: _,
+            arrow: _,
+        } => return *id,
         AstRelation::Var { id, var_name: _ } => return *id,
         AstRelation::BinaryOp {
             id,
+            op: _,
             arg1_id: _,
             arg2_id: _,
         } => return *id,
         AstRelation::EndItem { id, stmt_id: _ } => return *id,
+        AstRelation::EmptyItem { id } => return *id,
         AstRelation::Item {
             id,
             stmt_id: _,
             next_stmt_id: _,
         } => return *id,
+        AstRelation::Break { id } => return *id,
+        AstRelation::Continue { id } => return *id,
+        AstRelation::Label {
+            id,
+            name: _,
+            stmt_id: _,
+        } => return *id,
+        AstRelation::Goto { id, label_name: _ } => return *id,
         AstRelation::Compound { id, start_id: _ } => return *id,
         AstRelation::While {
             id,
             cond_id: _,
             body_id: _,
         } => return *id,
+        AstRelation::Switch {
+            id,
+            expr_id: _,
+            case_ids: _,
+        } => return *id,
+        AstRelation::Case {
+            id,
+            label_id: _,
+            body_id: _,
+        } => return *id,
+        AstRelation::DefaultCase { id, body_id: _ } => return *id,
         AstRelation::IfElse {
             id,
             cond_id: _,
@@ -1685,13 +4650,45 @@ pub fn get_relation_id(r: &AstRelation) -> ID {
             cond_id: _,
             then_id: _,
         } => return *id,
+        AstRelation::Decl {
+            id,
+            var_name: _,
+            type_id: _,
+        } => return *id,
         AstRelation::Return { id, expr_id: _ } => return *id,
+        AstRelation::Index {
+            id,
+            array_id: _,
+            index_id: _,
+        } => return *id,
+        AstRelation::ArrayInit { id, elem_ids: _ } => return *id,
+        AstRelation::UnaryOp {
+            id,
+            op: _,
+            expr_id: _,
+        } => return *id,
+        AstRelation::Deref { id, expr_id: _ } => return *id,
+        AstRelation::AddressOf { id, expr_id: _ } => return *id,
+        AstRelation::Ptr {
+            id,
+            pointee_type_id: _,
+        } => return *id,
         AstRelation::Assign {
             id,
             var_name: _,
             type_id: _,
             expr_id: _,
         } => return *id,
+        AstRelation::StructDef {
+            id,
+            name: _,
+            field_ids: _,
+        } => return *id,
+        AstRelation::Field {
+            id,
+            field_name: _,
+            type_id: _,
+        } => return *id,
         AstRelation::FunCall {
             id,
             fun_name: _,
@@ -1708,10 +4705,1031 @@ pub fn get_relation_id(r: &AstRelation) -> ID {
     }
 }
 
+// IDs of the nodes a relation refers to.
+pub fn get_child_ids(r: &AstRelation) -> Vec<ID> {
+    match r {
+        AstRelation::Char { .. }
+        | AstRelation::Float { .. }
+        | AstRelation::Double { .. }
+        | AstRelation::Int { .. }
+        | AstRelation::Struct { .. }
+        | AstRelation::Void { .. }
+        | AstRelation::Var { .. }
+        | AstRelation::Break { .. }
+        | AstRelation::Continue { .. }
+        | AstRelation::Goto { .. } => vec![],
+        AstRelation::Label { stmt_id, .. } => vec![*stmt_id],
+        AstRelation::Array { elem_type_id, .. } => vec![*elem_type_id],
+        AstRelation::FunPtr {
+            return_type_id,
+            arg_type_ids,
+            ..
+        } => {
+            let mut ids = vec![*return_type_id];
+            ids.extend(arg_type_ids.iter());
+            ids
+        }
+        AstRelation::Arg { type_id, .. } => vec![*type_id],
+        AstRelation::Cast {
+            target_type_id,
+            expr_id,
+            ..
+        } => vec![*target_type_id, *expr_id],
+        AstRelation::SizeOf { operand_id, .. } => vec![*operand_id],
+        AstRelation::Conditional {
+            cond_id,
+            then_id,
+            else_id,
+            ..
+        } => vec![*cond_id, *then_id, *else_id],
+        AstRelation::Member { base_id, .. } => vec![*base_id],
+        AstRelation::BinaryOp {
+            arg1_id, arg2_id, ..
+        } => vec![*arg1_id, *arg2_id],
+        AstRelation::EndItem { stmt_id, .. } => vec![*stmt_id],
+        AstRelation::EmptyItem { .. } => vec![],
+        AstRelation::Item {
+            stmt_id,
+            next_stmt_id,
+            ..
+        } => vec![*stmt_id, *next_stmt_id],
+        AstRelation::Compound { start_id, .. } => vec![*start_id],
+        AstRelation::While {
+            cond_id, body_id, ..
+        } => vec![*cond_id, *body_id],
+        AstRelation::Switch {
+            expr_id, case_ids, ..
+        } => {
+            let mut ids = vec![*expr_id];
+            ids.extend(case_ids.iter());
+            ids
+        }
+        AstRelation::Case {
+            label_id, body_id, ..
+        } => vec![*label_id, *body_id],
+        AstRelation::DefaultCase { body_id, .. } => vec![*body_id],
+        AstRelation::IfElse {
+            cond_id,
+            then_id,
+            else_id,
+            ..
+        } => vec![*cond_id, *then_id, *else_id],
+        AstRelation::If {
+            cond_id, then_id, ..
+        } => vec![*cond_id, *then_id],
+        AstRelation::Decl { type_id, .. } => vec![*type_id],
+        AstRelation::Return { expr_id, .. } => vec![*expr_id],
+        AstRelation::Index {
+            array_id, index_id, ..
+        } => vec![*array_id, *index_id],
+        AstRelation::ArrayInit { elem_ids, .. } => elem_ids.clone(),
+        AstRelation::UnaryOp { expr_id, .. } => vec![*expr_id],
+        AstRelation::Deref { expr_id, .. } => vec![*expr_id],
+        AstRelation::AddressOf { expr_id, .. } => vec![*expr_id],
+        AstRelation::Ptr {
+            pointee_type_id, ..
+        } => vec![*pointee_type_id],
+        AstRelation::Assign {
+            type_id, expr_id, ..
+        } => vec![*type_id, *expr_id],
+        AstRelation::StructDef { field_ids, .. } => field_ids.clone(),
+        AstRelation::Field { type_id, .. } => vec![*type_id],
+        AstRelation::FunCall { arg_ids, .. } => arg_ids.clone(),
+        AstRelation::FunDef {
+            return_type_id,
+            arg_ids,
+            body_id,
+            ..
+        } => {
+            let mut ids = vec![*return_type_id];
+            ids.extend(arg_ids.iter());
+            ids.push(*body_id);
+            ids
+        }
+        AstRelation::TransUnit { body_ids, .. } => body_ids.clone(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::ast::{
+        delete_onwards, get_diff_relation_set, get_edit_script, get_initial_relation_set,
+        get_relation_id, insert_onwards, relations_match, relations_match_cached,
+        replace_id_in_relation, sort_relations, summarize_diff, Edit, MatchCache, Tree,
+        TreeBuilder,
+    };
+    use crate::crate_path;
+    use crate::definitions::{AstRelation, ID};
+    use crate::parser_interface;
+    use crate::parser_interface::Backend;
+    use std::collections::HashSet;
+
+    // int add(int a, int b) { int c = a + b; return c; } int main(void) { return add(1, 2); }
+    fn build_two_functions() -> Tree {
+        let mut builder = TreeBuilder::new();
+        let a_type = builder.int();
+        let a = builder.arg("a", a_type);
+        let b_type = builder.int();
+        let b = builder.arg("b", b_type);
+        let add_return_type = builder.int();
+        let a_var = builder.var("a");
+        let b_var = builder.var("b");
+        let sum = builder.binary_op("+", a_var, b_var);
+        let c_type = builder.int();
+        let assign = builder.assign("c", c_type, sum);
+        let c_var = builder.var("c");
+        let add_return = builder.ret(c_var);
+        let add_body = builder.compound(vec![assign, add_return]);
+        let add = builder.fun_def("add", add_return_type, vec![a, b], add_body);
+        let main_return_type = builder.int();
+        let one = builder.int();
+        let two = builder.int();
+        let call = builder.fun_call("add", vec![one, two]);
+        let main_return = builder.ret(call);
+        let main_body = builder.compound(vec![main_return]);
+        let main = builder.fun_def("main", main_return_type, vec![], main_body);
+        builder.build(vec![add, main])
+    }
+    // Stand-in statements to chain.
+    fn add_statements(tree: &mut Tree, next_id: &mut ID, count: usize) -> Vec<ID> {
+        (0..count)
+            .map(|_| {
+                let node_id = *next_id;
+                *next_id = *next_id + 1;
+                tree.add_node(node_id, AstRelation::Void { id: node_id });
+                node_id
+            })
+            .collect()
+    }
     #[test]
-    fn delete_whole_tree() {}
+    fn item_chain_without_statements() {
+        let mut tree = Tree::new();
+        let mut next_id = 0;
+        let start_id = tree.add_item_chain(vec![], &mut next_id);
+        assert_eq!(
+            tree.get_relation(start_id),
+            AstRelation::EmptyItem { id: start_id }
+        );
+        assert!(tree.get_children(start_id).is_empty());
+    }
+    #[test]
+    fn item_chain_with_one_statement() {
+        let mut tree = Tree::new();
+        let mut next_id = 0;
+        let stmt_ids = add_statements(&mut tree, &mut next_id, 1);
+        let start_id = tree.add_item_chain(stmt_ids.clone(), &mut next_id);
+        assert_eq!(
+            tree.get_relation(start_id),
+            AstRelation::EndItem {
+                id: start_id,
+                stmt_id: stmt_ids[0]
+            }
+        );
+        assert_eq!(tree.get_children(start_id), stmt_ids);
+    }
+    #[test]
+    fn item_chain_keeps_statement_order() {
+        let mut tree = Tree::new();
+        let mut next_id = 0;
+        let stmt_ids = add_statements(&mut tree, &mut next_id, 3);
+        let mut item_id = tree.add_item_chain(stmt_ids.clone(), &mut next_id);
+        let mut chained = vec![];
+        loop {
+            match tree.get_relation(item_id) {
+                AstRelation::Item {
+                    id: _,
+                    stmt_id,
+                    next_stmt_id,
+                } => {
+                    assert_eq!(tree.get_children(item_id), vec![stmt_id, next_stmt_id]);
+                    chained.push(stmt_id);
+                    item_id = next_stmt_id;
+                }
+                AstRelation::EndItem { id: _, stmt_id } => {
+                    chained.push(stmt_id);
+                    break;
+                }
+                relation => panic!("Expected an item, got {}", relation),
+            }
+        }
+        assert_eq!(chained, stmt_ids);
+    }
+    #[test]
+    fn tree_builder_matches_parser() {
+        let tree = build_two_functions();
+        assert_eq!(tree.validate(), Ok(()));
+        let parsed = parser_interface::parse_source_into_ast(
+            "int add(int a, int b) { int c = a + b; return c; } int main(void) { return add(1, 2); }",
+        )
+        .unwrap();
+        assert_eq!(tree.to_c_source(), parsed.to_c_source());
+        assert!(tree.structurally_eq(&parsed));
+    }
+    #[test]
+    fn structurally_eq_ignores_ids() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int f(int a, int b) { return a; } int main(void) { return f(1, 2); }",
+        )
+        .unwrap();
+        // The diff keeps the IDs of the earlier version and gives the changed parts new ones.
+        let prev_ast = parser_interface::parse_source_into_ast(
+            "int f(int a, int b) { return b; } int main(void) { return f(2, 1) + 0; }",
+        )
+        .unwrap();
+        let (_, _, updated_tree) = get_diff_relation_set(&prev_ast, &ast);
+        assert_ne!(
+            get_initial_relation_set(&updated_tree),
+            get_initial_relation_set(&ast)
+        );
+        assert!(ast.structurally_eq(&updated_tree));
+        assert!(updated_tree.structurally_eq(&ast));
+        for source in [
+            "int f(int a, int b) { return a; } int main(void) { return f(1); }",
+            "int f(int a, int b) { return b; } int main(void) { return f(1, 2); }",
+            "int f(int a) { return a; } int main(void) { return f(1, 2); }",
+            "int main(void) { return f(1, 2); } int f(int a, int b) { return a; }",
+        ] {
+            let other = parser_interface::parse_source_into_ast(source).unwrap();
+            assert!(!ast.structurally_eq(&other), "{}", source);
+            assert!(!other.structurally_eq(&ast), "{}", source);
+        }
+    }
+    #[test]
+    fn content_hash_ignores_ids() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int f(int a, int b) { return a; } int main(void) { return f(1, 2); }",
+        )
+        .unwrap();
+        let prev_ast = parser_interface::parse_source_into_ast(
+            "int f(int a, int b) { return b; } int main(void) { return f(2, 1) + 0; }",
+        )
+        .unwrap();
+        let (_, _, mut updated_tree) = get_diff_relation_set(&prev_ast, &ast);
+        assert_ne!(
+            get_initial_relation_set(&updated_tree),
+            get_initial_relation_set(&ast)
+        );
+        assert_eq!(updated_tree.content_hash(), ast.content_hash());
+        updated_tree.compact();
+        assert_eq!(updated_tree.content_hash(), ast.content_hash());
+        assert_ne!(prev_ast.content_hash(), ast.content_hash());
+        // Swapping the functions changes the program as well.
+        let swapped_ast = parser_interface::parse_source_into_ast(
+            "int main(void) { return f(1, 2); } int f(int a, int b) { return a; }",
+        )
+        .unwrap();
+        assert_ne!(swapped_ast.content_hash(), ast.content_hash());
+    }
+    #[test]
+    fn delete_whole_tree() {
+        let mut tree = build_two_functions();
+        let root_id = tree.get_root();
+        let deletion_set = delete_onwards(root_id, &mut tree);
+        assert_eq!(
+            deletion_set,
+            get_initial_relation_set(&build_two_functions())
+        );
+        assert_eq!(tree.size(), 0);
+    }
+    #[test]
+    fn insert_whole_tree() {
+        let new_tree = build_two_functions();
+        let mut tree = Tree::new();
+        let (insertion_set, _) = insert_onwards(new_tree.get_root(), &mut tree, &new_tree);
+        assert_eq!(insertion_set.len(), new_tree.size());
+        assert_eq!(tree.size(), new_tree.size());
+        assert_eq!(tree.relation_histogram(), new_tree.relation_histogram());
+    }
+    #[test]
+    fn replace_id_in_fun_def() {
+        let relation = AstRelation::FunDef {
+            id: 7,
+            fun_name: String::from("main"),
+            return_type_id: 0,
+            arg_ids: vec![2, 3],
+            body_id: 6,
+        };
+        assert_eq!(
+            replace_id_in_relation(&relation, 12),
+            AstRelation::FunDef {
+                id: 12,
+                fun_name: String::from("main"),
+                return_type_id: 0,
+                arg_ids: vec![2, 3],
+                body_id: 6,
+            }
+        );
+    }
+    #[test]
+    fn replace_id_in_item() {
+        let relation = AstRelation::Item {
+            id: 4,
+            stmt_id: 1,
+            next_stmt_id: 3,
+        };
+        assert_eq!(
+            replace_id_in_relation(&relation, 9),
+            AstRelation::Item {
+                id: 9,
+                stmt_id: 1,
+                next_stmt_id: 3,
+            }
+        );
+    }
+    #[test]
+    fn summarize_replaced_variable() {
+        let insertion_set = HashSet::from([
+            AstRelation::Var {
+                id: 3,
+                var_name: String::from("b"),
+            },
+            AstRelation::Int { id: 4 },
+            AstRelation::Int { id: 5 },
+        ]);
+        let deletion_set = HashSet::from([AstRelation::Var {
+            id: 3,
+            var_name: String::from("a"),
+        }]);
+        let summary = summarize_diff(&insertion_set, &deletion_set);
+        assert_eq!(summary.inserted.get("Var"), Some(&1));
+        assert_eq!(summary.inserted.get("Int"), Some(&2));
+        assert_eq!(summary.deleted.get("Var"), Some(&1));
+        assert_eq!(summary.deleted.get("Int"), None);
+        assert_eq!(
+            summary.to_string(),
+            "inserted 3 (Int: 2, Var: 1), deleted 1 (Var: 1)"
+        );
+    }
+    #[test]
+    fn sort_relations_is_stable() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example2.c"),
+            Backend::LangC,
+        );
+        let relations: Vec<AstRelation> =
+            ast.iter().map(|(_, relation)| relation.clone()).collect();
+        let forward: HashSet<AstRelation> = relations.iter().cloned().collect();
+        let backward: HashSet<AstRelation> = relations.iter().rev().cloned().collect();
+        let sorted = sort_relations(&forward);
+        assert_eq!(sorted, sort_relations(&backward));
+        assert_eq!(sorted, sort_relations(&get_initial_relation_set(&ast)));
+        assert_eq!(sorted, relations);
+    }
+    // Sharing one cache between many comparisons must give the same answers as comparing afresh.
+    #[test]
+    fn cached_match_agrees_with_uncached() {
+        let else_if_chain = |last: &str| {
+            let branches: Vec<String> = (0..8)
+                .map(|index| format!("if (a == {}) {{ b = {}; }}", index, index))
+                .collect();
+            format!(
+                "int main(void) {{ int a = 1; int b = 0; {} else {{ b = {}; }} return b; }}",
+                branches.join(" else "),
+                last
+            )
+        };
+        let ast1 = parser_interface::parse_source_into_ast(&else_if_chain("9")).unwrap();
+        let ast2 = parser_interface::parse_source_into_ast(&else_if_chain("a")).unwrap();
+        let statements = |ast: &Tree| -> Vec<AstRelation> {
+            ast.iter()
+                .map(|(_, relation)| relation.clone())
+                .filter(|relation| {
+                    matches!(
+                        relation,
+                        AstRelation::IfElse { .. } | AstRelation::Item { .. }
+                    )
+                })
+                .collect()
+        };
+        let mut cache = MatchCache::new();
+        for r1 in &statements(&ast1) {
+            for r2 in &statements(&ast2) {
+                assert_eq!(
+                    relations_match_cached(r1, r2, &ast1, &ast2, &mut cache),
+                    relations_match(r1, r2, &ast1, &ast2)
+                );
+            }
+        }
+    }
+    #[test]
+    fn subtree_hash_ignores_ids() {
+        let ast1 =
+            parser_interface::parse_source_into_ast("int main(void) { int a = 1; return a + 1; }")
+                .unwrap();
+        let ast2 = parser_interface::parse_source_into_ast(
+            "int f(void) { return 0; } int main(void) { int a = 1; return a - 1; }",
+        )
+        .unwrap();
+        let find = |ast: &Tree, kind: fn(&AstRelation) -> bool| -> Vec<ID> {
+            ast.iter()
+                .filter(|(_, relation)| kind(relation))
+                .map(|(id, _)| id)
+                .collect()
+        };
+        let assigns1 = find(&ast1, |r| matches!(r, AstRelation::Assign { .. }));
+        let assigns2 = find(&ast2, |r| matches!(r, AstRelation::Assign { .. }));
+        assert_ne!(assigns1[0], assigns2[0]);
+        assert!(ast1.subtree_hash(assigns1[0]).is_some());
+        assert_eq!(
+            ast1.subtree_hash(assigns1[0]),
+            ast2.subtree_hash(assigns2[0])
+        );
+        // Only the operator differs.
+        let ops1 = find(&ast1, |r| matches!(r, AstRelation::BinaryOp { .. }));
+        let ops2 = find(&ast2, |r| matches!(r, AstRelation::BinaryOp { .. }));
+        assert_ne!(ast1.subtree_hash(ops1[0]), ast2.subtree_hash(ops2[0]));
+        assert!(!relations_match(
+            &ast1.get_relation(ops1[0]),
+            &ast2.get_relation(ops2[0]),
+            &ast1,
+            &ast2
+        ));
+    }
+    #[test]
+    fn edit_script_for_rename_and_reorder() {
+        let prev_ast = parser_interface::parse_source_into_ast(
+            "int f(int a) { int x = 1; int y = 2; return x; }",
+        )
+        .unwrap();
+        let new_ast = parser_interface::parse_source_into_ast(
+            "int f(int b) { int y = 2; int x = 1; return x; }",
+        )
+        .unwrap();
+        let (edit_script, updated_tree) = get_edit_script(&prev_ast, &new_ast);
+        // The renamed argument keeps its ID.
+        assert!(edit_script.iter().any(|edit| matches!(
+            edit,
+            Edit::Update {
+                old: AstRelation::Arg { var_name: old_name, .. },
+                new: AstRelation::Arg { var_name: new_name, .. },
+            } if old_name == "a" && new_name == "b"
+        )));
+        // The item for x = 1 now follows the newly inserted item for y = 2.
+        let first_item_id = match prev_ast.get_relation(prev_ast.get_root()) {
+            AstRelation::TransUnit { body_ids, .. } => match prev_ast.get_relation(body_ids[0]) {
+                AstRelation::FunDef { body_id, .. } => match prev_ast.get_relation(body_id) {
+                    AstRelation::Compound { start_id, .. } => start_id,
+                    _ => panic!("Expected a compound"),
+                },
+                _ => panic!("Expected a function"),
+            },
+            _ => panic!("Expected a translation unit"),
+        };
+        let new_parent = edit_script
+            .iter()
+            .find_map(|edit| match edit {
+                Edit::Move { id, new_parent } if *id == first_item_id => Some(*new_parent),
+                _ => None,
+            })
+            .unwrap();
+        assert!(edit_script.iter().any(|edit| matches!(
+            edit,
+            Edit::Insert(AstRelation::Item { id, .. }) if *id == new_parent
+        )));
+        // Replaying the script on the previous relations gives the updated tree.
+        let mut relation_set = get_initial_relation_set(&prev_ast);
+        for edit in edit_script {
+            match edit {
+                Edit::Insert(relation) => assert!(relation_set.insert(relation)),
+                Edit::Delete(relation) => assert!(relation_set.remove(&relation)),
+                Edit::Update { old, new } => {
+                    assert!(relation_set.remove(&old));
+                    assert!(relation_set.insert(new));
+                }
+                Edit::Move { .. } => {}
+            }
+        }
+        assert_eq!(relation_set, get_initial_relation_set(&updated_tree));
+    }
+    #[test]
+    fn apply_edit_script_round_trip() {
+        let prev_ast = parser_interface::parse_source_into_ast(
+            "int f(int a) { int x = 1; { x = x + a; } return x; } int g(void) { return 0; }",
+        )
+        .unwrap();
+        let new_ast = parser_interface::parse_source_into_ast(
+            "int f(int b) { int x = 1; { x = x * b; x = x - 1; } return x; } int h(void) { return f(2); }",
+        )
+        .unwrap();
+        let (edit_script, updated_tree) = get_edit_script(&prev_ast, &new_ast);
+        let applied_tree = prev_ast.apply(&edit_script);
+        assert_eq!(applied_tree.validate(), Ok(()));
+        assert_eq!(
+            get_initial_relation_set(&applied_tree),
+            get_initial_relation_set(&updated_tree)
+        );
+        let (insertion_set, deletion_set, _) = get_diff_relation_set(&applied_tree, &new_ast);
+        assert!(insertion_set.is_empty());
+        assert!(deletion_set.is_empty());
+    }
+    // Nested calls are inserted children first, so their IDs have to come from the updated tree.
+    #[test]
+    fn diff_inserted_calls_get_fresh_ids() {
+        let prev_ast = parser_interface::parse_source_into_ast(
+            "int f(int a) { return a; } int main(void) { return 0; }",
+        )
+        .unwrap();
+        let new_ast = parser_interface::parse_source_into_ast(
+            "int f(int a) { return a; } int main(void) { return f(f(1)); }",
+        )
+        .unwrap();
+        let (insertion_set, _, updated_tree) = get_diff_relation_set(&prev_ast, &new_ast);
+        assert_eq!(updated_tree.validate(), Ok(()));
+        for relation in &insertion_set {
+            assert_eq!(
+                &updated_tree.get_relation(get_relation_id(relation)),
+                relation
+            );
+        }
+        assert_eq!(updated_tree.to_c_source(), new_ast.to_c_source());
+    }
+    #[test]
+    fn children_of_function() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int add(int a, int b) { return a + b; } int main(void) { return add(1, 2); }",
+        )
+        .unwrap();
+        let fun_id = ast.function_def_id("add").unwrap();
+        match ast.get_relation(fun_id) {
+            AstRelation::FunDef {
+                return_type_id,
+                arg_ids,
+                body_id,
+                ..
+            } => {
+                let mut expected = arg_ids.clone();
+                expected.push(return_type_id);
+                expected.push(body_id);
+                assert_eq!(ast.get_children(fun_id), expected);
+            }
+            _ => panic!("Expected a function definition"),
+        }
+    }
+    #[test]
+    fn diff_swapped_functions() {
+        let prev_ast = parser_interface::parse_source_into_ast(
+            "int f(void) { return 1; } int g(void) { return 2; }",
+        )
+        .unwrap();
+        let new_ast = parser_interface::parse_source_into_ast(
+            "int g(void) { return 2; } int f(void) { return 1; }",
+        )
+        .unwrap();
+        // Just the declaration list is replaced.
+        let (insertion_set, deletion_set, updated_tree) =
+            get_diff_relation_set(&prev_ast, &new_ast);
+        assert_eq!(insertion_set.len(), 1);
+        assert_eq!(deletion_set.len(), 1);
+        assert!(matches!(
+            sort_relations(&insertion_set)[0],
+            AstRelation::TransUnit { .. }
+        ));
+        assert_eq!(updated_tree.to_c_source(), new_ast.to_c_source());
+        // Diffing against the same file again changes nothing.
+        let (insertion_set, deletion_set, _) = get_diff_relation_set(&updated_tree, &new_ast);
+        assert!(insertion_set.is_empty());
+        assert!(deletion_set.is_empty());
+    }
+    #[test]
+    fn diff_fills_and_empties_body() {
+        let empty_ast =
+            parser_interface::parse_source_into_ast("void f(void) {} int main(void) { return 0; }")
+                .unwrap();
+        let filled_ast = parser_interface::parse_source_into_ast(
+            "void f(void) { int a = 1; } int main(void) { return 0; }",
+        )
+        .unwrap();
+        let (insertion_set, deletion_set, updated_tree) =
+            get_diff_relation_set(&empty_ast, &filled_ast);
+        assert!(deletion_set
+            .iter()
+            .any(|relation| matches!(relation, AstRelation::EmptyItem { .. })));
+        assert!(!insertion_set.is_empty());
+        assert_eq!(updated_tree.validate(), Ok(()));
+        assert_eq!(updated_tree.to_c_source(), filled_ast.to_c_source());
+        let (insertion_set, _, updated_tree) = get_diff_relation_set(&updated_tree, &empty_ast);
+        assert!(insertion_set
+            .iter()
+            .any(|relation| matches!(relation, AstRelation::EmptyItem { .. })));
+        assert_eq!(updated_tree.validate(), Ok(()));
+        assert_eq!(updated_tree.to_c_source(), empty_ast.to_c_source());
+    }
+    #[test]
+    fn diff_swapped_args() {
+        let prev_ast = parser_interface::parse_source_into_ast(
+            "int foo(int a, float b) { return a; } int main(void) { return foo(1, 2.5); }",
+        )
+        .unwrap();
+        let new_ast = parser_interface::parse_source_into_ast(
+            "int foo(float b, int a) { return a; } int main(void) { return foo(1, 2.5); }",
+        )
+        .unwrap();
+        let (insertion_set, deletion_set, updated_tree) =
+            get_diff_relation_set(&prev_ast, &new_ast);
+        // Both arguments are kept, only the order in the function changes.
+        assert_eq!(deletion_set.len(), 1);
+        assert_eq!(insertion_set.len(), 1);
+        assert!(deletion_set
+            .iter()
+            .chain(insertion_set.iter())
+            .all(|relation| matches!(relation, AstRelation::FunDef { .. })));
+        assert_eq!(updated_tree.validate(), Ok(()));
+        assert!(updated_tree.structurally_eq(&new_ast));
+        // A renamed argument is still matched by its position.
+        let renamed_ast = parser_interface::parse_source_into_ast(
+            "int foo(int a, float c) { return a; } int main(void) { return foo(1, 2.5); }",
+        )
+        .unwrap();
+        let (insertion_set, deletion_set, updated_tree) =
+            get_diff_relation_set(&prev_ast, &renamed_ast);
+        assert_eq!(deletion_set.len(), 1);
+        assert_eq!(insertion_set.len(), 1);
+        assert!(deletion_set
+            .iter()
+            .chain(insertion_set.iter())
+            .all(|relation| matches!(relation, AstRelation::Arg { .. })));
+        assert!(updated_tree.structurally_eq(&renamed_ast));
+    }
+    // The patched tree has to be the new program and the sets have to turn the old relations into its relations.
+    fn assert_diff_applies(prev_source: &str, new_source: &str) {
+        let prev_ast = parser_interface::parse_source_into_ast(prev_source).unwrap();
+        let new_ast = parser_interface::parse_source_into_ast(new_source).unwrap();
+        let (insertion_set, deletion_set, updated_tree) =
+            get_diff_relation_set(&prev_ast, &new_ast);
+        assert_eq!(updated_tree.validate(), Ok(()), "{}", new_source);
+        assert!(updated_tree.structurally_eq(&new_ast), "{}", new_source);
+        let mut relations = get_initial_relation_set(&prev_ast);
+        relations.retain(|relation| !deletion_set.contains(relation));
+        relations.extend(insertion_set);
+        assert_eq!(
+            relations,
+            get_initial_relation_set(&updated_tree),
+            "{}",
+            new_source
+        );
+    }
+    #[test]
+    fn diff_inserted_while_loop() {
+        let prev_source = "int main(void) { int x = 0; return x; }";
+        let new_source = "int main(void) { int x = 0; while (x < 3) { x = x + 1; } return x; }";
+        assert_diff_applies(prev_source, new_source);
+        let prev_ast = parser_interface::parse_source_into_ast(prev_source).unwrap();
+        let new_ast = parser_interface::parse_source_into_ast(new_source).unwrap();
+        let (insertion_set, _, _) = get_diff_relation_set(&prev_ast, &new_ast);
+        assert!(insertion_set
+            .iter()
+            .any(|relation| matches!(relation, AstRelation::While { .. })));
+        assert!(!insertion_set
+            .iter()
+            .any(|relation| matches!(relation, AstRelation::If { .. })));
+    }
+    #[test]
+    fn diff_changed_pointer_types() {
+        assert_diff_applies("int f(int a) { return 0; }", "int f(int *a) { return 0; }");
+        assert_diff_applies(
+            "int f(int *a) { return 0; }",
+            "int f(float *a) { return 0; }",
+        );
+        assert_diff_applies("int f(int **a) { return 0; }", "int f(int a) { return 0; }");
+        assert_diff_applies("int *f(void) { return 0; }", "float *f(void) { return 0; }");
+        assert_diff_applies("int f(void) { return 0; }", "int *f(void) { return 0; }");
+    }
+    #[test]
+    fn diff_changed_array_types() {
+        assert_diff_applies(
+            "int f(int a) { return 0; }",
+            "int f(int a[3]) { return 0; }",
+        );
+        assert_diff_applies(
+            "int f(int a[3]) { return 0; }",
+            "int f(float a[3]) { return 0; }",
+        );
+        assert_diff_applies(
+            "int f(int a[3]) { return 0; }",
+            "int f(int *a[3]) { return 0; }",
+        );
+    }
+    #[test]
+    fn diff_changed_struct_types() {
+        assert_diff_applies(
+            "struct S { int x; }; int f(struct S s) { return 0; }",
+            "struct S { int x; }; int f(struct S *s) { return 0; }",
+        );
+        assert_diff_applies(
+            "struct S { int x; }; struct T { int y; }; int f(struct S *s) { return 0; }",
+            "struct S { int x; }; struct T { int y; }; int f(struct T *s) { return 0; }",
+        );
+        assert_diff_applies(
+            "struct S { int x; }; int f(int s) { return 0; }",
+            "struct S { int x; }; int f(struct S s) { return 0; }",
+        );
+    }
+    #[test]
+    fn diff_changed_function_pointer_types() {
+        assert_diff_applies(
+            "int f(int (*g)(int)) { return 0; }",
+            "int f(int (*g)(float)) { return 0; }",
+        );
+        assert_diff_applies(
+            "int f(int (*g)(int)) { return 0; }",
+            "int f(float (*g)(int, int)) { return 0; }",
+        );
+        assert_diff_applies(
+            "int f(int g) { return 0; }",
+            "int f(int (*g)(int)) { return 0; }",
+        );
+    }
+    #[test]
+    fn diff_statement_added_to_nested_block() {
+        let prev_ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int a = 1; { a = a + 1; } return a; }",
+        )
+        .unwrap();
+        let new_ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int a = 1; { a = a + 1; a = a * 2; } return a; }",
+        )
+        .unwrap();
+        let (insertion_set, deletion_set, updated_tree) =
+            get_diff_relation_set(&prev_ast, &new_ast);
+        // Only the last item of the block changes, the block itself and the statements around it are kept.
+        let deletions = sort_relations(&deletion_set);
+        assert_eq!(deletions.len(), 1);
+        assert!(matches!(deletions[0], AstRelation::EndItem { .. }));
+        assert!(!insertion_set.iter().any(|relation| matches!(
+            relation,
+            AstRelation::Compound { .. } | AstRelation::Return { .. }
+        )));
+        assert!(insertion_set
+            .iter()
+            .any(|relation| matches!(relation, AstRelation::BinaryOp { op, .. } if op == "*")));
+        // Diffing again finds nothing left to do.
+        let (insertion_set, deletion_set, _) = get_diff_relation_set(&updated_tree, &new_ast);
+        assert!(insertion_set.is_empty());
+        assert!(deletion_set.is_empty());
+    }
+    #[test]
+    fn compact_renumbers_densely() {
+        let prev_ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int a = 1; a = a + 2; return a * 3; }",
+        )
+        .unwrap();
+        let new_ast =
+            parser_interface::parse_source_into_ast("int main(void) { int a = 1; return a - 4; }")
+                .unwrap();
+        let (_, _, mut tree) = get_diff_relation_set(&prev_ast, &new_ast);
+        let source = tree.to_c_source();
+        let size = tree.size();
+        eprintln!(
+            "{:?} {}",
+            tree.iter().map(|(id, _)| id).collect::<Vec<ID>>(),
+            size
+        );
+        // The diff leaves gaps and appends the new nodes at the end.
+        assert!(tree.iter().last().unwrap().0 as usize >= size);
+        let mapping = tree.compact();
+        assert_eq!(mapping.len(), size);
+        let ids: Vec<ID> = tree.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, (0..size as ID).collect::<Vec<ID>>());
+        for (id, relation) in tree.iter() {
+            assert_eq!(get_relation_id(relation), id);
+        }
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.to_c_source(), source);
+        assert!(tree.structurally_eq(&new_ast));
+    }
+    #[test]
+    fn relation_display_is_compact() {
+        let fun_def = AstRelation::FunDef {
+            id: 5,
+            fun_name: String::from("foo"),
+            return_type_id: 3,
+            arg_ids: vec![1, 2],
+            body_id: 7,
+        };
+        assert_eq!(
+            fun_def.to_string(),
+            "FunDef#5 \"foo\" -> 3 (args: 1,2) body: 7"
+        );
+        let op = AstRelation::BinaryOp {
+            id: 4,
+            op: String::from("<<"),
+            arg1_id: 2,
+            arg2_id: 3,
+        };
+        assert_eq!(op.to_string(), "BinaryOp#4 2 << 3");
+        assert_eq!(AstRelation::Int { id: 1 }.to_string(), "Int#1");
+    }
+    #[test]
+    fn relation_histogram_counts_kinds() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int a = 1; int b = a + 2; return b; }",
+        )
+        .unwrap();
+        let histogram = ast.relation_histogram();
+        assert_eq!(histogram["Assign"], 2);
+        assert_eq!(histogram["Item"], 2);
+        assert_eq!(histogram["EndItem"], 1);
+        assert_eq!(histogram.get("While"), None);
+        assert_eq!(histogram.values().sum::<usize>(), ast.size());
+    }
+    #[test]
+    fn function_names_in_source_order() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int square(int x) { return x * x; } int main(void) { return square(2); }",
+        )
+        .unwrap();
+        assert_eq!(ast.function_names(), vec!["square", "main"]);
+        let main_id = ast.function_def_id("main").unwrap();
+        assert!(
+            matches!(ast.get_relation(main_id), AstRelation::FunDef { fun_name, .. } if fun_name == "main")
+        );
+        assert_eq!(ast.function_def_id("missing"), None);
+    }
+    #[test]
+    fn subtree_of_function() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int square(int x) { return x * x; } int main(void) { int a = 2; return square(a); }",
+        )
+        .unwrap();
+        let main_id = ast.function_def_id("main").unwrap();
+        let subtree = ast.subtree(main_id);
+        assert_eq!(subtree.get_root(), main_id);
+        assert_eq!(subtree.size(), ast.walk(main_id).count());
+        assert!(subtree.size() < ast.size());
+        assert_eq!(subtree.validate(), Ok(()));
+        for (id, relation) in subtree.iter() {
+            assert_eq!(*relation, ast.get_relation(id));
+        }
+    }
+    #[test]
+    fn iter_in_id_order() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example2.c"),
+            Backend::LangC,
+        );
+        let ids: Vec<ID> = ast.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids.len(), ast.size());
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+    #[test]
+    fn walk_in_pretty_print_order() {
+        // Same recursion as pretty_print.
+        fn collect(node_id: ID, ast: &Tree, ids: &mut Vec<ID>) {
+            ids.push(node_id);
+            for child_id in ast.get_children(node_id) {
+                collect(child_id, ast, ids);
+            }
+        }
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example6.c"),
+            Backend::LangC,
+        );
+        let mut expected = vec![];
+        collect(ast.get_root(), &ast, &mut expected);
+        let walked: Vec<ID> = ast.walk(ast.get_root()).map(|(id, _)| id).collect();
+        assert_eq!(walked, expected);
+        assert_eq!(walked.len(), ast.size());
+    }
+    #[test]
+    fn validate_parsed_tree() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example6.c"),
+            Backend::LangC,
+        );
+        assert_eq!(ast.validate(), Ok(()));
+    }
+    #[test]
+    fn validate_dangling_child() {
+        let mut ast = Tree::new();
+        ast.add_node(0, AstRelation::Return { id: 0, expr_id: 7 });
+        ast.add_root_node(
+            1,
+            AstRelation::TransUnit {
+                id: 1,
+                body_ids: vec![0, 5],
+            },
+        );
+        ast.link_child(1, 0);
+        assert_eq!(ast.validate(), Err(vec![5, 7]));
+    }
+    #[test]
+    fn validate_deleted_child() {
+        let mut ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example6.c"),
+            Backend::LangC,
+        );
+        let fun_id = ast.get_children(ast.get_root())[0];
+        ast.delete_node(fun_id);
+        assert_eq!(ast.validate(), Err(vec![fun_id]));
+    }
+    #[test]
+    fn print_c_source() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int f(int a) { while (a > 0) { a = a - 1 * 2; } return a; }",
+        )
+        .unwrap();
+        assert_eq!(
+            ast.to_c_source(),
+            "int f(int a)\n{\n    while (a > 0)\n    {\n        a = a - (0 * 0);\n    }\n    return a;\n}\n"
+        );
+    }
+    #[test]
+    fn c_source_round_trip() {
+        let source = "struct Point { int x; int *y; };
+            int get(struct Point *p, int a[]) { return p->x + a[1]; }
+            float main(void) {
+                struct Point p; int b[4]; int i = 0;
+                p.y = &b[0];
+                while (i < 4) { b[i] = i << 1; i = i + 1; }
+                switch (i) { case 1: i = 2; break; default: i = get(&p, b); }
+                if (i > 2) { return (float)i; } else { return 1.5; }
+            }";
+        let ast = parser_interface::parse_source_into_ast(source).unwrap();
+        let printed_ast = parser_interface::parse_source_into_ast(&ast.to_c_source()).unwrap();
+        assert_eq!(
+            get_initial_relation_set(&ast),
+            get_initial_relation_set(&printed_ast)
+        );
+    }
+    #[test]
+    fn c_source_round_trip_without_braces() {
+        let source = "int main(void) { int i = 0; if (i > 2) return 1; while (i < 4) i = i + 1; \
+            if (i == 0) { return 0; } else if (i == 1) return 1; else if (i == 2) { return 2; } else return 3; }";
+        let ast = parser_interface::parse_source_into_ast(source).unwrap();
+        let printed = ast.to_c_source();
+        assert!(printed.contains("else if ("));
+        let printed_ast = parser_interface::parse_source_into_ast(&printed).unwrap();
+        assert_eq!(
+            get_initial_relation_set(&ast),
+            get_initial_relation_set(&printed_ast)
+        );
+    }
+    #[test]
+    fn c_source_round_trip_prefix_operand_of_postfix() {
+        let source = "struct S { int x; }; int main(void) { int i = 0; int b = 1; int *c = &b; int **p = &c; \
+            struct S a; struct S *s = &a; a.x = (*p)[i]; b = (*s).x; return (&a)->x; }";
+        let ast = parser_interface::parse_source_into_ast(source).unwrap();
+        let printed = ast.to_c_source();
+        assert!(printed.contains("(*p)[i]"));
+        assert!(printed.contains("(*s).x"));
+        assert!(printed.contains("(&a)->x"));
+        let printed_ast = parser_interface::parse_source_into_ast(&printed).unwrap();
+        assert_eq!(
+            get_initial_relation_set(&ast),
+            get_initial_relation_set(&printed_ast)
+        );
+    }
+    // The inserted call takes several new IDs before the item wrapping it is created.
+    #[test]
+    fn diff_inserted_call_gets_fresh_ids() {
+        let prev_ast = parser_interface::parse_source_into_ast(
+            "void foo(int a, int b) { return; } int main(void) { int a = 1; a = 2; return a; }",
+        )
+        .unwrap();
+        let new_ast = parser_interface::parse_source_into_ast(
+            "void foo(int a, int b) { return; } int main(void) { int a = 1; foo(a, 2); a = 2; return a; }",
+        )
+        .unwrap();
+        let (_, _, updated_ast) = get_diff_relation_set(&prev_ast, &new_ast);
+        assert_eq!(updated_ast.validate(), Ok(()));
+        assert_eq!(updated_ast.size(), new_ast.size());
+        assert_eq!(updated_ast.to_c_source(), new_ast.to_c_source());
+    }
+    // The re-inserted subtree would previously have taken exactly the IDs that were just freed.
+    #[test]
+    fn deleted_ids_are_not_reused() {
+        let new_ast =
+            parser_interface::parse_source_into_ast("int main(void) { return 1 + 2; }").unwrap();
+        let (return_id, _) = new_ast
+            .iter()
+            .find(|(_, relation)| matches!(relation, AstRelation::Return { .. }))
+            .unwrap();
+        let mut ast = new_ast.clone();
+        let (_, first_id) = insert_onwards(return_id, &mut ast, &new_ast);
+        let first_ids: HashSet<ID> = ast.walk(first_id).map(|(id, _)| id).collect();
+        delete_onwards(first_id, &mut ast);
+        let (_, second_id) = insert_onwards(return_id, &mut ast, &new_ast);
+        let second_ids: HashSet<ID> = ast.walk(second_id).map(|(id, _)| id).collect();
+        assert_eq!(first_ids.len(), second_ids.len());
+        assert!(first_ids.is_disjoint(&second_ids));
+        assert!(second_ids.iter().all(|id| *id >= new_ast.size() as ID));
+    }
+    #[test]
+    fn add_node_rejects_used_id() {
+        let result = std::panic::catch_unwind(|| {
+            let mut ast = Tree::new();
+            ast.add_node(0, AstRelation::Int { id: 0 });
+            ast.add_node(0, AstRelation::Float { id: 0 });
+        });
+        assert!(result.is_err());
+    }
     #[test]
-    fn insert_whole_tree() {}
+    fn tree_json_round_trip() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example2.c"),
+            Backend::LangC,
+        );
+        let json = ast.to_json();
+        assert_eq!(Tree::from_json(&json).unwrap(), ast);
+    }
 }
@@ -11,7 +11,86 @@ use type_checker_ddlog::Relations;
 use std::collections::HashSet;
 
 // Internal imports.
-use crate::definitions::AstRelation;
+use crate::ast;
+use crate::definitions::{AstRelation, InternalError};
+
+// How much the incremental type checker prints (relation updates are only shown when debugging).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OutputLevel {
+    Quiet,
+    Results,
+    Debug,
+}
+
+// Owns a running DDlog program so the (expensive) setup only happens once.
+// The last result is kept since DDlog only reports changes to it.
+#[derive(Debug)]
+pub struct TypeChecker {
+    hddlog: HDDlog,
+    result: bool,
+    output_level: OutputLevel,
+}
+
+impl TypeChecker {
+    pub fn new(output_level: OutputLevel) -> Self {
+        let (hddlog, _) = type_checker_ddlog::run(1, false).unwrap();
+        Self {
+            hddlog,
+            result: false,
+            output_level,
+        }
+    }
+
+    // Type-check a whole tree (the DDlog state is assumed to be empty).
+    pub fn check_initial(&mut self, tree: &ast::Tree) -> Result<bool, InternalError> {
+        let insert_set = ast::get_initial_relation_set(tree);
+        self.check_relations(insert_set, HashSet::new())
+    }
+
+    // Type-check the changes between the previously checked tree and the new one.
+    // Returns the result and the updated tree to diff against next time.
+    pub fn check_diff(
+        &mut self,
+        prev: &ast::Tree,
+        new: &ast::Tree,
+    ) -> Result<(bool, ast::Tree), InternalError> {
+        let (insert_set, delete_set, updated_tree) = ast::get_diff_relation_set(prev, new);
+        Ok((self.check_relations(insert_set, delete_set)?, updated_tree))
+    }
+
+    // On failure the previous result is kept (the DDlog state is left as it was).
+    pub fn check_relations(
+        &mut self,
+        insert_set: HashSet<AstRelation>,
+        delete_set: HashSet<AstRelation>,
+    ) -> Result<bool, InternalError> {
+        self.result = run_ddlog_type_checker(
+            &self.hddlog,
+            insert_set,
+            delete_set,
+            self.result,
+            self.output_level,
+        )?;
+        Ok(self.result)
+    }
+
+    pub fn check_batch(
+        &mut self,
+        update_sets: Vec<(HashSet<AstRelation>, HashSet<AstRelation>)>,
+    ) -> Result<bool, InternalError> {
+        self.result = run_ddlog_type_checker_batch(
+            &self.hddlog,
+            update_sets,
+            self.result,
+            self.output_level,
+        )?;
+        Ok(self.result)
+    }
+
+    pub fn result(&self) -> bool {
+        self.result
+    }
+}
 
 enum UpdateKind {
     InsertUpdate,
@@ -23,54 +102,93 @@ pub fn run_ddlog_type_checker(
     insert_set: HashSet<AstRelation>,
     delete_set: HashSet<AstRelation>,
     prev_result: bool,
-    disable_output: bool,
-) -> bool {
-    println!("Insertions:");
-    println!("{:?}", insert_set);
-    println!("Deletions:");
-    println!("{:?}", delete_set);
+    output_level: OutputLevel,
+) -> Result<bool, InternalError> {
+    if output_level >= OutputLevel::Debug {
+        println!("Insertions:");
+        println!("{:?}", ast::sort_relations(&insert_set));
+        println!("Deletions:");
+        println!("{:?}", ast::sort_relations(&delete_set));
+    }
     // Start transaction.
-    hddlog.transaction_start().unwrap();
-    // Updates.
-    let delete_updates = delete_set
-        .iter()
-        .map(|x| convert_relation(x, UpdateKind::DeleteUpdate));
     hddlog
-        .apply_updates(&mut delete_updates.into_iter())
-        .unwrap();
-    let insert_updates = insert_set
-        .iter()
-        .map(|x| convert_relation(x, UpdateKind::InsertUpdate));
-    hddlog
-        .apply_updates(&mut insert_updates.into_iter())
-        .unwrap();
-    // See result.
+        .transaction_start()
+        .map_err(InternalError::DDlogError)?;
+    // Updates and result (the transaction is rolled back if DDlog rejects them).
     // Comment/uncomment dump delta debug statement.
-    let mut delta = hddlog.transaction_commit_dump_changes().unwrap();
+    let mut delta = apply_update_sets(hddlog, &insert_set, &delete_set)
+        .and_then(|_| hddlog.transaction_commit_dump_changes())
+        .map_err(|error| rollback(hddlog, error))?;
     // dump_delta(&delta);
+    Ok(get_result(&mut delta, prev_result, output_level))
+}
+
+// Applies the updates of several diffs (e.g. one per file) in a single transaction.
+// Each diff still has its deletions applied before its insertions.
+pub fn run_ddlog_type_checker_batch(
+    hddlog: &HDDlog,
+    update_sets: Vec<(HashSet<AstRelation>, HashSet<AstRelation>)>,
+    prev_result: bool,
+    output_level: OutputLevel,
+) -> Result<bool, InternalError> {
+    if output_level >= OutputLevel::Debug {
+        for (insert_set, delete_set) in &update_sets {
+            println!("Insertions:");
+            println!("{:?}", ast::sort_relations(insert_set));
+            println!("Deletions:");
+            println!("{:?}", ast::sort_relations(delete_set));
+        }
+    }
+    hddlog
+        .transaction_start()
+        .map_err(InternalError::DDlogError)?;
+    let mut delta = update_sets
+        .iter()
+        .try_for_each(|(insert_set, delete_set)| apply_update_sets(hddlog, insert_set, delete_set))
+        .and_then(|_| hddlog.transaction_commit_dump_changes())
+        .map_err(|error| rollback(hddlog, error))?;
+    Ok(get_result(&mut delta, prev_result, output_level))
+}
+
+// Abandons the open transaction so a failed run leaves the DDlog state unchanged.
+fn rollback(hddlog: &HDDlog, error: String) -> InternalError {
+    // The original error is the one worth reporting (not a follow-up one from the rollback).
+    let _ = hddlog.transaction_rollback();
+    InternalError::DDlogError(error)
+}
+
+fn apply_update_sets(
+    hddlog: &HDDlog,
+    insert_set: &HashSet<AstRelation>,
+    delete_set: &HashSet<AstRelation>,
+) -> Result<(), String> {
+    // Sorted so the same diff always produces the same update sequence.
+    let delete_updates = ast::sort_relations(delete_set)
+        .into_iter()
+        .map(|x| convert_relation(&x, UpdateKind::DeleteUpdate));
+    hddlog.apply_updates(&mut delete_updates.into_iter())?;
+    let insert_updates = ast::sort_relations(insert_set)
+        .into_iter()
+        .map(|x| convert_relation(&x, UpdateKind::InsertUpdate));
+    hddlog.apply_updates(&mut insert_updates.into_iter())
+}
+
+fn get_result(delta: &mut DeltaMap<DDValue>, prev_result: bool, output_level: OutputLevel) -> bool {
     let ok_program = delta.get_rel(Relations::OkProgram as RelId);
-    let mut new_result = false;
-    if !disable_output {
-        if prev_result {
-            for (_, weight) in ok_program.iter() {
-                if *weight == -1 {
-                    println!("Program typing error ❌");
-                }
-            }
-            if ok_program.len() == 0 {
-                println!("Program correctly typed ✅");
-                new_result = true;
-            }
+    // The OkProgram relation only shows up in the delta if the result changed.
+    let mut new_result = prev_result;
+    for (_, weight) in ok_program.iter() {
+        if *weight == 1 {
+            new_result = true;
+        } else if *weight == -1 {
+            new_result = false;
+        }
+    }
+    if output_level >= OutputLevel::Results {
+        if new_result {
+            println!("Program correctly typed ✅");
         } else {
-            for (_, weight) in ok_program.iter() {
-                if *weight == 1 {
-                    println!("Program correctly typed ✅");
-                    new_result = true;
-                }
-            }
-            if ok_program.len() == 0 {
-                println!("Program typing error ❌");
-            }
+            println!("Program typing error ❌");
         }
     }
     new_result
@@ -95,6 +213,29 @@ fn convert_relation(ast_relation: &AstRelation, update_type: UpdateKind) -> Upda
     }
 }
 
+// Writes a diff as commands for the DDlog CLI (to replay it directly against the compiled program).
+// Same order as when it is applied: deletions first, each sorted.
+// The updates form a single command like in the .dat files (separated by commas, ended by a semicolon).
+pub fn dump_updates_as_ddlog_commands(
+    insert_set: &HashSet<AstRelation>,
+    delete_set: &HashSet<AstRelation>,
+) -> String {
+    let mut updates = vec![];
+    for relation in ast::sort_relations(delete_set) {
+        updates.push(format!("delete {}", relation.ddlog_record()));
+    }
+    for relation in ast::sort_relations(insert_set) {
+        updates.push(format!("insert {}", relation.ddlog_record()));
+    }
+    let mut commands = String::from("start;\n");
+    if !updates.is_empty() {
+        commands.push_str(&updates.join(",\n"));
+        commands.push_str(";\n");
+    }
+    commands.push_str("commit;\n");
+    commands
+}
+
 // See relation changes (for debugging purposes).
 #[allow(dead_code)]
 fn dump_delta(delta: &DeltaMap<DDValue>) {
@@ -139,6 +280,32 @@ fn get_equiv_ddvalue(ast_relation: &AstRelation) -> DDValue {
             }
             .into_ddvalue()
         }
+        AstRelation::StructDef {
+            id,
+            name,
+            field_ids,
+        } => {
+            let mut converted_field_ids: DDlogVec<i32> = DDlogVec::new();
+            for vec_id in field_ids {
+                converted_field_ids.push(vec_id);
+            }
+            StructDef {
+                id,
+                name,
+                field_ids: converted_field_ids,
+            }
+            .into_ddvalue()
+        }
+        AstRelation::Field {
+            id,
+            field_name,
+            type_id,
+        } => Field {
+            id,
+            field_name,
+            type_id,
+        }
+        .into_ddvalue(),
         AstRelation::FunCall {
             id,
             fun_name,
@@ -167,6 +334,16 @@ fn get_equiv_ddvalue(ast_relation: &AstRelation) -> DDValue {
             expr_id,
         }
         .into_ddvalue(),
+        AstRelation::Decl {
+            id,
+            var_name,
+            type_id,
+        } => Decl {
+            id,
+            var_name,
+            type_id,
+        }
+        .into_ddvalue(),
         AstRelation::Return { id, expr_id } => Return { id, expr_id }.into_ddvalue(),
         AstRelation::If {
             id,
@@ -200,6 +377,37 @@ fn get_equiv_ddvalue(ast_relation: &AstRelation) -> DDValue {
             body_id,
         }
         .into_ddvalue(),
+        AstRelation::Break { id } => Break { id }.into_ddvalue(),
+        AstRelation::Continue { id } => Continue { id }.into_ddvalue(),
+        AstRelation::Label { id, name, stmt_id } => Label { id, name, stmt_id }.into_ddvalue(),
+        AstRelation::Goto { id, label_name } => Goto { id, label_name }.into_ddvalue(),
+        AstRelation::Switch {
+            id,
+            expr_id,
+            case_ids,
+        } => {
+            let mut converted_case_ids: DDlogVec<i32> = DDlogVec::new();
+            for vec_id in case_ids {
+                converted_case_ids.push(vec_id);
+            }
+            Switch {
+                id,
+                expr_id,
+                case_ids: converted_case_ids,
+            }
+            .into_ddvalue()
+        }
+        AstRelation::Case {
+            id,
+            label_id,
+            body_id,
+        } => Case {
+            id,
+            label_id,
+            body_id,
+        }
+        .into_ddvalue(),
+        AstRelation::DefaultCase { id, body_id } => DefaultCase { id, body_id }.into_ddvalue(),
         AstRelation::Compound { id, start_id } => Compound { id, start_id }.into_ddvalue(),
         AstRelation::Item {
             id,
@@ -212,16 +420,78 @@ fn get_equiv_ddvalue(ast_relation: &AstRelation) -> DDValue {
         }
         .into_ddvalue(),
         AstRelation::EndItem { id, stmt_id } => EndItem { id, stmt_id }.into_ddvalue(),
+        AstRelation::EmptyItem { id } => EmptyItem { id }.into_ddvalue(),
         AstRelation::BinaryOp {
             id,
+            op,
             arg1_id,
             arg2_id,
         } => BinaryOp {
             id,
+            op,
             arg1_id,
             arg2_id,
         }
         .into_ddvalue(),
+        AstRelation::UnaryOp { id, op, expr_id } => UnaryOp { id, op, expr_id }.into_ddvalue(),
+        AstRelation::Index {
+            id,
+            array_id,
+            index_id,
+        } => Index {
+            id,
+            array_id,
+            index_id,
+        }
+        .into_ddvalue(),
+        AstRelation::ArrayInit { id, elem_ids } => {
+            let mut converted_elem_ids: DDlogVec<i32> = DDlogVec::new();
+            for vec_id in elem_ids {
+                converted_elem_ids.push(vec_id);
+            }
+            ArrayInit {
+                id,
+                elem_ids: converted_elem_ids,
+            }
+            .into_ddvalue()
+        }
+        AstRelation::Deref { id, expr_id } => Deref { id, expr_id }.into_ddvalue(),
+        AstRelation::Cast {
+            id,
+            target_type_id,
+            expr_id,
+        } => Cast {
+            id,
+            target_type_id,
+            expr_id,
+        }
+        .into_ddvalue(),
+        AstRelation::AddressOf { id, expr_id } => AddressOf { id, expr_id }.into_ddvalue(),
+        AstRelation::SizeOf { id, operand_id } => SizeOf { id, operand_id }.into_ddvalue(),
+        AstRelation::Conditional {
+            id,
+            cond_id,
+            then_id,
+            else_id,
+        } => Conditional {
+            id,
+            cond_id,
+            then_id,
+            else_id,
+        }
+        .into_ddvalue(),
+        AstRelation::Member {
+            id,
+            base_id,
+            field_name,
+            arrow,
+        } => Member {
+            id,
+            base_id,
+            field_name,
+            arrow,
+        }
+        .into_ddvalue(),
         AstRelation::Var { id, var_name } => Var { id, var_name }.into_ddvalue(),
         AstRelation::Arg {
             id,
@@ -233,19 +503,65 @@ fn get_equiv_ddvalue(ast_relation: &AstRelation) -> DDValue {
             type_id,
         }
         .into_ddvalue(),
+        AstRelation::Ptr {
+            id,
+            pointee_type_id,
+        } => Ptr {
+            id,
+            pointee_type_id,
+        }
+        .into_ddvalue(),
+        AstRelation::Array {
+            id,
+            elem_type_id,
+            size,
+        } => Array {
+            id,
+            elem_type_id,
+            size,
+        }
+        .into_ddvalue(),
+        AstRelation::FunPtr {
+            id,
+            return_type_id,
+            arg_type_ids,
+        } => {
+            let mut converted_arg_type_ids: DDlogVec<i32> = DDlogVec::new();
+            for vec_id in arg_type_ids {
+                converted_arg_type_ids.push(vec_id);
+            }
+            FunPtr {
+                id,
+                return_type_id,
+                arg_type_ids: converted_arg_type_ids,
+            }
+            .into_ddvalue()
+        }
+        AstRelation::Struct { id, name } => Struct { id, name }.into_ddvalue(),
         AstRelation::Void { id } => Void { id }.into_ddvalue(),
         AstRelation::Int { id } => Int { id }.into_ddvalue(),
         AstRelation::Float { id } => Float { id }.into_ddvalue(),
+        AstRelation::Double { id } => Double { id }.into_ddvalue(),
         AstRelation::Char { id } => Char { id }.into_ddvalue(),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::ast;
+    use crate::crate_path;
     use crate::ddlog_interface::get_equiv_ddvalue;
     use crate::ddlog_interface::EquivRelId;
-    use crate::definitions::AstRelation;
+    use crate::ddlog_interface::{
+        apply_update_sets, dump_updates_as_ddlog_commands, rollback, run_ddlog_type_checker,
+        run_ddlog_type_checker_batch, OutputLevel, TypeChecker,
+    };
+    use crate::definitions::{AstRelation, InternalError};
+    use crate::parser_interface;
     use differential_datalog::ddval::DDValConvert;
+    use differential_datalog::program::{RelId, Update};
+    use differential_datalog::{DDlog, DDlogDynamic};
+    use std::collections::HashSet;
     use type_checker_ddlog::typedefs::ddlog_std::Vec as DDlogVec;
     use type_checker_ddlog::typedefs::*;
     use type_checker_ddlog::Relations;
@@ -286,4 +602,183 @@ mod tests {
         .into_ddvalue();
         assert_eq!(converted_int_relation, expected);
     }
+
+    #[test]
+    fn dump_diff_as_commands() {
+        let prev_ast =
+            parser_interface::parse_source_into_ast("int main(void) { return 1; }").unwrap();
+        let new_ast =
+            parser_interface::parse_source_into_ast("int main(void) { return 1 + 2; }").unwrap();
+        let (insert_set, delete_set, _) = ast::get_diff_relation_set(&prev_ast, &new_ast);
+        assert_eq!(
+            dump_updates_as_ddlog_commands(&insert_set, &delete_set),
+            "start;\n\
+             delete EndItem(3, 2),\n\
+             insert EndItem(3, 10),\n\
+             insert Int(7),\n\
+             insert Int(8),\n\
+             insert BinaryOp(9, \"+\", 7, 8),\n\
+             insert Return(10, 9);\n\
+             commit;\n"
+        );
+        assert_eq!(
+            dump_updates_as_ddlog_commands(&HashSet::new(), &HashSet::new()),
+            "start;\ncommit;\n"
+        );
+    }
+
+    // Batch test (an initial check followed by a change in the same transaction).
+    #[test]
+    fn batch_matches_separate_runs() {
+        let initial_ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example2.c"),
+            parser_interface::Backend::LangC,
+        );
+        let modified_ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example3.c"),
+            parser_interface::Backend::LangC,
+        );
+        let initial_set = ast::get_initial_relation_set(&initial_ast);
+        let (insert_set, delete_set, _) = ast::get_diff_relation_set(&initial_ast, &modified_ast);
+        let (hddlog, _) = type_checker_ddlog::run(1, false).unwrap();
+        let initial_result = run_ddlog_type_checker(
+            &hddlog,
+            initial_set.clone(),
+            HashSet::new(),
+            false,
+            OutputLevel::Quiet,
+        )
+        .unwrap();
+        let separate_result = run_ddlog_type_checker(
+            &hddlog,
+            insert_set.clone(),
+            delete_set.clone(),
+            initial_result,
+            OutputLevel::Quiet,
+        )
+        .unwrap();
+        let (batch_hddlog, _) = type_checker_ddlog::run(1, false).unwrap();
+        let batch_result = run_ddlog_type_checker_batch(
+            &batch_hddlog,
+            vec![(initial_set, HashSet::new()), (insert_set, delete_set)],
+            false,
+            OutputLevel::Quiet,
+        )
+        .unwrap();
+        assert_eq!(batch_result, separate_result);
+        assert_eq!(batch_result, false);
+    }
+
+    #[test]
+    fn reuse_type_checker() {
+        let initial_ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example2.c"),
+            parser_interface::Backend::LangC,
+        );
+        let modified_ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example3.c"),
+            parser_interface::Backend::LangC,
+        );
+        let mut checker = TypeChecker::new(OutputLevel::Quiet);
+        assert_eq!(checker.check_initial(&initial_ast), Ok(true));
+        let (result, updated_ast) = checker.check_diff(&initial_ast, &modified_ast).unwrap();
+        assert_eq!(result, false);
+        // Going back gives the original result again.
+        let (result, _) = checker.check_diff(&updated_ast, &initial_ast).unwrap();
+        assert_eq!(result, true);
+        assert_eq!(checker.result(), true);
+    }
+
+    #[test]
+    fn mismatched_assignment_is_flagged_incrementally() {
+        let initial_ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int x = 0; x = 1; return x; }",
+        )
+        .unwrap();
+        let modified_ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int x = 0; x = 1.0; return x; }",
+        )
+        .unwrap();
+        let mut checker = TypeChecker::new(OutputLevel::Quiet);
+        assert_eq!(checker.check_initial(&initial_ast), Ok(true));
+        let (result, updated_ast) = checker.check_diff(&initial_ast, &modified_ast).unwrap();
+        assert_eq!(result, false);
+        let (result, _) = checker.check_diff(&updated_ast, &initial_ast).unwrap();
+        assert_eq!(result, true);
+    }
+
+    #[test]
+    fn rejected_update_is_an_error() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example2.c"),
+            parser_interface::Backend::LangC,
+        );
+        let (hddlog, _) = type_checker_ddlog::run(1, false).unwrap();
+        // DDlog refuses to start a second transaction while one is still open.
+        hddlog.transaction_start().unwrap();
+        let result = run_ddlog_type_checker(
+            &hddlog,
+            ast::get_initial_relation_set(&ast),
+            HashSet::new(),
+            false,
+            OutputLevel::Quiet,
+        );
+        assert!(matches!(result, Err(InternalError::DDlogError(_))));
+        // The open transaction is untouched, so it can still be committed.
+        hddlog.transaction_commit().unwrap();
+    }
+
+    #[test]
+    fn failed_updates_are_rolled_back() {
+        let initial_ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example2.c"),
+            parser_interface::Backend::LangC,
+        );
+        let modified_ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example3.c"),
+            parser_interface::Backend::LangC,
+        );
+        let (insert_set, delete_set, _) = ast::get_diff_relation_set(&initial_ast, &modified_ast);
+        let (hddlog, _) = type_checker_ddlog::run(1, false).unwrap();
+        let initial_result = run_ddlog_type_checker(
+            &hddlog,
+            ast::get_initial_relation_set(&initial_ast),
+            HashSet::new(),
+            false,
+            OutputLevel::Quiet,
+        );
+        assert_eq!(initial_result, Ok(true));
+        // The diff (which introduces a type error) is applied first, then DDlog rejects
+        // the update to an output relation.
+        hddlog.transaction_start().unwrap();
+        let malformed_update = Update::Insert {
+            relid: Relations::OkProgram as RelId,
+            v: OkProgram { id: 0 }.into_ddvalue(),
+        };
+        let result = apply_update_sets(&hddlog, &insert_set, &delete_set)
+            .and_then(|_| hddlog.apply_updates(&mut vec![malformed_update].into_iter()))
+            .map_err(|error| rollback(&hddlog, error));
+        assert!(matches!(result, Err(InternalError::DDlogError(_))));
+        // Nothing from the rejected transaction is left, so an empty check doesn't change the result.
+        let result = run_ddlog_type_checker(
+            &hddlog,
+            HashSet::new(),
+            HashSet::new(),
+            true,
+            OutputLevel::Quiet,
+        );
+        assert_eq!(result, Ok(true));
+        // Later checks still work.
+        let result = run_ddlog_type_checker(
+            &hddlog,
+            insert_set.clone(),
+            delete_set.clone(),
+            true,
+            OutputLevel::Quiet,
+        );
+        assert_eq!(result, Ok(false));
+        let result =
+            run_ddlog_type_checker(&hddlog, delete_set, insert_set, false, OutputLevel::Quiet);
+        assert_eq!(result, Ok(true));
+    }
 }
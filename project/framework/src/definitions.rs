@@ -1,14 +1,36 @@
-use crate::ddlog_interface;
+use crate::ast::Location;
+#[cfg(feature = "ddlog")]
+use crate::ddlog_interface::EquivRelId;
+use convert_variant_derive::DDlogRecord;
+#[cfg(feature = "ddlog")]
 use convert_variant_derive::EquivRelId;
-use ddlog_interface::EquivRelId;
+use convert_variant_derive::VariantName;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
 // use type_checker_ddlog::typedefs::*;
+#[cfg(feature = "ddlog")]
 use type_checker_ddlog::Relations;
 
 // Type aliases for consistency and easy changes.
 pub type ID = i32;
 
 // Defines the permitted language constructs.
-#[derive(Debug, EquivRelId, Clone, PartialEq, Eq, Hash)]
+// The mapping to DDlog relations only exists when the incremental type checker is built.
+#[derive(
+    Debug,
+    VariantName,
+    DDlogRecord,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+)]
+#[cfg_attr(feature = "ddlog", derive(EquivRelId))]
 //#[derive(EquivDDValue)]
 pub enum AstRelation {
     TransUnit {
@@ -23,6 +45,16 @@ pub enum AstRelation {
         arg_ids: Vec<ID>,
         body_id: ID,
     },
+    StructDef {
+        id: ID,
+        name: String,
+        field_ids: Vec<ID>,
+    },
+    Field {
+        id: ID,
+        field_name: String,
+        type_id: ID,
+    },
     // Statements.
     FunCall {
         id: ID,
@@ -35,6 +67,12 @@ pub enum AstRelation {
         type_id: ID,
         expr_id: ID,
     },
+    // Declaration without an initializer.
+    Decl {
+        id: ID,
+        var_name: String,
+        type_id: ID,
+    },
     Return {
         id: ID,
         expr_id: ID,
@@ -55,6 +93,37 @@ pub enum AstRelation {
         cond_id: ID,
         body_id: ID,
     },
+    Break {
+        id: ID,
+    },
+    Continue {
+        id: ID,
+    },
+    // Labels are only there as goto targets.
+    Label {
+        id: ID,
+        name: String,
+        stmt_id: ID,
+    },
+    Goto {
+        id: ID,
+        label_name: String,
+    },
+    // Each case body is an independent compound (fall-through is ignored).
+    Switch {
+        id: ID,
+        expr_id: ID,
+        case_ids: Vec<ID>,
+    },
+    Case {
+        id: ID,
+        label_id: ID,
+        body_id: ID,
+    },
+    DefaultCase {
+        id: ID,
+        body_id: ID,
+    },
     // Items in compound to represent a sequence of statements.
     Compound {
         id: ID,
@@ -69,12 +138,66 @@ pub enum AstRelation {
         id: ID,
         stmt_id: ID,
     },
+    // The start of an empty compound (there are no statements to link).
+    EmptyItem {
+        id: ID,
+    },
     // Expressions.
+    // Operators are kept as their C token (e.g. "+" or "<<").
     BinaryOp {
         id: ID,
+        op: String,
         arg1_id: ID,
         arg2_id: ID,
     },
+    // Only logical negation ("!") for now, the pointer operators have their own relations.
+    UnaryOp {
+        id: ID,
+        op: String,
+        expr_id: ID,
+    },
+    Index {
+        id: ID,
+        array_id: ID,
+        index_id: ID,
+    },
+    // Brace-enclosed initializer list, only used as the value of an array declaration.
+    ArrayInit {
+        id: ID,
+        elem_ids: Vec<ID>,
+    },
+    Deref {
+        id: ID,
+        expr_id: ID,
+    },
+    AddressOf {
+        id: ID,
+        expr_id: ID,
+    },
+    Cast {
+        id: ID,
+        target_type_id: ID,
+        expr_id: ID,
+    },
+    // The operand is either a type or an expression (which is typed but never evaluated).
+    SizeOf {
+        id: ID,
+        operand_id: ID,
+    },
+    // cond ? then : else (only one of the arms is evaluated).
+    Conditional {
+        id: ID,
+        cond_id: ID,
+        then_id: ID,
+        else_id: ID,
+    },
+    // Field access (arrow is set for p->field, i.e. the base is a pointer).
+    Member {
+        id: ID,
+        base_id: ID,
+        field_name: String,
+        arrow: bool,
+    },
     // Values.
     Var {
         id: ID,
@@ -85,6 +208,28 @@ pub enum AstRelation {
         var_name: String,
         type_id: ID,
     },
+    // Derived types.
+    Ptr {
+        id: ID,
+        pointee_type_id: ID,
+    },
+    // Size is 0 if it isn't known (e.g. array parameters).
+    Array {
+        id: ID,
+        elem_type_id: ID,
+        size: i32,
+    },
+    // Pointer to a function with the given signature (e.g. int (*fp)(int)).
+    FunPtr {
+        id: ID,
+        return_type_id: ID,
+        arg_type_ids: Vec<ID>,
+    },
+    // Struct types are referred to by name (fields come from the definition).
+    Struct {
+        id: ID,
+        name: String,
+    },
     // Leaf types.
     Void {
         id: ID,
@@ -95,7 +240,314 @@ pub enum AstRelation {
     Float {
         id: ID,
     },
+    Double {
+        id: ID,
+    },
     Char {
         id: ID,
     },
 }
+
+// Compact form for debugging (e.g. FunDef#5 "foo" -> 3 (args: 1,2) body: 7), other relations are referred to by ID.
+impl fmt::Display for AstRelation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |ids: &Vec<ID>| {
+            ids.iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        };
+        match self {
+            AstRelation::TransUnit { id, body_ids } => {
+                write!(f, "TransUnit#{} (body: {})", id, join(body_ids))
+            }
+            AstRelation::FunDef {
+                id,
+                fun_name,
+                return_type_id,
+                arg_ids,
+                body_id,
+            } => write!(
+                f,
+                "FunDef#{} \"{}\" -> {} (args: {}) body: {}",
+                id,
+                fun_name,
+                return_type_id,
+                join(arg_ids),
+                body_id
+            ),
+            AstRelation::StructDef {
+                id,
+                name,
+                field_ids,
+            } => write!(
+                f,
+                "StructDef#{} \"{}\" (fields: {})",
+                id,
+                name,
+                join(field_ids)
+            ),
+            AstRelation::Field {
+                id,
+                field_name,
+                type_id,
+            } => write!(f, "Field#{} \"{}\": {}", id, field_name, type_id),
+            AstRelation::FunCall {
+                id,
+                fun_name,
+                arg_ids,
+            } => write!(
+                f,
+                "FunCall#{} \"{}\" (args: {})",
+                id,
+                fun_name,
+                join(arg_ids)
+            ),
+            AstRelation::Assign {
+                id,
+                var_name,
+                type_id,
+                expr_id,
+            } => write!(
+                f,
+                "Assign#{} \"{}\": {} = {}",
+                id, var_name, type_id, expr_id
+            ),
+            AstRelation::Decl {
+                id,
+                var_name,
+                type_id,
+            } => write!(f, "Decl#{} \"{}\": {}", id, var_name, type_id),
+            AstRelation::Return { id, expr_id } => write!(f, "Return#{} {}", id, expr_id),
+            AstRelation::If {
+                id,
+                cond_id,
+                then_id,
+            } => write!(f, "If#{} {} then: {}", id, cond_id, then_id),
+            AstRelation::IfElse {
+                id,
+                cond_id,
+                then_id,
+                else_id,
+            } => write!(
+                f,
+                "IfElse#{} {} then: {} else: {}",
+                id, cond_id, then_id, else_id
+            ),
+            AstRelation::While {
+                id,
+                cond_id,
+                body_id,
+            } => write!(f, "While#{} {} body: {}", id, cond_id, body_id),
+            AstRelation::Break { id } => write!(f, "Break#{}", id),
+            AstRelation::Continue { id } => write!(f, "Continue#{}", id),
+            AstRelation::Label { id, name, stmt_id } => {
+                write!(f, "Label#{} \"{}\": {}", id, name, stmt_id)
+            }
+            AstRelation::Goto { id, label_name } => write!(f, "Goto#{} \"{}\"", id, label_name),
+            AstRelation::Switch {
+                id,
+                expr_id,
+                case_ids,
+            } => write!(f, "Switch#{} {} (cases: {})", id, expr_id, join(case_ids)),
+            AstRelation::Case {
+                id,
+                label_id,
+                body_id,
+            } => write!(f, "Case#{} {} body: {}", id, label_id, body_id),
+            AstRelation::DefaultCase { id, body_id } => {
+                write!(f, "DefaultCase#{} body: {}", id, body_id)
+            }
+            AstRelation::Compound { id, start_id } => {
+                write!(f, "Compound#{} start: {}", id, start_id)
+            }
+            AstRelation::Item {
+                id,
+                stmt_id,
+                next_stmt_id,
+            } => write!(f, "Item#{} {} next: {}", id, stmt_id, next_stmt_id),
+            AstRelation::EndItem { id, stmt_id } => write!(f, "EndItem#{} {}", id, stmt_id),
+            AstRelation::EmptyItem { id } => write!(f, "EmptyItem#{}", id),
+            AstRelation::BinaryOp {
+                id,
+                op,
+                arg1_id,
+                arg2_id,
+            } => write!(f, "BinaryOp#{} {} {} {}", id, arg1_id, op, arg2_id),
+            AstRelation::Index {
+                id,
+                array_id,
+                index_id,
+            } => write!(f, "Index#{} {}[{}]", id, array_id, index_id),
+            AstRelation::ArrayInit { id, elem_ids } => {
+                write!(f, "ArrayInit#{} {{{}}}", id, join(elem_ids))
+            }
+            AstRelation::UnaryOp { id, op, expr_id } => {
+                write!(f, "UnaryOp#{} {}{}", id, op, expr_id)
+            }
+            AstRelation::Deref { id, expr_id } => write!(f, "Deref#{} *{}", id, expr_id),
+            AstRelation::AddressOf { id, expr_id } => write!(f, "AddressOf#{} &{}", id, expr_id),
+            AstRelation::Cast {
+                id,
+                target_type_id,
+                expr_id,
+            } => write!(f, "Cast#{} ({}) {}", id, target_type_id, expr_id),
+            AstRelation::SizeOf { id, operand_id } => {
+                write!(f, "SizeOf#{} {}", id, operand_id)
+            }
+            AstRelation::Conditional {
+                id,
+                cond_id,
+                then_id,
+                else_id,
+            } => write!(
+                f,
+                "Conditional#{} {} ? {} : {}",
+                id, cond_id, then_id, else_id
+            ),
+            AstRelation::Member {
+                id,
+                base_id,
+                field_name,
+                arrow,
+            } => write!(
+                f,
+                "Member#{} {}{}\"{}\"",
+                id,
+                base_id,
+                if *arrow { "->" } else { "." },
+                field_name
+            ),
+            AstRelation::Var { id, var_name } => write!(f, "Var#{} \"{}\"", id, var_name),
+            AstRelation::Arg {
+                id,
+                var_name,
+                type_id,
+            } => write!(f, "Arg#{} \"{}\": {}", id, var_name, type_id),
+            AstRelation::Ptr {
+                id,
+                pointee_type_id,
+            } => write!(f, "Ptr#{} *{}", id, pointee_type_id),
+            AstRelation::Array {
+                id,
+                elem_type_id,
+                size,
+            } => write!(f, "Array#{} {}[{}]", id, elem_type_id, size),
+            AstRelation::FunPtr {
+                id,
+                return_type_id,
+                arg_type_ids,
+            } => write!(
+                f,
+                "FunPtr#{} {} (args: {})",
+                id,
+                return_type_id,
+                join(arg_type_ids)
+            ),
+            AstRelation::Struct { id, name } => write!(f, "Struct#{} \"{}\"", id, name),
+            AstRelation::Void { id } => write!(f, "Void#{}", id),
+            AstRelation::Int { id } => write!(f, "Int#{}", id),
+            AstRelation::Float { id } => write!(f, "Float#{}", id),
+            AstRelation::Double { id } => write!(f, "Double#{}", id),
+            AstRelation::Char { id } => write!(f, "Char#{}", id),
+        }
+    }
+}
+
+// Machine-readable outcome of a type-check run (e.g. for editor integration).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckResult {
+    pub ok: bool,
+    pub errors: Vec<CheckError>,
+}
+
+// Wall-clock time spent in each stage of a single incremental type-check run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PipelineTimings {
+    pub parse: Duration,
+    pub diff: Duration,
+    pub check: Duration,
+}
+
+// A single diagnostic (location is left empty when it isn't known).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckError {
+    pub id: ID,
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+// Failures of the framework itself (as opposed to typing errors in the checked program).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InternalError {
+    ParseError(ParseError),
+    // The input couldn't be read (or preprocessed).
+    IoError(String),
+    // DDlog rejected an update (or the transaction around it).
+    DDlogError(String),
+}
+
+impl fmt::Display for InternalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InternalError::ParseError(error) => write!(f, "{}", error),
+            InternalError::IoError(message) => write!(f, "error reading input: {}", message),
+            InternalError::DDlogError(message) => write!(f, "type checker error: {}", message),
+        }
+    }
+}
+
+// Where a syntax error was found in the source.
+// Line and column are 1-based (the column counts characters), the snippet is the whole offending line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub path: String,
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub snippet: String,
+    pub message: String,
+}
+
+impl ParseError {
+    // The offending character in the source the line and column refer to (i.e. the file before preprocessing).
+    pub fn location_in(&self, source: &str) -> Option<Location> {
+        let line_start = match self.line.checked_sub(2) {
+            Some(index) => source.match_indices('\n').nth(index)?.0 + 1,
+            None => 0,
+        };
+        let line = source[line_start..].split('\n').next()?;
+        let (column_offset, length) = match line.char_indices().nth(self.column.checked_sub(1)?) {
+            Some((index, character)) => (index, character.len_utf8()),
+            None => (line.len(), 0),
+        };
+        let start = line_start + column_offset;
+        Some(Location {
+            start,
+            end: start + length,
+        })
+    }
+}
+
+// Rendered like rustc diagnostics, with a caret under the offending character.
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        // Keep tabs so the caret lines up with the snippet.
+        let padding: String = self
+            .snippet
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        writeln!(f, "error during parsing: {}", self.message)?;
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter, self.path, self.line, self.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.snippet)?;
+        write!(f, "{} | {}^", gutter, padding)
+    }
+}
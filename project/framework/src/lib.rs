@@ -1,101 +1,309 @@
 // Modules.
 pub mod ast;
+#[cfg(feature = "ddlog")]
 pub mod ddlog_interface;
 pub mod definitions;
 pub mod parser_interface;
 pub mod standard_type_checker;
+pub mod tree_sitter_interface;
 
 // General imports.
+use definitions::InternalError;
 use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
 
 // Imports for notify-rs.
 use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
-use std::sync::mpsc::channel;
+use std::sync::mpsc::{channel, Receiver};
 use std::time::Duration;
+#[cfg(feature = "ddlog")]
+use std::time::Instant;
 
-// DDlog imports.
-use differential_datalog::api::HDDlog;
+// How long the watcher waits for writes to settle before re-checking.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(1);
+
+// Resolve a path relative to the crate root, so tests and benchmarks don't depend on the working directory.
+pub fn crate_path(relative_path: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(relative_path)
+        .to_string_lossy()
+        .into_owned()
+}
 
 // Type-check a file once with the non-incremental type checker.
-pub fn single_standard_type_check(file_path: String) -> (bool, ast::Tree) {
-    let ast = parser_interface::parse_file_into_ast(&file_path);
+pub fn single_standard_type_check(file_path: String) -> Result<(bool, ast::Tree), InternalError> {
+    let ast =
+        parser_interface::try_parse_file_into_ast(&file_path, parser_interface::Backend::LangC)?;
     // ast.pretty_print();
-    return (standard_type_checker::type_check(&ast), ast);
+    return Ok((standard_type_checker::type_check(&ast), ast));
+}
+
+// Keep re-checking file with the standard type checker after each save, passing every result on.
+pub fn repeated_standard_type_check(
+    file_path: &String,
+    debounce: Duration,
+    on_result: impl FnMut(Result<definitions::CheckResult, InternalError>),
+) -> notify::Result<()> {
+    let (_watcher, events) = watch_file(file_path, debounce)?;
+    standard_check_events(file_path, &events, on_result);
+    Ok(())
 }
 
-pub fn repeated_standard_type_check(file_path: &String) -> notify::Result<()> {
-    // Create a channel to receive the events.
+// Watch a file for saves (e.g. to drive the check loops from another thread).
+// Dropping the watcher closes the event channel, which ends any loop reading from it.
+pub fn watch_file(
+    file_path: &String,
+    debounce: Duration,
+) -> notify::Result<(RecommendedWatcher, Receiver<DebouncedEvent>)> {
     let (tx, rx) = channel();
-    let mut watcher: RecommendedWatcher = watcher(tx, Duration::from_secs(1)).unwrap();
-    // Add the path to be watched.
-    watcher.watch(file_path, RecursiveMode::Recursive).unwrap();
-    loop {
-        match rx.recv() {
-            Ok(event) => match event {
-                DebouncedEvent::Write(ref _path) => {
-                    // Check file on any completed write.
-                    let (result, _) = single_standard_type_check(file_path.clone());
-                    if result {
-                        println!("Program correctly typed ✅");
-                    } else {
-                        println!("Program typing error ❌");
-                    }
-                }
-                _ => {}
-            },
-            Err(e) => println!("error: {:?}", e),
+    let mut watcher: RecommendedWatcher = watcher(tx, debounce)?;
+    watcher.watch(file_path, RecursiveMode::Recursive)?;
+    Ok((watcher, rx))
+}
+
+// Call on_save for every event that leaves new contents in the file, until the channel closes.
+// Besides plain writes, editors that save by replacing the file cause a create or rename instead.
+// (A removal on its own leaves nothing to check.)
+pub fn run_watch_loop(events: &Receiver<DebouncedEvent>, mut on_save: impl FnMut()) {
+    while let Ok(event) = events.recv() {
+        match event {
+            DebouncedEvent::Write(_) | DebouncedEvent::Create(_) | DebouncedEvent::Rename(_, _) => {
+                on_save()
+            }
+            _ => {}
         }
     }
 }
 
+// Check the file with the standard type checker on every save.
+// A syntax error is passed on as an error and the file watched further.
+pub fn standard_check_events(
+    file_path: &String,
+    events: &Receiver<DebouncedEvent>,
+    mut on_result: impl FnMut(Result<definitions::CheckResult, InternalError>),
+) {
+    run_watch_loop(events, || {
+        on_result(
+            parser_interface::try_parse_file_into_ast(file_path, parser_interface::Backend::LangC)
+                .map(|ast| {
+                    let errors = standard_type_checker::type_check_all_errors(&ast);
+                    definitions::CheckResult {
+                        ok: errors.is_empty(),
+                        errors,
+                    }
+                }),
+        )
+    });
+}
+
 // Type-check a file once with the incremental type checker.
-pub fn single_datalog_type_check(file_path: String) -> (bool, ast::Tree) {
-    let (hddlog, _) = type_checker_ddlog::run(1, false).unwrap();
-    let ast = parser_interface::parse_file_into_ast(&file_path);
-    let insert_set: HashSet<definitions::AstRelation> = ast::get_initial_relation_set(&ast);
-    let delete_set: HashSet<definitions::AstRelation> = HashSet::new();
-    return (
-        ddlog_interface::run_ddlog_type_checker(&hddlog, insert_set, delete_set, false, true),
-        ast,
-    );
-}
-
-// Keep re-checking file with incremental type checker after each save.
+#[cfg(feature = "ddlog")]
+pub fn single_datalog_type_check(file_path: String) -> Result<(bool, ast::Tree), InternalError> {
+    let mut checker = ddlog_interface::TypeChecker::new(ddlog_interface::OutputLevel::Quiet);
+    let ast =
+        parser_interface::try_parse_file_into_ast(&file_path, parser_interface::Backend::LangC)?;
+    return Ok((checker.check_initial(&ast)?, ast));
+}
+
+// Type-check a file once with the incremental type checker, timing each stage (e.g. to see why a file is slow).
+// For a single run the diff stage is extracting the initial relations of the tree.
+#[cfg(feature = "ddlog")]
+pub fn type_check_with_timings(
+    file_path: String,
+) -> Result<(bool, definitions::PipelineTimings), InternalError> {
+    let mut checker = ddlog_interface::TypeChecker::new(ddlog_interface::OutputLevel::Quiet);
+    let start = Instant::now();
+    let ast =
+        parser_interface::try_parse_file_into_ast(&file_path, parser_interface::Backend::LangC)?;
+    let parse = start.elapsed();
+    let start = Instant::now();
+    let insert_set = ast::get_initial_relation_set(&ast);
+    let diff = start.elapsed();
+    let start = Instant::now();
+    let result = checker.check_relations(insert_set, HashSet::new())?;
+    let check = start.elapsed();
+    Ok((result, definitions::PipelineTimings { parse, diff, check }))
+}
+
+// Type-check source held in memory once with the non-incremental type checker.
+pub fn standard_type_check_source(source: &str) -> Result<(bool, ast::Tree), InternalError> {
+    let ast = parser_interface::parse_source_into_ast(source)?;
+    return Ok((standard_type_checker::type_check(&ast), ast));
+}
+
+// Type-check source held in memory once with the incremental type checker.
+#[cfg(feature = "ddlog")]
+pub fn datalog_type_check_source(source: &str) -> Result<(bool, ast::Tree), InternalError> {
+    let ast = parser_interface::parse_source_into_ast(source)?;
+    let mut checker = ddlog_interface::TypeChecker::new(ddlog_interface::OutputLevel::Quiet);
+    return Ok((checker.check_initial(&ast)?, ast));
+}
+
+// Type-check a file once and return the result in a serializable form (e.g. for editor integration).
+// Errors are only reported individually by the standard type checker (the incremental one gives a single error).
+// Without the ddlog feature the standard type checker is always used.
+#[cfg_attr(not(feature = "ddlog"), allow(unused_variables))]
+pub fn single_type_check_result(file_path: String, standard: bool) -> definitions::CheckResult {
+    let ast = match parser_interface::try_parse_file_into_ast(
+        &file_path,
+        parser_interface::Backend::LangC,
+    ) {
+        Ok(ast) => ast,
+        Err(e) => return failed_check_result(&file_path, e),
+    };
+    #[cfg(feature = "ddlog")]
+    if !standard {
+        let mut checker = ddlog_interface::TypeChecker::new(ddlog_interface::OutputLevel::Quiet);
+        return match checker.check_initial(&ast) {
+            Ok(ok) => datalog_check_result(ok, &ast),
+            Err(e) => failed_check_result(&file_path, e),
+        };
+    }
+    let errors = standard_type_checker::type_check_all_errors(&ast);
+    definitions::CheckResult {
+        ok: errors.is_empty(),
+        errors,
+    }
+}
+
+// A run that didn't get to type-check (e.g. the file doesn't parse) as a failure with just that error.
+// There is no tree to point into, so the error has no node (-1) and only syntax errors have a location.
+fn failed_check_result(file_path: &str, error: InternalError) -> definitions::CheckResult {
+    let (location, message) = match error {
+        InternalError::ParseError(error) => {
+            let location = match fs::read_to_string(file_path) {
+                Ok(source) if error.path == file_path => error.location_in(&source),
+                _ => None,
+            };
+            (location, error.message)
+        }
+        error => (None, format!("{}", error)),
+    };
+    definitions::CheckResult {
+        ok: false,
+        errors: vec![definitions::CheckError {
+            id: -1,
+            location,
+            message,
+        }],
+    }
+}
+
+// Keep re-checking file with incremental type checker after each save, passing every result on.
+// The checker should already hold the initial tree.
+#[cfg(feature = "ddlog")]
 pub fn incremental_type_check(
     file_path: &String,
     initial_ast: &ast::Tree,
-    hddlog: HDDlog,
-    initial_result: bool,
+    checker: &mut ddlog_interface::TypeChecker,
+    debounce: Duration,
+    on_result: impl FnMut(Result<definitions::CheckResult, InternalError>),
 ) -> notify::Result<()> {
-    // Create a channel to receive the events.
-    let (tx, rx) = channel();
-    let mut watcher: RecommendedWatcher = watcher(tx, Duration::from_secs(1)).unwrap();
-    // Add the path to be watched.
-    watcher.watch(file_path, RecursiveMode::Recursive).unwrap();
+    let (_watcher, events) = watch_file(file_path, debounce)?;
+    incremental_check_events(file_path, initial_ast, checker, &events, on_result);
+    Ok(())
+}
+
+// Check the diff of the file against the last good tree on every save.
+// After a syntax error (or if DDlog rejects the diff) the last good tree is kept.
+// Errors are only reported individually by the standard type checker (the incremental one gives a single error).
+#[cfg(feature = "ddlog")]
+pub fn incremental_check_events(
+    file_path: &String,
+    initial_ast: &ast::Tree,
+    checker: &mut ddlog_interface::TypeChecker,
+    events: &Receiver<DebouncedEvent>,
+    mut on_result: impl FnMut(Result<definitions::CheckResult, InternalError>),
+) {
     let mut prev_ast = initial_ast.clone();
-    let mut prev_result = initial_result;
-    loop {
-        match rx.recv() {
-            Ok(event) => match event {
-                DebouncedEvent::Write(ref _path) => {
-                    // Check file on any completed write.
-                    // Type check initial input file.
-                    let ast = parser_interface::parse_file_into_ast(file_path);
-                    let (insert_set, delete_set, updated_tree) =
-                        ast::get_diff_relation_set(&prev_ast, &ast);
-                    let result = ddlog_interface::run_ddlog_type_checker(
-                        &hddlog,
-                        insert_set,
-                        delete_set,
-                        prev_result,
-                        false,
-                    );
-                    prev_ast = updated_tree.clone();
-                    prev_result = result;
-                }
-                _ => {}
-            },
-            Err(e) => println!("error: {:?}", e),
+    run_watch_loop(events, || {
+        let result =
+            parser_interface::try_parse_file_into_ast(file_path, parser_interface::Backend::LangC)
+                .and_then(|ast| checker.check_diff(&prev_ast, &ast));
+        on_result(result.map(|(ok, updated_tree)| {
+            let check_result = datalog_check_result(ok, &updated_tree);
+            prev_ast = updated_tree;
+            check_result
+        }))
+    });
+}
+
+// DDlog only says whether the whole program is typed correctly, so a failure is a single error for the root.
+#[cfg(feature = "ddlog")]
+fn datalog_check_result(ok: bool, ast: &ast::Tree) -> definitions::CheckResult {
+    let mut errors = vec![];
+    if !ok {
+        let root_id = ast.get_root();
+        errors.push(definitions::CheckError {
+            id: root_id,
+            location: ast.get_location(root_id),
+            message: String::from("program is not typed correctly"),
+        });
+    }
+    definitions::CheckResult { ok, errors }
+}
+
+// Type-check a file once with both type checkers (warning if they disagree).
+#[cfg(feature = "ddlog")]
+pub fn verify_consistency(file_path: &String) -> bool {
+    let ast = parser_interface::parse_file_into_ast(file_path, parser_interface::Backend::LangC);
+    let mut checker = ddlog_interface::TypeChecker::new(ddlog_interface::OutputLevel::Quiet);
+    let incremental_result = checker.check_initial(&ast);
+    results_agree(&ast, incremental_result)
+}
+
+// Keep checking file with both type checkers after each save, where the incremental one only sees the diffs.
+#[cfg(feature = "ddlog")]
+pub fn repeated_verified_type_check(file_path: &String, debounce: Duration) -> notify::Result<()> {
+    let mut prev_ast =
+        parser_interface::parse_file_into_ast(file_path, parser_interface::Backend::LangC);
+    let mut checker = ddlog_interface::TypeChecker::new(ddlog_interface::OutputLevel::Quiet);
+    let incremental_result = checker.check_initial(&prev_ast);
+    results_agree(&prev_ast, incremental_result);
+    let (_watcher, events) = watch_file(file_path, debounce)?;
+    run_watch_loop(&events, || {
+        let result =
+            parser_interface::try_parse_file_into_ast(file_path, parser_interface::Backend::LangC)
+                .and_then(|ast| {
+                    checker
+                        .check_diff(&prev_ast, &ast)
+                        .map(|result| (result, ast))
+                });
+        match result {
+            Ok(((incremental_result, updated_tree), ast)) => {
+                results_agree(&ast, Ok(incremental_result));
+                prev_ast = updated_tree;
+            }
+            Err(e) => println!("{}", e),
+        }
+    });
+    Ok(())
+}
+
+// Run the standard type checker on a tree and compare with the incremental result for the same tree.
+#[cfg(feature = "ddlog")]
+fn results_agree(ast: &ast::Tree, incremental_result: Result<bool, InternalError>) -> bool {
+    let standard_result = standard_type_checker::type_check(ast);
+    match incremental_result {
+        Ok(result) if result == standard_result => {
+            if result {
+                println!("Program correctly typed ✅");
+            } else {
+                println!("Program typing error ❌");
+            }
+            true
+        }
+        Ok(result) => {
+            println!(
+                "warning: type checkers disagree (standard: {}, incremental: {})",
+                standard_result, result
+            );
+            false
+        }
+        Err(e) => {
+            println!("{}", e);
+            false
         }
     }
 }
@@ -113,24 +321,20 @@ pub fn compute_diff(
 }
 
 // Insert given relations into given DDlog program state (mainly for benchmark tests).
+#[cfg(feature = "ddlog")]
 pub fn datalog_type_check_without_diff(
-    prev_result: bool,
-    hddlog: HDDlog,
+    checker: &mut ddlog_interface::TypeChecker,
     insertion_set: HashSet<definitions::AstRelation>,
     deletion_set: HashSet<definitions::AstRelation>,
 ) {
-    ddlog_interface::run_ddlog_type_checker(
-        &hddlog,
-        insertion_set,
-        deletion_set,
-        prev_result,
-        true,
-    );
+    checker
+        .check_relations(insertion_set, deletion_set)
+        .unwrap();
 }
 
 // Parse file into tree of AST relations (mainly for benchmark tests).
 pub fn parse_into_relation_tree(file_path: String) -> ast::Tree {
-    let ast = parser_interface::parse_file_into_ast(&file_path);
+    let ast = parser_interface::parse_file_into_ast(&file_path, parser_interface::Backend::LangC);
     return ast;
 }
 
@@ -138,3 +342,235 @@ pub fn parse_into_relation_tree(file_path: String) -> ast::Tree {
 pub fn standard_type_check_without_parse(program: ast::Tree) {
     standard_type_checker::type_check(&program);
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Location;
+    use crate::definitions::{CheckResult, InternalError};
+    use crate::{
+        crate_path, run_watch_loop, single_type_check_result, standard_check_events,
+        standard_type_check_source,
+    };
+    #[cfg(feature = "ddlog")]
+    use crate::{
+        datalog_type_check_source, ddlog_interface, incremental_check_events, parser_interface,
+        type_check_with_timings, verify_consistency,
+    };
+    use notify::DebouncedEvent;
+    use std::sync::mpsc::{channel, Receiver};
+
+    const SAVES: [&str; 4] = [
+        "int main(void) { return 0; }",
+        "int main(void) { float a = 1.5; return a; }",
+        "int main(void) { return 0 }",
+        "int main(void) { int a = 1; return a; }",
+    ];
+
+    // Stands in for the file watcher: saves the next program each time a result comes in.
+    // The event channel is closed after the last save has been checked, which ends the loop.
+    fn drive_saves(
+        file_name: &str,
+        check_events: impl FnOnce(
+            &String,
+            &Receiver<DebouncedEvent>,
+            &mut dyn FnMut(Result<CheckResult, InternalError>),
+        ),
+    ) -> Vec<Result<CheckResult, InternalError>> {
+        let file_path = std::env::temp_dir().join(file_name);
+        let (tx, rx) = channel();
+        let mut tx = Some(tx);
+        let mut results = vec![];
+        std::fs::write(&file_path, SAVES[0]).unwrap();
+        tx.as_ref()
+            .unwrap()
+            .send(DebouncedEvent::Write(file_path.clone()))
+            .unwrap();
+        check_events(
+            &file_path.to_string_lossy().to_string(),
+            &rx,
+            &mut |result| {
+                results.push(result);
+                match SAVES.get(results.len()) {
+                    Some(source) => {
+                        std::fs::write(&file_path, source).unwrap();
+                        let sender = tx.as_ref().unwrap();
+                        // Not a completed write, so nothing is checked for it.
+                        sender
+                            .send(DebouncedEvent::NoticeWrite(file_path.clone()))
+                            .unwrap();
+                        sender
+                            .send(DebouncedEvent::Write(file_path.clone()))
+                            .unwrap();
+                    }
+                    None => tx = None,
+                }
+            },
+        );
+        results
+    }
+
+    fn outcomes(results: &[Result<CheckResult, InternalError>]) -> Vec<Option<bool>> {
+        results
+            .iter()
+            .map(|result| result.as_ref().ok().map(|result| result.ok))
+            .collect()
+    }
+
+    #[test]
+    fn json_result_for_correct_program() {
+        let result = single_type_check_result(crate_path("tests/dev_examples/c/example2.c"), true);
+        assert_eq!(
+            serde_json::to_string(&result).unwrap(),
+            String::from("{\"ok\":true,\"errors\":[]}")
+        );
+    }
+
+    // A syntax error is a failed result pointing at the offending character in the file.
+    #[test]
+    fn json_result_for_syntax_error() {
+        let file_path = std::env::temp_dir().join("cerium_json_syntax_error.c");
+        std::fs::write(
+            &file_path,
+            "// Removed by the preprocessor.\nint main(void) {\n    int a = 1 +;\n}\n",
+        )
+        .unwrap();
+        let result = single_type_check_result(file_path.to_string_lossy().to_string(), true);
+        assert_eq!(result.ok, false);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].id, -1);
+        assert_eq!(
+            result.errors[0].location,
+            Some(Location { start: 64, end: 65 })
+        );
+        assert!(result.errors[0].message.starts_with("unexpected token"));
+    }
+
+    #[test]
+    fn check_correct_source() {
+        let (result, _) =
+            standard_type_check_source("int main(void) { int a = 1; int b = a + 2; return b; }")
+                .unwrap();
+        assert_eq!(result, true);
+    }
+
+    #[test]
+    fn check_error_source() {
+        let (result, _) =
+            standard_type_check_source("int main(void) { float a = 1.5; return a; }").unwrap();
+        assert_eq!(result, false);
+    }
+
+    #[cfg(feature = "ddlog")]
+    #[test]
+    fn verify_consistent_program() {
+        assert_eq!(
+            verify_consistency(&crate_path("tests/dev_examples/c/example2.c")),
+            true
+        );
+    }
+
+    #[cfg(feature = "ddlog")]
+    #[test]
+    fn checkers_agree_on_binary_operators() {
+        let sources = [
+            (
+                "int main(void) { float a = 1.5; int b = a > 0.5; return b; }",
+                true,
+            ),
+            (
+                "int main(void) { double a; double b; return a == b; }",
+                true,
+            ),
+            (
+                "int main(void) { int a = 1; int b = a != 2 && a < 3; return b; }",
+                true,
+            ),
+            (
+                "int main(void) { float a = 1.5; float b = a * 2.0; return 0; }",
+                true,
+            ),
+            (
+                "int main(void) { float a = 1.5; int b = a * 2.0; return b; }",
+                false,
+            ),
+            (
+                "int main(void) { float a = 1.5; float b = a < 2.0; return 0; }",
+                false,
+            ),
+        ];
+        for (source, expected) in sources {
+            let (standard, _) = standard_type_check_source(source).unwrap();
+            let (datalog, _) = datalog_type_check_source(source).unwrap();
+            assert_eq!(standard, expected, "{}", source);
+            assert_eq!(datalog, expected, "{}", source);
+        }
+    }
+
+    #[cfg(feature = "ddlog")]
+    #[test]
+    fn timings_are_populated() {
+        let (result, timings) =
+            type_check_with_timings(crate_path("tests/dev_examples/c/example2.c")).unwrap();
+        assert_eq!(result, true);
+        assert!(!timings.parse.is_zero());
+        assert!(!timings.diff.is_zero());
+        assert!(!timings.check.is_zero());
+    }
+
+    #[test]
+    fn standard_results_for_each_save() {
+        let results = drive_saves("cerium_standard_saves.c", |file_path, events, on_result| {
+            standard_check_events(file_path, events, on_result)
+        });
+        assert_eq!(
+            outcomes(&results),
+            vec![Some(true), Some(false), None, Some(true)]
+        );
+        assert!(!results[1].as_ref().unwrap().errors.is_empty());
+    }
+
+    // The program after the syntax error is diffed against the last one that parsed.
+    #[cfg(feature = "ddlog")]
+    #[test]
+    fn incremental_results_for_each_save() {
+        let initial_ast = parser_interface::parse_source_into_ast(SAVES[0]).unwrap();
+        let mut checker = ddlog_interface::TypeChecker::new(ddlog_interface::OutputLevel::Quiet);
+        checker.check_initial(&initial_ast).unwrap();
+        let results = drive_saves(
+            "cerium_incremental_saves.c",
+            |file_path, events, on_result| {
+                incremental_check_events(file_path, &initial_ast, &mut checker, events, on_result)
+            },
+        );
+        assert_eq!(
+            outcomes(&results),
+            vec![Some(true), Some(false), None, Some(true)]
+        );
+        // A failure still comes with an error (for the whole program).
+        assert_eq!(results[1].as_ref().unwrap().errors.len(), 1);
+        assert!(results[3].as_ref().unwrap().errors.is_empty());
+    }
+
+    #[test]
+    fn watch_loop_checks_saves_only() {
+        let file_path = std::env::temp_dir().join("cerium_watch_loop.c");
+        let moved_path = std::env::temp_dir().join("cerium_watch_loop.c~");
+        let (tx, rx) = channel();
+        for event in [
+            DebouncedEvent::NoticeWrite(file_path.clone()),
+            DebouncedEvent::Write(file_path.clone()),
+            // Saving by replacing the file.
+            DebouncedEvent::NoticeRemove(file_path.clone()),
+            DebouncedEvent::Remove(file_path.clone()),
+            DebouncedEvent::Create(file_path.clone()),
+            DebouncedEvent::Rename(moved_path, file_path.clone()),
+            DebouncedEvent::Chmod(file_path.clone()),
+        ] {
+            tx.send(event).unwrap();
+        }
+        drop(tx);
+        let mut saves = 0;
+        run_watch_loop(&rx, || saves += 1);
+        assert_eq!(saves, 3);
+    }
+}
@@ -1,11 +1,12 @@
 extern crate notify;
 
 // General imports.
-use std::collections::HashSet;
 use std::env;
+use std::process;
+use std::time::Duration;
 
 // Internal imports.
-use cerium_framework::ast;
+#[cfg(feature = "ddlog")]
 use cerium_framework::ddlog_interface;
 use cerium_framework::definitions;
 use cerium_framework::parser_interface;
@@ -16,38 +17,177 @@ fn main() {
     let args: Vec<String> = env::args().collect();
     let file_path = &args[1];
 
-    // Check if extra option is passed.
-    // (Currently just "-s" for standard type checking).
-    if args.len() == 3 {
-        let option = &args[2];
-        if *option == String::from("-s") {
-            let (initial_result, _) =
-                cerium_framework::single_standard_type_check(file_path.clone());
-            if initial_result {
-                println!("Program correctly typed ✅");
-            } else {
-                println!("Program typing error ❌");
+    // Check if extra options are passed.
+    // (Currently "-s" for standard type checking, "--json" for machine-readable output,
+    // "--once" to check a single time and exit with the result (e.g. in CI),
+    // "-v" to also print the parsed tree and relation updates,
+    // "--debounce-ms <n>" to set how long the watcher waits between saves,
+    // "--verify" to run both type checkers and warn if their results differ
+    // and "--dump-ast" to only print the parsed tree (as a flat list of relations with "--flat")).
+    let options = &args[2..];
+    let standard = options.contains(&String::from("-s"));
+    let verbose = options.contains(&String::from("-v"));
+    let mut debounce = cerium_framework::DEFAULT_DEBOUNCE;
+    if let Some(index) = options.iter().position(|option| option == "--debounce-ms") {
+        match options
+            .get(index + 1)
+            .and_then(|value| value.parse::<u64>().ok())
+        {
+            Some(millis) => debounce = Duration::from_millis(millis),
+            None => {
+                println!("error: --debounce-ms expects a number of milliseconds");
+                return;
             }
-            if let Err(e) = cerium_framework::repeated_standard_type_check(file_path) {
-                println!("error: {:?}", e)
+        }
+    }
+    // For debugging the parser, so this doesn't type check at all.
+    if options.contains(&String::from("--dump-ast")) {
+        let ast =
+            parser_interface::parse_file_into_ast(file_path, parser_interface::Backend::LangC);
+        if options.contains(&String::from("--flat")) {
+            ast.flat_print();
+        } else {
+            ast.pretty_print();
+        }
+        return;
+    }
+    if options.contains(&String::from("--json")) {
+        let result = cerium_framework::single_type_check_result(file_path.clone(), standard);
+        println!("{}", serde_json::to_string(&result).unwrap());
+        return;
+    }
+    if options.contains(&String::from("--verify")) {
+        verify(
+            file_path,
+            options.contains(&String::from("--once")),
+            debounce,
+        );
+        return;
+    }
+    if options.contains(&String::from("--once")) {
+        let result = if standard {
+            cerium_framework::single_standard_type_check(file_path.clone())
+        } else {
+            single_datalog_type_check(file_path)
+        };
+        // A file that doesn't parse (or a failing type checker) counts as a failed check.
+        match result {
+            Ok((true, _)) => {
+                println!("Program correctly typed ✅");
+                process::exit(0);
             }
+            Ok((false, _)) => println!("Program typing error ❌"),
+            Err(e) => println!("{}", e),
         }
+        process::exit(1);
+    }
+    if standard {
+        match cerium_framework::single_standard_type_check(file_path.clone()) {
+            Ok((true, _)) => println!("Program correctly typed ✅"),
+            Ok((false, _)) => println!("Program typing error ❌"),
+            Err(e) => println!("{}", e),
+        }
+        if let Err(e) =
+            cerium_framework::repeated_standard_type_check(file_path, debounce, print_result)
+        {
+            println!("error: {:?}", e)
+        }
+        return;
+    }
+    datalog_type_check(file_path, debounce, verbose);
+}
+
+#[cfg(feature = "ddlog")]
+fn single_datalog_type_check(
+    file_path: &String,
+) -> Result<(bool, cerium_framework::ast::Tree), definitions::InternalError> {
+    return cerium_framework::single_datalog_type_check(file_path.clone());
+}
+
+#[cfg(feature = "ddlog")]
+fn verify(file_path: &String, once: bool, debounce: Duration) {
+    if once {
+        process::exit(if cerium_framework::verify_consistency(file_path) {
+            0
+        } else {
+            1
+        });
+    }
+    if let Err(e) = cerium_framework::repeated_verified_type_check(file_path, debounce) {
+        println!("error: {:?}", e)
     }
+}
+
+#[cfg(feature = "ddlog")]
+fn datalog_type_check(file_path: &String, debounce: Duration, verbose: bool) {
+    let output_level = if verbose {
+        ddlog_interface::OutputLevel::Debug
+    } else {
+        ddlog_interface::OutputLevel::Results
+    };
 
     // Create instance of the DDlog type checking program.
-    let (hddlog, _) = type_checker_ddlog::run(1, false).unwrap();
+    let mut checker = ddlog_interface::TypeChecker::new(output_level);
 
     // Type check initial input file.
-    let ast = parser_interface::parse_file_into_ast(file_path);
-    ast.pretty_print();
+    let ast = parser_interface::parse_file_into_ast(file_path, parser_interface::Backend::LangC);
+    if output_level == ddlog_interface::OutputLevel::Debug {
+        ast.pretty_print();
+    }
     // ast.flat_print();
-    let insert_set: HashSet<definitions::AstRelation> = ast::get_initial_relation_set(&ast);
-    let delete_set: HashSet<definitions::AstRelation> = HashSet::new();
-    let result =
-        ddlog_interface::run_ddlog_type_checker(&hddlog, insert_set, delete_set, false, false);
+    if let Err(e) = checker.check_initial(&ast) {
+        println!("{}", e);
+        return;
+    }
 
     // Continue watching the file for changes.
-    if let Err(e) = cerium_framework::incremental_type_check(file_path, &ast, hddlog, result) {
+    // (The checker prints its own results, so only errors are left to print.)
+    if let Err(e) = cerium_framework::incremental_type_check(
+        file_path,
+        &ast,
+        &mut checker,
+        debounce,
+        |result| {
+            if let Err(e) = result {
+                println!("{}", e);
+            }
+        },
+    ) {
         println!("error: {:?}", e)
     }
 }
+
+// Without the DDlog type checker only "-s" and "--dump-ast" are available.
+#[cfg(not(feature = "ddlog"))]
+fn missing_ddlog() -> definitions::InternalError {
+    return definitions::InternalError::DDlogError(String::from(
+        "built without the \"ddlog\" feature (use \"-s\" for the standard type checker)",
+    ));
+}
+
+#[cfg(not(feature = "ddlog"))]
+fn single_datalog_type_check(
+    _file_path: &String,
+) -> Result<(bool, cerium_framework::ast::Tree), definitions::InternalError> {
+    return Err(missing_ddlog());
+}
+
+#[cfg(not(feature = "ddlog"))]
+fn verify(_file_path: &String, _once: bool, _debounce: Duration) {
+    println!("{}", missing_ddlog());
+    process::exit(1);
+}
+
+#[cfg(not(feature = "ddlog"))]
+fn datalog_type_check(_file_path: &String, _debounce: Duration, _verbose: bool) {
+    println!("{}", missing_ddlog());
+    process::exit(1);
+}
+
+fn print_result(result: Result<definitions::CheckResult, definitions::InternalError>) {
+    match result {
+        Ok(result) if result.ok => println!("Program correctly typed ✅"),
+        Ok(_) => println!("Program typing error ❌"),
+        Err(e) => println!("{}", e),
+    }
+}
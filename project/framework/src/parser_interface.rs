@@ -1,19 +1,295 @@
 extern crate lang_c;
 
 use lang_c::ast as parse_ast;
-use lang_c::driver::{parse, Config};
+use lang_c::driver::{parse, parse_preprocessed, Config};
 // use lang_c::print::Printer;
-use lang_c::span::Span;
+use lang_c::span::{Node, Span};
 // use lang_c::visit::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::thread;
 
-use crate::ast::Tree;
-use crate::definitions::{AstRelation, ID};
+use crate::ast::{get_diff_relation_set, Location, Tree};
+use crate::definitions::{AstRelation, InternalError, ParseError, ID};
+use crate::tree_sitter_interface::{parse_with_tree_sitter, try_parse_with_tree_sitter};
 
-pub fn parse_file_into_ast(file_path: &String) -> Tree {
-    parse_with_lang_c(file_path)
+// Available parsers to build the internal AST from.
+// (Tree-sitter recovers from syntax errors, lang_c gives more precise C semantics.)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    LangC,
+    TreeSitter,
+}
+
+pub fn parse_file_into_ast(file_path: &String, backend: Backend) -> Tree {
+    match backend {
+        Backend::LangC => parse_with_lang_c(file_path),
+        Backend::TreeSitter => parse_with_tree_sitter(file_path),
+    }
+}
+
+// Same as parse_file_into_ast but returns syntax errors instead of panicking (e.g. while watching a file).
+pub fn try_parse_file_into_ast(
+    file_path: &String,
+    backend: Backend,
+) -> Result<Tree, InternalError> {
+    match backend {
+        Backend::LangC => try_parse_with_lang_c(file_path),
+        Backend::TreeSitter => try_parse_with_tree_sitter(file_path),
+    }
+}
+
+// Parse source held in memory (e.g. an unsaved editor buffer) without going through a file.
+// The source isn't preprocessed, so it can't contain directives or comments.
+// (Declaration locations are offsets into the given source, the root location marks that it wasn't preprocessed.)
+pub fn parse_source_into_ast(source: &str) -> Result<Tree, InternalError> {
+    let config = Config::default();
+    match parse_preprocessed(&config, source.to_string()) {
+        Ok(parse) => {
+            let mut ast_builder = AstBuilder::new();
+            let mut tree = AstBuilder::build_tree(&mut ast_builder, &parse.unit);
+            if let Some((offset, message)) = ast_builder.error {
+                return Err(InternalError::ParseError(locate_parse_error(
+                    "<source>",
+                    &parse.source,
+                    offset,
+                    message,
+                )));
+            }
+            let root_id = tree.get_root();
+            tree.set_location(
+                root_id,
+                Some(Location {
+                    start: 0,
+                    end: source.len(),
+                }),
+            );
+            Ok(tree)
+        }
+        Err(e) => Err(to_parse_error("<source>", e)),
+    }
+}
+
+// Re-parse only the top level declarations overlapping an edit and diff just those against the previous tree.
+// The byte range is where the edited text now is in the file (empty for a pure deletion).
+// Falls back to diffing the whole file if the previous tree has no source offsets (i.e. it wasn't
+// parsed from unpreprocessed source), if the file has directives or typedefs (declarations can depend on those)
+// or if it has comments (only the preprocessor removes those).
+pub fn reparse_changed(
+    file_path: &String,
+    prev_tree: &Tree,
+    byte_range: Range<usize>,
+) -> Result<(HashSet<AstRelation>, HashSet<AstRelation>, Tree), InternalError> {
+    let source = match fs::read_to_string(file_path) {
+        Ok(source) => source,
+        Err(e) => return Err(InternalError::IoError(format!("{}", e))),
+    };
+    let root_id = prev_tree.get_root();
+    let prev_length = match prev_tree.get_location(root_id) {
+        Some(location) if !needs_preprocessor(&source) && !source.contains("typedef") => {
+            location.end
+        }
+        _ => return reparse_all(file_path, &source, prev_tree),
+    };
+    let prev_body_ids = declaration_ids(prev_tree);
+    // Previous declarations after the edit have moved by however much the file grew or shrank.
+    let shift = |offset: usize| (offset + source.len()).saturating_sub(prev_length);
+    let prev_edit_end = (byte_range.end + prev_length)
+        .saturating_sub(source.len())
+        .max(byte_range.start);
+    // Split the previous declarations into ones before the edit, after it and overlapping it.
+    // Only the region between the closest untouched declarations is parsed again.
+    let mut untouched_ids = vec![];
+    let mut changed_ids = vec![];
+    let mut region = 0..source.len();
+    for decl_id in &prev_body_ids {
+        let location = match prev_tree.get_location(*decl_id) {
+            Some(location) => location,
+            None => return reparse_all(file_path, &source, prev_tree),
+        };
+        if location.end <= byte_range.start {
+            untouched_ids.push((*decl_id, location));
+            region.start = region.start.max(location.end);
+        } else if location.start >= prev_edit_end {
+            let location = Location {
+                start: shift(location.start),
+                end: shift(location.end),
+            };
+            untouched_ids.push((*decl_id, location));
+            region.end = region.end.min(location.start);
+        } else {
+            changed_ids.push(*decl_id);
+        }
+    }
+    let region_source = match source.get(region.clone()) {
+        Some(region_source) if region.start <= region.end => region_source,
+        _ => return reparse_all(file_path, &source, prev_tree),
+    };
+    let region_tree = if region_source.trim().is_empty() {
+        let mut empty_tree = Tree::new();
+        empty_tree.add_root_node(
+            0,
+            AstRelation::TransUnit {
+                id: 0,
+                body_ids: vec![],
+            },
+        );
+        empty_tree
+    } else {
+        parse_source_into_ast(region_source)?
+    };
+
+    // Diff a copy of the previous tree that only lists the changed declarations
+    // (the untouched ones stay in the arena so new IDs don't clash with them).
+    let mut partial_tree = prev_tree.clone();
+    partial_tree.update_relation(
+        root_id,
+        AstRelation::TransUnit {
+            id: root_id,
+            body_ids: changed_ids.clone(),
+        },
+    );
+    partial_tree.replace_children(root_id, changed_ids);
+    let (mut insertion_set, mut deletion_set, mut updated_tree) =
+        get_diff_relation_set(&partial_tree, &region_tree);
+    // The root relations from that diff only list part of the declarations.
+    insertion_set.retain(|relation| !matches!(relation, AstRelation::TransUnit { .. }));
+    deletion_set.retain(|relation| !matches!(relation, AstRelation::TransUnit { .. }));
+    relocate_declarations(&mut updated_tree, &region_tree, region.start);
+
+    let mut declarations: Vec<(ID, Location)> = untouched_ids;
+    for decl_id in declaration_ids(&updated_tree) {
+        match updated_tree.get_location(decl_id) {
+            Some(location) => declarations.push((decl_id, location)),
+            None => panic!("Re-parsed declaration without a location"),
+        }
+    }
+    declarations.sort_by_key(|(_, location)| location.start);
+    let body_ids: Vec<ID> = declarations.iter().map(|(decl_id, _)| *decl_id).collect();
+    for (decl_id, location) in declarations {
+        updated_tree.set_location(decl_id, Some(location));
+    }
+    let root = AstRelation::TransUnit {
+        id: root_id,
+        body_ids: body_ids.clone(),
+    };
+    if body_ids != prev_body_ids {
+        deletion_set.insert(prev_tree.get_relation(root_id));
+        insertion_set.insert(root.clone());
+    }
+    updated_tree.update_relation(root_id, root);
+    updated_tree.replace_children(root_id, body_ids);
+    updated_tree.set_location(
+        root_id,
+        Some(Location {
+            start: 0,
+            end: source.len(),
+        }),
+    );
+    Ok((insertion_set, deletion_set, updated_tree))
+}
+
+fn reparse_all(
+    file_path: &String,
+    source: &str,
+    prev_tree: &Tree,
+) -> Result<(HashSet<AstRelation>, HashSet<AstRelation>, Tree), InternalError> {
+    let root_id = prev_tree.get_root();
+    if needs_preprocessor(source) {
+        // The source itself can't be parsed again later, so later edits go through here as well.
+        let new_tree = try_parse_with_lang_c(file_path)?;
+        let (insertion_set, deletion_set, mut updated_tree) =
+            get_diff_relation_set(prev_tree, &new_tree);
+        updated_tree.set_location(root_id, None);
+        Ok((insertion_set, deletion_set, updated_tree))
+    } else {
+        let new_tree = parse_source_into_ast(source)?;
+        let (insertion_set, deletion_set, mut updated_tree) =
+            get_diff_relation_set(prev_tree, &new_tree);
+        relocate_declarations(&mut updated_tree, &new_tree, 0);
+        updated_tree.set_location(root_id, new_tree.get_location(new_tree.get_root()));
+        Ok((insertion_set, deletion_set, updated_tree))
+    }
+}
+
+// Directives and comments are only handled by the preprocessor, which parse_source_into_ast doesn't run.
+// (A "//" inside a string literal counts as well, which only means the whole file is parsed again.)
+fn needs_preprocessor(source: &str) -> bool {
+    source.contains('#') || source.contains("//") || source.contains("/*")
+}
+
+// The diff keeps previous IDs for matching declarations, so carry their new locations over by name.
+fn relocate_declarations(updated_tree: &mut Tree, new_tree: &Tree, offset: usize) {
+    let new_decl_ids = declaration_ids(new_tree);
+    for decl_id in declaration_ids(updated_tree) {
+        let name = declaration_name(&updated_tree.get_relation(decl_id));
+        let new_decl_id = new_decl_ids
+            .iter()
+            .copied()
+            .find(|new_decl_id| declaration_name(&new_tree.get_relation(*new_decl_id)) == name);
+        if let Some(new_decl_id) = new_decl_id {
+            let location = new_tree.get_location(new_decl_id).map(|location| Location {
+                start: location.start + offset,
+                end: location.end + offset,
+            });
+            updated_tree.set_location(decl_id, location);
+        }
+    }
+}
+
+fn declaration_ids(tree: &Tree) -> Vec<ID> {
+    match tree.get_relation(tree.get_root()) {
+        AstRelation::TransUnit { id: _, body_ids } => body_ids,
+        _ => panic!("Unexpected root node"),
+    }
+}
+
+fn declaration_name(relation: &AstRelation) -> Option<String> {
+    match relation {
+        AstRelation::FunDef { fun_name, .. } => Some(fun_name.clone()),
+        AstRelation::StructDef { name, .. } => Some(name.clone()),
+        _ => None,
+    }
+}
+
+// Parse several files (e.g. a whole directory) in parallel with lang_c.
+// Each file gets its own parser and builder so there is no shared state between threads.
+pub fn parse_files(paths: &[String]) -> HashMap<String, Result<Tree, InternalError>> {
+    let thread_count = thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1);
+    let chunk_size = paths.len().div_ceil(thread_count);
+    if chunk_size == 0 {
+        return HashMap::new();
+    }
+    thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|path| (path.clone(), try_parse_with_lang_c(path)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
 }
 
 fn parse_with_lang_c(file_path: &String) -> Tree {
+    match try_parse_with_lang_c(file_path) {
+        Ok(tree) => tree,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+fn try_parse_with_lang_c(file_path: &String) -> Result<Tree, InternalError> {
     let config = Config::default();
     let parse_output = parse(&config, file_path);
     match parse_output {
@@ -22,17 +298,146 @@ fn parse_with_lang_c(file_path: &String) -> Tree {
             // Printer::new(s).visit_translation_unit(&parse.unit);
             // println!("{}", s);
             let mut ast_builder = AstBuilder::new();
-            return AstBuilder::build_tree(&mut ast_builder, &parse.unit);
+            let mut tree = AstBuilder::build_tree(&mut ast_builder, &parse.unit);
+            match ast_builder.error {
+                Some((offset, message)) => Err(InternalError::ParseError(locate_parse_error(
+                    file_path,
+                    &parse.source,
+                    offset,
+                    message,
+                ))),
+                None => {
+                    relocate_to_file(&mut tree, file_path, &parse.source);
+                    Ok(tree)
+                }
+            }
+        }
+        Err(lang_c::driver::Error::SyntaxError(e)) => Err(to_parse_error(file_path, e)),
+        Err(lang_c::driver::Error::PreprocessorError(e)) => {
+            Err(InternalError::IoError(format!("{}", e)))
+        }
+    }
+}
+
+// lang_c gives offsets into the preprocessed source, map declaration locations back to offsets into the file
+// (the same as parsing the file's contents with parse_source_into_ast gives).
+// Declarations from included files don't have a location in it.
+fn relocate_to_file(tree: &mut Tree, file_path: &str, preprocessed: &str) {
+    let raw = match fs::read_to_string(file_path) {
+        Ok(raw) => raw,
+        Err(_) => return,
+    };
+    let mut raw_line_starts = vec![0];
+    raw_line_starts.extend(raw.match_indices('\n').map(|(index, _)| index + 1));
+    // Start of every preprocessed line and the start of the same line in the file.
+    let mut line_starts: Vec<(usize, Option<usize>)> = vec![];
+    let mut path = file_path.to_string();
+    let mut line = 1;
+    let mut line_start = 0;
+    for text in preprocessed.split_inclusive('\n') {
+        match parse_line_marker(text.trim_end()) {
+            Some((marker_line, marker_path)) => {
+                line = marker_line;
+                path = marker_path;
+            }
+            None => {
+                // (gcc numbers the lines before the first marker from 0.)
+                let raw_line_start = match line.checked_sub(1) {
+                    Some(index) if path == file_path => raw_line_starts.get(index).copied(),
+                    _ => None,
+                };
+                line_starts.push((line_start, raw_line_start));
+                line += 1;
+            }
         }
-        Err(e) => {
-            panic!("Error during parsing: {:?}", e)
+        line_start += text.len();
+    }
+    let to_raw = |offset: usize| -> Option<usize> {
+        let index = line_starts
+            .partition_point(|(start, _)| *start <= offset)
+            .checked_sub(1)?;
+        let (start, raw_start) = line_starts[index];
+        Some((raw_start? + offset - start).min(raw.len()))
+    };
+    for decl_id in declaration_ids(tree) {
+        let location = tree.get_location(decl_id).and_then(|location| {
+            match (to_raw(location.start), to_raw(location.end)) {
+                (Some(start), Some(end)) => Some(Location { start, end }),
+                _ => None,
+            }
+        });
+        tree.set_location(decl_id, location);
+    }
+}
+
+fn to_parse_error(path: &str, error: lang_c::driver::SyntaxError) -> InternalError {
+    let mut expected: Vec<&str> = error.expected.iter().cloned().collect();
+    expected.sort();
+    let message = format!("unexpected token, expected one of {}", expected.join(", "));
+    InternalError::ParseError(locate_parse_error(
+        path,
+        &error.source,
+        error.offset,
+        message,
+    ))
+}
+
+// Find the line around a byte offset into the parsed (possibly preprocessed) source.
+// The preprocessor leaves line markers (# <line> "<file>") which give the file and line of the line after them.
+pub fn locate_parse_error(path: &str, source: &str, offset: usize, message: String) -> ParseError {
+    let offset = offset.min(source.len());
+    let line_start = source[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let line_end = source[offset..]
+        .find('\n')
+        .map_or(source.len(), |index| offset + index);
+    let mut path = path.to_string();
+    let mut line = 1;
+    for text in source[..line_start].lines() {
+        match parse_line_marker(text) {
+            Some((marker_line, marker_path)) => {
+                line = marker_line;
+                path = marker_path;
+            }
+            None => line += 1,
         }
     }
+    ParseError {
+        path,
+        offset,
+        line,
+        column: source[line_start..offset].chars().count() + 1,
+        snippet: source[line_start..line_end].to_string(),
+        message,
+    }
+}
+
+fn parse_line_marker(text: &str) -> Option<(usize, String)> {
+    let mut parts = text.strip_prefix("# ")?.splitn(2, ' ');
+    let line = parts.next()?.parse().ok()?;
+    let path = parts.next()?.split('"').nth(1)?;
+    Some((line, path.to_string()))
 }
 
 struct AstBuilder {
     tree: Tree,
     current_max_id: ID,
+    typedefs: HashMap<String, TypedefType>,
+    // The first problem lang_c doesn't report itself (byte offset and message), the tree is discarded if there is one.
+    error: Option<(usize, String)>,
+}
+
+// Typedefs don't get nodes of their own, instead every use is expanded into fresh type nodes.
+// (Aliases of aliases are resolved when they are defined.)
+#[derive(Clone)]
+struct TypedefType {
+    specifiers: Vec<parse_ast::TypeSpecifier>,
+    derived: Vec<DerivedType>,
+}
+
+#[derive(Clone, Copy)]
+enum DerivedType {
+    Pointer,
+    Array(i32),
 }
 
 // Traverse the parser output creating internal AST tree while keeping IDs consistent between nodes and relations.
@@ -42,6 +447,8 @@ impl<'a> AstBuilder {
         Self {
             tree: Tree::new(),
             current_max_id: 0,
+            typedefs: HashMap::new(),
+            error: None,
         }
     }
 
@@ -53,7 +460,16 @@ impl<'a> AstBuilder {
     fn visit_translation_unit(&mut self, node: &'a parse_ast::TranslationUnit) -> Tree {
         let mut body_ids = vec![];
         for element in &node.0 {
-            body_ids.push(self.visit_external_declaration(&element.node, &element.span));
+            if let Some(body_id) = self.visit_external_declaration(&element.node, &element.span) {
+                self.tree.set_location(
+                    body_id,
+                    Some(Location {
+                        start: element.span.start,
+                        end: element.span.end,
+                    }),
+                );
+                body_ids.push(body_id);
+            }
         }
         let node_id = self.current_max_id;
         self.current_max_id = self.current_max_id + 1;
@@ -63,22 +479,95 @@ impl<'a> AstBuilder {
         };
         self.tree.add_root_node(node_id, relation);
         self.tree.replace_children(node_id, body_ids);
-        return self.tree.clone();
+        // Moved out rather than cloned (the builder isn't used again), so a large tree isn't held twice.
+        return std::mem::take(&mut self.tree);
     }
 
-    // At the moment all declarations just traverse down to function definitions.
+    // At the moment declarations are either function definitions, struct definitions or typedefs.
     fn visit_external_declaration(
         &mut self,
         node: &'a parse_ast::ExternalDeclaration,
         _span: &'a Span,
-    ) -> ID {
+    ) -> Option<ID> {
         match *node {
             // No new node created here, just traverse.
             parse_ast::ExternalDeclaration::FunctionDefinition(ref f) => {
-                return self.visit_function_definition(&f.node, &f.span)
+                return Some(self.visit_function_definition(&f.node, &f.span))
+            }
+            parse_ast::ExternalDeclaration::Declaration(ref d) if is_typedef(&d.node) => {
+                self.visit_typedef(&d.node);
+                return None;
             }
+            parse_ast::ExternalDeclaration::Declaration(ref d) => {
+                return Some(self.visit_struct_definition(&d.node, &d.span))
+            }
+            _ => panic!("Feature not implemented"),
+        }
+    }
+
+    // Only plain named struct definitions are supported at the top level (e.g. struct Point { int x; int y; };).
+    fn visit_struct_definition(&mut self, node: &'a parse_ast::Declaration, _span: &'a Span) -> ID {
+        if !node.declarators.is_empty() || node.specifiers.len() != 1 {
+            panic!("Feature not implemented");
+        }
+        let struct_type = match node.specifiers[0].node {
+            parse_ast::DeclarationSpecifier::TypeSpecifier(ref t) => match t.node {
+                parse_ast::TypeSpecifier::Struct(ref s) => &s.node,
+                _ => panic!("Feature not implemented"),
+            },
+            _ => panic!("Feature not implemented"),
+        };
+        if struct_type.kind.node != parse_ast::StructKind::Struct {
+            panic!("Feature not implemented");
+        }
+        let (name, declarations) = match (&struct_type.identifier, &struct_type.declarations) {
+            (Some(identifier), Some(declarations)) => (identifier.node.name.clone(), declarations),
             _ => panic!("Feature not implemented"),
+        };
+        let mut field_ids = vec![];
+        for declaration in declarations {
+            match declaration.node {
+                parse_ast::StructDeclaration::Field(ref f) => {
+                    field_ids.extend(self.visit_struct_field(&f.node, &f.span))
+                }
+                _ => panic!("Feature not implemented"),
+            }
+        }
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        let relation = AstRelation::StructDef {
+            id: node_id,
+            name,
+            field_ids: field_ids.clone(),
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.replace_children(node_id, field_ids);
+        return node_id;
+    }
+
+    // Each declarator becomes its own field (with its own type node) in declaration order.
+    fn visit_struct_field(&mut self, node: &'a parse_ast::StructField, _span: &'a Span) -> Vec<ID> {
+        let mut field_ids = vec![];
+        for declarator in &node.declarators {
+            let declarator = match (&declarator.node.declarator, &declarator.node.bit_width) {
+                (Some(declarator), None) => declarator,
+                _ => panic!("Feature not implemented"),
+            };
+            let type_id = self.visit_specifier_qualifiers(&node.specifiers);
+            let type_id = self.visit_type_declarators(&declarator.node, type_id);
+            let field_name = self.visit_declarator(&declarator.node, &declarator.span);
+            let node_id = self.current_max_id;
+            self.current_max_id = self.current_max_id + 1;
+            let relation = AstRelation::Field {
+                id: node_id,
+                field_name,
+                type_id,
+            };
+            self.tree.add_node(node_id, relation);
+            self.tree.link_child(node_id, type_id);
+            field_ids.push(node_id);
         }
+        return field_ids;
     }
 
     // A function definition results in multiple nodes.
@@ -88,11 +577,7 @@ impl<'a> AstBuilder {
         _span: &'a Span,
     ) -> ID {
         // Get return type node ID (after creating node).
-        // We are for now assuming that there is only a type specifier (in any case, it will just get the last specifier).
-        let mut return_type_id = 0;
-        for specifier in &node.specifiers {
-            return_type_id = self.visit_declaration_specifier(&specifier.node, &specifier.span);
-        }
+        let return_type_id = self.visit_declaration_specifiers(&node.specifiers);
         // Get function body compound ID (after creating node).
         let body_id = self.visit_statement(&node.statement.node, &node.statement.span);
         // We'll create the function definition node in the declarator since it hold most of the information.
@@ -104,96 +589,118 @@ impl<'a> AstBuilder {
         );
     }
 
-    fn visit_declaration_specifier(
+    // A declaration can carry several type specifiers (e.g. unsigned long int) which together make up one type.
+    fn visit_declaration_specifiers(
         &mut self,
-        node: &'a parse_ast::DeclarationSpecifier,
-        _span: &'a Span,
+        specifiers: &'a [Node<parse_ast::DeclarationSpecifier>],
     ) -> ID {
-        match *node {
-            parse_ast::DeclarationSpecifier::TypeSpecifier(ref t) => {
-                return self.visit_type_specifier(&t.node, &t.span)
+        let mut type_specifiers = vec![];
+        for specifier in specifiers {
+            match specifier.node {
+                parse_ast::DeclarationSpecifier::TypeSpecifier(ref t) => {
+                    type_specifiers.push(&t.node)
+                }
+                // Qualifiers (e.g. const) don't change the type.
+                parse_ast::DeclarationSpecifier::TypeQualifier(_) => {}
+                _ => panic!("Feature not implemented"),
             }
-            _ => panic!("Feature not implemented"),
         }
+        return self.add_type_node(&type_specifiers);
     }
 
-    fn visit_type_specifier(&mut self, node: &'a parse_ast::TypeSpecifier, _span: &'a Span) -> ID {
-        match *node {
-            parse_ast::TypeSpecifier::Void => {
-                let node_id = self.current_max_id;
-                self.current_max_id = self.current_max_id + 1;
-                let relation = AstRelation::Void { id: node_id };
-                self.tree.add_node(node_id, relation);
-                return node_id;
+    // Type names (e.g. in casts or struct fields) only carry specifiers and qualifiers.
+    fn visit_specifier_qualifiers(
+        &mut self,
+        specifiers: &'a [Node<parse_ast::SpecifierQualifier>],
+    ) -> ID {
+        let mut type_specifiers = vec![];
+        for specifier in specifiers {
+            match specifier.node {
+                parse_ast::SpecifierQualifier::TypeSpecifier(ref t) => {
+                    type_specifiers.push(&t.node)
+                }
+                parse_ast::SpecifierQualifier::TypeQualifier(_) => {}
+                _ => panic!("Feature not implemented"),
             }
-            parse_ast::TypeSpecifier::Int => {
-                let node_id = self.current_max_id;
-                self.current_max_id = self.current_max_id + 1;
-                let relation = AstRelation::Int { id: node_id };
-                self.tree.add_node(node_id, relation);
-                return node_id;
+        }
+        return self.add_type_node(&type_specifiers);
+    }
+
+    fn add_type_node(&mut self, type_specifiers: &[&'a parse_ast::TypeSpecifier]) -> ID {
+        if let [parse_ast::TypeSpecifier::TypedefName(name)] = type_specifiers {
+            return self.add_typedef_type(&name.node.name);
+        }
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        let relation = get_type_relation(type_specifiers, node_id);
+        self.tree.add_node(node_id, relation);
+        return node_id;
+    }
+
+    fn add_typedef_type(&mut self, name: &str) -> ID {
+        let typedef_type = match self.typedefs.get(name) {
+            Some(typedef_type) => typedef_type.clone(),
+            None => panic!("Unknown type name"),
+        };
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        let specifiers: Vec<&parse_ast::TypeSpecifier> = typedef_type.specifiers.iter().collect();
+        let relation = get_type_relation(&specifiers, node_id);
+        self.tree.add_node(node_id, relation);
+        let mut type_id = node_id;
+        for derived in typedef_type.derived {
+            type_id = self.add_derived_type(derived, type_id);
+        }
+        return type_id;
+    }
+
+    // Record the aliased type for every declarator (e.g. typedef int myint, *myptr;).
+    fn visit_typedef(&mut self, node: &'a parse_ast::Declaration) {
+        let mut type_specifiers = vec![];
+        for specifier in &node.specifiers {
+            match specifier.node {
+                parse_ast::DeclarationSpecifier::StorageClass(_) => {}
+                parse_ast::DeclarationSpecifier::TypeSpecifier(ref t) => {
+                    type_specifiers.push(t.node.clone())
+                }
+                _ => panic!("Feature not implemented"),
             }
-            parse_ast::TypeSpecifier::Char => {
-                let node_id = self.current_max_id;
-                self.current_max_id = self.current_max_id + 1;
-                let relation = AstRelation::Char { id: node_id };
-                self.tree.add_node(node_id, relation);
-                return node_id;
+        }
+        let base_type = match type_specifiers.as_slice() {
+            [parse_ast::TypeSpecifier::TypedefName(name)] => {
+                match self.typedefs.get(&name.node.name) {
+                    Some(typedef_type) => typedef_type.clone(),
+                    None => panic!("Unknown type name"),
+                }
             }
-            parse_ast::TypeSpecifier::Float => {
-                let node_id = self.current_max_id;
-                self.current_max_id = self.current_max_id + 1;
-                let relation = AstRelation::Float { id: node_id };
-                self.tree.add_node(node_id, relation);
-                return node_id;
+            _ => TypedefType {
+                specifiers: type_specifiers,
+                derived: vec![],
+            },
+        };
+        for declarator in &node.declarators {
+            if declarator.node.initializer.is_some() {
+                panic!("Invalid typedef");
             }
-            _ => panic!("Feature not implemented"),
+            let declarator = &declarator.node.declarator;
+            let name = self.visit_declarator(&declarator.node, &declarator.span);
+            let mut typedef_type = base_type.clone();
+            typedef_type
+                .derived
+                .extend(get_derived_types(&declarator.node));
+            self.typedefs.insert(name, typedef_type);
         }
     }
 
     fn visit_statement(&mut self, node: &'a parse_ast::Statement, _span: &'a Span) -> ID {
         match *node {
             parse_ast::Statement::Compound(ref c) => {
-                let mut next_stmt_id = 0;
-                let mut start_id = 0;
-                let mut counter = 0;
-                // We will traverse the compound backwards in order to link the block items.
-                for item in c.iter().rev() {
-                    let stmt_id = self.visit_block_item(&item.node, &item.span);
-                    // Case: last item in compound.
-                    if counter == 0 {
-                        let node_id = self.current_max_id;
-                        self.current_max_id = self.current_max_id + 1;
-                        let relation = AstRelation::EndItem {
-                            id: node_id,
-                            stmt_id,
-                        };
-                        self.tree.add_node(node_id, relation);
-                        self.tree.link_child(node_id, stmt_id);
-                        next_stmt_id = node_id;
-                        // Case: first item in compound (could also be last).
-                        if counter == c.len() - 1 {
-                            start_id = node_id;
-                        }
-                    } else {
-                        let node_id = self.current_max_id;
-                        self.current_max_id = self.current_max_id + 1;
-                        let relation = AstRelation::Item {
-                            id: node_id,
-                            stmt_id,
-                            next_stmt_id,
-                        };
-                        self.tree.add_node(node_id, relation);
-                        self.tree.link_child(node_id, stmt_id);
-                        self.tree.link_child(node_id, next_stmt_id);
-                        next_stmt_id = node_id;
-                        // Case: first item in compound (could also be last).
-                        if counter == c.len() - 1 {
-                            start_id = node_id;
-                        }
-                    }
-                    counter = counter + 1;
+                // A block item can expand into several statements (e.g. multiple declarators).
+                let mut stmt_ids = vec![];
+                for item in c {
+                    stmt_ids.extend(self.visit_block_item(&item.node, &item.span));
                 }
+                let start_id = self.tree.add_item_chain(stmt_ids, &mut self.current_max_id);
                 let node_id = self.current_max_id;
                 self.current_max_id = self.current_max_id + 1;
                 let relation = AstRelation::Compound {
@@ -207,6 +714,35 @@ impl<'a> AstBuilder {
             parse_ast::Statement::Expression(Some(ref e)) => {
                 return self.visit_expression(&e.node, &e.span)
             }
+            // An empty statement on its own (e.g. a loop body) becomes an empty compound.
+            parse_ast::Statement::Expression(None) => {
+                let start_id = self.tree.add_item_chain(vec![], &mut self.current_max_id);
+                let node_id = self.current_max_id;
+                self.current_max_id = self.current_max_id + 1;
+                let relation = AstRelation::Compound {
+                    id: node_id,
+                    start_id,
+                };
+                self.tree.add_node(node_id, relation);
+                self.tree.link_child(node_id, start_id);
+                return node_id;
+            }
+            // A bare return returns void.
+            parse_ast::Statement::Return(None) => {
+                let expr_id = self.current_max_id;
+                self.current_max_id = self.current_max_id + 1;
+                self.tree
+                    .add_node(expr_id, AstRelation::Void { id: expr_id });
+                let node_id = self.current_max_id;
+                self.current_max_id = self.current_max_id + 1;
+                let relation = AstRelation::Return {
+                    id: node_id,
+                    expr_id,
+                };
+                self.tree.add_node(node_id, relation);
+                self.tree.link_child(node_id, expr_id);
+                return node_id;
+            }
             parse_ast::Statement::Return(Some(ref r)) => {
                 let expr_id = self.visit_expression(&r.node, &r.span);
                 let node_id = self.current_max_id;
@@ -225,6 +761,51 @@ impl<'a> AstBuilder {
             parse_ast::Statement::While(ref w) => {
                 return self.visit_while_statement(&w.node, &w.span);
             }
+            parse_ast::Statement::Switch(ref s) => {
+                return self.visit_switch_statement(&s.node, &s.span);
+            }
+            parse_ast::Statement::Break => {
+                let node_id = self.current_max_id;
+                self.current_max_id = self.current_max_id + 1;
+                self.tree
+                    .add_node(node_id, AstRelation::Break { id: node_id });
+                return node_id;
+            }
+            parse_ast::Statement::Continue => {
+                let node_id = self.current_max_id;
+                self.current_max_id = self.current_max_id + 1;
+                self.tree
+                    .add_node(node_id, AstRelation::Continue { id: node_id });
+                return node_id;
+            }
+            // Case labels are handled by the enclosing switch.
+            parse_ast::Statement::Labeled(ref l) => {
+                let name = match l.node.label.node {
+                    parse_ast::Label::Identifier(ref i) => i.node.name.clone(),
+                    _ => panic!("Feature not implemented"),
+                };
+                let stmt_id = self.visit_statement(&l.node.statement.node, &l.node.statement.span);
+                let node_id = self.current_max_id;
+                self.current_max_id = self.current_max_id + 1;
+                let relation = AstRelation::Label {
+                    id: node_id,
+                    name,
+                    stmt_id,
+                };
+                self.tree.add_node(node_id, relation);
+                self.tree.link_child(node_id, stmt_id);
+                return node_id;
+            }
+            parse_ast::Statement::Goto(ref i) => {
+                let node_id = self.current_max_id;
+                self.current_max_id = self.current_max_id + 1;
+                let relation = AstRelation::Goto {
+                    id: node_id,
+                    label_name: i.node.name.clone(),
+                };
+                self.tree.add_node(node_id, relation);
+                return node_id;
+            }
             _ => {
                 println!("{:?}", node);
                 panic!("Feature not implemented");
@@ -232,10 +813,20 @@ impl<'a> AstBuilder {
         }
     }
 
-    fn visit_block_item(&mut self, node: &'a parse_ast::BlockItem, _span: &'a Span) -> ID {
+    fn visit_block_item(&mut self, node: &'a parse_ast::BlockItem, _span: &'a Span) -> Vec<ID> {
         match *node {
+            // Empty statements inside a block are skipped.
+            parse_ast::BlockItem::Statement(ref s)
+                if matches!(s.node, parse_ast::Statement::Expression(None)) =>
+            {
+                return vec![]
+            }
             parse_ast::BlockItem::Statement(ref s) => {
-                return self.visit_statement(&s.node, &s.span)
+                return vec![self.visit_statement(&s.node, &s.span)]
+            }
+            parse_ast::BlockItem::Declaration(ref d) if is_typedef(&d.node) => {
+                self.visit_typedef(&d.node);
+                return vec![];
             }
             parse_ast::BlockItem::Declaration(ref d) => {
                 return self.visit_declaration(&d.node, &d.span)
@@ -245,16 +836,14 @@ impl<'a> AstBuilder {
     }
 
     // Currently just deals with normal assignments.
-    fn visit_declaration(&mut self, node: &'a parse_ast::Declaration, _span: &'a Span) -> ID {
-        let mut type_id = 0;
-        for specifier in &node.specifiers {
-            type_id = self.visit_declaration_specifier(&specifier.node, &specifier.span);
+    // Each declarator becomes its own statement (with its own type node) in declaration order.
+    fn visit_declaration(&mut self, node: &'a parse_ast::Declaration, _span: &'a Span) -> Vec<ID> {
+        let mut stmt_ids = vec![];
+        for declarator in &node.declarators {
+            let type_id = self.visit_declaration_specifiers(&node.specifiers);
+            stmt_ids.push(self.visit_init_declarator(&declarator.node, &declarator.span, type_id));
         }
-        return self.visit_init_declarator(
-            &node.declarators[0].node,
-            &node.declarators[0].span,
-            type_id,
-        );
+        return stmt_ids;
     }
 
     fn visit_init_declarator(
@@ -263,28 +852,60 @@ impl<'a> AstBuilder {
         _span: &'a Span,
         type_id: ID,
     ) -> ID {
+        let type_id = self.visit_type_declarators(&node.declarator.node, type_id);
         let var_name = self.visit_declarator(&node.declarator.node, &node.declarator.span);
         if let Some(ref initializer) = node.initializer {
-            match initializer.node {
-                parse_ast::Initializer::Expression(ref e) => {
-                    let expr_id = self.visit_expression(&e.node, &e.span);
-                    let node_id = self.current_max_id;
-                    self.current_max_id = self.current_max_id + 1;
-                    let relation = AstRelation::Assign {
-                        id: node_id,
-                        var_name: var_name.clone(),
-                        type_id,
-                        expr_id,
-                    };
-                    self.tree.add_node(node_id, relation);
-                    self.tree.link_child(node_id, type_id);
-                    self.tree.link_child(node_id, expr_id);
-                    return node_id;
+            let expr_id = self.visit_initializer(&initializer.node);
+            let node_id = self.current_max_id;
+            self.current_max_id = self.current_max_id + 1;
+            let relation = AstRelation::Assign {
+                id: node_id,
+                var_name: var_name.clone(),
+                type_id,
+                expr_id,
+            };
+            self.tree.add_node(node_id, relation);
+            self.tree.link_child(node_id, type_id);
+            self.tree.link_child(node_id, expr_id);
+            return node_id;
+        } else {
+            let node_id = self.current_max_id;
+            self.current_max_id = self.current_max_id + 1;
+            let relation = AstRelation::Decl {
+                id: node_id,
+                var_name: var_name.clone(),
+                type_id,
+            };
+            self.tree.add_node(node_id, relation);
+            self.tree.link_child(node_id, type_id);
+            return node_id;
+        }
+    }
+
+    // Designated initializers (e.g. {[1] = 2}) aren't supported.
+    fn visit_initializer(&mut self, node: &'a parse_ast::Initializer) -> ID {
+        match node {
+            parse_ast::Initializer::Expression(ref e) => self.visit_expression(&e.node, &e.span),
+            parse_ast::Initializer::List(ref items) => {
+                let mut elem_ids: Vec<ID> = vec![];
+                for item in items {
+                    if !item.node.designation.is_empty() {
+                        panic!("Feature not implemented");
+                    }
+                    elem_ids.push(self.visit_initializer(&item.node.initializer.node));
                 }
-                _ => panic!("Feature not implemented"),
+                let node_id = self.current_max_id;
+                self.current_max_id = self.current_max_id + 1;
+                let relation = AstRelation::ArrayInit {
+                    id: node_id,
+                    elem_ids: elem_ids.clone(),
+                };
+                self.tree.add_node(node_id, relation);
+                for elem_id in elem_ids {
+                    self.tree.link_child(node_id, elem_id);
+                }
+                return node_id;
             }
-        } else {
-            panic!("Feature not implemented")
         }
     }
 
@@ -308,22 +929,126 @@ impl<'a> AstBuilder {
         return node_id;
     }
 
-    fn visit_if_statement(&mut self, node: &'a parse_ast::IfStatement, _span: &'a Span) -> ID {
-        let cond_id = self.visit_expression(&node.condition.node, &node.condition.span);
-        let then_id = self.visit_statement(&node.then_statement.node, &node.then_statement.span);
-        if let Some(ref e) = node.else_statement {
-            let else_id = self.visit_statement(&e.node, &e.span);
-            let node_id = self.current_max_id;
-            self.current_max_id = self.current_max_id + 1;
-            let relation = AstRelation::IfElse {
-                id: node_id,
-                cond_id,
-                then_id,
-                else_id,
-            };
-            self.tree.add_node(node_id, relation);
-            self.tree.link_child(node_id, cond_id);
-            self.tree.link_child(node_id, then_id);
+    // Each case label starts a new statement sequence which becomes the body of that case.
+    fn visit_switch_statement(
+        &mut self,
+        node: &'a parse_ast::SwitchStatement,
+        _span: &'a Span,
+    ) -> ID {
+        let expr_id = self.visit_expression(&node.expression.node, &node.expression.span);
+        let block_items = match node.statement.node {
+            parse_ast::Statement::Compound(ref c) => c,
+            _ => panic!("Feature not implemented"),
+        };
+        let mut case_ids = vec![];
+        let mut index = 0;
+        while index < block_items.len() {
+            let labeled = match block_items[index].node {
+                parse_ast::BlockItem::Statement(ref s) => match s.node {
+                    parse_ast::Statement::Labeled(ref l) => l,
+                    _ => panic!("Expected a case label"),
+                },
+                _ => panic!("Expected a case label"),
+            };
+            // Collect the following statements up to the next case label.
+            let mut end = index + 1;
+            while end < block_items.len() && !is_case_label(&block_items[end].node) {
+                end = end + 1;
+            }
+            case_ids.push(self.visit_case(
+                &labeled.node,
+                &labeled.span,
+                &block_items[index + 1..end],
+            ));
+            index = end;
+        }
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        let relation = AstRelation::Switch {
+            id: node_id,
+            expr_id,
+            case_ids: case_ids.clone(),
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.link_child(node_id, expr_id);
+        for case_id in case_ids {
+            self.tree.link_child(node_id, case_id);
+        }
+        return node_id;
+    }
+
+    fn visit_case(
+        &mut self,
+        node: &'a parse_ast::LabeledStatement,
+        _span: &'a Span,
+        following_items: &'a [Node<parse_ast::BlockItem>],
+    ) -> ID {
+        let mut label_id = None;
+        match node.label.node {
+            parse_ast::Label::Case(ref e) => {
+                label_id = Some(self.visit_expression(&e.node, &e.span));
+            }
+            parse_ast::Label::Default => {}
+            _ => panic!("Feature not implemented"),
+        }
+        // The case body is chained like a compound (the labeled statement is the first item).
+        let mut stmt_ids = vec![self.visit_statement(&node.statement.node, &node.statement.span)];
+        for item in following_items {
+            stmt_ids.extend(self.visit_block_item(&item.node, &item.span));
+        }
+        let start_id = self.tree.add_item_chain(stmt_ids, &mut self.current_max_id);
+        let body_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        let relation = AstRelation::Compound {
+            id: body_id,
+            start_id,
+        };
+        self.tree.add_node(body_id, relation);
+        self.tree.link_child(body_id, start_id);
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        if let Some(label_id) = label_id {
+            let relation = AstRelation::Case {
+                id: node_id,
+                label_id,
+                body_id,
+            };
+            self.tree.add_node(node_id, relation);
+            self.tree.link_child(node_id, label_id);
+            self.tree.link_child(node_id, body_id);
+        } else {
+            let relation = AstRelation::DefaultCase {
+                id: node_id,
+                body_id,
+            };
+            self.tree.add_node(node_id, relation);
+            self.tree.link_child(node_id, body_id);
+        }
+        return node_id;
+    }
+
+    fn report_error(&mut self, offset: usize, message: &str) {
+        if self.error.is_none() {
+            self.error = Some((offset, message.to_string()));
+        }
+    }
+
+    fn visit_if_statement(&mut self, node: &'a parse_ast::IfStatement, _span: &'a Span) -> ID {
+        let cond_id = self.visit_expression(&node.condition.node, &node.condition.span);
+        let then_id = self.visit_statement(&node.then_statement.node, &node.then_statement.span);
+        if let Some(ref e) = node.else_statement {
+            let else_id = self.visit_statement(&e.node, &e.span);
+            let node_id = self.current_max_id;
+            self.current_max_id = self.current_max_id + 1;
+            let relation = AstRelation::IfElse {
+                id: node_id,
+                cond_id,
+                then_id,
+                else_id,
+            };
+            self.tree.add_node(node_id, relation);
+            self.tree.link_child(node_id, cond_id);
+            self.tree.link_child(node_id, then_id);
             self.tree.link_child(node_id, else_id);
             return node_id;
         } else {
@@ -341,7 +1066,7 @@ impl<'a> AstBuilder {
         }
     }
 
-    fn visit_expression(&mut self, node: &'a parse_ast::Expression, _span: &'a Span) -> ID {
+    fn visit_expression(&mut self, node: &'a parse_ast::Expression, span: &'a Span) -> ID {
         match *node {
             parse_ast::Expression::Identifier(ref i) => {
                 let var_name = i.node.name.clone();
@@ -361,11 +1086,152 @@ impl<'a> AstBuilder {
             parse_ast::Expression::BinaryOperator(ref b) => {
                 return self.visit_binary_operator_expression(&b.node, &b.span)
             }
-            parse_ast::Expression::Statement(ref s) => self.visit_statement(&s.node, &s.span),
+            parse_ast::Expression::UnaryOperator(ref u) => {
+                return self.visit_unary_operator_expression(&u.node, &u.span)
+            }
+            parse_ast::Expression::Cast(ref c) => {
+                return self.visit_cast_expression(&c.node, &c.span)
+            }
+            parse_ast::Expression::Member(ref m) => {
+                return self.visit_member_expression(&m.node, &m.span)
+            }
+            // Both forms of sizeof give the same relation, just with a type or an expression as operand.
+            parse_ast::Expression::SizeOfTy(ref s) => {
+                let operand_id = self.visit_type_name(&s.node.0.node);
+                return self.visit_sizeof(operand_id);
+            }
+            parse_ast::Expression::SizeOfVal(ref s) => {
+                let operand_id = self.visit_expression(&s.node.0.node, &s.node.0.span);
+                return self.visit_sizeof(operand_id);
+            }
+            parse_ast::Expression::Conditional(ref c) => {
+                return self.visit_conditional_expression(&c.node, &c.span)
+            }
+            // GNU statement expressions (({ ... })) are rejected, the void node only stands in until the error is returned.
+            parse_ast::Expression::Statement(_) => {
+                self.report_error(span.start, "statement expressions are not supported");
+                let node_id = self.current_max_id;
+                self.current_max_id = self.current_max_id + 1;
+                self.tree
+                    .add_node(node_id, AstRelation::Void { id: node_id });
+                return node_id;
+            }
             _ => panic!("Feature not implemented"),
         }
     }
 
+    fn visit_cast_expression(
+        &mut self,
+        node: &'a parse_ast::CastExpression,
+        _span: &'a Span,
+    ) -> ID {
+        let target_type_id = self.visit_type_name(&node.type_name.node);
+        let expr_id = self.visit_expression(&node.expression.node, &node.expression.span);
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        let relation = AstRelation::Cast {
+            id: node_id,
+            target_type_id,
+            expr_id,
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.link_child(node_id, target_type_id);
+        self.tree.link_child(node_id, expr_id);
+        return node_id;
+    }
+
+    fn visit_conditional_expression(
+        &mut self,
+        node: &'a parse_ast::ConditionalExpression,
+        _span: &'a Span,
+    ) -> ID {
+        let cond_id = self.visit_expression(&node.condition.node, &node.condition.span);
+        let then_id = self.visit_expression(&node.then_expression.node, &node.then_expression.span);
+        let else_id = self.visit_expression(&node.else_expression.node, &node.else_expression.span);
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        let relation = AstRelation::Conditional {
+            id: node_id,
+            cond_id,
+            then_id,
+            else_id,
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.link_child(node_id, cond_id);
+        self.tree.link_child(node_id, then_id);
+        self.tree.link_child(node_id, else_id);
+        return node_id;
+    }
+
+    // Abstract declarators can still make the type a pointer (e.g. (int *)p).
+    fn visit_type_name(&mut self, node: &'a parse_ast::TypeName) -> ID {
+        let type_id = self.visit_specifier_qualifiers(&node.specifiers);
+        match node.declarator {
+            Some(ref declarator) => self.visit_type_declarators(&declarator.node, type_id),
+            None => type_id,
+        }
+    }
+
+    fn visit_sizeof(&mut self, operand_id: ID) -> ID {
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        let relation = AstRelation::SizeOf {
+            id: node_id,
+            operand_id,
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.link_child(node_id, operand_id);
+        return node_id;
+    }
+
+    fn visit_member_expression(
+        &mut self,
+        node: &'a parse_ast::MemberExpression,
+        _span: &'a Span,
+    ) -> ID {
+        let base_id = self.visit_expression(&node.expression.node, &node.expression.span);
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        let relation = AstRelation::Member {
+            id: node_id,
+            base_id,
+            field_name: node.identifier.node.name.clone(),
+            arrow: node.operator.node == parse_ast::MemberOperator::Indirect,
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.link_child(node_id, base_id);
+        return node_id;
+    }
+
+    fn visit_unary_operator_expression(
+        &mut self,
+        node: &'a parse_ast::UnaryOperatorExpression,
+        _span: &'a Span,
+    ) -> ID {
+        let expr_id = self.visit_expression(&node.operand.node, &node.operand.span);
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        let relation = match node.operator.node {
+            parse_ast::UnaryOperator::Indirection => AstRelation::Deref {
+                id: node_id,
+                expr_id,
+            },
+            parse_ast::UnaryOperator::Address => AstRelation::AddressOf {
+                id: node_id,
+                expr_id,
+            },
+            parse_ast::UnaryOperator::Negate => AstRelation::UnaryOp {
+                id: node_id,
+                op: String::from("!"),
+                expr_id,
+            },
+            _ => panic!("Feature not implemented"),
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.link_child(node_id, expr_id);
+        return node_id;
+    }
+
     fn visit_call_expression(
         &mut self,
         node: &'a parse_ast::CallExpression,
@@ -401,152 +1267,62 @@ impl<'a> AstBuilder {
         let arg2_id = self.visit_expression(&node.rhs.node, &node.rhs.span);
         let node_id = self.current_max_id;
         self.current_max_id = self.current_max_id + 1;
-        match node.operator.node {
-            parse_ast::BinaryOperator::Plus => {
-                let relation = AstRelation::BinaryOp {
-                    id: node_id,
-                    arg1_id,
-                    arg2_id,
-                };
-                self.tree.add_node(node_id, relation);
-                self.tree.link_child(node_id, arg1_id);
-                self.tree.link_child(node_id, arg2_id);
-                return node_id;
-            }
-            parse_ast::BinaryOperator::Minus => {
-                let relation = AstRelation::BinaryOp {
-                    id: node_id,
-                    arg1_id,
-                    arg2_id,
-                };
-                self.tree.add_node(node_id, relation);
-                self.tree.link_child(node_id, arg1_id);
-                self.tree.link_child(node_id, arg2_id);
-                return node_id;
-            }
-            parse_ast::BinaryOperator::Multiply => {
-                let relation = AstRelation::BinaryOp {
-                    id: node_id,
-                    arg1_id,
-                    arg2_id,
-                };
-                self.tree.add_node(node_id, relation);
-                self.tree.link_child(node_id, arg1_id);
-                self.tree.link_child(node_id, arg2_id);
-                return node_id;
-            }
-            parse_ast::BinaryOperator::Divide => {
-                let relation = AstRelation::BinaryOp {
-                    id: node_id,
-                    arg1_id,
-                    arg2_id,
-                };
-                self.tree.add_node(node_id, relation);
-                self.tree.link_child(node_id, arg1_id);
-                self.tree.link_child(node_id, arg2_id);
-                return node_id;
-            }
-            parse_ast::BinaryOperator::Greater => {
-                let relation = AstRelation::BinaryOp {
-                    id: node_id,
-                    arg1_id,
-                    arg2_id,
-                };
-                self.tree.add_node(node_id, relation);
-                self.tree.link_child(node_id, arg1_id);
-                self.tree.link_child(node_id, arg2_id);
-                return node_id;
-            }
-            parse_ast::BinaryOperator::GreaterOrEqual => {
-                let relation = AstRelation::BinaryOp {
-                    id: node_id,
-                    arg1_id,
-                    arg2_id,
-                };
-                self.tree.add_node(node_id, relation);
-                self.tree.link_child(node_id, arg1_id);
-                self.tree.link_child(node_id, arg2_id);
-                return node_id;
-            }
-            parse_ast::BinaryOperator::Less => {
-                let relation = AstRelation::BinaryOp {
-                    id: node_id,
-                    arg1_id,
-                    arg2_id,
-                };
-                self.tree.add_node(node_id, relation);
-                self.tree.link_child(node_id, arg1_id);
-                self.tree.link_child(node_id, arg2_id);
-                return node_id;
-            }
-            parse_ast::BinaryOperator::LessOrEqual => {
-                let relation = AstRelation::BinaryOp {
-                    id: node_id,
-                    arg1_id,
-                    arg2_id,
-                };
-                self.tree.add_node(node_id, relation);
-                self.tree.link_child(node_id, arg1_id);
-                self.tree.link_child(node_id, arg2_id);
-                return node_id;
-            }
-            parse_ast::BinaryOperator::Equals => {
-                let relation = AstRelation::BinaryOp {
-                    id: node_id,
-                    arg1_id,
-                    arg2_id,
-                };
-                self.tree.add_node(node_id, relation);
-                self.tree.link_child(node_id, arg1_id);
-                self.tree.link_child(node_id, arg2_id);
-                return node_id;
-            }
-            parse_ast::BinaryOperator::LogicalAnd => {
-                let relation = AstRelation::BinaryOp {
-                    id: node_id,
-                    arg1_id,
-                    arg2_id,
-                };
-                self.tree.add_node(node_id, relation);
-                self.tree.link_child(node_id, arg1_id);
-                self.tree.link_child(node_id, arg2_id);
-                return node_id;
-            }
-            parse_ast::BinaryOperator::LogicalOr => {
-                let relation = AstRelation::BinaryOp {
+        let op = match node.operator.node {
+            parse_ast::BinaryOperator::Index => {
+                let relation = AstRelation::Index {
                     id: node_id,
-                    arg1_id,
-                    arg2_id,
-                };
-                self.tree.add_node(node_id, relation);
-                self.tree.link_child(node_id, arg1_id);
-                self.tree.link_child(node_id, arg2_id);
-                return node_id;
-            }
-            parse_ast::BinaryOperator::Assign => {
-                let relation = AstRelation::BinaryOp {
-                    id: node_id,
-                    arg1_id,
-                    arg2_id,
+                    array_id: arg1_id,
+                    index_id: arg2_id,
                 };
                 self.tree.add_node(node_id, relation);
                 self.tree.link_child(node_id, arg1_id);
                 self.tree.link_child(node_id, arg2_id);
                 return node_id;
             }
+            parse_ast::BinaryOperator::Plus => "+",
+            parse_ast::BinaryOperator::Minus => "-",
+            parse_ast::BinaryOperator::Multiply => "*",
+            parse_ast::BinaryOperator::Divide => "/",
+            parse_ast::BinaryOperator::Modulo => "%",
+            parse_ast::BinaryOperator::Greater => ">",
+            parse_ast::BinaryOperator::GreaterOrEqual => ">=",
+            parse_ast::BinaryOperator::Less => "<",
+            parse_ast::BinaryOperator::LessOrEqual => "<=",
+            parse_ast::BinaryOperator::Equals => "==",
+            parse_ast::BinaryOperator::NotEquals => "!=",
+            parse_ast::BinaryOperator::LogicalAnd => "&&",
+            parse_ast::BinaryOperator::LogicalOr => "||",
+            parse_ast::BinaryOperator::BitwiseAnd => "&",
+            parse_ast::BinaryOperator::BitwiseOr => "|",
+            parse_ast::BinaryOperator::BitwiseXor => "^",
+            parse_ast::BinaryOperator::ShiftLeft => "<<",
+            parse_ast::BinaryOperator::ShiftRight => ">>",
+            parse_ast::BinaryOperator::Assign => "=",
             _ => panic!("Feature not implemented"),
-        }
+        };
+        let relation = AstRelation::BinaryOp {
+            id: node_id,
+            op: String::from(op),
+            arg1_id,
+            arg2_id,
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.link_child(node_id, arg1_id);
+        self.tree.link_child(node_id, arg2_id);
+        return node_id;
     }
 
     fn visit_constant(&mut self, node: &'a parse_ast::Constant, _span: &'a Span) -> ID {
         let node_id = self.current_max_id;
         self.current_max_id = self.current_max_id + 1;
         match *node {
-            parse_ast::Constant::Integer(_) => {
-                let relation = AstRelation::Int { id: node_id };
+            // The base doesn't affect the type, the suffix does.
+            parse_ast::Constant::Integer(ref i) => {
+                let relation = get_integer_constant_relation(&i.suffix, node_id);
                 self.tree.add_node(node_id, relation);
                 return node_id;
             }
+            // Floating constants are typed as float regardless of suffix (there are no implicit conversions).
             parse_ast::Constant::Float(_) => {
                 let relation = AstRelation::Float { id: node_id };
                 self.tree.add_node(node_id, relation);
@@ -569,9 +1345,14 @@ impl<'a> AstBuilder {
         body_id: ID,
     ) -> ID {
         let fun_name = self.visit_declarator_kind(&node.kind.node, &node.kind.span);
+        // Pointers in the function declarator belong to the return type.
+        let return_type_id = self.visit_type_declarators(node, return_type_id);
         let mut arg_ids = vec![];
         for derived in &node.derived {
-            arg_ids = self.visit_derived_declarator(&derived.node, &derived.span);
+            match derived.node {
+                parse_ast::DerivedDeclarator::Pointer(_) => {}
+                _ => arg_ids = self.visit_derived_declarator(&derived.node, &derived.span),
+            }
         }
         let node_id = self.current_max_id;
         self.current_max_id = self.current_max_id + 1;
@@ -597,7 +1378,12 @@ impl<'a> AstBuilder {
     ) -> String {
         match *node {
             parse_ast::DeclaratorKind::Identifier(ref i) => return i.node.name.clone(),
-            _ => panic!("Feature not implemented"),
+            // Unnamed parameters (e.g. int (*)(int) or char[]).
+            parse_ast::DeclaratorKind::Abstract => return String::from(""),
+            // The name of a function pointer is inside the parentheses.
+            parse_ast::DeclaratorKind::Declarator(ref d) => {
+                return self.visit_declarator_kind(&d.node.kind.node, &d.node.kind.span)
+            }
         }
     }
 
@@ -611,6 +1397,10 @@ impl<'a> AstBuilder {
             parse_ast::DerivedDeclarator::Function(ref f) => {
                 return self.visit_function_declarator(&f.node, &f.span)
             }
+            // lang_c parses an empty parameter list (e.g. int foo()) as a K&R one without identifiers.
+            parse_ast::DerivedDeclarator::KRFunction(ref identifiers) if identifiers.is_empty() => {
+                return vec![]
+            }
             _ => panic!("Feature not implemented"),
         }
     }
@@ -621,6 +1411,10 @@ impl<'a> AstBuilder {
         _span: &'a Span,
     ) -> Vec<ID> {
         let mut arg_ids = vec![];
+        // A (void) parameter list means no parameters at all.
+        if is_void_parameter_list(&node.parameters) {
+            return arg_ids;
+        }
         for arg in &node.parameters {
             arg_ids.push(self.visit_parameter_declaration(&arg.node, &arg.span));
         }
@@ -632,12 +1426,23 @@ impl<'a> AstBuilder {
         node: &'a parse_ast::ParameterDeclaration,
         _span: &'a Span,
     ) -> ID {
-        let mut type_id = 0;
+        let mut type_specifiers = vec![];
         for specifier in &node.specifiers {
-            type_id = self.visit_declaration_specifier(&specifier.node, &specifier.span);
+            match specifier.node {
+                parse_ast::DeclarationSpecifier::TypeSpecifier(ref t) => {
+                    type_specifiers.push(&t.node)
+                }
+                // Register is the only storage class allowed here and doesn't change the type.
+                parse_ast::DeclarationSpecifier::TypeQualifier(_)
+                | parse_ast::DeclarationSpecifier::StorageClass(_) => {}
+                _ => panic!("Feature not implemented"),
+            }
         }
+        // lang_c's grammar already requires at least one type specifier.
+        let mut type_id = self.add_type_node(&type_specifiers);
         let var_name;
         if let Some(ref declarator) = node.declarator {
+            type_id = self.visit_type_declarators(&declarator.node, type_id);
             var_name = self.visit_declarator(&declarator.node, &declarator.span);
         } else {
             var_name = String::from("");
@@ -654,20 +1459,623 @@ impl<'a> AstBuilder {
         return node_id;
     }
 
+    // Wrap the declared type for every pointer or array in the declarator (e.g. int **p or int a[10]).
+    // A parenthesised pointer followed by a parameter list is a function pointer (e.g. int (*fp)(int)).
+    fn visit_type_declarators(&mut self, node: &'a parse_ast::Declarator, type_id: ID) -> ID {
+        let mut type_id = type_id;
+        for derived in get_derived_types(node) {
+            type_id = self.add_derived_type(derived, type_id);
+        }
+        if let parse_ast::DeclaratorKind::Declarator(ref inner) = node.kind.node {
+            let function = node.derived.iter().find_map(|derived| match derived.node {
+                parse_ast::DerivedDeclarator::Function(ref f) => Some(f),
+                _ => None,
+            });
+            match (function, inner.node.derived.as_slice()) {
+                (Some(f), [derived])
+                    if matches!(derived.node, parse_ast::DerivedDeclarator::Pointer(_)) =>
+                {
+                    type_id = self.visit_function_pointer(&f.node, type_id);
+                }
+                _ => panic!("Feature not implemented"),
+            }
+        }
+        return type_id;
+    }
+
+    fn visit_function_pointer(
+        &mut self,
+        node: &'a parse_ast::FunctionDeclarator,
+        return_type_id: ID,
+    ) -> ID {
+        let mut arg_type_ids = vec![];
+        if !is_void_parameter_list(&node.parameters) {
+            for arg in &node.parameters {
+                let mut arg_type_id = self.visit_declaration_specifiers(&arg.node.specifiers);
+                if let Some(ref declarator) = arg.node.declarator {
+                    arg_type_id = self.visit_type_declarators(&declarator.node, arg_type_id);
+                }
+                arg_type_ids.push(arg_type_id);
+            }
+        }
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        let relation = AstRelation::FunPtr {
+            id: node_id,
+            return_type_id,
+            arg_type_ids: arg_type_ids.clone(),
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.link_child(node_id, return_type_id);
+        for arg_type_id in arg_type_ids {
+            self.tree.link_child(node_id, arg_type_id);
+        }
+        return node_id;
+    }
+
+    fn add_derived_type(&mut self, derived: DerivedType, type_id: ID) -> ID {
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        let relation = match derived {
+            DerivedType::Pointer => AstRelation::Ptr {
+                id: node_id,
+                pointee_type_id: type_id,
+            },
+            DerivedType::Array(size) => AstRelation::Array {
+                id: node_id,
+                elem_type_id: type_id,
+                size,
+            },
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.link_child(node_id, type_id);
+        return node_id;
+    }
+
     // Separate method for argument declarator since we only need the variable name from here.
     fn visit_declarator(&mut self, node: &'a parse_ast::Declarator, _span: &'a Span) -> String {
         return self.visit_declarator_kind(&node.kind.node, &node.kind.span);
     }
 }
 
+// Combine the type specifiers of a declaration into a single leaf type.
+// Integer widths and signedness aren't distinguished yet, so they all collapse to int (long double to double).
+fn get_type_relation(specifiers: &[&parse_ast::TypeSpecifier], id: ID) -> AstRelation {
+    let (mut voids, mut chars, mut ints, mut floats, mut doubles) = (0, 0, 0, 0, 0);
+    let (mut shorts, mut longs, mut signeds, mut unsigneds) = (0, 0, 0, 0);
+    let (mut structs, mut struct_name) = (0, None);
+    for specifier in specifiers {
+        match specifier {
+            // Structs can only be referred to by name here (definitions are top level declarations).
+            parse_ast::TypeSpecifier::Struct(s) => match (&s.node.identifier, &s.node.declarations)
+            {
+                (Some(identifier), None) if s.node.kind.node == parse_ast::StructKind::Struct => {
+                    structs += 1;
+                    struct_name = Some(identifier.node.name.clone());
+                }
+                _ => panic!("Feature not implemented"),
+            },
+            parse_ast::TypeSpecifier::Void => voids += 1,
+            parse_ast::TypeSpecifier::Char => chars += 1,
+            parse_ast::TypeSpecifier::Int => ints += 1,
+            parse_ast::TypeSpecifier::Float => floats += 1,
+            parse_ast::TypeSpecifier::Double => doubles += 1,
+            parse_ast::TypeSpecifier::Short => shorts += 1,
+            parse_ast::TypeSpecifier::Long => longs += 1,
+            parse_ast::TypeSpecifier::Signed => signeds += 1,
+            parse_ast::TypeSpecifier::Unsigned => unsigneds += 1,
+            _ => panic!("Feature not implemented"),
+        }
+    }
+    let base = voids + chars + ints + floats + doubles + structs;
+    let modifiers = shorts + longs + signeds + unsigneds;
+    if base > 1 || shorts > 1 || longs > 2 || signeds + unsigneds > 1 || (shorts > 0 && longs > 0) {
+        panic!("Invalid combination of type specifiers");
+    }
+    if let Some(name) = struct_name {
+        if modifiers > 0 {
+            panic!("Invalid combination of type specifiers");
+        }
+        return AstRelation::Struct { id, name };
+    }
+    if voids == 1 && modifiers == 0 {
+        return AstRelation::Void { id };
+    }
+    if floats == 1 && modifiers == 0 {
+        return AstRelation::Float { id };
+    }
+    if doubles == 1 && modifiers == longs && longs <= 1 {
+        return AstRelation::Double { id };
+    }
+    if chars == 1 && shorts + longs == 0 {
+        return AstRelation::Char { id };
+    }
+    if base == ints && (ints == 1 || modifiers > 0) {
+        return AstRelation::Int { id };
+    }
+    panic!("Invalid combination of type specifiers");
+}
+
+// The type a suffix (e.g. 1UL) gives an integer constant, the same as the matching specifiers would.
+// Like those, every width and signedness is an int for now.
+fn get_integer_constant_relation(suffix: &parse_ast::IntegerSuffix, id: ID) -> AstRelation {
+    if suffix.imaginary {
+        panic!("Feature not implemented");
+    }
+    let (unsigned, int, long) = (
+        parse_ast::TypeSpecifier::Unsigned,
+        parse_ast::TypeSpecifier::Int,
+        parse_ast::TypeSpecifier::Long,
+    );
+    let mut specifiers = match suffix.size {
+        parse_ast::IntegerSize::Int => vec![&int],
+        parse_ast::IntegerSize::Long => vec![&long],
+        parse_ast::IntegerSize::LongLong => vec![&long, &long],
+    };
+    if suffix.unsigned {
+        specifiers.push(&unsigned);
+    }
+    get_type_relation(&specifiers, id)
+}
+
+// Pointers and arrays of a declarator from the innermost type outwards (function declarators are skipped).
+fn get_derived_types(node: &parse_ast::Declarator) -> Vec<DerivedType> {
+    let mut derived_types = vec![];
+    for derived in &node.derived {
+        match derived.node {
+            parse_ast::DerivedDeclarator::Pointer(_) => derived_types.push(DerivedType::Pointer),
+            parse_ast::DerivedDeclarator::Array(ref a) => {
+                derived_types.push(DerivedType::Array(get_array_size(&a.node.size)))
+            }
+            _ => {}
+        }
+    }
+    return derived_types;
+}
+
+fn is_typedef(node: &parse_ast::Declaration) -> bool {
+    node.specifiers.iter().any(|specifier| {
+        matches!(
+            specifier.node,
+            parse_ast::DeclarationSpecifier::StorageClass(ref s)
+                if s.node == parse_ast::StorageClassSpecifier::Typedef
+        )
+    })
+}
+
+fn is_void_parameter_list(parameters: &[Node<parse_ast::ParameterDeclaration>]) -> bool {
+    if let [parameter] = parameters {
+        if let [specifier] = parameter.node.specifiers.as_slice() {
+            if let parse_ast::DeclarationSpecifier::TypeSpecifier(ref t) = specifier.node {
+                return parameter.node.declarator.is_none()
+                    && t.node == parse_ast::TypeSpecifier::Void;
+            }
+        }
+    }
+    false
+}
+
+// Array sizes have to be integer constants (unknown sizes are stored as 0).
+fn get_array_size(node: &parse_ast::ArraySize) -> i32 {
+    match *node {
+        parse_ast::ArraySize::Unknown => 0,
+        parse_ast::ArraySize::VariableExpression(ref e) => match e.node {
+            parse_ast::Expression::Constant(ref c) => match c.node {
+                parse_ast::Constant::Integer(ref i) => {
+                    let radix = match i.base {
+                        parse_ast::IntegerBase::Decimal => 10,
+                        parse_ast::IntegerBase::Octal => 8,
+                        parse_ast::IntegerBase::Hexadecimal => 16,
+                        _ => panic!("Feature not implemented"),
+                    };
+                    return i32::from_str_radix(&i.number, radix).unwrap();
+                }
+                _ => panic!("Expected an integer array size"),
+            },
+            _ => panic!("Feature not implemented"),
+        },
+        _ => panic!("Feature not implemented"),
+    }
+}
+
+fn is_case_label(node: &parse_ast::BlockItem) -> bool {
+    match *node {
+        parse_ast::BlockItem::Statement(ref s) => match s.node {
+            parse_ast::Statement::Labeled(ref l) => match l.node.label.node {
+                parse_ast::Label::Case(_) | parse_ast::Label::Default => true,
+                _ => false,
+            },
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::ast;
+    use crate::ast::{Location, Tree};
+    use crate::crate_path;
+    use crate::definitions::{AstRelation, InternalError};
     use crate::parser_interface;
+    use crate::parser_interface::{AstBuilder, Backend};
+    use lang_c::driver::{parse_preprocessed, Config};
 
     // Run with "cargo test print_for_debug -- --show-output".
     #[test]
     fn print_for_debug() {
-        parser_interface::parse_with_lang_c(&String::from("./tests/dev_examples/c/example2.c"))
+        parser_interface::parse_with_lang_c(&crate_path("tests/dev_examples/c/example2.c"))
             .pretty_print();
     }
+
+    #[test]
+    fn parse_files_in_parallel() {
+        let paths: Vec<String> = ["example2", "example4", "example5", "missing"]
+            .iter()
+            .map(|example| crate_path(&format!("tests/dev_examples/c/{}.c", example)))
+            .collect();
+        let trees = parser_interface::parse_files(&paths);
+        assert_eq!(trees.len(), 4);
+        for path in &paths[..3] {
+            assert_eq!(
+                trees[path].as_ref().unwrap(),
+                &parser_interface::parse_file_into_ast(path, Backend::LangC)
+            );
+        }
+        assert!(trees[&paths[3]].is_err());
+    }
+
+    // Only the middle function is parsed again but the delta should be the same as for the whole file.
+    #[test]
+    fn reparse_changed_matches_full_diff() {
+        let prev_source =
+            "int f(int a) { return a; }\nint g(int b) { return b; }\nint main(void) { return f(1); }\n";
+        let new_source = prev_source.replace("return b;", "float c = 1.0; return b + 1;");
+        let file_path = std::env::temp_dir().join("cerium_reparse_changed.c");
+        std::fs::write(&file_path, &new_source).unwrap();
+        let edit_start = prev_source.find("return b;").unwrap();
+        let edit_end = edit_start + "float c = 1.0; return b + 1;".len();
+
+        let prev_ast = parser_interface::parse_source_into_ast(prev_source).unwrap();
+        let (insertion_set, deletion_set, updated_ast) = parser_interface::reparse_changed(
+            &file_path.to_string_lossy().to_string(),
+            &prev_ast,
+            edit_start..edit_end,
+        )
+        .unwrap();
+        let new_ast = parser_interface::parse_source_into_ast(&new_source).unwrap();
+        let (full_insertion_set, full_deletion_set, full_updated_ast) =
+            ast::get_diff_relation_set(&prev_ast, &new_ast);
+        assert_eq!(insertion_set, full_insertion_set);
+        assert_eq!(deletion_set, full_deletion_set);
+        assert_eq!(updated_ast.to_c_source(), full_updated_ast.to_c_source());
+        // The following function has moved along with the edit.
+        let (main_id, _) = updated_ast
+            .iter()
+            .find(|(_, relation)| {
+                matches!(relation, AstRelation::FunDef { fun_name, .. } if fun_name == "main")
+            })
+            .unwrap();
+        assert_eq!(
+            updated_ast
+                .get_location(main_id)
+                .map(|location| location.start),
+            new_source.find("int main")
+        );
+    }
+
+    // Comments need the preprocessor, so the edit is diffed against a parse of the whole file instead.
+    #[test]
+    fn reparse_changed_with_comments() {
+        let prev_source =
+            "int f(int a) { return a; }\nint g(int b) { return b; }\nint main(void) { return f(1); }\n";
+        let file_path = std::env::temp_dir().join("cerium_reparse_comments.c");
+        let file_path_string = file_path.to_string_lossy().to_string();
+        let prev_ast = parser_interface::parse_source_into_ast(prev_source).unwrap();
+        for edited in [
+            "// doc\nint g(int b) { return b + 1; }",
+            "int g(int b) { return b + 1; /* doc */ }",
+        ] {
+            let new_source = prev_source.replace("int g(int b) { return b; }", edited);
+            std::fs::write(&file_path, &new_source).unwrap();
+            let edit_start = prev_source.find("int g").unwrap();
+            let (insertion_set, deletion_set, updated_ast) = parser_interface::reparse_changed(
+                &file_path_string,
+                &prev_ast,
+                edit_start..edit_start + edited.len(),
+            )
+            .unwrap();
+            let new_ast = parser_interface::parse_file_into_ast(&file_path_string, Backend::LangC);
+            let (full_insertion_set, full_deletion_set, _) =
+                ast::get_diff_relation_set(&prev_ast, &new_ast);
+            assert_eq!(insertion_set, full_insertion_set);
+            assert_eq!(deletion_set, full_deletion_set);
+            assert!(updated_ast.structurally_eq(&new_ast));
+        }
+    }
+
+    // Both backends should agree (including IDs) on the constructs they support.
+    #[test]
+    fn tree_sitter_matches_lang_c() {
+        for example in [
+            "example2",
+            "example4",
+            "example5",
+            "example6",
+            "example17",
+            "example22",
+        ] {
+            let file_path = crate_path(&format!("tests/dev_examples/c/{}.c", example));
+            let lang_c_ast = parser_interface::parse_file_into_ast(&file_path, Backend::LangC);
+            let tree_sitter_ast =
+                parser_interface::parse_file_into_ast(&file_path, Backend::TreeSitter);
+            assert_eq!(
+                ast::get_initial_relation_set(&lang_c_ast),
+                ast::get_initial_relation_set(&tree_sitter_ast)
+            );
+        }
+    }
+
+    // Typedefs don't add any nodes, so using an alias gives the same relations as the aliased type.
+    #[test]
+    fn typedef_in_signature() {
+        let typedef_ast = parser_interface::parse_source_into_ast(
+            "typedef int myint; typedef myint *intptr; myint get(intptr p, myint a) { return *p + a; }",
+        )
+        .unwrap();
+        let plain_ast =
+            parser_interface::parse_source_into_ast("int get(int *p, int a) { return *p + a; }")
+                .unwrap();
+        assert_eq!(
+            ast::get_initial_relation_set(&typedef_ast),
+            ast::get_initial_relation_set(&plain_ast)
+        );
+    }
+
+    // (void) means no parameters, same as an empty list.
+    #[test]
+    fn void_parameter_list() {
+        let void_ast =
+            parser_interface::parse_source_into_ast("int foo(void) { return 0; }").unwrap();
+        let empty_ast = parser_interface::parse_source_into_ast("int foo() { return 0; }").unwrap();
+        assert_eq!(
+            ast::get_initial_relation_set(&void_ast),
+            ast::get_initial_relation_set(&empty_ast)
+        );
+        assert!(ast::get_initial_relation_set(&void_ast).iter().any(
+            |relation| matches!(relation, AstRelation::FunDef { arg_ids, .. } if arg_ids.is_empty())
+        ));
+    }
+
+    // Combined specifiers should make up a single type node.
+    #[test]
+    fn unsigned_long_declaration() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { unsigned long a = 1; return a; }",
+        )
+        .unwrap();
+        // Return type, declared type and constant.
+        let int_count = ast::get_initial_relation_set(&ast)
+            .iter()
+            .filter(|relation| matches!(relation, AstRelation::Int { id: _ }))
+            .count();
+        assert_eq!(int_count, 3);
+    }
+
+    // A suffixed constant gets the type its suffix spells out (0x and 0 prefixes don't matter).
+    #[test]
+    fn suffixed_integer_constants() {
+        let suffixed_ast = parser_interface::parse_source_into_ast(
+            "int main(void) { unsigned long a = 1UL; long b = 0x10L; unsigned c = 017u; return 0; }",
+        )
+        .unwrap();
+        let declared_ast = parser_interface::parse_source_into_ast(
+            "int main(void) { unsigned long a = 1; long b = 16; unsigned c = 15; return 0; }",
+        )
+        .unwrap();
+        assert_eq!(
+            ast::get_initial_relation_set(&suffixed_ast),
+            ast::get_initial_relation_set(&declared_ast)
+        );
+        assert!(crate::standard_type_checker::type_check(&suffixed_ast));
+    }
+
+    // Long double is the widest of the float family, which is as far as double goes for now.
+    #[test]
+    fn long_double_declaration() {
+        let long_double_ast = parser_interface::parse_source_into_ast(
+            "long double f(long double a) { long double b = a; return b; }",
+        )
+        .unwrap();
+        let double_ast = parser_interface::parse_source_into_ast(
+            "double f(double a) { double b = a; return b; }",
+        )
+        .unwrap();
+        assert_eq!(
+            ast::get_initial_relation_set(&long_double_ast),
+            ast::get_initial_relation_set(&double_ast)
+        );
+        // Return type, parameter type and declared type.
+        let double_count = ast::get_initial_relation_set(&long_double_ast)
+            .iter()
+            .filter(|relation| matches!(relation, AstRelation::Double { id: _ }))
+            .count();
+        assert_eq!(double_count, 3);
+    }
+
+    // Qualifiers are dropped, so a const declaration gives the same relations as a plain one.
+    #[test]
+    fn const_declaration() {
+        let const_ast = parser_interface::parse_source_into_ast(
+            "int main(void) { const int x = 5; return x; }",
+        )
+        .unwrap();
+        let plain_ast =
+            parser_interface::parse_source_into_ast("int main(void) { int x = 5; return x; }")
+                .unwrap();
+        assert_eq!(
+            ast::get_initial_relation_set(&const_ast),
+            ast::get_initial_relation_set(&plain_ast)
+        );
+    }
+
+    #[test]
+    fn function_pointer_declaration() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int (*fp)(int, float); return 0; }",
+        )
+        .unwrap();
+        let (decl_name, type_id) = ast
+            .iter()
+            .find_map(|(_, relation)| match relation {
+                AstRelation::Decl {
+                    var_name, type_id, ..
+                } => Some((var_name.clone(), *type_id)),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(decl_name, "fp");
+        match ast.get_relation(type_id) {
+            AstRelation::FunPtr {
+                return_type_id,
+                arg_type_ids,
+                ..
+            } => {
+                assert!(matches!(
+                    ast.get_relation(return_type_id),
+                    AstRelation::Int { .. }
+                ));
+                assert_eq!(arg_type_ids.len(), 2);
+                assert!(matches!(
+                    ast.get_relation(arg_type_ids[1]),
+                    AstRelation::Float { .. }
+                ));
+            }
+            relation => panic!("Expected a function pointer type, got {}", relation),
+        }
+    }
+
+    // Parsing from a string should give the same tree as parsing the same program from a file.
+    #[test]
+    fn source_matches_file() {
+        let file_path = crate_path("tests/dev_examples/c/example2.c");
+        let source = std::fs::read_to_string(&file_path).unwrap();
+        let source_ast = parser_interface::parse_source_into_ast(&source).unwrap();
+        let file_ast = parser_interface::parse_file_into_ast(&file_path, Backend::LangC);
+        assert!(source_ast.structurally_eq(&file_ast));
+        // Both give declaration locations as offsets into the file, not into the preprocessed source.
+        let source_locations: Vec<Option<Location>> =
+            parser_interface::declaration_ids(&source_ast)
+                .into_iter()
+                .map(|decl_id| source_ast.get_location(decl_id))
+                .collect();
+        let file_locations: Vec<Option<Location>> = parser_interface::declaration_ids(&file_ast)
+            .into_iter()
+            .map(|decl_id| file_ast.get_location(decl_id))
+            .collect();
+        assert!(source_locations.iter().all(|location| location.is_some()));
+        assert_eq!(source_locations, file_locations);
+    }
+
+    #[test]
+    fn build_tree_moves_tree_out() {
+        let source = "int f(int a) { return a; } int main(void) { return f(1); }";
+        let parse = parse_preprocessed(&Config::default(), source.to_string()).unwrap();
+        let mut ast_builder = AstBuilder::new();
+        let tree = ast_builder.build_tree(&parse.unit);
+        assert_eq!(ast_builder.tree, Tree::new());
+        // The same tree as before (parsing from source only adds the root location).
+        let mut expected = parser_interface::parse_source_into_ast(source).unwrap();
+        expected.set_location(expected.get_root(), None);
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn statement_expression_is_rejected() {
+        match parser_interface::parse_source_into_ast(
+            "int main(void) {\n    int x = ({ int y = 1; y; });\n    return x;\n}",
+        ) {
+            Err(InternalError::ParseError(error)) => {
+                assert_eq!(error.line, 2);
+                assert_eq!(error.message, "statement expressions are not supported");
+            }
+            _ => panic!("Expected an error for the statement expression"),
+        }
+    }
+
+    #[test]
+    fn parameter_storage_class() {
+        // The storage class is simply dropped.
+        let register_ast =
+            parser_interface::parse_source_into_ast("int f(register int a) { return a; }").unwrap();
+        let plain_ast =
+            parser_interface::parse_source_into_ast("int f(int a) { return a; }").unwrap();
+        assert_eq!(
+            ast::get_initial_relation_set(&register_ast),
+            ast::get_initial_relation_set(&plain_ast)
+        );
+    }
+
+    #[test]
+    fn source_syntax_error() {
+        assert!(parser_interface::parse_source_into_ast("int main(void) { return 1 }").is_err());
+    }
+
+    #[test]
+    fn syntax_error_location() {
+        let source = "int main(void) {\n    int a = 1 +;\n    return a;\n}\n";
+        match parser_interface::parse_source_into_ast(source) {
+            Err(InternalError::ParseError(error)) => {
+                assert_eq!(error.line, 2);
+                assert_eq!(error.column, 16);
+                assert_eq!(error.snippet, "    int a = 1 +;");
+            }
+            _ => panic!("Expected a syntax error"),
+        }
+    }
+
+    // Lines are counted in the original file, not in the preprocessed source.
+    #[test]
+    fn syntax_error_location_in_file() {
+        let file_path = std::env::temp_dir().join("cerium_syntax_error.c");
+        std::fs::write(
+            &file_path,
+            "// Comments are removed by the preprocessor.\n\nint main(void) {\n    int a = 1 +;\n}\n",
+        )
+        .unwrap();
+        let file_path = file_path.to_string_lossy().to_string();
+        match parser_interface::try_parse_file_into_ast(&file_path, Backend::LangC) {
+            Err(InternalError::ParseError(error)) => {
+                assert_eq!(error.path, file_path);
+                assert_eq!(error.line, 4);
+                assert!(format!("{}", error).contains("4 |     int a = 1 +;"));
+            }
+            _ => panic!("Expected a syntax error"),
+        }
+    }
+
+    // Tree-sitter recovers from syntax errors, the error and missing nodes it inserts are reported instead.
+    #[test]
+    fn tree_sitter_syntax_errors() {
+        let file_path = std::env::temp_dir().join("cerium_tree_sitter_error.c");
+        let file_path_string = file_path.to_string_lossy().to_string();
+        let sources = [
+            "int main(void) {\n    int a = 1 +;\n}\n",
+            "int main(void) {\n    int a = 1 $ 2;\n}\n",
+            "int main(void) {\n    int *p;\n}\n",
+        ];
+        for source in sources {
+            std::fs::write(&file_path, source).unwrap();
+            match parser_interface::try_parse_file_into_ast(&file_path_string, Backend::TreeSitter)
+            {
+                Err(InternalError::ParseError(error)) => assert_eq!(error.line, 2),
+                _ => panic!("Expected a syntax error for {}", source),
+            }
+        }
+        std::fs::write(&file_path, "int main(void) {\n    int a;\n}\n").unwrap();
+        assert!(
+            parser_interface::try_parse_file_into_ast(&file_path_string, Backend::TreeSitter)
+                .is_ok()
+        );
+    }
 }
@@ -1,69 +1,733 @@
-use crate::ast::Tree;
-use crate::definitions::{AstRelation, ID};
-use std::collections::HashMap;
+use crate::ast::{get_child_ids, Tree};
+use crate::definitions::{AstRelation, CheckError, ID};
+use std::collections::{HashMap, HashSet};
 
 #[derive(PartialEq, Clone, Debug)]
-enum Type {
+pub enum Type {
     VoidType,
     IntType,
     FloatType,
+    DoubleType,
     CharType,
+    PtrType(Box<Type>),
+    ArrayType(Box<Type>),
+    StructType {
+        name: String,
+        fields: Vec<(String, Type)>,
+    },
+    FunPtrType {
+        return_type: Box<Type>,
+        arg_types: Vec<Type>,
+    },
     OkType,
     ErrorType,
 }
 
 #[derive(PartialEq, Clone, Debug)]
-struct FunType {
-    return_type: Type,
-    arg_types: Vec<Type>,
+pub struct FunType {
+    pub return_type: Type,
+    pub arg_types: Vec<Type>,
+}
+
+// How operands of different arithmetic types are treated.
+// Strict needs both sides to have the same type, Promotion converts them like C does (int to float to double).
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ArithmeticMode {
+    Strict,
+    Promotion,
 }
 
 pub fn type_check(ast: &Tree) -> bool {
-    let root_index = ast.get_root();
-    let var_context: HashMap<String, Type> = HashMap::new();
-    let fun_context: HashMap<String, FunType> = HashMap::new();
-    type_check_trans_unit(ast.get_relation(root_index), &ast, var_context, fun_context)
-        == Type::OkType
+    type_check_all_errors(ast).is_empty()
 }
 
-// Traverse the AST to type-check the program recursively.
-fn type_check_trans_unit(
-    node: AstRelation,
+pub fn type_check_with_mode(ast: &Tree, mode: ArithmeticMode) -> bool {
+    type_check_all_errors_with_mode(ast, mode).is_empty()
+}
+
+pub fn type_check_all_errors(ast: &Tree) -> Vec<CheckError> {
+    type_check_all_errors_with_mode(ast, ArithmeticMode::Strict)
+}
+
+// Builtins are library functions that the program calls without defining (e.g. putchar).
+// A definition in the program with the same name takes precedence.
+pub fn type_check_with_builtins(ast: &Tree, builtins: &HashMap<String, FunType>) -> bool {
+    collect_errors(ast, ArithmeticMode::Strict, builtins.clone()).is_empty()
+}
+
+pub fn type_check_all_errors_with_mode(ast: &Tree, mode: ArithmeticMode) -> Vec<CheckError> {
+    collect_errors(ast, mode, HashMap::new())
+}
+
+// Every function is checked (and every statement of it) instead of stopping at the first error.
+// A failing statement is blamed on the innermost statements below it that fail on their own.
+fn collect_errors(
+    ast: &Tree,
+    mode: ArithmeticMode,
+    mut fun_context: HashMap<String, FunType>,
+) -> Vec<CheckError> {
+    let mut errors = find_duplicate_definitions(ast);
+    errors.extend(find_undefined_labels(ast));
+    let body_ids = match ast.get_relation(ast.get_root()) {
+        AstRelation::TransUnit { id: _, body_ids } => body_ids,
+        _ => panic!("Unexpected syntax"),
+    };
+    for body_id in body_ids {
+        match ast.get_relation(body_id) {
+            // Struct definitions don't change any context (they are looked up in the tree).
+            AstRelation::StructDef {
+                id,
+                name,
+                field_ids,
+            } => {
+                if resolve_fields(&field_ids, ast).is_none() {
+                    errors.push(CheckError {
+                        id,
+                        location: None,
+                        message: format!("struct `{}` has an invalid field", name),
+                    });
+                }
+            }
+            AstRelation::FunDef {
+                id,
+                fun_name,
+                return_type_id,
+                arg_ids,
+                body_id,
+            } => {
+                let return_type = type_check_literal(ast.relation_ref(return_type_id), ast);
+                if return_type != Type::VoidType
+                    && !returns_on_all_paths(ast.relation_ref(body_id), ast)
+                {
+                    errors.push(CheckError {
+                        id,
+                        location: None,
+                        message: format!("`{}` doesn't return on every path", fun_name),
+                    });
+                }
+                if !jumps_inside_loops(ast.relation_ref(body_id), ast, false, false) {
+                    errors.push(CheckError {
+                        id,
+                        location: None,
+                        message: format!(
+                            "`{}` has a break or continue outside of a loop",
+                            fun_name
+                        ),
+                    });
+                }
+                // Every function starts from an empty context (there are no global variables), so
+                // parameters are only visible inside their own function.
+                let (var_context, arg_types) = bind_arguments(arg_ids, HashMap::new(), ast);
+                if arg_types.contains(&Type::ErrorType) {
+                    errors.push(CheckError {
+                        id,
+                        location: None,
+                        message: format!("`{}` has an ill-typed parameter", fun_name),
+                    });
+                }
+                fun_context.insert(
+                    fun_name.clone(),
+                    FunType {
+                        return_type,
+                        arg_types,
+                    },
+                );
+                compound_errors(
+                    body_id,
+                    ast,
+                    var_context,
+                    &fun_context,
+                    &fun_name,
+                    mode,
+                    &mut errors,
+                );
+            }
+            _ => panic!("Unexpected syntax"),
+        }
+    }
+    errors
+}
+
+fn compound_errors(
+    compound_id: ID,
     ast: &Tree,
     var_context: HashMap<String, Type>,
-    fun_context: HashMap<String, FunType>,
-) -> Type {
+    fun_context: &HashMap<String, FunType>,
+    current_fun: &str,
+    mode: ArithmeticMode,
+    errors: &mut Vec<CheckError>,
+) {
+    let mut item_id = match ast.get_relation(compound_id) {
+        AstRelation::Compound { id: _, start_id } if !is_empty_compound(start_id, ast) => {
+            Some(start_id)
+        }
+        _ => None,
+    };
+    let mut var_context = var_context;
+    while let Some(id) = item_id {
+        let (stmt_id, next_id) = match ast.get_relation(id) {
+            AstRelation::Item {
+                id: _,
+                stmt_id,
+                next_stmt_id,
+            } => (stmt_id, Some(next_stmt_id)),
+            AstRelation::EndItem { id: _, stmt_id } => (stmt_id, None),
+            _ => panic!("Unexpected syntax"),
+        };
+        var_context = statement_errors(
+            stmt_id,
+            ast,
+            var_context,
+            fun_context,
+            current_fun,
+            mode,
+            errors,
+        );
+        item_id = next_id;
+    }
+}
+
+// Returns the context for the next statement (a declaration still binds its variable if it is wrongly typed).
+fn statement_errors(
+    stmt_id: ID,
+    ast: &Tree,
+    var_context: HashMap<String, Type>,
+    fun_context: &HashMap<String, FunType>,
+    current_fun: &str,
+    mode: ArithmeticMode,
+    errors: &mut Vec<CheckError>,
+) -> HashMap<String, Type> {
+    let node = ast.get_relation(stmt_id);
+    if let AstRelation::Label { stmt_id, .. } = node {
+        return statement_errors(
+            stmt_id,
+            ast,
+            var_context,
+            fun_context,
+            current_fun,
+            mode,
+            errors,
+        );
+    }
+    let (stmt_type, new_var_context) = type_check_statement(
+        node.clone(),
+        ast,
+        var_context.clone(),
+        fun_context.clone(),
+        current_fun.to_string(),
+        mode,
+    );
+    if stmt_type != Type::ErrorType {
+        return new_var_context;
+    }
+    let errors_before = errors.len();
+    let mut check_body = |body_id: ID| {
+        compound_errors(
+            body_id,
+            ast,
+            var_context.clone(),
+            fun_context,
+            current_fun,
+            mode,
+            errors,
+        )
+    };
+    match &node {
+        AstRelation::If { then_id, .. } => check_body(*then_id),
+        AstRelation::IfElse {
+            then_id, else_id, ..
+        } => {
+            check_body(*then_id);
+            check_body(*else_id);
+        }
+        AstRelation::While { body_id, .. } => check_body(*body_id),
+        AstRelation::Switch { case_ids, .. } => {
+            for case_id in case_ids {
+                match ast.get_relation(*case_id) {
+                    AstRelation::Case { body_id, .. }
+                    | AstRelation::DefaultCase { body_id, .. } => check_body(body_id),
+                    _ => panic!("Unexpected syntax"),
+                }
+            }
+        }
+        AstRelation::Compound { .. } => check_body(stmt_id),
+        _ => {}
+    }
+    if errors.len() == errors_before {
+        errors.push(CheckError {
+            id: stmt_id,
+            location: None,
+            message: String::from("typing error"),
+        });
+    }
+    let mut var_context = var_context;
     match node {
-        AstRelation::TransUnit { id: _, body_ids } => {
-            let mut new_var_context = var_context.clone();
-            let mut new_fun_context = fun_context.clone();
-            for body_id in body_ids {
-                match type_check_fun_def(
-                    ast.get_relation(body_id),
-                    ast,
-                    new_var_context.clone(),
-                    new_fun_context.clone(),
-                ) {
-                    (Type::ErrorType, _, _) => {
-                        return Type::ErrorType;
+        AstRelation::Assign {
+            var_name, type_id, ..
+        }
+        | AstRelation::Decl {
+            var_name, type_id, ..
+        } => {
+            var_context.insert(var_name, type_check_literal(ast.relation_ref(type_id), ast));
+        }
+        _ => {}
+    }
+    var_context
+}
+
+// Opt-in: also rejects reads of variables that may not have been assigned yet (C itself allows them).
+pub fn type_check_strict_init(ast: &Tree, mode: ArithmeticMode) -> bool {
+    type_check_with_mode(ast, mode) && find_uninitialized_reads(ast).is_empty()
+}
+
+// Type-check the subtree rooted at any node (e.g. to show the type of the expression under the cursor).
+// The caller has to supply the context of the enclosing function (variables in scope and the function name).
+pub fn type_check_node(
+    ast: &Tree,
+    id: ID,
+    var_context: HashMap<String, Type>,
+    fun_context: HashMap<String, FunType>,
+    current_fun: String,
+    mode: ArithmeticMode,
+) -> Result<Type, CheckError> {
+    let node = ast.get_relation(id);
+    let node_type = match node {
+        AstRelation::FunDef { .. } => {
+            type_check_fun_def(node, ast, var_context, fun_context, mode).0
+        }
+        AstRelation::Compound { .. } => {
+            type_check_compound(&node, ast, var_context, fun_context, current_fun, mode).0
+        }
+        AstRelation::Item { .. } | AstRelation::EndItem { .. } | AstRelation::EmptyItem { .. } => {
+            type_check_item(node, ast, var_context, fun_context, current_fun, mode).0
+        }
+        _ => type_check_statement(node, ast, var_context, fun_context, current_fun, mode).0,
+    };
+    match node_type {
+        Type::ErrorType => Err(CheckError {
+            id,
+            location: None,
+            message: String::from("typing error"),
+        }),
+        _ => Ok(node_type),
+    }
+}
+
+// Signatures of all functions defined in the program (as a starting point for type_check_node).
+pub fn get_fun_context(ast: &Tree) -> HashMap<String, FunType> {
+    let mut fun_context = HashMap::new();
+    if let AstRelation::TransUnit { id: _, body_ids } = ast.get_relation(ast.get_root()) {
+        for body_id in body_ids {
+            if let AstRelation::FunDef {
+                id: _,
+                fun_name,
+                return_type_id,
+                arg_ids,
+                body_id: _,
+            } = ast.get_relation(body_id)
+            {
+                let return_type = type_check_literal(ast.relation_ref(return_type_id), ast);
+                let (_, arg_types) = bind_arguments(arg_ids, HashMap::new(), ast);
+                fun_context.insert(
+                    fun_name,
+                    FunType {
+                        return_type,
+                        arg_types,
+                    },
+                );
+            }
+        }
+    }
+    fun_context
+}
+
+// Functions defined more than once and parameters declared more than once in the same function.
+// (Otherwise the last definition would silently win.)
+pub fn find_duplicate_definitions(ast: &Tree) -> Vec<CheckError> {
+    let mut errors = vec![];
+    let mut fun_names = HashSet::new();
+    let body_ids = match ast.get_relation(ast.get_root()) {
+        AstRelation::TransUnit { id: _, body_ids } => body_ids,
+        _ => return errors,
+    };
+    for body_id in body_ids {
+        if let AstRelation::FunDef {
+            id,
+            fun_name,
+            return_type_id: _,
+            arg_ids,
+            body_id: _,
+        } = ast.get_relation(body_id)
+        {
+            if !fun_names.insert(fun_name.clone()) {
+                errors.push(CheckError {
+                    id,
+                    location: None,
+                    message: format!("function `{}` is defined more than once", fun_name),
+                });
+            }
+            let mut arg_names = HashSet::new();
+            for arg_id in arg_ids {
+                if let AstRelation::Arg {
+                    id,
+                    var_name,
+                    type_id: _,
+                } = ast.get_relation(arg_id)
+                {
+                    if !arg_names.insert(var_name.clone()) {
+                        errors.push(CheckError {
+                            id,
+                            location: None,
+                            message: format!(
+                                "parameter `{}` is declared more than once in `{}`",
+                                var_name, fun_name
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    errors
+}
+
+// Gotos to a label that isn't defined in the same function.
+pub fn find_undefined_labels(ast: &Tree) -> Vec<CheckError> {
+    let mut errors = vec![];
+    if let AstRelation::TransUnit { id: _, body_ids } = ast.get_relation(ast.get_root()) {
+        for body_id in body_ids {
+            if let AstRelation::FunDef { body_id, .. } = ast.get_relation(body_id) {
+                let mut label_names = HashSet::new();
+                let mut gotos = vec![];
+                for (id, relation) in ast.walk(body_id) {
+                    match relation {
+                        AstRelation::Label { name, .. } => {
+                            label_names.insert(name.clone());
+                        }
+                        AstRelation::Goto { label_name, .. } => {
+                            gotos.push((id, label_name.clone()))
+                        }
+                        _ => {}
                     }
-                    (_, updated_var_context, updated_fun_context) => {
-                        new_var_context = updated_var_context;
-                        new_fun_context = updated_fun_context;
+                }
+                for (id, label_name) in gotos {
+                    if !label_names.contains(&label_name) {
+                        errors.push(CheckError {
+                            id,
+                            location: None,
+                            message: format!("label `{}` is not defined", label_name),
+                        });
                     }
                 }
             }
-            return Type::OkType;
         }
-        _ => panic!("Unexpected syntax"),
     }
+    errors
+}
+
+// Statements directly following a return in the same block (a return inside a nested branch doesn't count).
+// Only the first of them is reported, and a label makes the code reachable again through a goto.
+pub fn find_unreachable_code(ast: &Tree) -> Vec<CheckError> {
+    let mut errors = vec![];
+    if let AstRelation::TransUnit { id: _, body_ids } = ast.get_relation(ast.get_root()) {
+        for body_id in body_ids {
+            if let AstRelation::FunDef { body_id, .. } = ast.get_relation(body_id) {
+                // Items after one that was reported (up to the next label), the walk reaches them later.
+                let mut dead_item_ids = HashSet::new();
+                for (item_id, relation) in ast.walk(body_id) {
+                    if let AstRelation::Item {
+                        id: _,
+                        stmt_id,
+                        next_stmt_id,
+                    } = relation
+                    {
+                        let next_id = match ast.relation_ref(*next_stmt_id) {
+                            AstRelation::Item { stmt_id, .. }
+                            | AstRelation::EndItem { id: _, stmt_id } => *stmt_id,
+                            _ => continue,
+                        };
+                        if matches!(ast.relation_ref(next_id), AstRelation::Label { .. }) {
+                            continue;
+                        }
+                        if dead_item_ids.contains(&item_id) {
+                            dead_item_ids.insert(*next_stmt_id);
+                        } else if matches!(ast.relation_ref(*stmt_id), AstRelation::Return { .. }) {
+                            dead_item_ids.insert(*next_stmt_id);
+                            errors.push(CheckError {
+                                id: next_id,
+                                location: None,
+                                message: String::from("unreachable code after return"),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+    errors
+}
+
+// Reads of variables declared without an initializer that aren't assigned on every path before the read.
+// Arrays and structs are left out since they are usually filled element by element.
+pub fn find_uninitialized_reads(ast: &Tree) -> Vec<CheckError> {
+    let mut errors = vec![];
+    if let AstRelation::TransUnit { id: _, body_ids } = ast.get_relation(ast.get_root()) {
+        for body_id in body_ids {
+            if let AstRelation::FunDef { body_id, .. } = ast.get_relation(body_id) {
+                uninitialized_reads(body_id, ast, HashSet::new(), &mut errors);
+            }
+        }
+    }
+    errors
+}
+
+// Returns the variables that may still be unassigned afterwards (None if the statement always returns).
+fn uninitialized_reads(
+    id: ID,
+    ast: &Tree,
+    mut uninitialized: HashSet<String>,
+    errors: &mut Vec<CheckError>,
+) -> Option<HashSet<String>> {
+    match ast.relation_ref(id) {
+        AstRelation::Compound { id: _, start_id } => {
+            if is_empty_compound(*start_id, ast) {
+                return Some(uninitialized);
+            }
+            let inner = uninitialized_reads(*start_id, ast, uninitialized.clone(), errors)?;
+            // Declarations inside the block only shadow the outer variables until the end of it.
+            let declared = block_declarations(*start_id, ast);
+            Some(
+                inner
+                    .into_iter()
+                    .filter(|name| !declared.contains(name))
+                    .chain(
+                        uninitialized
+                            .into_iter()
+                            .filter(|name| declared.contains(name)),
+                    )
+                    .collect(),
+            )
+        }
+        AstRelation::Item {
+            id: _,
+            stmt_id,
+            next_stmt_id,
+        } => {
+            let after_stmt = uninitialized_reads(*stmt_id, ast, uninitialized, errors)?;
+            uninitialized_reads(*next_stmt_id, ast, after_stmt, errors)
+        }
+        AstRelation::EndItem { id: _, stmt_id } => {
+            uninitialized_reads(*stmt_id, ast, uninitialized, errors)
+        }
+        AstRelation::Decl {
+            id: _,
+            var_name,
+            type_id,
+        } => {
+            match ast.get_relation(*type_id) {
+                AstRelation::Array { .. } | AstRelation::Struct { .. } => {
+                    uninitialized.remove(var_name)
+                }
+                _ => uninitialized.insert(var_name.clone()),
+            };
+            Some(uninitialized)
+        }
+        AstRelation::Assign {
+            id: _,
+            var_name,
+            type_id: _,
+            expr_id,
+        } => {
+            expression_reads(*expr_id, ast, &mut uninitialized, errors);
+            uninitialized.remove(var_name);
+            Some(uninitialized)
+        }
+        AstRelation::Return { id: _, expr_id } => {
+            expression_reads(*expr_id, ast, &mut uninitialized, errors);
+            None
+        }
+        // A branch that always returns doesn't count towards what is assigned afterwards.
+        AstRelation::If {
+            id: _,
+            cond_id,
+            then_id,
+        } => {
+            expression_reads(*cond_id, ast, &mut uninitialized, errors);
+            match uninitialized_reads(*then_id, ast, uninitialized.clone(), errors) {
+                Some(after_then) => Some(&uninitialized | &after_then),
+                None => Some(uninitialized),
+            }
+        }
+        AstRelation::IfElse {
+            id: _,
+            cond_id,
+            then_id,
+            else_id,
+        } => {
+            expression_reads(*cond_id, ast, &mut uninitialized, errors);
+            match (
+                uninitialized_reads(*then_id, ast, uninitialized.clone(), errors),
+                uninitialized_reads(*else_id, ast, uninitialized, errors),
+            ) {
+                (Some(after_then), Some(after_else)) => Some(&after_then | &after_else),
+                (after_then, after_else) => after_then.or(after_else),
+            }
+        }
+        // The body might not run at all (and can only assign, so nothing changes afterwards).
+        AstRelation::While {
+            id: _,
+            cond_id,
+            body_id,
+        } => {
+            expression_reads(*cond_id, ast, &mut uninitialized, errors);
+            uninitialized_reads(*body_id, ast, uninitialized.clone(), errors);
+            Some(uninitialized)
+        }
+        AstRelation::Switch {
+            id: _,
+            expr_id,
+            case_ids,
+        } => {
+            expression_reads(*expr_id, ast, &mut uninitialized, errors);
+            let mut after_cases = None;
+            let mut has_default = false;
+            for case_id in case_ids {
+                let body_id = match ast.get_relation(*case_id) {
+                    AstRelation::Case {
+                        id: _,
+                        label_id: _,
+                        body_id,
+                    } => body_id,
+                    AstRelation::DefaultCase { id: _, body_id } => {
+                        has_default = true;
+                        body_id
+                    }
+                    _ => panic!("Unexpected syntax"),
+                };
+                if let Some(after_case) =
+                    uninitialized_reads(body_id, ast, uninitialized.clone(), errors)
+                {
+                    after_cases = Some(match after_cases {
+                        Some(after_cases) => &after_cases | &after_case,
+                        None => after_case,
+                    });
+                }
+            }
+            if has_default {
+                after_cases
+            } else {
+                Some(match after_cases {
+                    Some(after_cases) => &after_cases | &uninitialized,
+                    None => uninitialized,
+                })
+            }
+        }
+        AstRelation::Break { .. } | AstRelation::Continue { .. } => Some(uninitialized),
+        AstRelation::Label { stmt_id, .. } => {
+            uninitialized_reads(*stmt_id, ast, uninitialized, errors)
+        }
+        // Jumps aren't followed, whatever comes after a goto is only checked on the paths that fall through.
+        AstRelation::Goto { .. } => None,
+        // Expression statement.
+        _ => {
+            expression_reads(id, ast, &mut uninitialized, errors);
+            Some(uninitialized)
+        }
+    }
+}
+
+fn expression_reads(
+    id: ID,
+    ast: &Tree,
+    uninitialized: &mut HashSet<String>,
+    errors: &mut Vec<CheckError>,
+) {
+    match ast.get_relation(id) {
+        AstRelation::Var { id, var_name } => {
+            if uninitialized.contains(&var_name) {
+                errors.push(CheckError {
+                    id,
+                    location: None,
+                    message: format!("`{}` may be used before it is assigned", var_name),
+                });
+            }
+        }
+        AstRelation::BinaryOp {
+            id: _,
+            op,
+            arg1_id,
+            arg2_id,
+        } if op == "=" => {
+            expression_reads(arg2_id, ast, uninitialized, errors);
+            match ast.get_relation(arg1_id) {
+                AstRelation::Var { id: _, var_name } => {
+                    uninitialized.remove(&var_name);
+                }
+                _ => expression_reads(arg1_id, ast, uninitialized, errors),
+            }
+        }
+        // Taking the address is assumed to be for initializing the variable (e.g. scanf("%d", &x)).
+        AstRelation::AddressOf { id: _, expr_id } => match ast.get_relation(expr_id) {
+            AstRelation::Var { id: _, var_name } => {
+                uninitialized.remove(&var_name);
+            }
+            _ => expression_reads(expr_id, ast, uninitialized, errors),
+        },
+        // The operand is never evaluated.
+        AstRelation::SizeOf { .. } => {}
+        // Like an if-else, a variable is only assigned afterwards if both arms assign it.
+        AstRelation::Conditional {
+            id: _,
+            cond_id,
+            then_id,
+            else_id,
+        } => {
+            expression_reads(cond_id, ast, uninitialized, errors);
+            let mut after_else = uninitialized.clone();
+            expression_reads(then_id, ast, uninitialized, errors);
+            expression_reads(else_id, ast, &mut after_else, errors);
+            uninitialized.extend(after_else);
+        }
+        relation => {
+            for child_id in get_child_ids(&relation) {
+                expression_reads(child_id, ast, uninitialized, errors);
+            }
+        }
+    }
+}
+
+// Variables declared directly in a block (not in nested ones).
+fn block_declarations(start_id: ID, ast: &Tree) -> HashSet<String> {
+    let mut declared = HashSet::new();
+    let mut item_id = Some(start_id);
+    while let Some(id) = item_id {
+        let (stmt_id, next_id) = match ast.get_relation(id) {
+            AstRelation::Item {
+                id: _,
+                stmt_id,
+                next_stmt_id,
+            } => (stmt_id, Some(next_stmt_id)),
+            AstRelation::EndItem { id: _, stmt_id } => (stmt_id, None),
+            _ => panic!("Unexpected syntax"),
+        };
+        match ast.get_relation(stmt_id) {
+            AstRelation::Decl { var_name, .. } | AstRelation::Assign { var_name, .. } => {
+                declared.insert(var_name);
+            }
+            _ => {}
+        }
+        item_id = next_id;
+    }
+    declared
 }
 
+// Traverse the AST to type-check the program recursively.
 fn type_check_fun_def(
     node: AstRelation,
     ast: &Tree,
     var_context: HashMap<String, Type>,
     fun_context: HashMap<String, FunType>,
+    mode: ArithmeticMode,
 ) -> (Type, HashMap<String, Type>, HashMap<String, FunType>) {
     match node {
         AstRelation::FunDef {
@@ -73,8 +737,20 @@ fn type_check_fun_def(
             arg_ids,
             body_id,
         } => {
-            let return_type = type_check_literal(&ast.get_relation(return_type_id));
+            let return_type = type_check_literal(ast.relation_ref(return_type_id), ast);
+            // Only void functions are allowed to fall off the end of the body.
+            if return_type != Type::VoidType
+                && !returns_on_all_paths(ast.relation_ref(body_id), ast)
+            {
+                return (Type::ErrorType, var_context, fun_context);
+            }
+            if !jumps_inside_loops(ast.relation_ref(body_id), ast, false, false) {
+                return (Type::ErrorType, var_context, fun_context);
+            }
             let (new_var_context, arg_types) = bind_arguments(arg_ids, var_context, ast);
+            if arg_types.contains(&Type::ErrorType) {
+                return (Type::ErrorType, new_var_context, fun_context);
+            }
             let mut new_fun_context = fun_context.clone();
             new_fun_context.insert(
                 fun_name.clone(),
@@ -86,11 +762,12 @@ fn type_check_fun_def(
             // Because of scoping any context modification inside the function doesn't affect top level.
             return (
                 type_check_compound(
-                    &ast.get_relation(body_id),
+                    ast.relation_ref(body_id),
                     ast,
                     new_var_context.clone(),
                     new_fun_context.clone(),
                     fun_name,
+                    mode,
                 )
                 .0,
                 new_var_context,
@@ -109,14 +786,18 @@ fn bind_arguments(
     let mut new_var_context = var_context.clone();
     let mut arg_types = vec![];
     for arg_id in arg_ids {
-        let arg_relation = &ast.get_relation(arg_id);
+        let arg_relation = ast.relation_ref(arg_id);
         match arg_relation {
             AstRelation::Arg {
                 id: _,
                 var_name,
                 type_id,
             } => {
-                let arg_type = type_check_literal(&ast.get_relation(*type_id));
+                let arg_type = match type_check_literal(ast.relation_ref(*type_id), ast) {
+                    // Only a return type can be void.
+                    Type::VoidType => Type::ErrorType,
+                    arg_type => arg_type,
+                };
                 new_var_context.insert(var_name.clone(), arg_type.clone());
                 arg_types.push(arg_type);
             }
@@ -126,79 +807,210 @@ fn bind_arguments(
     (new_var_context, arg_types)
 }
 
+// A compound opens a new scope so declarations inside it (which may shadow outer ones) are dropped on exit.
 fn type_check_compound(
     node: &AstRelation,
     ast: &Tree,
     var_context: HashMap<String, Type>,
     fun_context: HashMap<String, FunType>,
     current_fun: String,
+    mode: ArithmeticMode,
 ) -> (Type, HashMap<String, Type>) {
     match *node {
         AstRelation::Compound { id: _, start_id } => {
-            return type_check_item(
+            if is_empty_compound(start_id, ast) {
+                return (Type::OkType, var_context);
+            }
+            let (compound_type, _) = type_check_item(
                 ast.get_relation(start_id),
                 ast,
-                var_context,
+                var_context.clone(),
                 fun_context,
                 current_fun,
-            )
+                mode,
+            );
+            return (compound_type, var_context);
         }
         _ => panic!("Unexpected syntax"),
     }
 }
 
-fn type_check_item(
-    node: AstRelation,
-    ast: &Tree,
-    var_context: HashMap<String, Type>,
-    fun_context: HashMap<String, FunType>,
-    current_fun: String,
-) -> (Type, HashMap<String, Type>) {
+fn is_empty_compound(start_id: ID, ast: &Tree) -> bool {
+    matches!(ast.get_relation(start_id), AstRelation::EmptyItem { .. })
+}
+
+// Check that every control path through a statement ends in a return.
+// (Return types themselves are checked when type-checking the return statement.)
+fn returns_on_all_paths(node: &AstRelation, ast: &Tree) -> bool {
     match node {
+        AstRelation::Compound { id: _, start_id } => {
+            !is_empty_compound(*start_id, ast)
+                && returns_on_all_paths(ast.relation_ref(*start_id), ast)
+        }
         AstRelation::Item {
             id: _,
             stmt_id,
             next_stmt_id,
         } => {
-            match type_check_statement(
-                ast.get_relation(stmt_id),
-                ast,
-                var_context.clone(),
-                fun_context.clone(),
-                current_fun.clone(),
-            ) {
-                (Type::ErrorType, _) => (Type::ErrorType, var_context),
-                (_, new_var_context) => {
-                    return type_check_item(
-                        ast.get_relation(next_stmt_id),
-                        ast,
-                        new_var_context,
-                        fun_context,
-                        current_fun,
-                    )
-                }
-            }
+            returns_on_all_paths(ast.relation_ref(*stmt_id), ast)
+                || returns_on_all_paths(ast.relation_ref(*next_stmt_id), ast)
         }
         AstRelation::EndItem { id: _, stmt_id } => {
-            return type_check_statement(
-                ast.get_relation(stmt_id),
-                ast,
-                var_context,
-                fun_context,
-                current_fun,
-            )
+            returns_on_all_paths(ast.relation_ref(*stmt_id), ast)
         }
-        _ => panic!("Unexpected syntax"),
-    }
-}
-
-// Since every expression can be a statement we will check them in one function.
-fn type_check_statement(
+        AstRelation::Return { .. } => true,
+        AstRelation::Label {
+            id: _,
+            name: _,
+            stmt_id,
+        } => returns_on_all_paths(ast.relation_ref(*stmt_id), ast),
+        AstRelation::IfElse {
+            id: _,
+            cond_id: _,
+            then_id,
+            else_id,
+        } => {
+            returns_on_all_paths(ast.relation_ref(*then_id), ast)
+                && returns_on_all_paths(ast.relation_ref(*else_id), ast)
+        }
+        // Without a default case the switch can be skipped entirely.
+        AstRelation::Switch {
+            id: _,
+            expr_id: _,
+            case_ids,
+        } => {
+            case_ids.iter().any(|case_id| {
+                matches!(ast.get_relation(*case_id), AstRelation::DefaultCase { .. })
+            }) && case_ids
+                .iter()
+                .all(|case_id| match ast.get_relation(*case_id) {
+                    AstRelation::Case {
+                        id: _,
+                        label_id: _,
+                        body_id,
+                    }
+                    | AstRelation::DefaultCase { id: _, body_id } => {
+                        returns_on_all_paths(ast.relation_ref(body_id), ast)
+                    }
+                    _ => panic!("Unexpected syntax"),
+                })
+        }
+        // Loop bodies (and if without else) might not be executed at all.
+        _ => false,
+    }
+}
+
+// Check that break only appears inside a loop or switch and continue only inside a loop.
+fn jumps_inside_loops(node: &AstRelation, ast: &Tree, in_loop: bool, in_switch: bool) -> bool {
+    let check = |id: &ID, in_loop: bool, in_switch: bool| {
+        jumps_inside_loops(ast.relation_ref(*id), ast, in_loop, in_switch)
+    };
+    match node {
+        AstRelation::Compound { id: _, start_id } => {
+            is_empty_compound(*start_id, ast) || check(start_id, in_loop, in_switch)
+        }
+        AstRelation::Item {
+            id: _,
+            stmt_id,
+            next_stmt_id,
+        } => check(stmt_id, in_loop, in_switch) && check(next_stmt_id, in_loop, in_switch),
+        AstRelation::EndItem { id: _, stmt_id } => check(stmt_id, in_loop, in_switch),
+        AstRelation::If {
+            id: _,
+            cond_id: _,
+            then_id,
+        } => check(then_id, in_loop, in_switch),
+        AstRelation::IfElse {
+            id: _,
+            cond_id: _,
+            then_id,
+            else_id,
+        } => check(then_id, in_loop, in_switch) && check(else_id, in_loop, in_switch),
+        AstRelation::While {
+            id: _,
+            cond_id: _,
+            body_id,
+        } => check(body_id, true, in_switch),
+        AstRelation::Switch {
+            id: _,
+            expr_id: _,
+            case_ids,
+        } => case_ids.iter().all(|case_id| check(case_id, in_loop, true)),
+        AstRelation::Case {
+            id: _,
+            label_id: _,
+            body_id,
+        }
+        | AstRelation::DefaultCase { id: _, body_id } => check(body_id, in_loop, in_switch),
+        AstRelation::Label {
+            id: _,
+            name: _,
+            stmt_id,
+        } => check(stmt_id, in_loop, in_switch),
+        AstRelation::Break { id: _ } => in_loop || in_switch,
+        AstRelation::Continue { id: _ } => in_loop,
+        _ => true,
+    }
+}
+
+fn type_check_item(
+    node: AstRelation,
+    ast: &Tree,
+    var_context: HashMap<String, Type>,
+    fun_context: HashMap<String, FunType>,
+    current_fun: String,
+    mode: ArithmeticMode,
+) -> (Type, HashMap<String, Type>) {
+    match node {
+        AstRelation::Item {
+            id: _,
+            stmt_id,
+            next_stmt_id,
+        } => {
+            match type_check_statement(
+                ast.get_relation(stmt_id),
+                ast,
+                var_context.clone(),
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            ) {
+                (Type::ErrorType, _) => (Type::ErrorType, var_context),
+                (_, new_var_context) => {
+                    return type_check_item(
+                        ast.get_relation(next_stmt_id),
+                        ast,
+                        new_var_context,
+                        fun_context,
+                        current_fun,
+                        mode,
+                    )
+                }
+            }
+        }
+        AstRelation::EndItem { id: _, stmt_id } => {
+            return type_check_statement(
+                ast.get_relation(stmt_id),
+                ast,
+                var_context,
+                fun_context,
+                current_fun,
+                mode,
+            )
+        }
+        AstRelation::EmptyItem { .. } => (Type::OkType, var_context),
+        _ => panic!("Unexpected syntax"),
+    }
+}
+
+// Since every expression can be a statement we will check them in one function.
+fn type_check_statement(
     node: AstRelation,
     ast: &Tree,
     var_context: HashMap<String, Type>,
     fun_context: HashMap<String, FunType>,
     current_fun: String,
+    mode: ArithmeticMode,
 ) -> (Type, HashMap<String, Type>) {
     match node {
         AstRelation::Assign {
@@ -207,15 +1019,20 @@ fn type_check_statement(
             type_id,
             expr_id,
         } => {
-            let assign_type = type_check_literal(&ast.get_relation(type_id));
+            let assign_type = type_check_literal(ast.relation_ref(type_id), ast);
             let (expr_type, new_var_context) = type_check_statement(
                 ast.get_relation(expr_id),
                 ast,
                 var_context.clone(),
                 fun_context.clone(),
                 current_fun.clone(),
+                mode,
             );
-            if assign_type == expr_type {
+            // A void call has no value to assign.
+            if assign_type == expr_type
+                && assign_type != Type::ErrorType
+                && assign_type != Type::VoidType
+            {
                 let mut new_var_context = new_var_context.clone();
                 new_var_context.insert(var_name.clone(), assign_type);
                 return (Type::OkType, new_var_context);
@@ -223,6 +1040,19 @@ fn type_check_statement(
                 return (Type::ErrorType, var_context.clone());
             }
         }
+        AstRelation::Decl {
+            id: _,
+            var_name,
+            type_id,
+        } => {
+            let decl_type = type_check_literal(ast.relation_ref(type_id), ast);
+            if decl_type == Type::ErrorType || decl_type == Type::VoidType {
+                return (Type::ErrorType, var_context);
+            }
+            let mut new_var_context = var_context.clone();
+            new_var_context.insert(var_name.clone(), decl_type);
+            return (Type::OkType, new_var_context);
+        }
         AstRelation::Return { id: _, expr_id } => {
             let (expr_type, new_var_context) = type_check_statement(
                 ast.get_relation(expr_id),
@@ -230,6 +1060,7 @@ fn type_check_statement(
                 var_context.clone(),
                 fun_context.clone(),
                 current_fun.clone(),
+                mode,
             );
             let fun_type_option = fun_context.get(&current_fun);
             match fun_type_option {
@@ -248,8 +1079,25 @@ fn type_check_statement(
             fun_name,
             arg_ids,
         } => {
-            let fun_type = fun_context.get(&fun_name).unwrap();
+            // The callee may be a function pointer variable or undefined (e.g. an inner call in an argument).
+            let fun_type = match (var_context.get(&fun_name), fun_context.get(&fun_name)) {
+                (
+                    Some(Type::FunPtrType {
+                        return_type,
+                        arg_types,
+                    }),
+                    _,
+                ) => FunType {
+                    return_type: *return_type.clone(),
+                    arg_types: arg_types.clone(),
+                },
+                (_, Some(fun_type)) => fun_type.clone(),
+                _ => return (Type::ErrorType, var_context),
+            };
             let fun_types = fun_type.arg_types.clone();
+            if fun_types.len() != arg_ids.len() {
+                return (Type::ErrorType, var_context);
+            }
             let mut counter = 0;
             for arg_id in arg_ids {
                 let (arg_type, var_context) = type_check_statement(
@@ -258,6 +1106,7 @@ fn type_check_statement(
                     var_context.clone(),
                     fun_context.clone(),
                     current_fun.clone(),
+                    mode,
                 );
                 if fun_types[counter] != arg_type {
                     return (Type::ErrorType, var_context);
@@ -268,6 +1117,7 @@ fn type_check_statement(
         }
         AstRelation::BinaryOp {
             id: _,
+            op,
             arg1_id,
             arg2_id,
         } => {
@@ -277,6 +1127,7 @@ fn type_check_statement(
                 var_context.clone(),
                 fun_context.clone(),
                 current_fun.clone(),
+                mode,
             );
             let (arg2_type, new_var_context) = type_check_statement(
                 ast.get_relation(arg2_id),
@@ -284,20 +1135,272 @@ fn type_check_statement(
                 new_var_context,
                 fun_context.clone(),
                 current_fun.clone(),
+                mode,
+            );
+            // An assignment has the type of the variable assigned to (the value is converted in promotion mode).
+            if op == "=" {
+                // Any variable type works if the value has the same type (or an arithmetic one it converts to).
+                let assignable =
+                    !matches!(arg1_type, Type::VoidType | Type::OkType | Type::ErrorType)
+                        && (arg1_type == arg2_type
+                            || (mode == ArithmeticMode::Promotion
+                                && is_numeric(&arg1_type)
+                                && promote_operands(&arg1_type, &arg2_type) != Type::ErrorType));
+                if !assignable {
+                    return (Type::ErrorType, var_context);
+                }
+                // Bind the variable like a declaration with an initializer does.
+                let mut new_var_context = new_var_context;
+                if let AstRelation::Var { id: _, var_name } = ast.get_relation(arg1_id) {
+                    new_var_context.insert(var_name, arg1_type.clone());
+                }
+                return (arg1_type, new_var_context);
+            }
+            // Bitwise operators and modulo only work on integers, as do the logical operators.
+            if is_integer_only(&op) || is_logical(&op) {
+                if arg1_type == Type::IntType && arg2_type == Type::IntType {
+                    return (Type::IntType, new_var_context);
+                } else {
+                    return (Type::ErrorType, var_context);
+                }
+            }
+            let operand_type = match mode {
+                ArithmeticMode::Promotion => promote_operands(&arg1_type, &arg2_type),
+                ArithmeticMode::Strict if arg1_type == arg2_type => arg1_type,
+                ArithmeticMode::Strict => Type::ErrorType,
+            };
+            // Void calls (like any other non-numeric type) can't be operands.
+            // Chars are promoted to int like in C, so arithmetic on them gives an int as well.
+            match operand_type {
+                Type::IntType | Type::FloatType | Type::DoubleType if is_comparison(&op) => {
+                    (Type::IntType, new_var_context)
+                }
+                Type::IntType | Type::CharType => (Type::IntType, new_var_context),
+                Type::FloatType => (Type::FloatType, new_var_context),
+                Type::DoubleType => (Type::DoubleType, new_var_context),
+                _ => (Type::ErrorType, var_context.clone()),
+            }
+        }
+        AstRelation::Index {
+            id: _,
+            array_id,
+            index_id,
+        } => {
+            let (array_type, new_var_context) = type_check_statement(
+                ast.get_relation(array_id),
+                ast,
+                var_context.clone(),
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            );
+            let (index_type, new_var_context) = type_check_statement(
+                ast.get_relation(index_id),
+                ast,
+                new_var_context,
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
             );
-            if arg1_type == arg2_type {
-                match arg1_type {
-                    Type::IntType => (Type::IntType, new_var_context),
-                    Type::FloatType => (Type::FloatType, new_var_context),
-                    _ => (Type::ErrorType, var_context.clone()),
+            match (array_type, index_type) {
+                (Type::ArrayType(elem_type), Type::IntType) => {
+                    return (*elem_type, new_var_context)
+                }
+                _ => return (Type::ErrorType, var_context),
+            }
+        }
+        // All elements need the same type (an empty list has no element type to give).
+        AstRelation::ArrayInit { id: _, elem_ids } => {
+            let mut elem_types: Vec<Type> = vec![];
+            for elem_id in elem_ids {
+                let (elem_type, _) = type_check_statement(
+                    ast.get_relation(elem_id),
+                    ast,
+                    var_context.clone(),
+                    fun_context.clone(),
+                    current_fun.clone(),
+                    mode,
+                );
+                elem_types.push(elem_type);
+            }
+            match elem_types.first() {
+                Some(elem_type)
+                    if *elem_type != Type::ErrorType
+                        && elem_types.iter().all(|t| t == elem_type) =>
+                {
+                    return (Type::ArrayType(Box::new(elem_type.clone())), var_context)
                 }
+                _ => return (Type::ErrorType, var_context),
+            }
+        }
+        AstRelation::UnaryOp { id: _, op, expr_id } => {
+            let (expr_type, new_var_context) = type_check_statement(
+                ast.get_relation(expr_id),
+                ast,
+                var_context.clone(),
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            );
+            // Logical negation only applies to (and gives) an int.
+            if op == "!" && expr_type == Type::IntType {
+                return (Type::IntType, new_var_context);
+            } else {
+                return (Type::ErrorType, var_context);
+            }
+        }
+        // Both arms need to agree on a type (and only one of them is evaluated, so only the condition can bind).
+        AstRelation::Conditional {
+            id: _,
+            cond_id,
+            then_id,
+            else_id,
+        } => {
+            let (cond_type, new_var_context) = type_check_statement(
+                ast.get_relation(cond_id),
+                ast,
+                var_context.clone(),
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            );
+            let (then_type, _) = type_check_statement(
+                ast.get_relation(then_id),
+                ast,
+                new_var_context.clone(),
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            );
+            let (else_type, _) = type_check_statement(
+                ast.get_relation(else_id),
+                ast,
+                new_var_context.clone(),
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            );
+            let arms_type = match mode {
+                ArithmeticMode::Promotion => promote_operands(&then_type, &else_type),
+                ArithmeticMode::Strict if then_type == else_type => then_type,
+                ArithmeticMode::Strict => Type::ErrorType,
+            };
+            if cond_type == Type::IntType && arms_type != Type::ErrorType {
+                return (arms_type, new_var_context);
             } else {
                 return (Type::ErrorType, var_context);
             }
         }
+        AstRelation::Deref { id: _, expr_id } => {
+            let (expr_type, new_var_context) = type_check_statement(
+                ast.get_relation(expr_id),
+                ast,
+                var_context.clone(),
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            );
+            // Only pointers can be dereferenced.
+            match expr_type {
+                Type::PtrType(pointee_type) => return (*pointee_type, new_var_context),
+                _ => return (Type::ErrorType, var_context),
+            }
+        }
+        AstRelation::AddressOf { id: _, expr_id } => {
+            let (expr_type, new_var_context) = type_check_statement(
+                ast.get_relation(expr_id),
+                ast,
+                var_context.clone(),
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            );
+            match expr_type {
+                Type::ErrorType | Type::VoidType => return (Type::ErrorType, var_context),
+                _ => return (Type::PtrType(Box::new(expr_type)), new_var_context),
+            }
+        }
+        AstRelation::Cast {
+            id: _,
+            target_type_id,
+            expr_id,
+        } => {
+            let target_type = type_check_literal(ast.relation_ref(target_type_id), ast);
+            let (expr_type, new_var_context) = type_check_statement(
+                ast.get_relation(expr_id),
+                ast,
+                var_context.clone(),
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            );
+            // Only numeric types can be cast into each other (and pointers into other pointers).
+            match (&target_type, &expr_type) {
+                (Type::PtrType(_), Type::PtrType(_)) => return (target_type, new_var_context),
+                _ if is_numeric(&target_type) && is_numeric(&expr_type) => {
+                    return (target_type, new_var_context)
+                }
+                _ => return (Type::ErrorType, var_context),
+            }
+        }
+        // The operand is never evaluated, it only has to be well-typed.
+        AstRelation::SizeOf { id: _, operand_id } => {
+            let operand = ast.get_relation(operand_id);
+            let operand_type = match operand {
+                AstRelation::Ptr { .. }
+                | AstRelation::Array { .. }
+                | AstRelation::Struct { .. } => type_check_literal(&operand, ast),
+                _ => {
+                    type_check_statement(
+                        operand,
+                        ast,
+                        var_context.clone(),
+                        fun_context.clone(),
+                        current_fun.clone(),
+                        mode,
+                    )
+                    .0
+                }
+            };
+            match operand_type {
+                Type::ErrorType => return (Type::ErrorType, var_context),
+                _ => return (Type::IntType, var_context),
+            }
+        }
+        AstRelation::Member {
+            id: _,
+            base_id,
+            field_name,
+            arrow,
+        } => {
+            let (base_type, new_var_context) = type_check_statement(
+                ast.get_relation(base_id),
+                ast,
+                var_context.clone(),
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            );
+            // With an arrow the base has to be a pointer to a struct instead.
+            let struct_type = match (base_type, arrow) {
+                (Type::PtrType(pointee_type), true) => *pointee_type,
+                (base_type, false) => base_type,
+                _ => return (Type::ErrorType, var_context),
+            };
+            match struct_type {
+                Type::StructType { name: _, fields } => {
+                    match fields.into_iter().find(|(name, _)| *name == field_name) {
+                        Some((_, field_type)) => return (field_type, new_var_context),
+                        None => return (Type::ErrorType, var_context),
+                    }
+                }
+                _ => return (Type::ErrorType, var_context),
+            }
+        }
+        // The variable may be undefined (or out of scope, e.g. declared in the other branch of an if).
         AstRelation::Var { id: _, var_name } => match var_context.get(&var_name) {
             Some(var_type) => return (var_type.clone(), var_context),
-            None => panic!("Unexpected variable name"),
+            None => return (Type::ErrorType, var_context),
         },
         AstRelation::If {
             id: _,
@@ -310,17 +1413,18 @@ fn type_check_statement(
                 var_context.clone(),
                 fun_context.clone(),
                 current_fun.clone(),
+                mode,
             );
             let (then_type, new_var_context) = type_check_compound(
-                &ast.get_relation(then_id),
+                ast.relation_ref(then_id),
                 ast,
                 new_var_context,
                 fun_context.clone(),
                 current_fun.clone(),
+                mode,
             );
-            let fun_type = fun_context.get(&current_fun).unwrap();
-            let return_type = fun_type.return_type.clone();
-            if cond_type == Type::IntType && then_type == return_type {
+            // Whether the branch returns is up to the return analysis (the branch is a statement like a loop body).
+            if cond_type == Type::IntType && then_type != Type::ErrorType {
                 return (Type::OkType, new_var_context);
             } else {
                 return (Type::ErrorType, new_var_context);
@@ -338,20 +1442,24 @@ fn type_check_statement(
                 var_context.clone(),
                 fun_context.clone(),
                 current_fun.clone(),
+                mode,
             );
-            let (then_type, new_var_context) = type_check_compound(
-                &ast.get_relation(then_id),
+            // Both branches start from the scope after the condition (declarations in one aren't visible in the other).
+            let (then_type, _) = type_check_compound(
+                ast.relation_ref(then_id),
                 ast,
-                new_var_context,
+                new_var_context.clone(),
                 fun_context.clone(),
                 current_fun.clone(),
+                mode,
             );
-            let (else_type, new_var_context) = type_check_compound(
-                &ast.get_relation(else_id),
+            let (else_type, _) = type_check_compound(
+                ast.relation_ref(else_id),
                 ast,
-                new_var_context,
+                new_var_context.clone(),
                 fun_context.clone(),
                 current_fun.clone(),
+                mode,
             );
             if cond_type == Type::IntType
                 && then_type != Type::ErrorType
@@ -373,13 +1481,15 @@ fn type_check_statement(
                 var_context.clone(),
                 fun_context.clone(),
                 current_fun.clone(),
+                mode,
             );
             let (body_type, new_var_context) = type_check_compound(
-                &ast.get_relation(body_id),
+                ast.relation_ref(body_id),
                 ast,
                 new_var_context,
                 fun_context.clone(),
                 current_fun.clone(),
+                mode,
             );
             if cond_type == Type::IntType && body_type != Type::ErrorType {
                 return (Type::OkType, new_var_context);
@@ -387,42 +1497,1103 @@ fn type_check_statement(
                 return (Type::ErrorType, new_var_context);
             }
         }
-        AstRelation::Void { id: _ } => (Type::VoidType, var_context),
-        AstRelation::Int { id: _ } => (Type::IntType, var_context),
-        AstRelation::Float { id: _ } => (Type::FloatType, var_context),
-        AstRelation::Char { id: _ } => (Type::CharType, var_context),
-        _ => panic!("Unexpected syntax"),
-    }
-}
-
-fn type_check_literal(node: &AstRelation) -> Type {
-    match *node {
-        AstRelation::Void { id: _ } => Type::VoidType,
-        AstRelation::Int { id: _ } => Type::IntType,
-        AstRelation::Float { id: _ } => Type::FloatType,
-        AstRelation::Char { id: _ } => Type::CharType,
-        _ => panic!("Unexpected syntax"),
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::parser_interface;
-    use crate::standard_type_checker::type_check;
-
-    #[test]
-    fn check_correct_program() {
-        let ast = parser_interface::parse_file_into_ast(&String::from(
-            "./tests/dev_examples/c/example2.c",
-        ));
-        assert_eq!(type_check(&ast), true);
-    }
-
-    #[test]
-    fn check_error_program() {
-        let ast = parser_interface::parse_file_into_ast(&String::from(
-            "./tests/dev_examples/c/example3.c",
-        ));
-        assert_eq!(type_check(&ast), false);
+        AstRelation::Switch {
+            id: _,
+            expr_id,
+            case_ids,
+        } => {
+            let (expr_type, new_var_context) = type_check_statement(
+                ast.get_relation(expr_id),
+                ast,
+                var_context.clone(),
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            );
+            let mut cases_typed = true;
+            // Cases don't fall through so each one is checked in the same context.
+            for case_id in case_ids {
+                let (case_type, _) = type_check_statement(
+                    ast.get_relation(case_id),
+                    ast,
+                    new_var_context.clone(),
+                    fun_context.clone(),
+                    current_fun.clone(),
+                    mode,
+                );
+                if case_type == Type::ErrorType {
+                    cases_typed = false;
+                }
+            }
+            if expr_type == Type::IntType && cases_typed {
+                return (Type::OkType, new_var_context);
+            } else {
+                return (Type::ErrorType, new_var_context);
+            }
+        }
+        AstRelation::Case {
+            id: _,
+            label_id,
+            body_id,
+        } => {
+            // Case labels have to be integer constants.
+            let label_typed = match ast.get_relation(label_id) {
+                AstRelation::Int { id: _ } => true,
+                _ => false,
+            };
+            let (body_type, new_var_context) = type_check_compound(
+                ast.relation_ref(body_id),
+                ast,
+                var_context,
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            );
+            if label_typed && body_type != Type::ErrorType {
+                return (Type::OkType, new_var_context);
+            } else {
+                return (Type::ErrorType, new_var_context);
+            }
+        }
+        AstRelation::DefaultCase { id: _, body_id } => {
+            let (body_type, new_var_context) = type_check_compound(
+                ast.relation_ref(body_id),
+                ast,
+                var_context,
+                fun_context.clone(),
+                current_fun.clone(),
+                mode,
+            );
+            if body_type != Type::ErrorType {
+                return (Type::OkType, new_var_context);
+            } else {
+                return (Type::ErrorType, new_var_context);
+            }
+        }
+        // Nested block.
+        AstRelation::Compound { .. } => {
+            type_check_compound(&node, ast, var_context, fun_context, current_fun, mode)
+        }
+        AstRelation::Label {
+            id: _,
+            name: _,
+            stmt_id,
+        } => type_check_statement(
+            ast.get_relation(stmt_id),
+            ast,
+            var_context,
+            fun_context,
+            current_fun,
+            mode,
+        ),
+        // Placement of jumps is checked separately for the whole function body (as are goto targets).
+        AstRelation::Break { id: _ }
+        | AstRelation::Continue { id: _ }
+        | AstRelation::Goto {
+            id: _,
+            label_name: _,
+        } => (Type::OkType, var_context),
+        AstRelation::Void { id: _ } => (Type::VoidType, var_context),
+        AstRelation::Int { id: _ } => (Type::IntType, var_context),
+        AstRelation::Float { id: _ } => (Type::FloatType, var_context),
+        AstRelation::Double { id: _ } => (Type::DoubleType, var_context),
+        AstRelation::Char { id: _ } => (Type::CharType, var_context),
+        _ => panic!("Unexpected syntax"),
+    }
+}
+
+fn is_integer_only(op: &str) -> bool {
+    matches!(op, "%" | "&" | "|" | "^" | "<<" | ">>")
+}
+
+// Comparisons give an int whatever the operand type, as C has no boolean type.
+fn is_comparison(op: &str) -> bool {
+    matches!(op, ">" | ">=" | "<" | "<=" | "==" | "!=")
+}
+
+// The operands are used as conditions, which are ints just like the result.
+fn is_logical(op: &str) -> bool {
+    matches!(op, "&&" | "||")
+}
+
+// Common type of mixed arithmetic operands (the error type if they can't be combined).
+fn promote_operands(t1: &Type, t2: &Type) -> Type {
+    let promote_char = |t: &Type| match t {
+        Type::CharType => Type::IntType,
+        _ => t.clone(),
+    };
+    let (t1, t2) = (&promote_char(t1), &promote_char(t2));
+    match (t1, t2) {
+        _ if t1 == t2 => t1.clone(),
+        (Type::IntType, Type::FloatType) | (Type::FloatType, Type::IntType) => Type::FloatType,
+        (Type::IntType | Type::FloatType, Type::DoubleType)
+        | (Type::DoubleType, Type::IntType | Type::FloatType) => Type::DoubleType,
+        _ => Type::ErrorType,
+    }
+}
+
+fn is_numeric(t: &Type) -> bool {
+    matches!(
+        t,
+        Type::IntType | Type::FloatType | Type::DoubleType | Type::CharType
+    )
+}
+
+// Get the name and type of every field in a struct definition (None if any of them is invalid).
+// Fields can't have struct types themselves for now (which also rules out recursive structs).
+fn resolve_fields(field_ids: &[ID], ast: &Tree) -> Option<Vec<(String, Type)>> {
+    let mut fields: Vec<(String, Type)> = vec![];
+    for field_id in field_ids {
+        match ast.get_relation(*field_id) {
+            AstRelation::Field {
+                id: _,
+                field_name,
+                type_id,
+            } => {
+                if contains_struct(type_id, ast)
+                    || fields.iter().any(|(name, _)| *name == field_name)
+                {
+                    return None;
+                }
+                match type_check_literal(ast.relation_ref(type_id), ast) {
+                    Type::ErrorType => return None,
+                    field_type => fields.push((field_name, field_type)),
+                }
+            }
+            _ => panic!("Unexpected syntax"),
+        }
+    }
+    Some(fields)
+}
+
+fn contains_struct(type_id: ID, ast: &Tree) -> bool {
+    match ast.get_relation(type_id) {
+        AstRelation::Struct { id: _, name: _ } => true,
+        AstRelation::Ptr {
+            id: _,
+            pointee_type_id,
+        } => contains_struct(pointee_type_id, ast),
+        AstRelation::Array {
+            id: _,
+            elem_type_id,
+            size: _,
+        } => contains_struct(elem_type_id, ast),
+        _ => false,
+    }
+}
+
+fn type_check_literal(node: &AstRelation, ast: &Tree) -> Type {
+    match *node {
+        AstRelation::Void { id: _ } => Type::VoidType,
+        AstRelation::Int { id: _ } => Type::IntType,
+        AstRelation::Float { id: _ } => Type::FloatType,
+        AstRelation::Double { id: _ } => Type::DoubleType,
+        AstRelation::Char { id: _ } => Type::CharType,
+        AstRelation::Ptr {
+            id: _,
+            pointee_type_id,
+        } => Type::PtrType(Box::new(type_check_literal(
+            ast.relation_ref(pointee_type_id),
+            ast,
+        ))),
+        // Struct types are looked up by name in the top level declarations.
+        AstRelation::Struct { id: _, ref name } => {
+            let root = ast.get_relation(ast.get_root());
+            if let AstRelation::TransUnit { id: _, body_ids } = root {
+                for body_id in body_ids {
+                    if let AstRelation::StructDef {
+                        id: _,
+                        name: def_name,
+                        field_ids,
+                    } = ast.get_relation(body_id)
+                    {
+                        if def_name == *name {
+                            return match resolve_fields(&field_ids, ast) {
+                                Some(fields) => Type::StructType {
+                                    name: name.clone(),
+                                    fields,
+                                },
+                                None => Type::ErrorType,
+                            };
+                        }
+                    }
+                }
+            }
+            Type::ErrorType
+        }
+        // Array sizes aren't part of the type.
+        AstRelation::Array {
+            id: _,
+            elem_type_id,
+            size: _,
+        } => Type::ArrayType(Box::new(type_check_literal(
+            ast.relation_ref(elem_type_id),
+            ast,
+        ))),
+        AstRelation::FunPtr {
+            id: _,
+            return_type_id,
+            ref arg_type_ids,
+        } => Type::FunPtrType {
+            return_type: Box::new(type_check_literal(ast.relation_ref(return_type_id), ast)),
+            arg_types: arg_type_ids
+                .iter()
+                .map(|arg_type_id| type_check_literal(ast.relation_ref(*arg_type_id), ast))
+                .collect(),
+        },
+        _ => panic!("Unexpected syntax"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Tree;
+    use crate::crate_path;
+    use crate::definitions::AstRelation;
+    use crate::parser_interface;
+    use crate::parser_interface::Backend;
+    use crate::standard_type_checker::{
+        find_duplicate_definitions, find_undefined_labels, find_uninitialized_reads,
+        find_unreachable_code, get_fun_context, type_check, type_check_all_errors, type_check_item,
+        type_check_node, type_check_strict_init, type_check_with_builtins, type_check_with_mode,
+        ArithmeticMode, FunType, Type,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn check_correct_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example2.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_error_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example3.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_switch_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example8.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_switch_error_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example9.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_pointer_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example11.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_pointer_error_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example12.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_array_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example13.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_array_error_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example14.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_double_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example15.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_double_error_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example16.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_cast_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example18.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_cast_error_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example19.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_sizeof_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example23.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_conditional_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example24.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_conditional_assignment() {
+        // Both arms are ints but the variable is a float.
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int c = 1; float x = c ? 1 : 2; return c; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        let errors = type_check_all_errors(&ast);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            ast.get_relation(errors[0].id),
+            AstRelation::Assign { .. }
+        ));
+        // Only one arm matches the variable.
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int c = 1; int x = c ? 1 : 2.5; return x; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        // Promoted the arms are a double, which still isn't an int.
+        assert_eq!(type_check_with_mode(&ast, ArithmeticMode::Promotion), false);
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int c = 1; int x = c ? 1 : 2; return x; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    // The operand isn't evaluated but it still has to be well-typed.
+    #[test]
+    fn check_sizeof_ill_typed_operand() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { float f = 1.0; return sizeof(f << 1); }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_missing_return() {
+        let ast = parser_interface::parse_source_into_ast("int main(void) { int a = 1; }").unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_empty_non_void_body() {
+        let ast = parser_interface::parse_source_into_ast("int main(void) {}").unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_empty_void_body() {
+        let ast = parser_interface::parse_source_into_ast(
+            "void f(void) {} int main(void) { f(); return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_void_falls_off_end() {
+        let ast =
+            parser_interface::parse_source_into_ast("void main(void) { int a = 1; }").unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_if_else_both_return() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { if (1 > 0) { return 1; } else { return 0; } }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_if_only_then_returns() {
+        let ast =
+            parser_interface::parse_source_into_ast("int main(void) { if (1 > 0) { return 1; } }")
+                .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_break_inside_loop() {
+        let ast = parser_interface::parse_source_into_ast(
+            "void main(void) { int a = 0; while (a < 10) { a = a + 1; continue; } while (a > 0) { break; } }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_break_outside_loop() {
+        let ast = parser_interface::parse_source_into_ast("void main(void) { int a = 0; break; }")
+            .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_continue_inside_switch() {
+        let ast = parser_interface::parse_source_into_ast(
+            "void main(void) { int a = 0; switch (a) { case 0: break; default: continue; } }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_struct_program() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example20.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_struct_unknown_field() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example21.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_recursive_struct() {
+        let ast = parser_interface::parse_source_into_ast(
+            "struct Node { int value; struct Node *next; }; int main(void) { return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_member_of_non_struct() {
+        let ast =
+            parser_interface::parse_source_into_ast("int main(void) { int a = 1; return a.x; }")
+                .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_shift_in_index() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int a[8]; int i = 1; a[i << 2] = i & 3; return a[4]; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_bitwise_float() {
+        let ast = parser_interface::parse_source_into_ast(
+            "float main(void) { float a = 1.5; return a & a; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_assignment_statement() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int x = 1; x = x + 1; return x; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        // The assignment keeps the type of the variable even if the value is promoted.
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int x = 1; x = x + 1.5; return x; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        assert_eq!(type_check_with_mode(&ast, ArithmeticMode::Promotion), true);
+        let assign_id = ast
+            .iter()
+            .find(|(_, relation)| matches!(relation, AstRelation::BinaryOp { op, .. } if op == "="))
+            .map(|(id, _)| id)
+            .unwrap();
+        let mut var_context = HashMap::new();
+        var_context.insert(String::from("x"), Type::IntType);
+        let result = type_check_node(
+            &ast,
+            assign_id,
+            var_context,
+            get_fun_context(&ast),
+            String::from("main"),
+            ArithmeticMode::Promotion,
+        );
+        assert_eq!(result, Ok(Type::IntType));
+    }
+
+    #[test]
+    fn check_assignment_in_condition() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int foo(void) { return 1; } int main(void) { int x = 0; if ((x = foo()) > 0) { return x; } return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        let ast = parser_interface::parse_source_into_ast(
+            "int foo(void) { return 1; } int main(void) { int x = 0; while ((x = foo()) > 0) { x = x - 1; } return x; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        // The value assigned still has to match the variable.
+        let ast = parser_interface::parse_source_into_ast(
+            "float foo(void) { return 1.5; } int main(void) { int x = 0; if ((x = foo()) > 0) { return x; } return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        // Not just arithmetic variables.
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { char c = 'a'; char d = 'b'; if ((c = d) == d) { return 1; } return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int a = 1; int *p = &a; int *q = &a; if (*(p = q) > 0) { return *p; } return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int a = 1; int *p = &a; if (*(p = a) > 0) { return 1; } return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn all_errors_are_reported() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int f(int a) { float x = a; if (a) { int y = 1.5; } return a; } int g(void) { int z = 2; return 1.5; }",
+        )
+        .unwrap();
+        let errors = type_check_all_errors(&ast);
+        assert_eq!(errors.len(), 3);
+        // Errors inside a statement are reported instead of the statement itself.
+        let error_relations: Vec<AstRelation> = errors
+            .iter()
+            .map(|error| ast.get_relation(error.id))
+            .collect();
+        assert!(
+            matches!(&error_relations[0], AstRelation::Assign { var_name, .. } if var_name == "x")
+        );
+        assert!(
+            matches!(&error_relations[1], AstRelation::Assign { var_name, .. } if var_name == "y")
+        );
+        assert!(matches!(error_relations[2], AstRelation::Return { .. }));
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn uninitialized_read_after_one_branch() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int c = 1; int x; if (c) { x = 1; } return x; }",
+        )
+        .unwrap();
+        // Only rejected when asked for.
+        assert_eq!(type_check(&ast), true);
+        assert_eq!(type_check_strict_init(&ast, ArithmeticMode::Strict), false);
+        assert_eq!(find_uninitialized_reads(&ast).len(), 1);
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int c = 1; int x; if (c) { x = 1; } else { x = 2; } return x; }",
+        )
+        .unwrap();
+        assert_eq!(type_check_strict_init(&ast, ArithmeticMode::Strict), true);
+        // A branch that returns never reaches the read.
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int c = 1; int x; if (c) { x = 1; } else { return 0; } return x; }",
+        )
+        .unwrap();
+        assert_eq!(type_check_strict_init(&ast, ArithmeticMode::Strict), true);
+    }
+
+    #[test]
+    fn unreachable_code_after_return() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int x = 1; return x; x = 2; return x; }",
+        )
+        .unwrap();
+        // Only a warning, the program still type-checks.
+        assert_eq!(type_check(&ast), true);
+        let errors = find_unreachable_code(&ast);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            ast.get_relation(errors[0].id),
+            AstRelation::BinaryOp { .. }
+        ));
+        // A return in a nested branch leaves the rest of the block reachable.
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int x = 1; if (x) { return 0; } x = 2; return x; }",
+        )
+        .unwrap();
+        assert!(find_unreachable_code(&ast).is_empty());
+        // Dead code inside the branch is still found.
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int x = 1; if (x) { return 0; x = 2; } return x; }",
+        )
+        .unwrap();
+        assert_eq!(find_unreachable_code(&ast).len(), 1);
+        // Consecutive dead statements (returns included) only give the first one.
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int x = 1; return 1; return 2; x = 3; return x; }",
+        )
+        .unwrap();
+        let errors = find_unreachable_code(&ast);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            ast.get_relation(errors[0].id),
+            AstRelation::Return { .. }
+        ));
+    }
+
+    #[test]
+    fn check_goto() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int x = 0; goto end; x = 1; end: return x; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int x = 0; goto missing; x = 1; end: return x; }",
+        )
+        .unwrap();
+        assert_eq!(find_undefined_labels(&ast).len(), 1);
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_nested_calls() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int bar(int x) { return x + 1; } int foo(int y) { return y * 2; } int main(void) { int a = 1; return foo(bar(a)); }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        let ast = parser_interface::parse_source_into_ast(
+            "int foo(int y) { return y * 2; } int main(void) { int a = 1; return foo(bar(a)); }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_char_operands() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int less(char c1, char c2) { return c1 < c2; } int sum(char c1, char c2) { return c1 + c2; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        // Mixing with an int needs promotion.
+        let ast = parser_interface::parse_source_into_ast(
+            "int next(char c) { int one = 1; return c + one; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        assert_eq!(type_check_with_mode(&ast, ArithmeticMode::Promotion), true);
+    }
+
+    #[test]
+    fn check_array_initializer() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int one = 1; int a[3] = {one, 2, 3}; return a[0]; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int a[2] = {1, 2.5}; return a[0]; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { float a[2] = {1, 2}; return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_function_pointer_call() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int apply(int (*fp)(int), int x) { return fp(x); }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        let ast = parser_interface::parse_source_into_ast(
+            "int apply(int (*fp)(int), float x) { return fp(x); }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        let ast = parser_interface::parse_source_into_ast(
+            "float apply(int (*fp)(int), int x) { return fp(x); }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_with_builtins() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int c = putchar(65); return c; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        let mut builtins = HashMap::new();
+        builtins.insert(
+            String::from("putchar"),
+            FunType {
+                return_type: Type::IntType,
+                arg_types: vec![Type::IntType],
+            },
+        );
+        assert_eq!(type_check_with_builtins(&ast, &builtins), true);
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int c = putchar(6.5); return c; }",
+        )
+        .unwrap();
+        assert_eq!(type_check_with_builtins(&ast, &builtins), false);
+    }
+
+    #[test]
+    fn check_void_variable() {
+        let ast = parser_interface::parse_source_into_ast("int main(void) { void x; return 0; }")
+            .unwrap();
+        assert_eq!(type_check(&ast), false);
+        let ast = parser_interface::parse_source_into_ast(
+            "int f(void a) { return 0; } int main(void) { return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        let ast = parser_interface::parse_source_into_ast(
+            "void f(int a) { return; } int main(void) { f(1); return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_logical_operators() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int a = 1; int b = 0; return a && b || a; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { float a = 1.0f; float b = 0.0f; return a && b; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        // Comparisons give ints so they can be combined.
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { float a = 1.0f; float b = 0.0f; return a < b && b < a; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_merged_files() {
+        let lib_ast =
+            parser_interface::parse_source_into_ast("int add(int a, int b) { return a + b; }")
+                .unwrap();
+        let main_ast =
+            parser_interface::parse_source_into_ast("int main(void) { return add(1, 2); }")
+                .unwrap();
+        assert_eq!(type_check(&main_ast), false);
+        let merged = Tree::merge(vec![lib_ast, main_ast]);
+        assert_eq!(merged.validate(), Ok(()));
+        assert_eq!(merged.function_names(), vec!["add", "main"]);
+        assert_eq!(type_check(&merged), true);
+    }
+
+    #[test]
+    fn check_else_branch_scope() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int c = 1; if (c) { int x = 1; } else { x = 2; } return c; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int c = 1; if (c) { int x = 1; } else { int x = 2; } return c; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_negated_condition() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int x = 0; if (!x) { x = 1; } return x; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { float x = 0.5; if (!x) { return 1; } return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_modulo_in_condition() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int x = 7; int even = 0; if (x % 2 == 0) { even = 1; } return even; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { float x = 7.5; if (x % 2.0) { return 1; } return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_void_call_as_operand() {
+        let ast = parser_interface::parse_source_into_ast(
+            "void f(void) { } int main(void) { int a = 1; return a + f(); }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_void_call_as_statement() {
+        let ast = parser_interface::parse_source_into_ast(
+            "void f(void) { } int main(void) { f(); return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    const MIXED_ARITHMETIC_PROGRAM: &str =
+        "float main(void) { int a = 1; float b = a + 1.0; return b * 2; }";
+
+    #[test]
+    fn check_mixed_arithmetic_strict() {
+        let ast = parser_interface::parse_source_into_ast(MIXED_ARITHMETIC_PROGRAM).unwrap();
+        assert_eq!(type_check(&ast), false);
+        assert_eq!(type_check_with_mode(&ast, ArithmeticMode::Strict), false);
+    }
+
+    #[test]
+    fn check_mixed_arithmetic_promotion() {
+        let ast = parser_interface::parse_source_into_ast(MIXED_ARITHMETIC_PROGRAM).unwrap();
+        assert_eq!(type_check_with_mode(&ast, ArithmeticMode::Promotion), true);
+    }
+
+    #[test]
+    fn check_inner_declaration_shadows_outer() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { float x = 1.5; { int x = 1; x = x + 2; } x = x + 2.5; return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_inner_declaration_does_not_leak() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { float x = 1.5; { int x = 1; } return x; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_parameter_does_not_leak() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int f(int a) { return a; } int main(void) { return a; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        // Another function can still use the same name for its own parameter.
+        let ast = parser_interface::parse_source_into_ast(
+            "int f(int a) { return a; } int g(float a) { return 1; } int main(void) { return f(1); }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_duplicate_function() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int foo(void) { return 1; } int foo(void) { return 2; } int main(void) { return foo(); }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        let errors = find_duplicate_definitions(&ast);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "function `foo` is defined more than once"
+        );
+    }
+
+    #[test]
+    fn check_duplicate_parameter() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int add(int a, int a) { return a; } int main(void) { return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), false);
+        let errors = find_duplicate_definitions(&ast);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "parameter `a` is declared more than once in `add`"
+        );
+    }
+
+    #[test]
+    fn check_bare_return_in_void() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example22.c"),
+            Backend::LangC,
+        );
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_bare_return_in_non_void() {
+        let ast = parser_interface::parse_source_into_ast("int main(void) { return; }").unwrap();
+        assert_eq!(type_check(&ast), false);
+    }
+
+    #[test]
+    fn check_typedef_program() {
+        let ast = parser_interface::parse_source_into_ast(
+            "typedef float real; real half(real a) { return a * 0.5; } int main(void) { real b = half(1.5); return 0; }",
+        )
+        .unwrap();
+        assert_eq!(type_check(&ast), true);
+    }
+
+    #[test]
+    fn check_subexpression_type() {
+        let ast = parser_interface::parse_source_into_ast(
+            "int main(void) { int a = 1; float b = 2.5; return a * 2; }",
+        )
+        .unwrap();
+        let op_id = ast
+            .iter()
+            .find(|(_, relation)| matches!(relation, AstRelation::BinaryOp { .. }))
+            .map(|(id, _)| id)
+            .unwrap();
+        let mut var_context = HashMap::new();
+        var_context.insert(String::from("a"), Type::IntType);
+        var_context.insert(String::from("b"), Type::FloatType);
+        let result = type_check_node(
+            &ast,
+            op_id,
+            var_context.clone(),
+            get_fun_context(&ast),
+            String::from("main"),
+            ArithmeticMode::Strict,
+        );
+        assert_eq!(result, Ok(Type::IntType));
+        // The same expression doesn't type-check if a is a float.
+        var_context.insert(String::from("a"), Type::FloatType);
+        let result = type_check_node(
+            &ast,
+            op_id,
+            var_context,
+            get_fun_context(&ast),
+            String::from("main"),
+            ArithmeticMode::Strict,
+        );
+        assert_eq!(result.unwrap_err().id, op_id);
+    }
+
+    #[test]
+    fn check_multiple_declarators() {
+        let ast = parser_interface::parse_file_into_ast(
+            &crate_path("tests/dev_examples/c/example10.c"),
+            Backend::LangC,
+        );
+        let body_id = match ast.get_relation(ast.get_root()) {
+            AstRelation::TransUnit { id: _, body_ids } => match ast.get_relation(body_ids[0]) {
+                AstRelation::FunDef { body_id, .. } => body_id,
+                _ => panic!("Expected a function definition"),
+            },
+            _ => panic!("Expected a translation unit"),
+        };
+        let mut fun_context = HashMap::new();
+        fun_context.insert(
+            String::from("main"),
+            FunType {
+                return_type: Type::IntType,
+                arg_types: vec![],
+            },
+        );
+        // Check the items directly since the compound drops its declarations on exit.
+        let start_id = match ast.get_relation(body_id) {
+            AstRelation::Compound { id: _, start_id } => start_id,
+            _ => panic!("Expected a compound"),
+        };
+        let (body_type, var_context) = type_check_item(
+            ast.get_relation(start_id),
+            &ast,
+            HashMap::new(),
+            fun_context,
+            String::from("main"),
+            ArithmeticMode::Strict,
+        );
+        assert_eq!(body_type, Type::OkType);
+        assert_eq!(var_context.get("a"), Some(&Type::IntType));
+        assert_eq!(var_context.get("b"), Some(&Type::IntType));
     }
 }
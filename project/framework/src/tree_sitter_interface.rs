@@ -0,0 +1,572 @@
+extern crate tree_sitter;
+extern crate tree_sitter_c;
+
+use std::fs;
+use tree_sitter::{Node, Parser};
+
+use crate::ast::Tree;
+use crate::definitions::{AstRelation, InternalError, ID};
+use crate::parser_interface::locate_parse_error;
+
+pub fn parse_with_tree_sitter(file_path: &String) -> Tree {
+    match try_parse_with_tree_sitter(file_path) {
+        Ok(tree) => tree,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+// Tree-sitter recovers from syntax errors by inserting error (and missing) nodes, those are reported instead.
+// Syntax the builder doesn't support yet is reported the same way.
+pub fn try_parse_with_tree_sitter(file_path: &String) -> Result<Tree, InternalError> {
+    let source = match fs::read_to_string(file_path) {
+        Ok(source) => source,
+        Err(e) => return Err(InternalError::IoError(format!("{}", e))),
+    };
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_c::language()).unwrap();
+    let parse = match parser.parse(&source, None) {
+        Some(parse) => parse,
+        None => panic!("Error during parsing: no tree produced"),
+    };
+    let (offset, message) = match first_syntax_error(parse.root_node()) {
+        Some(node) if node.is_missing() => (node.start_byte(), format!("missing {}", node.kind())),
+        Some(node) => (node.start_byte(), String::from("syntax error")),
+        None => {
+            let mut ast_builder = TreeSitterAstBuilder::new(&source);
+            let tree = TreeSitterAstBuilder::build_tree(&mut ast_builder, parse.root_node());
+            match ast_builder.error {
+                Some(error) => error,
+                None => return Ok(tree),
+            }
+        }
+    };
+    Err(InternalError::ParseError(locate_parse_error(
+        file_path, &source, offset, message,
+    )))
+}
+
+// Missing nodes are tokens (e.g. a semicolon) so unnamed children have to be searched as well.
+fn first_syntax_error(node: Node) -> Option<Node> {
+    if node.is_error() || node.is_missing() {
+        return Some(node);
+    }
+    if !node.has_error() {
+        return None;
+    }
+    let mut cursor = node.walk();
+    let children: Vec<Node> = node.children(&mut cursor).collect();
+    children.into_iter().find_map(first_syntax_error)
+}
+
+struct TreeSitterAstBuilder<'a> {
+    source: &'a str,
+    tree: Tree,
+    current_max_id: ID,
+    // The first unsupported construct found (offset into the source and message).
+    error: Option<(usize, String)>,
+}
+
+// Traverse the tree-sitter concrete syntax tree creating the same internal AST tree as the lang_c builder.
+// Nodes are visited in the same order as in the lang_c builder so that both produce identical IDs.
+impl<'a> TreeSitterAstBuilder<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            tree: Tree::new(),
+            current_max_id: 0,
+            error: None,
+        }
+    }
+
+    pub fn build_tree(&mut self, node: Node<'a>) -> Tree {
+        Self::visit_translation_unit(self, node)
+    }
+
+    fn next_id(&mut self) -> ID {
+        let node_id = self.current_max_id;
+        self.current_max_id = self.current_max_id + 1;
+        return node_id;
+    }
+
+    // Records the construct and returns a void node standing in for it (the tree is dropped for the error anyway).
+    fn report_unsupported(&mut self, node: Node<'a>) -> ID {
+        if self.error.is_none() {
+            self.error = Some((
+                node.start_byte(),
+                format!("unsupported syntax: {}", node.kind()),
+            ));
+        }
+        let node_id = self.next_id();
+        self.tree
+            .add_node(node_id, AstRelation::Void { id: node_id });
+        return node_id;
+    }
+
+    fn text(&self, node: Node<'a>) -> &'a str {
+        return node.utf8_text(self.source.as_bytes()).unwrap();
+    }
+
+    // Comments are part of the concrete syntax tree but irrelevant here.
+    fn named_children(&self, node: Node<'a>) -> Vec<Node<'a>> {
+        let mut cursor = node.walk();
+        return node
+            .named_children(&mut cursor)
+            .filter(|child| child.kind() != "comment")
+            .collect();
+    }
+
+    fn field(&self, node: Node<'a>, name: &str) -> Node<'a> {
+        match node.child_by_field_name(name) {
+            Some(child) => return child,
+            None => panic!("Expected field {} in {}", name, node.kind()),
+        }
+    }
+
+    // For now we will assume a single translation unit as root of tree.
+    fn visit_translation_unit(&mut self, node: Node<'a>) -> Tree {
+        let mut body_ids = vec![];
+        for element in self.named_children(node) {
+            body_ids.push(self.visit_external_declaration(element));
+        }
+        let node_id = self.next_id();
+        let relation = AstRelation::TransUnit {
+            id: node_id,
+            body_ids: body_ids.clone(),
+        };
+        self.tree.add_root_node(node_id, relation);
+        self.tree.replace_children(node_id, body_ids);
+        return std::mem::take(&mut self.tree);
+    }
+
+    fn visit_external_declaration(&mut self, node: Node<'a>) -> ID {
+        match node.kind() {
+            "function_definition" => return self.visit_function_definition(node),
+            _ => return self.report_unsupported(node),
+        }
+    }
+
+    fn visit_function_definition(&mut self, node: Node<'a>) -> ID {
+        let return_type_id = self.visit_type_specifier(self.field(node, "type"));
+        let body_id = self.visit_statement(self.field(node, "body"));
+        let declarator = self.field(node, "declarator");
+        if declarator.kind() != "function_declarator" {
+            return self.report_unsupported(declarator);
+        }
+        let fun_name = self.visit_declarator(self.field(declarator, "declarator"));
+        let mut arg_ids = vec![];
+        let parameters = self.named_children(self.field(declarator, "parameters"));
+        // A (void) parameter list means no parameters at all.
+        if !self.is_void_parameter_list(&parameters) {
+            for parameter in parameters {
+                arg_ids.push(self.visit_parameter_declaration(parameter));
+            }
+        }
+        let node_id = self.next_id();
+        let relation = AstRelation::FunDef {
+            id: node_id,
+            fun_name,
+            return_type_id,
+            arg_ids: arg_ids.clone(),
+            body_id,
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.replace_children(node_id, arg_ids);
+        self.tree.link_child(node_id, return_type_id);
+        self.tree.link_child(node_id, body_id);
+        return node_id;
+    }
+
+    // Integer widths and signedness aren't distinguished yet (see lang_c backend).
+    fn visit_type_specifier(&mut self, node: Node<'a>) -> ID {
+        let node = match node.kind() {
+            "primitive_type" => node,
+            "sized_type_specifier" => match node.child_by_field_name("type") {
+                Some(base) if base.kind() == "primitive_type" => base,
+                Some(base) => return self.report_unsupported(base),
+                None => {
+                    let node_id = self.next_id();
+                    self.tree
+                        .add_node(node_id, AstRelation::Int { id: node_id });
+                    return node_id;
+                }
+            },
+            _ => return self.report_unsupported(node),
+        };
+        let node_id = self.next_id();
+        let relation = match self.text(node) {
+            "void" => AstRelation::Void { id: node_id },
+            "int" => AstRelation::Int { id: node_id },
+            "char" => AstRelation::Char { id: node_id },
+            "float" => AstRelation::Float { id: node_id },
+            "double" => AstRelation::Double { id: node_id },
+            _ => return self.report_unsupported(node),
+        };
+        self.tree.add_node(node_id, relation);
+        return node_id;
+    }
+
+    fn visit_statement(&mut self, node: Node<'a>) -> ID {
+        match node.kind() {
+            "compound_statement" => {
+                let mut stmt_ids = vec![];
+                for item in self.named_children(node) {
+                    stmt_ids.extend(self.visit_block_item(item));
+                }
+                let start_id = self.tree.add_item_chain(stmt_ids, &mut self.current_max_id);
+                let node_id = self.next_id();
+                let relation = AstRelation::Compound {
+                    id: node_id,
+                    start_id,
+                };
+                self.tree.add_node(node_id, relation);
+                self.tree.link_child(node_id, start_id);
+                return node_id;
+            }
+            "expression_statement" => match self.named_children(node).first() {
+                Some(expression) => return self.visit_expression(*expression),
+                // An empty statement on its own (e.g. a loop body) becomes an empty compound.
+                None => {
+                    let start_id = self.tree.add_item_chain(vec![], &mut self.current_max_id);
+                    let node_id = self.next_id();
+                    let relation = AstRelation::Compound {
+                        id: node_id,
+                        start_id,
+                    };
+                    self.tree.add_node(node_id, relation);
+                    self.tree.link_child(node_id, start_id);
+                    return node_id;
+                }
+            },
+            "return_statement" => {
+                let expr_id = match self.named_children(node).first() {
+                    Some(expression) => self.visit_expression(*expression),
+                    // A bare return returns void.
+                    None => {
+                        let expr_id = self.next_id();
+                        self.tree
+                            .add_node(expr_id, AstRelation::Void { id: expr_id });
+                        expr_id
+                    }
+                };
+                let node_id = self.next_id();
+                let relation = AstRelation::Return {
+                    id: node_id,
+                    expr_id,
+                };
+                self.tree.add_node(node_id, relation);
+                self.tree.link_child(node_id, expr_id);
+                return node_id;
+            }
+            "if_statement" => return self.visit_if_statement(node),
+            "while_statement" => return self.visit_while_statement(node),
+            "break_statement" => {
+                let node_id = self.next_id();
+                self.tree
+                    .add_node(node_id, AstRelation::Break { id: node_id });
+                return node_id;
+            }
+            "continue_statement" => {
+                let node_id = self.next_id();
+                self.tree
+                    .add_node(node_id, AstRelation::Continue { id: node_id });
+                return node_id;
+            }
+            "labeled_statement" => {
+                let name = self.text(self.field(node, "label")).to_string();
+                let stmt_id = match self.named_children(node).last() {
+                    Some(statement) => self.visit_statement(*statement),
+                    None => panic!("Unexpected syntax"),
+                };
+                let node_id = self.next_id();
+                let relation = AstRelation::Label {
+                    id: node_id,
+                    name,
+                    stmt_id,
+                };
+                self.tree.add_node(node_id, relation);
+                self.tree.link_child(node_id, stmt_id);
+                return node_id;
+            }
+            "goto_statement" => {
+                let label_name = self.text(self.field(node, "label")).to_string();
+                let node_id = self.next_id();
+                self.tree.add_node(
+                    node_id,
+                    AstRelation::Goto {
+                        id: node_id,
+                        label_name,
+                    },
+                );
+                return node_id;
+            }
+            _ => return self.report_unsupported(node),
+        }
+    }
+
+    fn visit_block_item(&mut self, node: Node<'a>) -> Vec<ID> {
+        match node.kind() {
+            "declaration" => return self.visit_declaration(node),
+            // Empty statements inside a block are skipped.
+            "expression_statement" if self.named_children(node).is_empty() => return vec![],
+            _ => return vec![self.visit_statement(node)],
+        }
+    }
+
+    // One assignment (or declaration without a value) per declarator.
+    fn visit_declaration(&mut self, node: Node<'a>) -> Vec<ID> {
+        let mut cursor = node.walk();
+        let declarators: Vec<Node<'a>> = node
+            .children_by_field_name("declarator", &mut cursor)
+            .collect();
+        let mut stmt_ids = vec![];
+        for declarator in declarators {
+            let type_id = self.visit_type_specifier(self.field(node, "type"));
+            if declarator.kind() != "init_declarator" {
+                let var_name = self.visit_declarator(declarator);
+                let node_id = self.next_id();
+                let relation = AstRelation::Decl {
+                    id: node_id,
+                    var_name,
+                    type_id,
+                };
+                self.tree.add_node(node_id, relation);
+                self.tree.link_child(node_id, type_id);
+                stmt_ids.push(node_id);
+                continue;
+            }
+            let var_name = self.visit_declarator(self.field(declarator, "declarator"));
+            let expr_id = self.visit_expression(self.field(declarator, "value"));
+            let node_id = self.next_id();
+            let relation = AstRelation::Assign {
+                id: node_id,
+                var_name,
+                type_id,
+                expr_id,
+            };
+            self.tree.add_node(node_id, relation);
+            self.tree.link_child(node_id, type_id);
+            self.tree.link_child(node_id, expr_id);
+            stmt_ids.push(node_id);
+        }
+        return stmt_ids;
+    }
+
+    fn visit_while_statement(&mut self, node: Node<'a>) -> ID {
+        let cond_id = self.visit_expression(self.field(node, "condition"));
+        let body_id = self.visit_statement(self.field(node, "body"));
+        let node_id = self.next_id();
+        let relation = AstRelation::While {
+            id: node_id,
+            cond_id,
+            body_id,
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.link_child(node_id, cond_id);
+        self.tree.link_child(node_id, body_id);
+        return node_id;
+    }
+
+    fn visit_if_statement(&mut self, node: Node<'a>) -> ID {
+        let cond_id = self.visit_expression(self.field(node, "condition"));
+        let then_id = self.visit_statement(self.field(node, "consequence"));
+        if let Some(alternative) = node.child_by_field_name("alternative") {
+            // Newer grammar versions wrap the else branch in its own node.
+            let else_statement = if alternative.kind() == "else_clause" {
+                self.named_children(alternative)[0]
+            } else {
+                alternative
+            };
+            let else_id = self.visit_statement(else_statement);
+            let node_id = self.next_id();
+            let relation = AstRelation::IfElse {
+                id: node_id,
+                cond_id,
+                then_id,
+                else_id,
+            };
+            self.tree.add_node(node_id, relation);
+            self.tree.link_child(node_id, cond_id);
+            self.tree.link_child(node_id, then_id);
+            self.tree.link_child(node_id, else_id);
+            return node_id;
+        } else {
+            let node_id = self.next_id();
+            let relation = AstRelation::If {
+                id: node_id,
+                cond_id,
+                then_id,
+            };
+            self.tree.add_node(node_id, relation);
+            self.tree.link_child(node_id, cond_id);
+            self.tree.link_child(node_id, then_id);
+            return node_id;
+        }
+    }
+
+    fn visit_expression(&mut self, node: Node<'a>) -> ID {
+        match node.kind() {
+            "identifier" => {
+                let node_id = self.next_id();
+                let relation = AstRelation::Var {
+                    id: node_id,
+                    var_name: String::from(self.text(node)),
+                };
+                self.tree.add_node(node_id, relation);
+                return node_id;
+            }
+            "number_literal" | "char_literal" => return self.visit_constant(node),
+            "call_expression" => return self.visit_call_expression(node),
+            "binary_expression" | "assignment_expression" => {
+                return self.visit_binary_operator_expression(node)
+            }
+            "unary_expression" => return self.visit_unary_expression(node),
+            "conditional_expression" => return self.visit_conditional_expression(node),
+            // Parentheses don't create a node of their own (matching lang_c).
+            "parenthesized_expression" => {
+                return self.visit_expression(self.named_children(node)[0])
+            }
+            _ => return self.report_unsupported(node),
+        }
+    }
+
+    fn visit_call_expression(&mut self, node: Node<'a>) -> ID {
+        let callee = self.field(node, "function");
+        if callee.kind() != "identifier" {
+            return self.report_unsupported(callee);
+        }
+        let fun_name = String::from(self.text(callee));
+        let mut arg_ids = vec![];
+        for argument in self.named_children(self.field(node, "arguments")) {
+            arg_ids.push(self.visit_expression(argument));
+        }
+        let node_id = self.next_id();
+        let relation = AstRelation::FunCall {
+            id: node_id,
+            fun_name,
+            arg_ids: arg_ids.clone(),
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.replace_children(node_id, arg_ids);
+        return node_id;
+    }
+
+    fn visit_conditional_expression(&mut self, node: Node<'a>) -> ID {
+        let cond_id = self.visit_expression(self.field(node, "condition"));
+        let then_id = self.visit_expression(self.field(node, "consequence"));
+        let else_id = self.visit_expression(self.field(node, "alternative"));
+        let node_id = self.next_id();
+        let relation = AstRelation::Conditional {
+            id: node_id,
+            cond_id,
+            then_id,
+            else_id,
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.link_child(node_id, cond_id);
+        self.tree.link_child(node_id, then_id);
+        self.tree.link_child(node_id, else_id);
+        return node_id;
+    }
+
+    fn visit_binary_operator_expression(&mut self, node: Node<'a>) -> ID {
+        let arg1_id = self.visit_expression(self.field(node, "left"));
+        let arg2_id = self.visit_expression(self.field(node, "right"));
+        match self.field(node, "operator").kind() {
+            op @ ("+" | "-" | "*" | "/" | "%" | ">" | ">=" | "<" | "<=" | "==" | "!=" | "&&"
+            | "||" | "&" | "|" | "^" | "<<" | ">>" | "=") => {
+                let node_id = self.next_id();
+                let relation = AstRelation::BinaryOp {
+                    id: node_id,
+                    op: String::from(op),
+                    arg1_id,
+                    arg2_id,
+                };
+                self.tree.add_node(node_id, relation);
+                self.tree.link_child(node_id, arg1_id);
+                self.tree.link_child(node_id, arg2_id);
+                return node_id;
+            }
+            _ => return self.report_unsupported(self.field(node, "operator")),
+        }
+    }
+
+    fn visit_unary_expression(&mut self, node: Node<'a>) -> ID {
+        let expr_id = self.visit_expression(self.field(node, "argument"));
+        match self.field(node, "operator").kind() {
+            "!" => {
+                let node_id = self.next_id();
+                let relation = AstRelation::UnaryOp {
+                    id: node_id,
+                    op: String::from("!"),
+                    expr_id,
+                };
+                self.tree.add_node(node_id, relation);
+                self.tree.link_child(node_id, expr_id);
+                return node_id;
+            }
+            _ => return self.report_unsupported(self.field(node, "operator")),
+        }
+    }
+
+    fn visit_constant(&mut self, node: Node<'a>) -> ID {
+        let node_id = self.next_id();
+        let relation = if node.kind() == "char_literal" {
+            AstRelation::Char { id: node_id }
+        } else if is_float_literal(self.text(node)) {
+            AstRelation::Float { id: node_id }
+        } else {
+            AstRelation::Int { id: node_id }
+        };
+        self.tree.add_node(node_id, relation);
+        return node_id;
+    }
+
+    // Only plain identifiers are supported as declarators for now.
+    fn visit_declarator(&mut self, node: Node<'a>) -> String {
+        match node.kind() {
+            "identifier" => return String::from(self.text(node)),
+            _ => {
+                self.report_unsupported(node);
+                return String::new();
+            }
+        }
+    }
+
+    fn is_void_parameter_list(&self, parameters: &[Node<'a>]) -> bool {
+        match parameters {
+            [parameter] => {
+                parameter.child_by_field_name("declarator").is_none()
+                    && self.text(self.field(*parameter, "type")) == "void"
+            }
+            _ => false,
+        }
+    }
+
+    fn visit_parameter_declaration(&mut self, node: Node<'a>) -> ID {
+        let type_id = self.visit_type_specifier(self.field(node, "type"));
+        let var_name;
+        if let Some(declarator) = node.child_by_field_name("declarator") {
+            var_name = self.visit_declarator(declarator);
+        } else {
+            var_name = String::from("");
+        }
+        let node_id = self.next_id();
+        let relation = AstRelation::Arg {
+            id: node_id,
+            var_name,
+            type_id,
+        };
+        self.tree.add_node(node_id, relation);
+        self.tree.link_child(node_id, type_id);
+        return node_id;
+    }
+}
+
+// Tree-sitter doesn't distinguish integer and floating constants so look at the literal itself.
+fn is_float_literal(literal: &str) -> bool {
+    let lower = literal.to_lowercase();
+    if lower.starts_with("0x") {
+        return lower.contains('.') || lower.contains('p');
+    }
+    return lower.contains('.') || lower.contains('e');
+}
@@ -0,0 +1,13 @@
+int read(int *p)
+{
+    return *p;
+}
+
+int main(void)
+{
+    int a = 1;
+    int *p = &a;
+    int **pp = &p;
+    *p = 2;
+    return read(*pp);
+}
@@ -0,0 +1,18 @@
+int sum(void)
+{
+    int a[10];
+    int i = 0;
+    while (i < 10)
+    {
+        a[i] = i * 2;
+        i = i + 1;
+    }
+    int total = 0;
+    i = 0;
+    while (i < 10)
+    {
+        total = total + a[i];
+        i = i + 1;
+    }
+    return total;
+}
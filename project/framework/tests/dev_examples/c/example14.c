@@ -0,0 +1,6 @@
+int first(void)
+{
+    int a[10];
+    a[0] = 1;
+    return a[1.5];
+}
@@ -0,0 +1,13 @@
+double average(double a, double b, double n)
+{
+    double sum = a + b;
+    return sum / n;
+}
+
+int main(void)
+{
+    double x;
+    double y;
+    double z = average(x, y, x);
+    return 0;
+}
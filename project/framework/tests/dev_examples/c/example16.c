@@ -0,0 +1,4 @@
+double scale(double a, float factor)
+{
+    return a * factor;
+}
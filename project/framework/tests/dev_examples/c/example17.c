@@ -0,0 +1,13 @@
+unsigned long widen(unsigned int a, short b)
+{
+    long c = a + b;
+    unsigned long long d = c * 2;
+    return d;
+}
+
+int main(void)
+{
+    signed char e = 'e';
+    long double f = widen(1, 2);
+    return 0;
+}
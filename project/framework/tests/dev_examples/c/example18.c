@@ -0,0 +1,12 @@
+float average(int total, int count)
+{
+    float mean = (float)total / (float)count;
+    return mean + 0.5;
+}
+
+int main(void)
+{
+    float a = average(10, 4);
+    int b = (int)a;
+    return b;
+}
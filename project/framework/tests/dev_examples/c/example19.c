@@ -0,0 +1,5 @@
+float address(int a)
+{
+    int *p = &a;
+    return (float)p;
+}
@@ -0,0 +1,20 @@
+struct Point
+{
+    int x;
+    int y;
+};
+
+int sum(struct Point p)
+{
+    return p.x + p.y;
+}
+
+int main(void)
+{
+    struct Point p;
+    p.x = 1;
+    p.y = 2;
+    struct Point *q = &p;
+    q->x = 3;
+    return sum(p);
+}
@@ -0,0 +1,10 @@
+struct Point
+{
+    int x;
+    int y;
+};
+
+int depth(struct Point p)
+{
+    return p.z;
+}
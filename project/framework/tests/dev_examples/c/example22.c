@@ -0,0 +1,17 @@
+void reset(int a)
+{
+    while (a > 0)
+    {
+        a = a - 1;
+        return;
+    }
+    ;
+    return;
+}
+
+int main(void)
+{
+    reset(1);
+    ;
+    return 0;
+}
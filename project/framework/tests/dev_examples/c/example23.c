@@ -0,0 +1,13 @@
+int element_count(void)
+{
+    int values[8];
+    int count = sizeof(values) / sizeof(int);
+    return count + sizeof count;
+}
+
+int main(void)
+{
+    int *p;
+    int bytes = sizeof(int *) * element_count();
+    return bytes + sizeof(*p);
+}
@@ -0,0 +1,12 @@
+int max(int a, int b)
+{
+    return a > b ? a : b;
+}
+
+int main(void)
+{
+    int c = 1;
+    float f = c ? 1.5 : 2.5;
+    int x = max(c, 2) > 1 ? max(c, 2) : 0;
+    return c ? x : 0;
+}
@@ -0,0 +1,19 @@
+int classify(int x)
+{
+    switch (x)
+    {
+    case 0:
+        return 0;
+    case 1:
+        return 10;
+    default:
+        return 20;
+    }
+    return 30;
+}
+
+int main(void)
+{
+    classify(1);
+    return 0;
+}
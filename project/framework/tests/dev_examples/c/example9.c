@@ -0,0 +1,11 @@
+int classify(float x)
+{
+    switch (x)
+    {
+    case 0:
+        return 0;
+    default:
+        return 20;
+    }
+    return 30;
+}
@@ -0,0 +1,31 @@
+// Run the binary with --dump-ast, which prints the parsed tree and exits without type checking.
+use cerium_framework::crate_path;
+use std::process::Command;
+
+fn dump_ast(options: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_cerium_framework"))
+        .arg(crate_path("tests/dev_examples/c/example2.c"))
+        .arg("--dump-ast")
+        .args(options)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn dump_ast_as_tree() {
+    let output = dump_ast(&[]);
+    assert!(output.starts_with("TransUnit"));
+    assert!(output.contains("\n   FunDef"));
+    assert!(output.contains("Return"));
+    assert!(!output.contains("Program"));
+}
+
+#[test]
+fn dump_ast_flat() {
+    let output = dump_ast(&["--flat"]);
+    assert!(output.contains("\nFunDef"));
+    assert!(output.contains("TransUnit"));
+    assert!(output.lines().all(|line| !line.starts_with(' ')));
+}
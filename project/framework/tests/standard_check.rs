@@ -0,0 +1,39 @@
+// Run the standard (-s) path of the binary end to end.
+use cerium_framework::crate_path;
+use std::process::Command;
+
+fn run_standard_once(file_path: &str) -> bool {
+    let status = Command::new(env!("CARGO_BIN_EXE_cerium_framework"))
+        .args([file_path, "-s", "--once"])
+        .status()
+        .unwrap();
+    status.success()
+}
+
+#[test]
+fn standard_check_correct_program() {
+    assert_eq!(
+        run_standard_once(&crate_path("tests/dev_examples/c/example2.c")),
+        true
+    );
+}
+
+#[test]
+fn standard_check_error_program() {
+    assert_eq!(
+        run_standard_once(&crate_path("tests/dev_examples/c/example3.c")),
+        false
+    );
+}
+
+// A file that doesn't parse fails the check like a typing error does (instead of panicking).
+#[test]
+fn standard_check_syntax_error() {
+    let file_path = std::env::temp_dir().join("cerium_once_syntax_error.c");
+    std::fs::write(&file_path, "int main(void) { int a = 1 +; }\n").unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_cerium_framework"))
+        .args([file_path.to_string_lossy().as_ref(), "-s", "--once"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(1));
+}